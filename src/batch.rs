@@ -10,10 +10,15 @@ use bytes::{BufMut, Bytes, BytesMut};
 use prost::{decode_length_delimiter, encode_length_delimiter};
 
 use crate::data::log_record::{LogRecord, LogRecordType};
-use crate::db::Engine;
+use crate::db::{Engine, update_index};
 use crate::errors::{Errors, Result};
+use crate::index::check_index_budget;
 use crate::options::{IndexType, WriteBatchOptions};
 
+/// 事务完成记录的key，加上事务序列号写入数据文件
+///
+/// 用户可以写入同名的key：加载索引、预热读取、merge和回放操作日志时都按记录类型识别事务完成记录，
+/// 事务完成记录不更新索引，事务中的用户记录只会是Normal或Deleted类型，因此两者不会冲突
 const TX_FIN_KEY: &[u8] = b"txn-fin";
 pub(crate) const NON_TRANSACTION_SEQ_NUMBER: usize = 0;
 
@@ -28,7 +33,7 @@ pub struct WriteBatch<'a> {
 }
 
 impl Engine {
-    pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch> {
+    pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch<'_>> {
         if !self.sequence_number_file_exists
             && self.options.index_type == IndexType::BPlusTree
             && !self.is_first_load
@@ -48,24 +53,41 @@ impl WriteBatch<'_> {
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
+        self.engine.check_key(&key)?;
         let record = LogRecord {
             key: key.to_vec(),
             value: value.to_vec(),
             rec_type: crate::data::log_record::LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
         };
-        self.pending_writes.lock().insert(key.to_vec(), record);
+        let pending = {
+            let mut pending_writes = self.pending_writes.lock();
+            pending_writes.insert(self.engine.index_key(&key), record);
+            pending_writes.len()
+        };
+        // 达到自动提交阈值，提交已暂存的记录，之后的写入属于新的事务
+        if let Some(auto_commit_at) = self.options.auto_commit_at
+            && pending >= auto_commit_at
+        {
+            self.commit()?;
+        }
         Ok(())
     }
 
+    /// 删除key，如果key只存在于当前批次中（尚未提交），则只撤销暂存的写入，不会写入删除记录
     pub fn delete(&self, key: Bytes) -> Result<()> {
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
+        self.engine.check_key(&key)?;
 
+        self.engine.wait_index_ready()?;
+        let index_key = self.engine.index_key(&key);
         let mut pending_writes = self.pending_writes.lock();
         // 索引中不存在，直接在pending_writes中删除
-        if self.engine.index.get(key.to_vec()).is_none() {
-            pending_writes.remove(key.as_ref());
+        if self.engine.index.get(index_key.clone()).is_none() {
+            pending_writes.remove(&index_key);
             return Ok(());
         }
 
@@ -73,19 +95,39 @@ impl WriteBatch<'_> {
             key: key.to_vec(),
             value: vec![],
             rec_type: crate::data::log_record::LogRecordType::Deleted,
+            value_encoded: false,
+            expire_at: None,
         };
-        pending_writes.insert(key.to_vec(), record);
+        pending_writes.insert(index_key, record);
         Ok(())
     }
 
     pub fn commit(&self) -> Result<()> {
+        self.commit_with_seq().map(|_| ())
+    }
+
+    /// 提交批量写入，返回本次事务使用的序列号
+    ///
+    /// 批次为空时不会消耗序列号，返回NON_TRANSACTION_SEQ_NUMBER
+    pub fn commit_with_seq(&self) -> Result<usize> {
         if self.pending_writes.lock().is_empty() {
-            return Ok(());
+            return Ok(NON_TRANSACTION_SEQ_NUMBER);
         }
         if self.pending_writes.lock().len() > self.options.max_batch_size {
             return Err(Errors::BatchSizeExceeded);
         }
 
+        self.engine.wait_index_ready()?;
+        let _merge_guard = self.engine.wait_merge_if_blocking();
+        // 写入数据文件前检查新key是否超出内存索引的预算
+        check_index_budget(
+            self.engine.index.as_ref(),
+            self.pending_writes
+                .lock()
+                .iter()
+                .filter(|(_, record)| record.rec_type == LogRecordType::Normal)
+                .map(|(key, _)| key.as_slice()),
+        )?;
         // 加锁，防止多个写入操作同时进行
         let batch_commit_lock = self.engine.batch_commit_mutex.lock();
         // 更新到下一个事务序列号
@@ -96,43 +138,106 @@ impl WriteBatch<'_> {
 
         let mut pending_writes = self.pending_writes.lock();
         let mut positions = HashMap::new();
+        // 持有锁直到索引更新完成，与Engine::delete互斥
+        let mut tombstones = self.engine.active_tombstones.lock();
+        let active_file_id = self.engine.active_file.read().get_file_id();
+        let mut keys = Vec::with_capacity(pending_writes.len());
+        let mut records = Vec::with_capacity(pending_writes.len() + 1);
         for (key, record) in pending_writes.iter() {
-            let mut record = LogRecord {
-                key: get_record_sequence_number_with_key(key, sequence_number),
+            // 暂存后key已被删除，且活跃文件中已有删除记录，跳过重复的删除记录
+            if record.rec_type == LogRecordType::Deleted
+                && self.engine.index.get(key.clone()).is_none()
+                && tombstones.contains(active_file_id, key)
+            {
+                continue;
+            }
+            // 用户记录与事务完成记录只通过记录类型区分，即使key相同
+            debug_assert_ne!(record.rec_type, LogRecordType::TxnFinished);
+            keys.push(key.clone());
+            // 数据文件中保存原始key，pending_writes的key是变换后的key
+            records.push(LogRecord {
+                key: get_record_sequence_number_with_key(&record.key, sequence_number),
                 value: record.value.clone(),
                 rec_type: record.rec_type,
-            };
-            // 写入数据文件
-            let pos = self.engine.append_log_record(&mut record)?;
-            positions.insert(key.clone(), pos);
+                value_encoded: false,
+                expire_at: None,
+            });
         }
         // 最后一条记录表示事务完成
-        let mut finished_record = LogRecord {
+        records.push(LogRecord {
             key: get_record_sequence_number_with_key(TX_FIN_KEY, sequence_number),
             value: vec![],
             rec_type: LogRecordType::TxnFinished,
+            value_encoded: false,
+            expire_at: None,
+        });
+        let _index_guard = self.engine.index_update_lock.read();
+        // 写入数据文件，single_write时所有记录一次写入
+        let mut record_positions = match self.options.single_write {
+            true => self.engine.append_log_records(&mut records)?,
+            false => records
+                .iter_mut()
+                .map(|record| self.engine.append_log_record(record))
+                .collect::<Result<Vec<_>>>()?,
         };
-        self.engine.append_log_record(&mut finished_record)?;
+        let finished_pos = record_positions.pop().unwrap();
+        for ((key, record), pos) in keys.into_iter().zip(&records).zip(record_positions) {
+            if record.rec_type == LogRecordType::Deleted {
+                tombstones.insert(pos.file_id, key.clone());
+            }
+            positions.insert(key, pos);
+        }
 
-        // 同步写入
-        if self.options.sync_write {
+        // 同步写入，或每提交sync_every个批次同步一次
+        let commit_count = self
+            .engine
+            .batch_commit_count
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+            + 1;
+        let sync_every = self.options.sync_every;
+        if self.options.sync_write || (sync_every > 0 && commit_count.is_multiple_of(sync_every)) {
             self.engine.sync()?;
+            #[cfg(test)]
+            self.engine
+                .batch_sync_count
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         }
 
-        // 写入index
-        for (_, record) in pending_writes.drain() {
-            match record.rec_type {
-                LogRecordType::Normal => {
-                    let pos = positions.get(&record.key).unwrap();
-                    self.engine.index.put(record.key.clone(), *pos);
-                }
-                LogRecordType::Deleted => {
-                    self.engine.index.delete(record.key);
-                }
-                LogRecordType::TxnFinished => {}
+        // 写入index，记录已经落盘，删除的key提交前可能已不在索引中，由update_index忽略
+        let mut journal_records = Vec::new();
+        for (key, record) in pending_writes.drain() {
+            let Some(pos) = positions.get(&key) else {
+                continue;
+            };
+            self.engine.invalidate_cached(&key);
+            update_index(
+                self.engine.index.as_ref(),
+                &self.engine.dead_bytes,
+                key,
+                record.rec_type,
+                *pos,
+            )?;
+            if self.engine.ops_journal.is_some() {
+                journal_records.push(record);
             }
         }
-        Ok(())
+        update_index(
+            self.engine.index.as_ref(),
+            &self.engine.dead_bytes,
+            TX_FIN_KEY.to_vec(),
+            LogRecordType::TxnFinished,
+            finished_pos,
+        )?;
+        self.engine
+            .journal_ops(journal_records.iter().map(|record| {
+                (
+                    record.key.as_slice(),
+                    record.value.as_slice(),
+                    record.rec_type,
+                    record.expire_at,
+                )
+            }))?;
+        Ok(sequence_number)
     }
 }
 
@@ -170,6 +275,7 @@ mod tests {
             bytes_per_sync: 100,
             index_type: IndexType::BTree,
             use_mmap: false,
+            ..Default::default()
         };
         let engine_dir = engine_opts.dir_path.clone();
         let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
@@ -200,6 +306,77 @@ mod tests {
         std::fs::remove_dir_all(engine_dir).expect("Failed to remove engine dir");
     }
 
+    #[test]
+    fn test_write_batch_txn_fin_key() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_write_batch_txn_fin_key"),
+            data_file_size: 8 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let fin_key = Bytes::from_static(TX_FIN_KEY);
+        // 直接写入与事务完成记录同名的key
+        engine
+            .put(fin_key.clone(), "v0".into())
+            .expect("Failed to put data");
+        // 在事务中写入同名key，同一个事务中还有事务完成记录
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        batch
+            .put(fin_key.clone(), "v1".into())
+            .expect("Failed to put");
+        batch
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put");
+        batch.commit().expect("Failed to commit");
+        assert_eq!(engine.get(fin_key.clone()), Ok("v1".into()));
+        // 在事务中删除
+        let batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        batch.delete(fin_key.clone()).expect("Failed to delete");
+        batch.commit().expect("Failed to commit");
+        assert_eq!(engine.get(fin_key.clone()), Err(Errors::KeyNotFound));
+        // 再次写入，之后还有其他事务
+        engine
+            .put(fin_key.clone(), "v2".into())
+            .expect("Failed to put data");
+        for i in 2..200 {
+            let mut batch = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .expect("Failed to create write batch");
+            batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put");
+            batch.commit().expect("Failed to commit");
+        }
+        assert_eq!(engine.get(fin_key.clone()), Ok("v2".into()));
+
+        // 重新打开后从数据文件重建的索引中，用户key和事务都不受影响
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.get(fin_key.clone()), Ok("v2".into()));
+        for i in 1..200 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        assert_eq!(engine.list_keys().map(|keys| keys.len()), Ok(200));
+
+        // merge后重新打开
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.get(fin_key.clone()), Ok("v2".into()));
+        for i in 1..200 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove engine dir");
+    }
+
     #[test]
     fn test_write_batch_reopen() {
         let engine_opts = Options {
@@ -209,6 +386,7 @@ mod tests {
             bytes_per_sync: 1000000,
             index_type: IndexType::BTree,
             use_mmap: false,
+            ..Default::default()
         };
         let engine_dir = engine_opts.dir_path.clone();
         let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
@@ -271,6 +449,7 @@ mod tests {
             bytes_per_sync: 1000000,
             index_type: IndexType::BTree,
             use_mmap: true,
+            ..Default::default()
         };
         let engine_dir = engine_opts.dir_path.clone();
         let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
@@ -278,6 +457,7 @@ mod tests {
             .new_write_batch(WriteBatchOptions {
                 max_batch_size: 10000000,
                 sync_write: false,
+                ..Default::default()
             })
             .expect("Failed to create write batch");
 
@@ -291,4 +471,315 @@ mod tests {
 
         std::fs::remove_dir_all(engine_dir).expect("Failed to remove engine dir");
     }
+
+    #[test]
+    fn test_write_batch_put_then_delete() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_write_batch_put_then_delete"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let mut write_batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        // 同一批次中先写入再删除，提交后key不存在
+        write_batch
+            .put("k1".into(), "v1".into())
+            .expect("Failed to put");
+        write_batch
+            .put("k2".into(), "v2".into())
+            .expect("Failed to put");
+        write_batch.delete("k1".into()).expect("Failed to delete");
+        write_batch.commit().expect("Failed to commit");
+        assert_eq!(engine.get("k1".into()), Err(Errors::KeyNotFound));
+        assert_eq!(engine.get("k2".into()), Ok("v2".into()));
+
+        // 重启后依然不存在
+        std::mem::drop(write_batch);
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.get("k1".into()), Err(Errors::KeyNotFound));
+        assert_eq!(engine.get("k2".into()), Ok("v2".into()));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove engine dir");
+    }
+
+    #[test]
+    fn test_write_batch_commit_with_seq() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_write_batch_commit_with_seq"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+
+        // 空批次不消耗序列号
+        let write_batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        assert_eq!(
+            write_batch.commit_with_seq(),
+            Ok(NON_TRANSACTION_SEQ_NUMBER)
+        );
+
+        let mut last_seq = NON_TRANSACTION_SEQ_NUMBER;
+        for i in 0..5 {
+            let mut write_batch = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .expect("Failed to create write batch");
+            write_batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put");
+            let seq = write_batch.commit_with_seq().expect("Failed to commit");
+            assert!(seq > last_seq);
+            last_seq = seq;
+        }
+
+        // 重启后序列号继续递增
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        let mut write_batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        write_batch
+            .put(get_test_key(100), get_test_value(100))
+            .expect("Failed to put");
+        let seq = write_batch.commit_with_seq().expect("Failed to commit");
+        assert!(seq > last_seq);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove engine dir");
+    }
+
+    #[test]
+    fn test_write_batch_sync_every() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_write_batch_sync_every"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+        let batch_opts = WriteBatchOptions {
+            sync_every: 10,
+            ..Default::default()
+        };
+        for i in 0..95 {
+            let mut write_batch = engine
+                .new_write_batch(batch_opts)
+                .expect("Failed to create write batch");
+            write_batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put");
+            write_batch.commit().expect("Failed to commit");
+        }
+        // 每10个批次持久化一次
+        assert_eq!(
+            engine
+                .batch_sync_count
+                .load(std::sync::atomic::Ordering::SeqCst),
+            9
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove engine dir");
+    }
+
+    #[test]
+    fn test_write_batch_single_write() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_write_batch_single_write"),
+            data_file_size: 64 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let write_count = || {
+            engine
+                .data_file_write_count
+                .load(std::sync::atomic::Ordering::SeqCst)
+        };
+
+        // 逐条写入，每条记录一次写入
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        for i in 0..100 {
+            batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put");
+        }
+        batch.commit().expect("Failed to commit");
+        assert_eq!(write_count(), 101);
+
+        // 一次写入，记录超过活跃文件剩余空间时切换文件后继续写入
+        let batch_opts = WriteBatchOptions {
+            single_write: true,
+            ..Default::default()
+        };
+        let mut batch = engine
+            .new_write_batch(batch_opts)
+            .expect("Failed to create write batch");
+        for i in 100..5000 {
+            batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put");
+        }
+        batch.delete(get_test_key(0)).expect("Failed to delete");
+        let file_count = engine.data_file_ids().len();
+        batch.commit().expect("Failed to commit");
+        let new_files = engine.data_file_ids().len() - file_count;
+        assert!(new_files > 0);
+        assert_eq!(write_count(), 101 + new_files + 1);
+
+        // 每条记录的位置都指向对应的记录
+        let mut records = HashMap::new();
+        for file_id in engine.data_file_ids() {
+            for (record, pos) in engine.read_file_records(file_id).unwrap() {
+                records.insert((pos.file_id, pos.offset), (record, pos));
+            }
+        }
+        for i in 100..5000 {
+            let pos = engine.index.get(get_test_key(i).to_vec()).unwrap();
+            let (record, record_pos) = records
+                .get(&(pos.file_id, pos.offset))
+                .expect("Record not found at position");
+            assert_eq!(*record_pos, pos);
+            assert_eq!(record.value, get_test_value(i).to_vec());
+        }
+        let check = |engine: &Engine| {
+            assert!(engine.get(get_test_key(0)).is_err());
+            for i in 1..5000 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).expect("Failed to get"),
+                    get_test_value(i)
+                );
+            }
+        };
+        check(&engine);
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        check(&engine);
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_write_batch_auto_commit() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_write_batch_auto_commit"),
+            data_file_size: 64 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions {
+                auto_commit_at: Some(100),
+                ..Default::default()
+            })
+            .expect("Failed to create write batch");
+        for i in 0..1050 {
+            batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put");
+            // 达到阈值时自动提交，暂存的记录数不会超过阈值
+            assert_eq!(batch.pending_writes.lock().len(), (i as usize + 1) % 100);
+        }
+        // 已自动提交的记录立即可见
+        assert_eq!(engine.get(get_test_key(999)), Ok(get_test_value(999)));
+        assert_eq!(engine.get(get_test_key(1000)), Err(Errors::KeyNotFound));
+        batch.commit().expect("Failed to commit");
+
+        // 每次自动提交都是一个独立的事务，使用不同的序列号
+        let mut seq_numbers = std::collections::HashSet::new();
+        for file_id in engine.data_file_ids() {
+            for (record, _) in engine.read_file_records(file_id).unwrap() {
+                if record.rec_type == LogRecordType::TxnFinished {
+                    let (seq_number, _) = parse_record_sequence_number_with_key(&record.key);
+                    assert!(seq_numbers.insert(seq_number));
+                }
+            }
+        }
+        assert_eq!(seq_numbers.len(), 11);
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..1050 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    /// 写入总是失败的索引，其余操作交给内部索引
+    struct FailingPutIndex(Box<dyn crate::index::Indexer>);
+
+    impl crate::index::Indexer for FailingPutIndex {
+        fn put(&self, _key: Vec<u8>, _pos: crate::data::log_record::LogRecordPos) -> bool {
+            false
+        }
+        fn get(&self, key: Vec<u8>) -> Option<crate::data::log_record::LogRecordPos> {
+            self.0.get(key)
+        }
+        fn delete(&self, key: Vec<u8>) -> bool {
+            self.0.delete(key)
+        }
+        fn iterator(
+            &self,
+            options: crate::options::IteratorOptions,
+        ) -> Result<Box<dyn crate::index::IndexIterator>> {
+            self.0.iterator(options)
+        }
+        fn list_keys(&self) -> Result<Vec<Bytes>> {
+            self.0.list_keys()
+        }
+    }
+
+    #[test]
+    fn test_write_batch_index_error() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_write_batch_index_error"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let _ = std::fs::remove_dir_all(&engine_dir);
+        let mut engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+        // 删除不在索引中的key不是错误
+        let batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        batch.delete(get_test_key(1)).expect("Failed to delete");
+        assert_eq!(batch.commit(), Ok(()));
+
+        // 写入索引失败时提交返回错误
+        engine.index = Arc::new(FailingPutIndex(
+            crate::index::new_indexer(IndexType::BTree, &engine_dir)
+                .expect("Failed to create indexer"),
+        ));
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        batch
+            .put(get_test_key(2), get_test_value(2))
+            .expect("Failed to put");
+        assert_eq!(batch.commit(), Err(Errors::FailedToUpdateIndex));
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
 }
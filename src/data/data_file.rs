@@ -1,22 +1,49 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+use std::collections::BTreeSet;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
-use crate::data::log_record::{LogRecord, max_log_record_header_size};
+use crate::batch::{NON_TRANSACTION_SEQ_NUMBER, get_record_sequence_number_with_key};
+use crate::data::log_record::{
+    EXPIRY_FLAG, EXPIRY_LEN, LogRecord, PREFIX_COMPRESSED_FLAG, RECORD_FORMAT_VERSION,
+    RECORD_VERSION_MASK, RECORD_VERSION_SHIFT, VALUE_CODEC_FLAG, max_prefix_log_record_header_size,
+};
 use crate::errors::{Errors, Result};
 use crate::fio::{IOManager, new_io_manager};
-use crate::options::IOType;
-use bytes::{Buf, BytesMut};
-use parking_lot::RwLock;
-use prost::{decode_length_delimiter, length_delimiter_len};
+use crate::options::{Format, IOType};
+use bytes::{Buf, BufMut, BytesMut};
+use parking_lot::{Mutex, RwLock};
+use prost::{decode_length_delimiter, encode_length_delimiter, length_delimiter_len};
 
 use super::log_record::{LogRecordPos, LogRecordType, ReadLogRecord};
 
 pub const DATA_FILE_NAME_SUFFIX: &str = ".data";
 pub(crate) const HINT_FILE_NAME: &str = "hint-index";
+pub(crate) const HINT_TMP_FILE_NAME: &str = "hint-index.tmp";
 pub(crate) const MERGE_FINISHED_FILE_NAME: &str = "merge-finished";
 pub(crate) const SEQUENCE_NUMBER_FILE_NAME: &str = "sequence-number";
+pub(crate) const VERSION_FILE_NAME: &str = "version";
+pub(crate) const MERGE_PROGRESS_FILE_NAME: &str = "merge-progress";
+pub(crate) const QUARANTINE_FILE_NAME: &str = "quarantine";
+/// 记录带内联hint的merge后数据文件id上界，小于该id的文件末尾可能有内联hint
+pub(crate) const INLINE_HINTS_FILE_NAME: &str = "inline-hints";
+/// 不带CRC的hint索引文件以该字节开头，原生格式的记录首字节是记录类型，不会为0
+pub(crate) const HINT_RAW_FORMAT_MARK: u8 = 0;
+
+/// merge生成的数据文件末尾内联hint的标识
+const INLINE_HINTS_MAGIC: &[u8; 8] = b"BCINHINT";
+/// 内联hint的尾部：hint记录和长度的CRC(u32) + hint记录总长度(u64) + 标识
+const INLINE_HINTS_FOOTER_SIZE: u64 = 4 + 8 + INLINE_HINTS_MAGIC.len() as u64;
+
+/// 不带CRC的hint记录中，offset变长编码的最大长度
+const HINT_RAW_OFFSET_MAX_LEN: usize = 10;
+
+/// Go版本bitcask记录头：key长度(u32) + value长度(u64)
+const GO_BITCASK_HEADER_SIZE: usize = 4 + 8;
+/// Go版本bitcask记录尾：value的CRC(u32) + 过期时间(u64)
+const GO_BITCASK_TRAILER_SIZE: usize = 4 + 8;
 
 /// 数据文件
 pub struct DataFile {
@@ -26,6 +53,60 @@ pub struct DataFile {
     write_offset: Arc<RwLock<u64>>,
     /// io管理接口
     io_manager: Box<dyn IOManager>,
+    /// 记录格式
+    format: Format,
+    /// key前缀压缩的读写状态
+    key_prefix: KeyPrefixState,
+    /// 记录中key和value长度的上限，用于计算读取记录头的缓冲区大小
+    max_record_size: usize,
+    /// merge时写入了内联hint的文件中，内联hint的起始偏移，即数据记录的结束位置
+    inline_hints_offset: Option<u64>,
+}
+
+/// 原生格式的记录头
+struct RecordHeader {
+    rec_type: LogRecordType,
+    /// value是否经过ValueCodec编码
+    value_encoded: bool,
+    /// value的前EXPIRY_LEN个字节是否为过期时间
+    has_expiry: bool,
+    /// 与上一条记录key的公共前缀长度，不是前缀压缩的记录时为None
+    shared_len: Option<usize>,
+    /// key长度，前缀压缩的记录中为key后缀的长度
+    key_len: usize,
+    value_len: usize,
+    /// 记录头大小
+    size: usize,
+}
+
+/// 数据文件的key前缀压缩状态
+#[derive(Default)]
+struct KeyPrefixState {
+    /// 已写入的记录对应的写入状态
+    writer: Mutex<KeyPrefixWriter>,
+    /// 已编码但尚未写入的记录对应的写入状态，写入成功后才提交到writer
+    pending: Mutex<Option<PendingKeyPrefix>>,
+    /// 文件中是否存在前缀压缩的记录
+    compressed: AtomicBool,
+    /// 已知的重启点偏移，即完整key记录的起始位置
+    restarts: RwLock<BTreeSet<u64>>,
+    /// 上一次读取的记录之后的偏移和记录的key，顺序读取时用于还原下一条记录的key
+    last_read: Mutex<Option<(u64, Vec<u8>)>>,
+}
+
+#[derive(Default, Clone)]
+struct KeyPrefixWriter {
+    /// 上一条写入记录的key
+    last_key: Vec<u8>,
+    /// 自上一个重启点以来写入的记录数，为0时下一条记录写为重启点
+    since_restart: usize,
+}
+
+/// 编码到同一个缓冲区中、尚未写入的记录对应的前缀压缩状态
+struct PendingKeyPrefix {
+    writer: KeyPrefixWriter,
+    /// 缓冲区中重启点记录的偏移
+    restarts: Vec<u64>,
 }
 
 impl DataFile {
@@ -37,9 +118,119 @@ impl DataFile {
             file_id: Arc::new(RwLock::new(file_id)),
             write_offset: Default::default(),
             io_manager,
+            format: Format::Native,
+            key_prefix: Default::default(),
+            max_record_size: u32::MAX as usize,
+            inline_hints_offset: None,
         })
     }
 
+    /// 读取文件尾部的内联hint，只对记录为带内联hint的merge后数据文件调用
+    pub fn load_inline_hints(&mut self) -> Result<()> {
+        self.inline_hints_offset = self.find_inline_hints()?;
+        Ok(())
+    }
+
+    /// 读取文件尾部，文件末尾有完整的内联hint时返回其起始偏移，标识或CRC不匹配时返回None
+    fn find_inline_hints(&self) -> Result<Option<u64>> {
+        let file_size = self.file_size();
+        if file_size < INLINE_HINTS_FOOTER_SIZE {
+            return Ok(None);
+        }
+        let mut footer = [0u8; INLINE_HINTS_FOOTER_SIZE as usize];
+        self.io_manager
+            .read(&mut footer, file_size - INLINE_HINTS_FOOTER_SIZE)?;
+        if &footer[12..] != INLINE_HINTS_MAGIC {
+            return Ok(None);
+        }
+        let crc = (&footer[..4]).get_u32();
+        let hints_len = (&footer[4..12]).get_u64();
+        let Some(offset) = (file_size - INLINE_HINTS_FOOTER_SIZE).checked_sub(hints_len) else {
+            return Ok(None);
+        };
+        let mut hints = vec![0u8; hints_len as usize];
+        self.io_manager.read(&mut hints, offset)?;
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&hints);
+        hasher.update(&footer[4..12]);
+        if hasher.finalize() != crc {
+            return Ok(None);
+        }
+        Ok(Some(offset))
+    }
+
+    /// 内联hint的起始偏移，没有内联hint时为None
+    pub fn get_inline_hints_offset(&self) -> Option<u64> {
+        self.inline_hints_offset
+    }
+
+    /// 设置内联hint的起始偏移，重新打开已加载过内联hint的文件时使用
+    pub fn set_inline_hints_offset(&mut self, offset: Option<u64>) {
+        self.inline_hints_offset = offset;
+    }
+
+    /// 文件中是否有merge时写入的内联hint
+    pub fn has_inline_hints(&self) -> bool {
+        self.inline_hints_offset.is_some()
+    }
+
+    /// 在文件末尾追加内联hint，记录格式与不带CRC的hint记录相同，
+    /// 之后是hint记录和长度的CRC、hint记录总长度和标识
+    ///
+    /// 只用于merge生成的、不再写入的数据文件，重新打开文件并调用load_inline_hints后才识别出内联hint
+    pub fn write_inline_hints(&self, hints: &[(Vec<u8>, LogRecordPos)]) -> Result<()> {
+        let mut buf = BytesMut::new();
+        for (key, record_pos) in hints {
+            encode_length_delimiter(key.len(), &mut buf).expect("Failed to encode key length");
+            buf.put(key.as_slice());
+            buf.put(record_pos.encode().as_slice());
+        }
+        let hints_len = (buf.len() as u64).to_be_bytes();
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&buf);
+        hasher.update(&hints_len);
+        buf.put_u32(hasher.finalize());
+        buf.put(&hints_len[..]);
+        buf.put(&INLINE_HINTS_MAGIC[..]);
+        self.write(&buf)?;
+        Ok(())
+    }
+
+    /// 读取文件末尾的内联hint，返回key和记录位置，没有内联hint时返回空
+    pub fn read_inline_hints(&self) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+        let Some(offset) = self.inline_hints_offset else {
+            return Ok(vec![]);
+        };
+        let mut buf = vec![0u8; (self.file_size() - INLINE_HINTS_FOOTER_SIZE - offset) as usize];
+        self.io_manager.read(&mut buf, offset)?;
+        let mut buf = &buf[..];
+        let mut hints = Vec::new();
+        while !buf.is_empty() {
+            let key_len =
+                decode_length_delimiter(&mut buf).map_err(|_| Errors::InvalidHintRecord)?;
+            if buf.len() < key_len {
+                return Err(Errors::InvalidHintRecord);
+            }
+            let key = buf[..key_len].to_vec();
+            buf.advance(key_len);
+            let mut decode =
+                || decode_length_delimiter(&mut buf).map_err(|_| Errors::InvalidHintRecord);
+            let record_pos = LogRecordPos {
+                file_id: decode()? as u32,
+                offset: decode()? as u64,
+                size: decode()? as u32,
+            };
+            hints.push((key, record_pos));
+        }
+        Ok(hints)
+    }
+
+    /// 替换io管理接口，用于测试中模拟io错误
+    #[cfg(test)]
+    pub(crate) fn replace_io_manager(&mut self, io_manager: Box<dyn IOManager>) {
+        self.io_manager = io_manager;
+    }
+
     /// 设置写偏移
     pub fn set_write_offset(&self, offset: u64) {
         *self.write_offset.write() = offset;
@@ -50,6 +241,21 @@ impl DataFile {
         *self.write_offset.read()
     }
 
+    /// 设置记录中key和value长度的上限，通常为配置的data_file_size，读取记录头时只分配对应大小的缓冲区
+    pub fn set_max_record_size(&mut self, max_record_size: u64) {
+        self.max_record_size = max_record_size.min(u32::MAX as u64) as usize;
+    }
+
+    /// 设置记录格式
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    /// 获取记录格式
+    pub fn get_format(&self) -> Format {
+        self.format
+    }
+
     /// 同步数据文件
     pub fn sync(&self) -> Result<()> {
         self.io_manager.sync()
@@ -68,49 +274,504 @@ impl DataFile {
         Ok(n_bytes)
     }
 
+    /// 截断数据文件到offset处，丢弃之后未写完的数据，并重置写偏移
+    pub fn truncate(&self, offset: u64) -> Result<()> {
+        self.io_manager.truncate(offset)?;
+        self.set_write_offset(offset);
+        Ok(())
+    }
+
     /// 从给定偏移处读取一条记录
     pub fn read_log_record(&self, offset: u64) -> Result<ReadLogRecord> {
-        // 读取header，此处读取的header_buf大小为max_log_record_header_size()
-        let mut header_buf = BytesMut::zeroed(max_log_record_header_size());
-        self.io_manager.read(&mut header_buf, offset)?;
-        // 取出record type
-        let record_type = header_buf.get_u8();
-        // 取出key长度
-        let key_len = decode_length_delimiter(&mut header_buf).unwrap();
+        self.read_log_record_with_buf(offset, &mut BytesMut::new())
+    }
+
+    /// 从给定偏移处读取一条记录，使用调用方提供的缓冲区，顺序扫描时可复用同一块内存
+    ///
+    /// 缓冲区只在当前调用中使用，并发读取时每个调用方应持有各自的缓冲区
+    pub fn read_log_record_with_buf(
+        &self,
+        offset: u64,
+        scratch: &mut BytesMut,
+    ) -> Result<ReadLogRecord> {
+        if self.format == Format::GoBitcask {
+            return self.read_go_log_record(offset, scratch);
+        }
+        let header = self.read_native_header(offset, scratch)?;
+        // 前缀压缩的记录需要上一条记录的key才能还原完整key
+        let prev_key = match header.shared_len {
+            Some(_) => self.prefix_base_key(offset, scratch)?,
+            None => Vec::new(),
+        };
+        let read_record = self.read_native_body(offset, &header, &prev_key, scratch)?;
+        self.remember_key(offset, &header, &read_record);
+        Ok(read_record)
+    }
+
+    /// 读取并解析原生格式的记录头
+    fn read_native_header(&self, offset: u64, scratch: &mut BytesMut) -> Result<RecordHeader> {
+        let header_len = max_prefix_log_record_header_size(self.max_record_size);
+        let full_header_len = max_prefix_log_record_header_size(u32::MAX as usize);
+        match self.parse_native_header(offset, header_len, scratch) {
+            // 记录可能是在更大的data_file_size配置下写入的，记录头超出了按当前配置计算的大小
+            Err(Errors::InvalidLogRecordCrc) if header_len < full_header_len => {
+                self.parse_native_header(offset, full_header_len, scratch)
+            }
+            res => res,
+        }
+    }
+
+    /// 读取header_len字节并解析原生格式的记录头
+    fn parse_native_header(
+        &self,
+        offset: u64,
+        header_len: usize,
+        scratch: &mut BytesMut,
+    ) -> Result<RecordHeader> {
+        self.read_header_buf(offset, header_len, scratch)?;
+        let mut header_buf = &scratch[..];
+        // 取出record type，文件损坏时返回错误而不是panic
+        let type_byte = header_buf.get_u8();
+        let record_type = type_byte
+            & !(PREFIX_COMPRESSED_FLAG | VALUE_CODEC_FLAG | EXPIRY_FLAG | RECORD_VERSION_MASK);
+        if record_type > LogRecordType::TxnFinished as u8
+            || (type_byte != record_type && record_type == 0)
+        {
+            return Err(Errors::InvalidLogRecordCrc);
+        }
+        // 记录类型有效时才检查版本，更新版本写入的记录格式可能不同，无法按当前格式解析
+        let version = (type_byte & RECORD_VERSION_MASK) >> RECORD_VERSION_SHIFT;
+        if version > RECORD_FORMAT_VERSION {
+            return Err(Errors::UnsupportedRecordVersion { version });
+        }
+        // 前缀压缩的记录先取出与上一条记录key的公共前缀长度
+        let shared_len = match type_byte & PREFIX_COMPRESSED_FLAG != 0 {
+            true => Some(
+                decode_length_delimiter(&mut header_buf)
+                    .map_err(|_| Errors::InvalidLogRecordCrc)?,
+            ),
+            false => None,
+        };
+        // 取出key长度，前缀压缩的记录中为key后缀的长度
+        let key_len =
+            decode_length_delimiter(&mut header_buf).map_err(|_| Errors::InvalidLogRecordCrc)?;
         // 取出value长度
-        let value_len = decode_length_delimiter(&mut header_buf).unwrap();
+        let value_len =
+            decode_length_delimiter(&mut header_buf).map_err(|_| Errors::InvalidLogRecordCrc)?;
         // 如果key长度和value长度都为0，则表示读取到文件末尾
-        if key_len == 0 && value_len == 0 {
+        if shared_len.is_none() && key_len == 0 && value_len == 0 {
             // 读取到文件末尾
             // 在数据库加载时，如果读取到文件末尾，会continue，读取下一个文件的内容
             return Err(Errors::ReadDataFileEof);
         }
         // 计算实际的header大小
-        let actual_header_size =
-            1 + length_delimiter_len(key_len) + length_delimiter_len(value_len);
-        // 读取key，value，CRC
-        let mut k_v_crc_buf = BytesMut::zeroed(key_len + value_len + 4);
-        self.io_manager
-            .read(&mut k_v_crc_buf, offset + actual_header_size as u64)?;
-        // 构造log record
-        let record = LogRecord {
-            key: k_v_crc_buf.get(0..key_len).unwrap().to_vec(),
-            value: k_v_crc_buf
-                .get(key_len..k_v_crc_buf.len() - 4)
-                .unwrap()
-                .to_vec(),
+        let size = 1
+            + shared_len.map_or(0, length_delimiter_len)
+            + length_delimiter_len(key_len)
+            + length_delimiter_len(value_len);
+        Ok(RecordHeader {
             rec_type: record_type.into(),
+            value_encoded: type_byte & VALUE_CODEC_FLAG != 0,
+            has_expiry: type_byte & EXPIRY_FLAG != 0,
+            shared_len,
+            key_len,
+            value_len,
+            size,
+        })
+    }
+
+    /// 读取记录头到缓冲区，文件末尾不足max_len字节时只读取剩余部分，其余补0
+    fn read_header_buf(&self, offset: u64, max_len: usize, scratch: &mut BytesMut) -> Result<()> {
+        // 空文件或已到文件末尾时直接返回，避免把补0的缓冲区当作记录头解析，内联hint不是数据记录
+        if offset >= self.inline_hints_offset.unwrap_or_else(|| self.file_size()) {
+            return Err(Errors::ReadDataFileEof);
+        }
+        scratch.clear();
+        scratch.resize(max_len, 0);
+        match self.io_manager.read(scratch, offset) {
+            Err(Errors::ReadDataFileEof) => {
+                let file_size = self.file_size();
+                if offset >= file_size {
+                    return Err(Errors::ReadDataFileEof);
+                }
+                scratch.truncate(((file_size - offset) as usize).min(max_len));
+                self.io_manager.read(scratch, offset)?;
+                scratch.resize(max_len, 0);
+                Ok(())
+            }
+            res => res.map(|_| ()),
+        }
+    }
+
+    /// 读取记录头之后的key，value和CRC，prev_key为上一条记录的key，只在前缀压缩的记录中使用
+    fn read_native_body(
+        &self,
+        offset: u64,
+        header: &RecordHeader,
+        prev_key: &[u8],
+        scratch: &mut BytesMut,
+    ) -> Result<ReadLogRecord> {
+        let key_len = header.key_len;
+        let value_len = header.value_len;
+        self.check_record_len(
+            offset,
+            header
+                .size
+                .checked_add(key_len)
+                .and_then(|len| len.checked_add(value_len))
+                .and_then(|len| len.checked_add(4)),
+        )?;
+        // 读取key，value，CRC，复用缓冲区，容量不足时才重新分配
+        scratch.clear();
+        scratch.resize(key_len + value_len + 4, 0);
+        self.io_manager.read(scratch, offset + header.size as u64)?;
+        let key = match header.shared_len {
+            Some(shared_len) => {
+                if shared_len > prev_key.len() {
+                    return Err(Errors::InvalidLogRecordCrc);
+                }
+                let mut key = Vec::with_capacity(shared_len + key_len);
+                key.extend_from_slice(&prev_key[..shared_len]);
+                key.extend_from_slice(&scratch[..key_len]);
+                key
+            }
+            None => scratch[..key_len].to_vec(),
+        };
+        // 构造log record，有过期时间时从value中取出
+        let (expire_at, value) = split_expiry(header, &scratch[key_len..key_len + value_len])?;
+        let record = LogRecord {
+            key,
+            value: value.to_vec(),
+            rec_type: header.rec_type,
+            value_encoded: header.value_encoded,
+            expire_at,
         };
         // 读取CRC
-        k_v_crc_buf.advance(key_len + value_len);
-        let crc = k_v_crc_buf.get_u32();
-        // 验证CRC
-        if record.get_crc() != crc {
+        let crc = (&scratch[key_len + value_len..]).get_u32();
+        // 验证CRC，前缀压缩的记录按写入时的格式重新编码后计算
+        let expected_crc = match header.shared_len {
+            Some(_) => record.encode_with_prefix_into(prev_key, &mut BytesMut::new()),
+            None => record.get_crc(),
+        };
+        if expected_crc != crc {
             return Err(Errors::InvalidLogRecordCrc);
         }
         Ok(ReadLogRecord {
             record,
-            size: (actual_header_size + key_len + value_len + 4) as u64,
+            size: (header.size + key_len + value_len + 4) as u64,
+            crc,
+        })
+    }
+
+    /// 获取offset处CRC校验失败的记录的大小，用于加载时跳过该记录
+    ///
+    /// 只有记录头可以解析且整条记录没有超出文件末尾时才能确定大小，否则返回None
+    pub fn corrupt_record_size(&self, offset: u64) -> Option<u64> {
+        let mut scratch = BytesMut::new();
+        let size = match self.format {
+            Format::GoBitcask => {
+                scratch.resize(GO_BITCASK_HEADER_SIZE, 0);
+                self.io_manager.read(&mut scratch, offset).ok()?;
+                let mut header_buf = &scratch[..];
+                let key_len = header_buf.get_u32() as u64;
+                let value_len = header_buf.get_u64();
+                key_len
+                    .checked_add(value_len)?
+                    .checked_add((GO_BITCASK_HEADER_SIZE + GO_BITCASK_TRAILER_SIZE) as u64)?
+            }
+            Format::Native => {
+                let header = self.read_native_header(offset, &mut scratch).ok()?;
+                (header.size + header.key_len).checked_add(header.value_len)? as u64 + 4
+            }
+        };
+        (offset.checked_add(size)? <= self.file_size()).then_some(size)
+    }
+
+    /// offset之后直到文件末尾是否全部为0，即预留写入空间后、截断之前中断留下的空白
+    pub fn is_zero_tail(&self, offset: u64) -> bool {
+        let file_size = self.file_size();
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut offset = offset;
+        while offset < file_size {
+            let len = ((file_size - offset) as usize).min(buf.len());
+            if self.io_manager.read(&mut buf[..len], offset).is_err()
+                || buf[..len].iter().any(|b| *b != 0)
+            {
+                return false;
+            }
+            offset += len as u64;
+        }
+        true
+    }
+
+    /// offset处的记录是否超出文件末尾，即写入中断留下的不完整记录
+    ///
+    /// 记录头被截断时按补0的缓冲区解析可能失败，此时只要记录头的范围超出文件末尾就视为不完整
+    pub fn record_past_eof(&self, offset: u64) -> bool {
+        let file_size = self.file_size();
+        let mut scratch = BytesMut::new();
+        let (header_len, record_len) = match self.format {
+            Format::GoBitcask => {
+                scratch.resize(GO_BITCASK_HEADER_SIZE, 0);
+                let record_len = match self.io_manager.read(&mut scratch, offset) {
+                    Ok(_) => {
+                        let mut header_buf = &scratch[..];
+                        let key_len = header_buf.get_u32() as u64;
+                        let value_len = header_buf.get_u64();
+                        key_len.checked_add(value_len).and_then(|len| {
+                            len.checked_add(
+                                (GO_BITCASK_HEADER_SIZE + GO_BITCASK_TRAILER_SIZE) as u64,
+                            )
+                        })
+                    }
+                    Err(_) => None,
+                };
+                (GO_BITCASK_HEADER_SIZE, record_len)
+            }
+            Format::Native => {
+                let record_len =
+                    self.read_native_header(offset, &mut scratch)
+                        .ok()
+                        .and_then(|header| {
+                            (header.size + header.key_len)
+                                .checked_add(header.value_len)?
+                                .checked_add(4)
+                                .map(|len| len as u64)
+                        });
+                (
+                    max_prefix_log_record_header_size(self.max_record_size),
+                    record_len,
+                )
+            }
+        };
+        match record_len {
+            Some(len) => offset.saturating_add(len) > file_size,
+            None => offset + header_len as u64 > file_size,
+        }
+    }
+
+    /// 校验从记录头中解码出的记录长度，避免长度字段损坏时按其分配过大的内存
+    ///
+    /// 记录不会超过max_record_size，超过时可能是在更大的data_file_size配置下写入的，
+    /// 此时再确认记录没有超出文件末尾
+    fn check_record_len(&self, offset: u64, record_len: Option<usize>) -> Result<()> {
+        match record_len {
+            Some(len) if len <= self.max_record_size => Ok(()),
+            Some(len) if offset + len as u64 <= self.file_size() => Ok(()),
+            _ => Err(Errors::InvalidLogRecordLength),
+        }
+    }
+
+    /// 获取offset处前缀压缩记录的上一条记录的key
+    ///
+    /// 顺序读取时直接使用上一次读取的key，否则从offset之前最近的重启点开始顺序解码
+    fn prefix_base_key(&self, offset: u64, scratch: &mut BytesMut) -> Result<Vec<u8>> {
+        if let Some((next_offset, key)) = &*self.key_prefix.last_read.lock()
+            && *next_offset == offset
+        {
+            return Ok(key.clone());
+        }
+        let mut cur = self
+            .key_prefix
+            .restarts
+            .read()
+            .range(..offset)
+            .next_back()
+            .copied()
+            .unwrap_or(0);
+        let mut key = Vec::new();
+        while cur < offset {
+            let header = self.read_native_header(cur, scratch)?;
+            let read_record = self.read_native_body(cur, &header, &key, scratch)?;
+            if header.shared_len.is_none() {
+                self.key_prefix.restarts.write().insert(cur);
+            }
+            cur += read_record.size;
+            key = read_record.record.key;
+        }
+        // 重启点之后的记录边界与offset不一致，说明offset不是一条记录的起始位置
+        if cur != offset {
+            return Err(Errors::InvalidLogRecordCrc);
+        }
+        Ok(key)
+    }
+
+    /// 文件中存在前缀压缩的记录时，记录读取到的key和重启点，供后续读取还原key
+    fn remember_key(&self, offset: u64, header: &RecordHeader, read_record: &ReadLogRecord) {
+        match header.shared_len {
+            Some(_) => self
+                .key_prefix
+                .compressed
+                .store(true, std::sync::atomic::Ordering::Relaxed),
+            None => {
+                if !self
+                    .key_prefix
+                    .compressed
+                    .load(std::sync::atomic::Ordering::Relaxed)
+                {
+                    return;
+                }
+                self.key_prefix.restarts.write().insert(offset);
+            }
+        }
+        *self.key_prefix.last_read.lock() =
+            Some((offset + read_record.size, read_record.record.key.clone()));
+    }
+
+    /// 将记录编码后追加到缓冲区，返回CRC，缓冲区中的内容需要从当前写偏移处开始写入
+    ///
+    /// restart_interval大于0时开启key前缀压缩：记录只保存与上一条写入记录key的公共前缀长度和后缀，
+    /// 每个文件的第一条记录以及此后每restart_interval条记录写入一条完整key的重启点记录。
+    /// 缓冲区写入成功后需要调用commit_encoded提交前缀压缩的状态，
+    /// 写入失败时不提交，下一次编码到空缓冲区时从已写入的记录重新开始
+    pub fn encode_log_record(
+        &self,
+        record: &LogRecord,
+        restart_interval: usize,
+        buf: &mut BytesMut,
+    ) -> u32 {
+        if restart_interval == 0 {
+            return record.encode_into(buf);
+        }
+        let mut pending = self.key_prefix.pending.lock();
+        if buf.is_empty() {
+            *pending = None;
+        }
+        let pending = pending.get_or_insert_with(|| PendingKeyPrefix {
+            writer: self.key_prefix.writer.lock().clone(),
+            restarts: Vec::new(),
+        });
+        let writer = &mut pending.writer;
+        let crc = match writer.since_restart == 0 || writer.since_restart >= restart_interval {
+            true => {
+                pending
+                    .restarts
+                    .push(self.get_write_offset() + buf.len() as u64);
+                writer.since_restart = 0;
+                record.encode_into(buf)
+            }
+            false => record.encode_with_prefix_into(&writer.last_key, buf),
+        };
+        writer.since_restart += 1;
+        writer.last_key.clear();
+        writer.last_key.extend_from_slice(&record.key);
+        crc
+    }
+
+    /// encode_log_record编码的缓冲区写入成功后，提交其中记录的前缀压缩状态
+    pub fn commit_encoded(&self) {
+        let Some(pending) = self.key_prefix.pending.lock().take() else {
+            return;
+        };
+        self.key_prefix.restarts.write().extend(pending.restarts);
+        self.key_prefix
+            .compressed
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        *self.key_prefix.writer.lock() = pending.writer;
+    }
+
+    /// 在offset处直接写入了一条完整格式的记录，作为重启点更新前缀压缩的状态
+    pub(crate) fn record_restart(&self, offset: u64, key: &[u8], restart_interval: usize) {
+        if restart_interval == 0 {
+            return;
+        }
+        let mut writer = self.key_prefix.writer.lock();
+        self.key_prefix.restarts.write().insert(offset);
+        self.key_prefix
+            .compressed
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        writer.since_restart = 1;
+        writer.last_key.clear();
+        writer.last_key.extend_from_slice(key);
+    }
+
+    /// 只读取给定偏移处的记录头，返回记录类型、value长度和过期时间，不读取key和value
+    pub fn read_log_record_header(
+        &self,
+        offset: u64,
+    ) -> Result<(LogRecordType, usize, Option<u64>)> {
+        if self.format == Format::GoBitcask {
+            let mut header_buf = [0u8; GO_BITCASK_HEADER_SIZE];
+            self.io_manager.read(&mut header_buf, offset)?;
+            let mut header_buf = &header_buf[..];
+            let key_len = header_buf.get_u32() as usize;
+            let value_len = header_buf.get_u64() as usize;
+            return match (key_len, value_len) {
+                (0, 0) => Err(Errors::ReadDataFileEof),
+                (_, 0) => Ok((LogRecordType::Deleted, 0, None)),
+                _ => Ok((LogRecordType::Normal, value_len, None)),
+            };
+        }
+        let mut scratch = BytesMut::new();
+        let header = self.read_native_header(offset, &mut scratch)?;
+        if !header.has_expiry {
+            return Ok((header.rec_type, header.value_len, None));
+        }
+        // 过期时间在key之后，value的开头
+        let mut expiry_buf = [0u8; EXPIRY_LEN];
+        if header.value_len >= EXPIRY_LEN {
+            self.io_manager.read(
+                &mut expiry_buf,
+                offset + (header.size + header.key_len) as u64,
+            )?;
+        }
+        let (expire_at, _) =
+            split_expiry(&header, &expiry_buf[..header.value_len.min(EXPIRY_LEN)])?;
+        Ok((header.rec_type, header.value_len - EXPIRY_LEN, expire_at))
+    }
+
+    /// 读取一条Go版本bitcask格式的记录，并转换为原生记录
+    //
+    //	+-------------+--------------+-------------+--------------+-------------+-------------+
+    //	|  key size   |  value size  |     key     |    value     | value crc   |   expiry    |
+    //	+-------------+--------------+-------------+--------------+-------------+-------------+
+    //	  4字节(大端)     8字节(大端)       变长          变长         4字节(大端)    8字节(大端)
+    //
+    // Go版本没有事务，value为空表示删除
+    fn read_go_log_record(&self, offset: u64, scratch: &mut BytesMut) -> Result<ReadLogRecord> {
+        scratch.clear();
+        scratch.resize(GO_BITCASK_HEADER_SIZE, 0);
+        self.io_manager.read(scratch, offset)?;
+        let mut header_buf = &scratch[..];
+        let key_len = header_buf.get_u32() as usize;
+        let value_len = header_buf.get_u64() as usize;
+        if key_len == 0 && value_len == 0 {
+            return Err(Errors::ReadDataFileEof);
+        }
+        self.check_record_len(
+            offset,
+            key_len
+                .checked_add(value_len)
+                .and_then(|len| len.checked_add(GO_BITCASK_HEADER_SIZE + GO_BITCASK_TRAILER_SIZE)),
+        )?;
+        scratch.clear();
+        scratch.resize(key_len + value_len + GO_BITCASK_TRAILER_SIZE, 0);
+        self.io_manager
+            .read(scratch, offset + GO_BITCASK_HEADER_SIZE as u64)?;
+        let value = &scratch[key_len..key_len + value_len];
+        let crc = (&scratch[key_len + value_len..]).get_u32();
+        if crc32fast::hash(value) != crc {
+            return Err(Errors::InvalidLogRecordCrc);
+        }
+        let rec_type = match value_len {
+            0 => LogRecordType::Deleted,
+            _ => LogRecordType::Normal,
+        };
+        let record = LogRecord {
+            // 补上非事务序列号，与原生记录的key格式保持一致
+            key: get_record_sequence_number_with_key(
+                &scratch[..key_len],
+                NON_TRANSACTION_SEQ_NUMBER,
+            ),
+            value: value.to_vec(),
+            rec_type,
+            value_encoded: false,
+            expire_at: None,
+        };
+        Ok(ReadLogRecord {
+            record,
+            size: (GO_BITCASK_HEADER_SIZE + key_len + value_len + GO_BITCASK_TRAILER_SIZE) as u64,
+            crc,
         })
     }
 
@@ -122,6 +783,25 @@ impl DataFile {
             file_id: Arc::new(RwLock::new(0)),
             write_offset: Default::default(),
             io_manager,
+            format: Format::Native,
+            key_prefix: Default::default(),
+            max_record_size: u32::MAX as usize,
+            inline_hints_offset: None,
+        })
+    }
+
+    /// 打开或创建临时hint索引文件，写入完成后再rename为正式的hint索引文件
+    pub fn new_hint_tmp_file(dir_path: &Path) -> Result<Self> {
+        let file_name = dir_path.join(HINT_TMP_FILE_NAME);
+        let io_manager = new_io_manager(&file_name, IOType::StandardFileIO)?;
+        Ok(Self {
+            file_id: Arc::new(RwLock::new(0)),
+            write_offset: Default::default(),
+            io_manager,
+            format: Format::Native,
+            key_prefix: Default::default(),
+            max_record_size: u32::MAX as usize,
+            inline_hints_offset: None,
         })
     }
 
@@ -133,6 +813,10 @@ impl DataFile {
             file_id: Arc::new(RwLock::new(0)),
             write_offset: Default::default(),
             io_manager,
+            format: Format::Native,
+            key_prefix: Default::default(),
+            max_record_size: u32::MAX as usize,
+            inline_hints_offset: None,
         })
     }
 
@@ -144,6 +828,54 @@ impl DataFile {
             file_id: Arc::new(RwLock::new(0)),
             write_offset: Default::default(),
             io_manager,
+            format: Format::Native,
+            key_prefix: Default::default(),
+            max_record_size: u32::MAX as usize,
+            inline_hints_offset: None,
+        })
+    }
+
+    /// 打开或创建记录merge进度的文件
+    pub fn new_merge_progress_file(dir_path: &Path) -> Result<Self> {
+        let file_name = dir_path.join(MERGE_PROGRESS_FILE_NAME);
+        let io_manager = new_io_manager(&file_name, IOType::StandardFileIO)?;
+        Ok(Self {
+            file_id: Arc::new(RwLock::new(0)),
+            write_offset: Default::default(),
+            io_manager,
+            format: Format::Native,
+            key_prefix: Default::default(),
+            max_record_size: u32::MAX as usize,
+            inline_hints_offset: None,
+        })
+    }
+
+    /// 打开或创建存储数据库格式版本的文件
+    pub fn new_version_file(dir_path: &Path) -> Result<Self> {
+        let file_name = dir_path.join(VERSION_FILE_NAME);
+        let io_manager = new_io_manager(&file_name, IOType::StandardFileIO)?;
+        Ok(Self {
+            file_id: Arc::new(RwLock::new(0)),
+            write_offset: Default::default(),
+            io_manager,
+            format: Format::Native,
+            key_prefix: Default::default(),
+            max_record_size: u32::MAX as usize,
+            inline_hints_offset: None,
+        })
+    }
+
+    /// 打开或创建操作日志文件，操作日志独立于db目录，路径由调用方指定
+    pub fn new_ops_journal_file(file_path: &Path) -> Result<Self> {
+        let io_manager = new_io_manager(file_path, IOType::StandardFileIO)?;
+        Ok(Self {
+            file_id: Arc::new(RwLock::new(0)),
+            write_offset: Default::default(),
+            io_manager,
+            format: Format::Native,
+            key_prefix: Default::default(),
+            max_record_size: u32::MAX as usize,
+            inline_hints_offset: None,
         })
     }
 
@@ -153,12 +885,87 @@ impl DataFile {
             key,
             value: record_pos.encode(),
             rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
         };
         let encoded_record = hint_record.encode();
         self.write(&encoded_record)?;
         Ok(())
     }
 
+    /// 写入不带CRC的hint索引记录，省去编码和加载时的CRC计算
+    //
+    //	+-------------+-------------+-------------+-------------+-------------+
+    //	|  key size   |     key     |   file id   |   offset    | record size |
+    //	+-------------+-------------+-------------+-------------+-------------+
+    //	  变长（最大5）      变长        变长（最大5）  变长（最大10）  变长（最大5）
+    pub fn write_hint_record_raw(&self, key: Vec<u8>, record_pos: LogRecordPos) -> Result<()> {
+        let mut buf = BytesMut::new();
+        encode_length_delimiter(key.len(), &mut buf).expect("Failed to encode key length");
+        buf.put(key.as_slice());
+        buf.put(record_pos.encode().as_slice());
+        self.write(&buf)?;
+        Ok(())
+    }
+
+    /// hint索引文件中的记录是否不带CRC
+    pub fn is_raw_hint_file(&self) -> Result<bool> {
+        if self.file_size() == 0 {
+            return Ok(false);
+        }
+        let mut mark = [0u8; 1];
+        self.io_manager.read(&mut mark, 0)?;
+        Ok(mark[0] == HINT_RAW_FORMAT_MARK)
+    }
+
+    /// 从给定偏移处读取一条不带CRC的hint索引记录，返回key、记录位置和记录大小
+    pub fn read_hint_record_raw(
+        &self,
+        offset: u64,
+        scratch: &mut BytesMut,
+    ) -> Result<(Vec<u8>, LogRecordPos, u64)> {
+        let file_size = self.file_size();
+        if offset >= file_size {
+            return Err(Errors::ReadDataFileEof);
+        }
+        // 读取key长度
+        let header_len = (file_size - offset).min(length_delimiter_len(u32::MAX as usize) as u64);
+        scratch.clear();
+        scratch.resize(header_len as usize, 0);
+        self.io_manager.read(scratch, offset)?;
+        let key_len =
+            decode_length_delimiter(&scratch[..]).map_err(|_| Errors::InvalidHintRecord)?;
+        let key_offset = offset + length_delimiter_len(key_len) as u64;
+        // 读取key和位置，位置由file id、offset和size三个变长整数组成
+        let body_len = (file_size - key_offset).min(
+            (key_len + length_delimiter_len(u32::MAX as usize) * 2 + HINT_RAW_OFFSET_MAX_LEN)
+                as u64,
+        ) as usize;
+        if body_len < key_len {
+            return Err(Errors::InvalidHintRecord);
+        }
+        scratch.clear();
+        scratch.resize(body_len, 0);
+        self.io_manager.read(scratch, key_offset)?;
+        let mut pos_buf = &scratch[key_len..];
+        let file_id =
+            decode_length_delimiter(&mut pos_buf).map_err(|_| Errors::InvalidHintRecord)? as u32;
+        let record_offset =
+            decode_length_delimiter(&mut pos_buf).map_err(|_| Errors::InvalidHintRecord)? as u64;
+        let record_size =
+            decode_length_delimiter(&mut pos_buf).map_err(|_| Errors::InvalidHintRecord)? as u32;
+        let size = (key_offset - offset) as usize + body_len - pos_buf.len();
+        Ok((
+            scratch[..key_len].to_vec(),
+            LogRecordPos {
+                file_id,
+                offset: record_offset,
+                size: record_size,
+            },
+            size as u64,
+        ))
+    }
+
     /// 获取文件大小
     pub fn file_size(&self) -> u64 {
         self.io_manager.size()
@@ -172,6 +979,30 @@ impl DataFile {
         )?;
         Ok(())
     }
+
+    /// 以指定的io管理器类型重新打开文件，保留格式、写偏移和已知的重启点等状态
+    ///
+    /// 旧数据文件可能被其他线程通过快照引用，不能原地替换io管理器，需要生成新的对象
+    pub fn reopen(&self, dir_path: &Path, io_type: IOType) -> Result<Self> {
+        let file_id = self.get_file_id();
+        Ok(Self {
+            file_id: Arc::new(RwLock::new(file_id)),
+            write_offset: Arc::new(RwLock::new(self.get_write_offset())),
+            io_manager: new_io_manager(&create_data_file_name(dir_path, file_id), io_type)?,
+            format: self.format,
+            key_prefix: KeyPrefixState {
+                compressed: AtomicBool::new(
+                    self.key_prefix
+                        .compressed
+                        .load(std::sync::atomic::Ordering::SeqCst),
+                ),
+                restarts: RwLock::new(self.key_prefix.restarts.read().clone()),
+                ..Default::default()
+            },
+            max_record_size: self.max_record_size,
+            inline_hints_offset: self.inline_hints_offset,
+        })
+    }
 }
 
 pub(crate) fn create_data_file_name(dir_path: &Path, file_id: u32) -> PathBuf {
@@ -179,6 +1010,18 @@ pub(crate) fn create_data_file_name(dir_path: &Path, file_id: u32) -> PathBuf {
     dir_path.join(file_name)
 }
 
+/// 带有过期时间的记录，value的前EXPIRY_LEN个字节为过期时间，返回过期时间和剩余的value
+fn split_expiry<'a>(header: &RecordHeader, value: &'a [u8]) -> Result<(Option<u64>, &'a [u8])> {
+    if !header.has_expiry {
+        return Ok((None, value));
+    }
+    if value.len() < EXPIRY_LEN {
+        return Err(Errors::InvalidLogRecordCrc);
+    }
+    let (mut expiry, value) = value.split_at(EXPIRY_LEN);
+    Ok((Some(expiry.get_u64()), value))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::data::log_record::LogRecordType;
@@ -262,6 +1105,8 @@ mod tests {
             key: "hello".into(),
             value: "world".into(),
             rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
         };
         let encoded = record.encode();
         data_file.write(&encoded).unwrap();
@@ -277,6 +1122,8 @@ mod tests {
             key: "abc".into(),
             value: "123".into(),
             rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
         };
         let encoded = record.encode();
         data_file.write(&encoded).unwrap();
@@ -292,4 +1139,420 @@ mod tests {
         println!("file_path: {}", file_path.display());
         std::fs::remove_file(file_path).unwrap();
     }
+
+    #[test]
+    fn test_data_file_read_log_record_with_buf() {
+        let dir_path = std::env::temp_dir();
+        let data_file_res = DataFile::new(&dir_path, 333, IOType::StandardFileIO);
+        assert!(data_file_res.is_ok());
+        let data_file = data_file_res.unwrap();
+        let mut offsets = Vec::new();
+        for i in 0..100 {
+            let record = LogRecord {
+                key: format!("key-{:03}", i).into(),
+                value: format!("value-{:03}", i).into(),
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            };
+            offsets.push(data_file.get_write_offset());
+            data_file.write(&record.encode()).unwrap();
+        }
+
+        // 复用缓冲区的顺序扫描结果与逐条分配的读取结果一致
+        let mut scratch = BytesMut::new();
+        let mut offset = 0;
+        let mut buf_ptr = None;
+        for expected_offset in &offsets {
+            assert_eq!(offset, *expected_offset);
+            let read_res = data_file.read_log_record_with_buf(offset, &mut scratch);
+            assert!(read_res.is_ok());
+            let read_log_record = read_res.unwrap();
+            let plain_read = data_file.read_log_record(offset).unwrap();
+            assert_eq!(read_log_record.record.key, plain_read.record.key);
+            assert_eq!(read_log_record.record.value, plain_read.record.value);
+            assert_eq!(read_log_record.size, plain_read.size);
+            // 记录大小相同，首次分配后不再重新分配
+            match buf_ptr {
+                None => buf_ptr = Some(scratch.as_ptr()),
+                Some(ptr) => assert_eq!(ptr, scratch.as_ptr()),
+            }
+            offset += read_log_record.size;
+        }
+        assert_eq!(
+            data_file
+                .read_log_record_with_buf(offset, &mut scratch)
+                .err(),
+            Some(Errors::ReadDataFileEof)
+        );
+
+        let file_path = create_data_file_name(&dir_path, 333);
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_data_file_key_prefix_compression() {
+        let dir_path = std::env::temp_dir();
+        let file_path = create_data_file_name(&dir_path, 334);
+        let _ = std::fs::remove_file(&file_path);
+        let data_file = DataFile::new(&dir_path, 334, IOType::StandardFileIO).unwrap();
+        let records: Vec<_> = (0..100)
+            .map(|i| LogRecord {
+                key: format!("user:profile:{:05}", i).into(),
+                value: match i % 10 {
+                    9 => vec![],
+                    _ => format!("value-{}", i).into(),
+                },
+                rec_type: match i % 10 {
+                    9 => LogRecordType::Deleted,
+                    _ => LogRecordType::Normal,
+                },
+                value_encoded: false,
+                expire_at: None,
+            })
+            .collect();
+        let mut offsets = Vec::new();
+        let mut buf = BytesMut::new();
+        for record in &records {
+            buf.clear();
+            data_file.encode_log_record(record, 16, &mut buf);
+            offsets.push(data_file.get_write_offset());
+            data_file.write(&buf).unwrap();
+            data_file.commit_encoded();
+        }
+        // 共享前缀的key只保存后缀，比不压缩时更小
+        let uncompressed: usize = records.iter().map(|r| r.encoded_length()).sum();
+        assert!(data_file.get_write_offset() < uncompressed as u64);
+
+        // 顺序读取
+        let reader = DataFile::new(&dir_path, 334, IOType::StandardFileIO).unwrap();
+        let mut offset = 0;
+        for (record, expected_offset) in records.iter().zip(&offsets) {
+            assert_eq!(offset, *expected_offset);
+            let read_log_record = reader.read_log_record(offset).unwrap();
+            assert_eq!(read_log_record.record.key, record.key);
+            assert_eq!(read_log_record.record.value, record.value);
+            assert_eq!(read_log_record.record.rec_type, record.rec_type);
+            offset += read_log_record.size;
+        }
+        assert_eq!(
+            reader.read_log_record(offset).err(),
+            Some(Errors::ReadDataFileEof)
+        );
+
+        // 倒序随机读取，从最近的重启点还原key
+        let reader = DataFile::new(&dir_path, 334, IOType::StandardFileIO).unwrap();
+        for (record, offset) in records.iter().zip(&offsets).rev() {
+            let read_log_record = reader.read_log_record(*offset).unwrap();
+            assert_eq!(read_log_record.record.key, record.key);
+            assert_eq!(read_log_record.record.value, record.value);
+            let (rec_type, value_len, _) = reader.read_log_record_header(*offset).unwrap();
+            assert_eq!(rec_type, record.rec_type);
+            assert_eq!(value_len, record.value.len());
+        }
+
+        // mmap读取最后一条较短的记录
+        let reader = DataFile::new(&dir_path, 334, IOType::MmapIO).unwrap();
+        let last = reader.read_log_record(*offsets.last().unwrap()).unwrap();
+        assert_eq!(last.record.key, records.last().unwrap().key);
+
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_data_file_key_prefix_failed_write() {
+        let dir_path = std::env::temp_dir();
+        let file_path = create_data_file_name(&dir_path, 338);
+        let _ = std::fs::remove_file(&file_path);
+        let data_file = DataFile::new(&dir_path, 338, IOType::StandardFileIO).unwrap();
+        let records: Vec<_> = (0..4)
+            .map(|i| LogRecord {
+                key: format!("user:profile:{:05}", i).into(),
+                value: format!("value-{}", i).into(),
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            })
+            .collect();
+        let mut buf = BytesMut::new();
+        // 第一条记录编码后写入失败，没有写入文件，也不提交前缀压缩的状态
+        data_file.encode_log_record(&records[0], 16, &mut buf);
+        let mut offsets = Vec::new();
+        for record in &records[1..] {
+            buf.clear();
+            data_file.encode_log_record(record, 16, &mut buf);
+            offsets.push(data_file.get_write_offset());
+            data_file.write(&buf).unwrap();
+            data_file.commit_encoded();
+        }
+        // 已写入的第一条记录是重启点，之后的记录按已写入的key压缩
+        for (record, offset) in records[1..].iter().zip(&offsets) {
+            let reader = DataFile::new(&dir_path, 338, IOType::StandardFileIO).unwrap();
+            assert_eq!(
+                reader.read_log_record(*offset).unwrap().record.key,
+                record.key
+            );
+        }
+        let uncompressed: usize = records[1..].iter().map(|r| r.encoded_length()).sum();
+        assert!(data_file.get_write_offset() < uncompressed as u64);
+
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_data_file_max_record_size() {
+        let dir_path = std::env::temp_dir();
+        let file_path = create_data_file_name(&dir_path, 335);
+        let _ = std::fs::remove_file(&file_path);
+        let data_file = DataFile::new(&dir_path, 335, IOType::StandardFileIO).unwrap();
+        // 长度分别使用1、2、3字节变长编码的记录，最后一条超过了之后配置的大小
+        let records: Vec<_> = [10, 100, 200, 1000, 20000]
+            .into_iter()
+            .map(|len| LogRecord {
+                key: vec![b'k'; len / 2],
+                value: vec![b'v'; len - len / 2],
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            })
+            .collect();
+        for record in &records {
+            data_file.write(&record.encode()).unwrap();
+        }
+
+        let mut reader = DataFile::new(&dir_path, 335, IOType::StandardFileIO).unwrap();
+        reader.set_max_record_size(1024);
+        assert_eq!(reader.max_record_size, 1024);
+        let mut offset = 0;
+        for record in &records {
+            let read_log_record = reader.read_log_record(offset).unwrap();
+            assert_eq!(read_log_record.record.key, record.key);
+            assert_eq!(read_log_record.record.value, record.value);
+            let (_, value_len, _) = reader.read_log_record_header(offset).unwrap();
+            assert_eq!(value_len, record.value.len());
+            offset += read_log_record.size;
+        }
+        assert_eq!(
+            reader.read_log_record(offset).err(),
+            Some(Errors::ReadDataFileEof)
+        );
+
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_data_file_corrupt_record_length() {
+        let dir_path = std::env::temp_dir();
+        let file_path = create_data_file_name(&dir_path, 336);
+        let _ = std::fs::remove_file(&file_path);
+        let data_file = DataFile::new(&dir_path, 336, IOType::StandardFileIO).unwrap();
+        let record = LogRecord {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+            rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
+        };
+        data_file.write(&record.encode()).unwrap();
+        // key长度字段损坏为巨大的值
+        let mut corrupted = BytesMut::new();
+        corrupted.put_u8(LogRecordType::Normal as u8);
+        encode_length_delimiter(1 << 40, &mut corrupted).unwrap();
+        encode_length_delimiter(5, &mut corrupted).unwrap();
+        corrupted.extend_from_slice(b"keyvalue");
+        data_file.write(&corrupted).unwrap();
+
+        let mut reader = DataFile::new(&dir_path, 336, IOType::StandardFileIO).unwrap();
+        let read_log_record = reader.read_log_record(0).unwrap();
+        assert_eq!(read_log_record.record.key, record.key);
+        assert_eq!(
+            reader.read_log_record(read_log_record.size).err(),
+            Some(Errors::InvalidLogRecordLength)
+        );
+        // 超过max_record_size但仍在文件范围内的记录可以正常读取
+        reader.set_max_record_size(8);
+        assert_eq!(
+            reader.read_log_record(0).unwrap().record.value,
+            record.value
+        );
+
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_data_file_hint_record_raw() {
+        let dir_path = std::env::temp_dir().join("test_data_file_hint_record_raw");
+        std::fs::create_dir_all(&dir_path).unwrap();
+        let hint_file = DataFile::new_hint_file(&dir_path).unwrap();
+        let positions: Vec<LogRecordPos> = (0..100)
+            .map(|i| LogRecordPos {
+                file_id: i,
+                offset: i as u64 * 1_000_000_007,
+                size: i * 7,
+            })
+            .collect();
+        for (i, pos) in positions.iter().enumerate() {
+            hint_file
+                .write_hint_record_raw(format!("key-{}", i).into(), *pos)
+                .unwrap();
+        }
+
+        let mut scratch = BytesMut::new();
+        let mut offset = 0;
+        for (i, pos) in positions.iter().enumerate() {
+            let (key, read_pos, size) = hint_file
+                .read_hint_record_raw(offset, &mut scratch)
+                .unwrap();
+            assert_eq!(key, format!("key-{}", i).into_bytes());
+            assert_eq!(read_pos, *pos);
+            offset += size;
+        }
+        assert_eq!(offset, hint_file.file_size());
+        assert_eq!(
+            hint_file.read_hint_record_raw(offset, &mut scratch).err(),
+            Some(Errors::ReadDataFileEof)
+        );
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+
+    #[test]
+    fn test_data_file_read_go_bitcask_record() {
+        let dir_path = std::env::temp_dir();
+        let mut data_file = DataFile::new(&dir_path, 444, IOType::StandardFileIO).unwrap();
+        data_file.set_format(Format::GoBitcask);
+        // Go版本bitcask写入的 hello => world
+        let blob: &[u8] = &[
+            0x00, 0x00, 0x00, 0x05, // key size
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, // value size
+            b'h', b'e', b'l', b'l', b'o', // key
+            b'w', b'o', b'r', b'l', b'd', // value
+            0x3a, 0x77, 0x11, 0x43, // crc32(value)
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // expiry
+        ];
+        data_file.write(blob).unwrap();
+
+        let read_log_record = data_file.read_log_record(0).unwrap();
+        assert_eq!(read_log_record.size, blob.len() as u64);
+        assert_eq!(
+            read_log_record.record.key,
+            get_record_sequence_number_with_key(b"hello", NON_TRANSACTION_SEQ_NUMBER)
+        );
+        assert_eq!(read_log_record.record.value, b"world");
+        assert_eq!(read_log_record.record.rec_type, LogRecordType::Normal);
+        assert_eq!(
+            data_file.read_log_record(read_log_record.size).err(),
+            Some(Errors::ReadDataFileEof)
+        );
+
+        let file_path = create_data_file_name(&dir_path, 444);
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_data_file_record_expiry() {
+        let dir_path = std::env::temp_dir();
+        let file_path = create_data_file_name(&dir_path, 337);
+        let _ = std::fs::remove_file(&file_path);
+        let data_file = DataFile::new(&dir_path, 337, IOType::StandardFileIO).unwrap();
+        let records = [
+            LogRecord {
+                key: "hello".into(),
+                value: "world".into(),
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: Some(1_700_000_000_000),
+            },
+            LogRecord {
+                key: "hello2".into(),
+                value: "bitcask".into(),
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            },
+            // 空value也能带过期时间
+            LogRecord {
+                key: "hello3".into(),
+                value: vec![],
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: Some(u64::MAX),
+            },
+        ];
+        // 普通格式和前缀压缩格式都保存过期时间
+        let mut offsets = Vec::new();
+        let mut buf = BytesMut::new();
+        for record in &records {
+            buf.clear();
+            data_file.encode_log_record(record, 3, &mut buf);
+            offsets.push(data_file.get_write_offset());
+            data_file.write(&buf).unwrap();
+            data_file.commit_encoded();
+        }
+        assert_eq!(records[0].encoded_length(), records[0].encode().len());
+        for (record, offset) in records.iter().zip(&offsets) {
+            let read_log_record = data_file.read_log_record(*offset).unwrap();
+            assert_eq!(read_log_record.record.key, record.key);
+            assert_eq!(read_log_record.record.value, record.value);
+            assert_eq!(read_log_record.record.expire_at, record.expire_at);
+            assert_eq!(
+                data_file.read_log_record_header(*offset),
+                Ok((record.rec_type, record.value.len(), record.expire_at))
+            );
+        }
+        assert!(records[0].is_expired());
+        assert!(!records[1].is_expired());
+        assert!(!records[2].is_expired());
+
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_data_file_inline_hints() {
+        let dir_path = std::env::temp_dir();
+        let file_path = create_data_file_name(&dir_path, 339);
+        let _ = std::fs::remove_file(&file_path);
+        let data_file = DataFile::new(&dir_path, 339, IOType::StandardFileIO).unwrap();
+        let record = LogRecord {
+            key: "hello".into(),
+            value: "world".into(),
+            rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
+        };
+        let encoded = record.encode();
+        data_file.write(&encoded).unwrap();
+        let record_pos = LogRecordPos {
+            file_id: 339,
+            offset: 0,
+            size: encoded.len() as u32,
+        };
+        data_file
+            .write_inline_hints(&[(b"hello".to_vec(), record_pos)])
+            .unwrap();
+
+        // 打开文件时不读取文件尾部，加载后才识别出内联hint
+        let mut reader = DataFile::new(&dir_path, 339, IOType::StandardFileIO).unwrap();
+        assert!(!reader.has_inline_hints());
+        reader.load_inline_hints().unwrap();
+        assert_eq!(reader.get_inline_hints_offset(), Some(encoded.len() as u64));
+        assert_eq!(
+            reader.read_inline_hints(),
+            Ok(vec![(b"hello".to_vec(), record_pos)])
+        );
+        assert_eq!(
+            reader.read_log_record(encoded.len() as u64).err(),
+            Some(Errors::ReadDataFileEof)
+        );
+
+        // hint记录损坏时CRC不匹配，不识别为内联hint
+        let mut content = std::fs::read(&file_path).unwrap();
+        content[encoded.len()] ^= 0xff;
+        std::fs::write(&file_path, content).unwrap();
+        let mut reader = DataFile::new(&dir_path, 339, IOType::StandardFileIO).unwrap();
+        reader.load_inline_hints().unwrap();
+        assert!(!reader.has_inline_hints());
+
+        std::fs::remove_file(file_path).unwrap();
+    }
 }
@@ -1,13 +1,20 @@
 #![allow(dead_code)]
 
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use bytes::{BufMut, BytesMut};
+use log::error;
 use prost::{decode_length_delimiter, encode_length_delimiter, length_delimiter_len};
 
+use crate::errors::{Errors, Result};
+
 /// record position in the log file for index
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct LogRecordPos {
     pub(crate) file_id: u32,
     pub(crate) offset: u64,
+    /// 记录在数据文件中占用的字节数，用于统计可回收空间
+    pub(crate) size: u32,
 }
 
 impl LogRecordPos {
@@ -15,27 +22,51 @@ impl LogRecordPos {
         let mut buf = BytesMut::new();
         encode_length_delimiter(self.file_id as usize, &mut buf).expect("Failed to encode file id");
         encode_length_delimiter(self.offset as usize, &mut buf).expect("Failed to encode offset");
+        encode_length_delimiter(self.size as usize, &mut buf).expect("Failed to encode size");
         buf.to_vec()
     }
 }
 
-pub(crate) fn decode_log_record_pos(buf: &[u8]) -> LogRecordPos {
+/// 解码索引中保存的记录位置，内容损坏时返回IndexOperationFailed而不是panic
+pub(crate) fn decode_log_record_pos(buf: &[u8]) -> Result<LogRecordPos> {
     let mut buf = BytesMut::from(buf);
-    let file_id = match decode_length_delimiter(&mut buf) {
-        Ok(v) => v as u32,
-        Err(e) => {
-            panic!("Failed to decode file id: {}", e);
-        }
+    let file_id = decode_pos_field(&mut buf, "file id")? as u32;
+    let offset = decode_pos_field(&mut buf, "offset")? as u64;
+    // 旧版本编码的位置不包含记录大小
+    let size = match buf.is_empty() {
+        true => 0,
+        false => decode_pos_field(&mut buf, "size")? as u32,
     };
-    let offset = match decode_length_delimiter(&mut buf) {
-        Ok(v) => v as u64,
-        Err(e) => {
-            panic!("Failed to decode offset: {}", e);
-        }
-    };
-    LogRecordPos { file_id, offset }
+    Ok(LogRecordPos {
+        file_id,
+        offset,
+        size,
+    })
+}
+
+fn decode_pos_field(buf: &mut BytesMut, field: &str) -> Result<usize> {
+    decode_length_delimiter(buf).map_err(|e| {
+        error!("Failed to decode {}: {}", field, e);
+        Errors::IndexOperationFailed
+    })
 }
 
+/// 记录类型字节的最高位表示该记录的key经过前缀压缩
+pub(crate) const PREFIX_COMPRESSED_FLAG: u8 = 0x80;
+/// 记录类型字节的次高位表示该记录的value经过ValueCodec编码，value的第一个字节为编解码器id
+pub(crate) const VALUE_CODEC_FLAG: u8 = 0x40;
+/// 记录类型字节的第三高位表示该记录带有过期时间，value的前EXPIRY_LEN个字节为过期时间
+pub(crate) const EXPIRY_FLAG: u8 = 0x20;
+/// 过期时间的长度，毫秒Unix时间戳，大端序
+pub(crate) const EXPIRY_LEN: usize = 8;
+/// 记录类型字节的第4、5位为记录格式版本，只能读取不高于RECORD_FORMAT_VERSION的版本，
+/// 低3位的记录类型在各版本中保持不变，用于区分更新版本的记录和损坏的记录，
+/// 增加标志位或改变记录布局时，较低版本无法解析，需要同时递增DB_FORMAT_VERSION
+pub(crate) const RECORD_VERSION_MASK: u8 = 0x18;
+pub(crate) const RECORD_VERSION_SHIFT: u8 = 3;
+/// 当前写入的记录格式版本，之前写入的记录版本位都为0
+pub(crate) const RECORD_FORMAT_VERSION: u8 = 0;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
 pub enum LogRecordType {
     #[default]
@@ -60,6 +91,10 @@ pub struct LogRecord {
     pub(crate) key: Vec<u8>,
     pub(crate) value: Vec<u8>,
     pub(crate) rec_type: LogRecordType,
+    /// value是否经过ValueCodec编码
+    pub(crate) value_encoded: bool,
+    /// 过期时间，毫秒Unix时间戳，None表示不会过期
+    pub(crate) expire_at: Option<u64>,
 }
 
 impl LogRecord {
@@ -83,48 +118,158 @@ impl LogRecord {
     /// 将记录编码为字节流，并返回字节流和CRC
     fn encode_and_get_crc(&self) -> (Vec<u8>, u32) {
         let mut buf = BytesMut::with_capacity(self.encoded_length());
+        let crc = self.encode_into(&mut buf);
+        (buf.to_vec(), crc)
+    }
+
+    /// 将记录编码后追加到给定缓冲区，返回CRC，便于复用缓冲区减少内存分配
+    pub fn encode_into(&self, buf: &mut BytesMut) -> u32 {
+        let start = buf.len();
+        buf.reserve(self.encoded_length());
         // 写入记录类型
-        buf.put_u8(self.rec_type as u8);
+        buf.put_u8(self.type_byte());
         // 写入key长度
-        encode_length_delimiter(self.key.len(), &mut buf).expect("Failed to encode key length");
-        // 写入value长度
-        encode_length_delimiter(self.value.len(), &mut buf).expect("Failed to encode value length");
+        encode_length_delimiter(self.key.len(), buf).expect("Failed to encode key length");
+        // 写入value长度，包含过期时间
+        encode_length_delimiter(self.stored_value_len(), buf)
+            .expect("Failed to encode value length");
         // 写入key
         buf.put(self.key.as_slice());
-        // 写入value
+        // 写入过期时间和value
+        self.put_expiry(buf);
         buf.put(self.value.as_slice());
-        // 计算CRC
+        // 计算CRC，只包含本条记录的内容
         use crc32fast::Hasher;
         let mut hasher = Hasher::new();
-        hasher.update(&buf);
+        hasher.update(&buf[start..]);
         let crc = hasher.finalize();
         // 写入CRC
         buf.put_u32(crc);
-        (buf.to_vec(), crc)
+        crc
+    }
+
+    /// 流式写入value时，只编码记录头和key，value长度由调用方给出
+    ///
+    /// 返回已包含记录头和key的CRC计算状态，调用方写入value时继续更新，最后写入CRC
+    pub(crate) fn encode_header_into(
+        &self,
+        value_len: usize,
+        buf: &mut BytesMut,
+    ) -> crc32fast::Hasher {
+        let start = buf.len();
+        buf.put_u8(self.type_byte());
+        encode_length_delimiter(self.key.len(), buf).expect("Failed to encode key length");
+        let expiry_len = self.stored_value_len() - self.value.len();
+        encode_length_delimiter(value_len + expiry_len, buf)
+            .expect("Failed to encode value length");
+        buf.put(self.key.as_slice());
+        self.put_expiry(buf);
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&buf[start..]);
+        hasher
+    }
+
+    /// 将记录以key前缀压缩的格式编码后追加到给定缓冲区，返回CRC
+    //
+    //	+-------------+-------------+-------------+--------------+-------------+-------------+-------------+
+    //	| type|0x80   | shared size | suffix size |  value size  | key suffix  |    value    |  crc 校验值  |
+    //	+-------------+-------------+-------------+--------------+-------------+-------------+-------------+
+    //	    1字节       变长（最大5）   变长（最大5）   变长（最大5）       变长          变长          4字节
+    //
+    // shared size为与上一条记录key的公共前缀长度，key suffix为去掉公共前缀后的部分
+    pub(crate) fn encode_with_prefix_into(&self, prev_key: &[u8], buf: &mut BytesMut) -> u32 {
+        let shared = shared_prefix_len(prev_key, &self.key);
+        let suffix = &self.key[shared..];
+        let start = buf.len();
+        buf.put_u8(self.type_byte() | PREFIX_COMPRESSED_FLAG);
+        encode_length_delimiter(shared, buf).expect("Failed to encode shared key length");
+        encode_length_delimiter(suffix.len(), buf).expect("Failed to encode key suffix length");
+        encode_length_delimiter(self.stored_value_len(), buf)
+            .expect("Failed to encode value length");
+        buf.put(suffix);
+        self.put_expiry(buf);
+        buf.put(self.value.as_slice());
+        let crc = crc32fast::hash(&buf[start..]);
+        buf.put_u32(crc);
+        crc
+    }
+
+    /// 记录类型字节，value经过编码时带上VALUE_CODEC_FLAG，有过期时间时带上EXPIRY_FLAG
+    fn type_byte(&self) -> u8 {
+        let mut type_byte = self.rec_type as u8;
+        if self.value_encoded {
+            type_byte |= VALUE_CODEC_FLAG;
+        }
+        if self.expire_at.is_some() {
+            type_byte |= EXPIRY_FLAG;
+        }
+        type_byte
+    }
+
+    /// 写入文件中的value长度，有过期时间时包含过期时间的长度
+    fn stored_value_len(&self) -> usize {
+        match self.expire_at {
+            Some(_) => EXPIRY_LEN + self.value.len(),
+            None => self.value.len(),
+        }
+    }
+
+    /// 有过期时间时写入过期时间
+    fn put_expiry(&self, buf: &mut BytesMut) {
+        if let Some(expire_at) = self.expire_at {
+            buf.put_u64(expire_at);
+        }
     }
 
-    fn encoded_length(&self) -> usize {
+    /// 记录是否已过期
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expire_at
+            .is_some_and(|expire_at| expire_at <= now_millis())
+    }
+
+    pub(crate) fn encoded_length(&self) -> usize {
         std::mem::size_of::<u8>()
             + length_delimiter_len(self.key.len())
-            + length_delimiter_len(self.value.len())
+            + length_delimiter_len(self.stored_value_len())
             + self.key.len()
-            + self.value.len()
+            + self.stored_value_len()
             + 4
     }
 }
 
+/// 当前时间，毫秒Unix时间戳
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
 /// 从文件读取的一条记录，包含其大小
 pub struct ReadLogRecord {
     pub(crate) record: LogRecord,
     pub(crate) size: u64,
+    /// 文件中保存的CRC
+    pub(crate) crc: u32,
 }
 
 /// 最大日志记录头大小
-pub fn max_log_record_header_size() -> usize {
+///
+/// key和value的长度都不会超过max_record_size，通常为配置的data_file_size
+pub fn max_log_record_header_size(max_record_size: usize) -> usize {
     // 记录类型 + key长度 + value长度
     // length_delimiter_len:编码一个长度分隔符所需的字节数,长度分隔符是用来表示一个长度可变的字段的长度的，
     // 在编码变长消息之前，需要预先计算出需要多少空间来存储长度分隔符
-    std::mem::size_of::<LogRecordType>() + prost::length_delimiter_len(u32::MAX as usize) * 2
+    std::mem::size_of::<LogRecordType>() + prost::length_delimiter_len(max_record_size) * 2
+}
+
+/// 前缀压缩记录的最大记录头大小，比普通记录多一个公共前缀长度
+pub(crate) fn max_prefix_log_record_header_size(max_record_size: usize) -> usize {
+    max_log_record_header_size(max_record_size) + prost::length_delimiter_len(max_record_size)
+}
+
+/// 两个key的公共前缀长度
+pub(crate) fn shared_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
 }
 
 /// 事务记录
@@ -143,6 +288,8 @@ mod tests {
             key: "hello".into(),
             value: "world".into(),
             rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
         };
         let encoded = record.encode();
         assert!(encoded.len() > 5);
@@ -152,9 +299,72 @@ mod tests {
             key: "abc".into(),
             value: "123".into(),
             rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
         };
         let encoded = record.encode();
         assert!(encoded.len() > 5);
         assert_eq!(819267436, record.get_crc());
     }
+
+    #[test]
+    fn test_log_record_encode_into() {
+        let records = [
+            LogRecord {
+                key: "hello".into(),
+                value: "world".into(),
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            },
+            LogRecord {
+                key: "abc".into(),
+                value: "123".into(),
+                rec_type: LogRecordType::Deleted,
+                value_encoded: false,
+                expire_at: None,
+            },
+        ];
+        let mut buf = BytesMut::with_capacity(64);
+        let buf_ptr = buf.as_ptr();
+        for record in &records {
+            buf.clear();
+            let crc = record.encode_into(&mut buf);
+            // 与encode结果一致
+            assert_eq!(buf.as_ref(), record.encode().as_slice());
+            assert_eq!(crc, record.get_crc());
+            // 复用缓冲区，没有重新分配
+            assert_eq!(buf.as_ptr(), buf_ptr);
+        }
+
+        // 追加到已有内容之后，CRC只覆盖本条记录
+        buf.clear();
+        records[0].encode_into(&mut buf);
+        let crc = records[1].encode_into(&mut buf);
+        assert_eq!(crc, records[1].get_crc());
+        assert_eq!(&buf[records[0].encoded_length()..], records[1].encode());
+    }
+
+    #[test]
+    fn test_max_log_record_header_size() {
+        assert_eq!(max_log_record_header_size(u32::MAX as usize), 11);
+        assert_eq!(max_log_record_header_size(127), 3);
+        assert_eq!(max_log_record_header_size(128), 5);
+        assert_eq!(max_log_record_header_size(64 * 1024 * 1024), 9);
+        assert_eq!(max_prefix_log_record_header_size(u32::MAX as usize), 16);
+        assert_eq!(max_prefix_log_record_header_size(1024), 7);
+
+        // 长度不超过max_record_size的记录，记录头都不超过计算出的大小
+        for max_record_size in [127, 128, 16 * 1024, 256 * 1024 * 1024] {
+            let record = LogRecord {
+                key: vec![1; max_record_size / 2],
+                value: vec![2; max_record_size - max_record_size / 2],
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            };
+            let header_size = record.encoded_length() - record.key.len() - record.value.len() - 4;
+            assert!(header_size <= max_log_record_header_size(max_record_size));
+        }
+    }
 }
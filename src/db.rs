@@ -1,16 +1,22 @@
 #![allow(dead_code)]
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::File,
-    path::Path,
-    sync::{Arc, atomic::AtomicUsize},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use fs2::FileExt;
 use log::{error, warn};
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
 
 use crate::{
     batch::{
@@ -19,33 +25,78 @@ use crate::{
     },
     data::{
         data_file::{
-            DATA_FILE_NAME_SUFFIX, DataFile, MERGE_FINISHED_FILE_NAME, SEQUENCE_NUMBER_FILE_NAME,
+            DATA_FILE_NAME_SUFFIX, DataFile, HINT_FILE_NAME, HINT_TMP_FILE_NAME,
+            INLINE_HINTS_FILE_NAME, MERGE_FINISHED_FILE_NAME, QUARANTINE_FILE_NAME,
+            SEQUENCE_NUMBER_FILE_NAME, VERSION_FILE_NAME,
+        },
+        log_record::{
+            LogRecord, LogRecordPos, LogRecordType, RECORD_FORMAT_VERSION, ReadLogRecord,
+            TransactionRecord, now_millis,
         },
-        log_record::{LogRecord, LogRecordPos, LogRecordType, TransactionRecord},
     },
     errors::{Errors, Result},
-    index::{Indexer, new_indexer},
-    merge::load_merge_files,
-    options::{IOType, IndexType, Options},
+    fio,
+    index::{
+        BPTREE_DIRTY_FILE_NAME, BPTREE_INDEX_FILE_NAME, BPlusTree, BudgetIndexer, Indexer,
+        check_index_budget, new_indexer,
+    },
+    latency::{LatencyReport, LatencyTracker},
+    merge::{
+        MergeStat, inline_hints_file_id, load_merge_files, load_merge_stat, remove_file_if_exists,
+    },
+    options::{
+        CorruptionPolicy, Format, IOType, IndexType, IteratorOptions, KeyTransform, Options,
+    },
 };
 
 const INITIAL_DATA_FILE_ID: u32 = 0;
 const SEQUENCE_NUMBER_KEY: &str = "sequence.number";
+const VERSION_KEY: &str = "version";
+const RECORD_VERSION_KEY: &str = "record.version";
+/// 数据库格式版本，记录格式或辅助文件格式不兼容地变化时递增，
+/// 可以打开较低版本创建的数据库，打开后版本文件升级为当前版本
+///
+/// 1：初始版本
+/// 2：记录类型字节增加过期时间、key前缀压缩和value编码标志位
+pub const DB_FORMAT_VERSION: u32 = 2;
 pub(crate) const FILE_LOCK_NAME: &str = "file-lock";
+/// put_stream每次从reader读取并写入数据文件的字节数
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+/// 配置了min_free_bytes时，每写入该次数重新获取一次磁盘可用空间
+const DISK_SPACE_CHECK_INTERVAL: usize = 64;
+
+/// 文件id到旧数据文件的映射
+pub(crate) type OlderFiles = HashMap<u32, Arc<DataFile>>;
 
 pub struct Engine {
     /// 配置
     pub(crate) options: Arc<Options>,
     /// 活跃数据文件
     pub(crate) active_file: Arc<RwLock<DataFile>>,
-    /// 旧数据文件
-    pub(crate) older_files: Arc<RwLock<HashMap<u32, DataFile>>>,
+    /// 旧数据文件，读取时复制一份快照后释放锁，切换活跃文件等修改时写时复制，
+    /// 读写旧数据文件不需要在整个读取过程中持有锁
+    pub(crate) older_files: Arc<RwLock<Arc<OlderFiles>>>,
     /// 内存索引
-    pub(crate) index: Box<dyn Indexer>,
+    pub(crate) index: Arc<dyn Indexer>,
+    /// 每个数据文件中已被覆盖或删除、merge时可回收的字节数
+    pub(crate) dead_bytes: Arc<RwLock<HashMap<u32, u64>>>,
     /// 文件id,只用于启动时加载索引使用
     file_ids: Vec<u32>,
+    /// 打开时因无法读取而跳过的旧数据文件id
+    skipped_file_ids: Arc<Mutex<Vec<u32>>>,
+    /// 加载索引时因CRC校验失败而跳过的记录，文件id和偏移
+    pub(crate) quarantined_records: Arc<Mutex<Vec<(u32, u64)>>>,
+    /// 超出恢复时间预算后交给后台线程加载的文件id
+    deferred_file_ids: Vec<u32>,
+    /// 后台加载索引的状态
+    index_warmup: Arc<IndexWarmup>,
     /// 批量写入互斥锁
     pub(crate) batch_commit_mutex: Mutex<()>,
+    /// 已提交的批次数，用于WriteBatchOptions::sync_every
+    pub(crate) batch_commit_count: AtomicUsize,
+    /// 批量提交触发的持久化次数，只用于测试
+    #[cfg(test)]
+    pub(crate) batch_sync_count: AtomicUsize,
     /// 序列号
     pub(crate) sequence_number: Arc<AtomicUsize>,
     /// 防止多个线程同时merge
@@ -55,54 +106,89 @@ pub struct Engine {
     /// 是否是首次加载db
     pub(crate) is_first_load: bool,
     /// 文件锁,保证在db目录只打开一个db实例
-    pub(crate) lock_file: File,
-    /// 累计写入阈值
+    /// 只读打开且目录不可写时为None
+    pub(crate) lock_file: Option<File>,
+    /// 上次持久化活跃文件后累计写入的字节数，打开时为0
     pub(crate) bytes_write: Arc<AtomicUsize>,
+    /// 是否每次写入后立刻持久化，初始值来自配置，可在运行时修改
+    pub(crate) sync_write: AtomicBool,
+    /// 追加写入时复用的编码缓冲区
+    write_buf: Mutex<BytesMut>,
+    /// 写入数据文件的次数
+    pub(crate) data_file_write_count: AtomicUsize,
+    /// scan时后台线程预读的数据文件数，只用于测试
+    #[cfg(test)]
+    pub(crate) scan_prefetch_count: AtomicUsize,
+    /// get因数据文件被删除而重试的次数
+    pub(crate) read_retry_count: AtomicUsize,
+    /// get_timeout尚未结束的读取线程数，包括已超时返回的读取
+    pub(crate) pending_timeout_reads: Arc<AtomicUsize>,
+    /// 后台线程的停止信号和句柄，close时停止并等待退出
+    background: Arc<BackgroundTasks>,
+    /// 后台定时持久化的次数
+    pub(crate) background_sync_count: Arc<AtomicUsize>,
+    /// 延迟统计，未开启track_latency时为None
+    pub(crate) latency: Option<LatencyTracker>,
+    /// 是否已关闭，保证close只执行一次
+    closed: AtomicBool,
+    /// get_cached使用的读缓存，key被再次写入或删除时失效
+    pub(crate) read_cache: RwLock<HashMap<Vec<u8>, CachedValue>>,
+    /// 活跃数据文件中已写入删除记录的key，同时串行化删除时的索引检查和写入
+    pub(crate) active_tombstones: Mutex<ActiveTombstones>,
+    /// 写入记录到更新索引期间持有读锁，compact_range重写记录时持有写锁，
+    /// 保证重写期间key不会被并发写入，避免旧value覆盖新写入的value
+    pub(crate) index_update_lock: RwLock<()>,
+    /// 最近一次merge的统计信息
+    pub(crate) last_merge_stat: RwLock<Option<MergeStat>>,
+    /// 操作日志文件，未配置ops_journal时为None
+    pub(crate) ops_journal: Option<DataFile>,
+    /// 检查磁盘可用空间的写入次数
+    disk_space_checks: AtomicUsize,
+    /// 最近一次获取的磁盘可用空间是否低于min_free_bytes
+    disk_space_low: AtomicBool,
 }
 
 impl Engine {
     pub fn open(opts: Options) -> Result<Self> {
         check_options(&opts)?;
-        // 判断目录是否存在
         let dir_path = opts.dir_path.clone();
-        let mut is_first_load = false;
-        if !dir_path.is_dir() {
-            // println!(
-            //     "Database dir not found, creating dir: {}",
-            //     dir_path.display()
-            // );
-            is_first_load = true;
-            std::fs::create_dir_all(&dir_path).map_err(|e| {
-                warn!("Failed to create database dir: {}", e);
-                Errors::FailedToCreateDatabaseDir
-            })?;
-        }
-
-        // 判断db目录是否正被使用中
-        // 打开或创建文件锁
-        let lock_file = std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(dir_path.join(FILE_LOCK_NAME))
-            .map_err(|e| {
-                warn!("Failed to create file lock: {}", e);
-                Errors::FailedToCreateFileLock
-            })?;
-        if lock_file.try_lock_exclusive().is_err() {
-            return Err(Errors::DatabaseIsUsing);
+        // 纯内存模式不创建目录，也不读取目录中的文件
+        let OpenedDir {
+            is_first_load,
+            lock_file,
+            last_merge_stat,
+            mut data_files,
+            skipped_file_ids,
+        } = match opts.in_memory {
+            true => OpenedDir {
+                is_first_load: true,
+                ..Default::default()
+            },
+            false => open_dir(&opts)?,
+        };
+        // 已有数据文件为Go版本bitcask格式时只读，新记录写入新的原生格式活跃文件
+        if opts.format == Format::GoBitcask
+            && let Some(last_file) = data_files.last()
+        {
+            let new_file_id = last_file.get_file_id() + 1;
+            for data_file in data_files.iter_mut() {
+                data_file.set_format(Format::GoBitcask);
+            }
+            if !opts.read_only {
+                data_files.push(DataFile::new(
+                    &dir_path,
+                    new_file_id,
+                    IOType::StandardFileIO,
+                )?);
+                if opts.sync_dir_on_rotate {
+                    fio::sync_dir(&dir_path)?;
+                }
+            }
         }
-
-        // 空目录也认为是首次加载
-        let entries = std::fs::read_dir(&dir_path).expect("Failed to read database dir");
-        if entries.count() == 0 {
-            is_first_load = true;
+        // 记录大小不超过data_file_size，按此计算读取记录头的缓冲区大小
+        for data_file in data_files.iter_mut() {
+            data_file.set_max_record_size(opts.data_file_size);
         }
-
-        // 加载merge目录,删除已merge的数据文件，将已merge的数据文件移动到当前db
-        load_merge_files(&dir_path)?;
-
-        let mut data_files = load_data_files(&dir_path, opts.use_mmap)?;
         // 新数据文件在开头
         data_files.reverse();
         let file_ids: Vec<_> = data_files.iter().map(|f| f.get_file_id()).rev().collect();
@@ -111,119 +197,836 @@ impl Engine {
         if data_files.len() > 1 {
             for _ in 0..=data_files.len() - 2 {
                 let file = data_files.pop().unwrap();
-                older_files.insert(file.get_file_id(), file);
+                older_files.insert(file.get_file_id(), Arc::new(file));
             }
         }
         // 最后一个是活跃数据文件
         let active_file = match data_files.pop() {
             Some(file) => file,
-            None => DataFile::new(&dir_path, INITIAL_DATA_FILE_ID, IOType::StandardFileIO)?,
+            // 只读打开时不创建数据文件
+            None if opts.read_only => return Err(Errors::DataFileNotFound),
+            None => {
+                let mut data_file =
+                    DataFile::new(&dir_path, INITIAL_DATA_FILE_ID, data_file_io_type(&opts))?;
+                data_file.set_max_record_size(opts.data_file_size);
+                // 持久化新数据文件的目录项
+                if opts.sync_dir_on_rotate && !opts.in_memory {
+                    fio::sync_dir(&dir_path)?;
+                }
+                data_file
+            }
         };
         let idx_type = opts.index_type;
+        // B+Tree索引文件不存在时（如被repair删除），需要从数据文件重建索引
+        let rebuild_index = opts.index_type != IndexType::BPlusTree
+            || !dir_path.join(BPTREE_INDEX_FILE_NAME).is_file();
+        // 暂存B+Tree索引更新时，后台线程需要通过具体类型提交
+        let buffered_bptree =
+            match idx_type == IndexType::BPlusTree && opts.index_flush_interval.is_some() {
+                true => Some(Arc::new(BPlusTree::with_buffered_writes(&dir_path)?)),
+                false => None,
+            };
         let mut engine = Self {
             options: Arc::new(opts.clone()),
             active_file: Arc::new(RwLock::new(active_file)),
-            older_files: Arc::new(RwLock::new(older_files)),
-            index: new_indexer(idx_type, &dir_path),
+            older_files: Arc::new(RwLock::new(Arc::new(older_files))),
+            index: match opts.index_memory_budget {
+                Some(budget) if idx_type != IndexType::BPlusTree => Arc::new(BudgetIndexer::new(
+                    new_indexer(idx_type, &dir_path)?,
+                    budget,
+                )),
+                _ if let Some(tree) = &buffered_bptree => tree.clone(),
+                _ => Arc::from(new_indexer(idx_type, &dir_path)?),
+            },
+            dead_bytes: Default::default(),
             file_ids,
+            skipped_file_ids: Arc::new(Mutex::new(skipped_file_ids)),
+            quarantined_records: Default::default(),
+            deferred_file_ids: Vec::new(),
+            index_warmup: Arc::new(IndexWarmup::ready()),
             batch_commit_mutex: Mutex::new(()),
+            batch_commit_count: AtomicUsize::new(0),
+            #[cfg(test)]
+            batch_sync_count: AtomicUsize::new(0),
             sequence_number: Arc::new(AtomicUsize::new(1)),
             merge_lock: Mutex::new(()),
             sequence_number_file_exists: false,
             is_first_load,
             lock_file,
             bytes_write: Default::default(),
+            sync_write: AtomicBool::new(opts.sync_write),
+            write_buf: Mutex::new(BytesMut::new()),
+            data_file_write_count: AtomicUsize::new(0),
+            #[cfg(test)]
+            scan_prefetch_count: AtomicUsize::new(0),
+            read_retry_count: AtomicUsize::new(0),
+            pending_timeout_reads: Default::default(),
+            background: Arc::new(BackgroundTasks::default()),
+            background_sync_count: Default::default(),
+            latency: opts.track_latency.then(LatencyTracker::default),
+            closed: AtomicBool::new(false),
+            read_cache: RwLock::new(HashMap::new()),
+            active_tombstones: Default::default(),
+            index_update_lock: RwLock::new(()),
+            last_merge_stat: RwLock::new(last_merge_stat),
+            ops_journal: match &opts.ops_journal {
+                Some(path) if !opts.read_only => Some(DataFile::new_ops_journal_file(path)?),
+                _ => None,
+            },
+            disk_space_checks: AtomicUsize::new(0),
+            disk_space_low: AtomicBool::new(false),
         };
 
-        // B+Tree索引，不需要从数据文件加载索引
-        if opts.index_type != IndexType::BPlusTree {
+        // B+Tree索引已持久化，不需要从数据文件加载索引；纯内存模式没有需要加载的文件
+        if rebuild_index && !opts.in_memory {
             // 读取merge目录，从索引文件hint中，加载内存索引
-            engine.load_index_from_hint_file()?;
+            if !opts.index_only_in_memory {
+                engine.load_index_from_hint_file()?;
+            }
 
             // 读取数据文件来加载内存索引
             let seq_number = engine.load_index_from_data_files()?;
             if seq_number > NON_TRANSACTION_SEQ_NUMBER {
                 engine
                     .sequence_number
-                    .store(seq_number + 1, std::sync::atomic::Ordering::SeqCst); // 更新到下一个事务序列号
+                    .fetch_max(seq_number + 1, std::sync::atomic::Ordering::SeqCst); // 更新到下一个事务序列号
             }
-            // 加载数据文件后，恢复标准文件IO
-            if opts.use_mmap {
-                engine.reset_io_type()?;
+            // 加载数据文件后，恢复标准文件IO，后台加载时由后台线程负责
+            if opts.use_mmap && !engine.is_warming() {
+                reset_io_type(&dir_path, &engine.active_file, &engine.older_files)?;
+            }
+            // 加载时活跃文件的写偏移停在最后一条完整记录之后，截断之后不完整的记录
+            if !opts.read_only && !engine.is_warming() {
+                let active_file = engine.active_file.write();
+                truncate_torn_tail(&active_file, active_file.get_write_offset())?;
             }
         }
 
-        if opts.index_type == IndexType::BPlusTree {
-            // 从sequence number文件中，加载事务序列号
-            let (exists, seq_number) = engine.load_sequence_number_from_file();
+        // 从close写入的sequence number文件中，加载事务序列号
+        let (exists, seq_number) = match opts.in_memory {
+            true => (false, 0),
+            false => engine.load_sequence_number_from_file(),
+        };
+        if rebuild_index {
+            // 已从数据文件重建索引，事务序列号和写偏移也已恢复，取两者中较大的序列号
+            engine.sequence_number_file_exists = true;
+            engine
+                .sequence_number
+                .fetch_max(seq_number, std::sync::atomic::Ordering::SeqCst);
+        } else {
             engine.sequence_number_file_exists = exists;
             engine
                 .sequence_number
                 .store(seq_number, std::sync::atomic::Ordering::SeqCst);
-            // 设置活跃文件的写偏移
+            // 活跃文件的追加写入使用标准文件IO
+            if opts.use_mmap {
+                reset_io_type(&dir_path, &engine.active_file, &engine.older_files)?;
+            }
+            // 设置活跃文件的写偏移，上次写入中断留下的不完整记录会被截断
             let active_file = engine.active_file.write();
-            active_file.set_write_offset(active_file.file_size());
+            let write_offset = last_valid_offset(&active_file, opts.max_load_crc_errors > 0)?;
+            truncate_torn_tail(&active_file, write_offset)?;
+        }
+
+        // merge后的文件在下次打开时生效，重新打开时不再检查，避免重复merge
+        if let Some(ratio) = opts.compact_on_open
+            && !opts.read_only
+            && engine.reclaimable_ratio()? >= ratio.max(opts.data_file_merge_ratio)
+        {
+            engine.merge()?;
+            std::mem::drop(engine);
+            return Engine::open(Options {
+                compact_on_open: None,
+                ..opts
+            });
+        }
+
+        if let Some(interval) = opts.sync_interval {
+            engine.start_timed_sync(interval);
+        }
+        if let Some(interval) = opts.index_flush_interval
+            && let Some(tree) = buffered_bptree
+        {
+            engine.start_index_flush(interval, tree);
+        }
+
+        // 首次创建db，执行回调
+        if engine.is_first_load
+            && let Some(hook) = &opts.on_first_load
+        {
+            hook(&engine)?;
         }
         Ok(engine)
     }
 
+    /// 运行时切换是否每次写入后立刻持久化，例如关键写入时开启，批量导入时关闭
+    pub fn set_sync_write(&self, on: bool) {
+        self.sync_write
+            .store(on, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// 修复数据库：删除hint索引、merge完成标识和B+Tree索引等辅助文件，
+    /// 下次打开时只依据数据文件重建索引
+    ///
+    /// 需要获取文件锁，db正在使用时返回DatabaseIsUsing
+    pub fn repair(dir_path: &Path) -> Result<()> {
+        if !dir_path.is_dir() {
+            return Err(Errors::DirPathNotADirectory);
+        }
+        let lock_file = lock_dir(dir_path)?;
+        for file_name in [
+            HINT_FILE_NAME,
+            HINT_TMP_FILE_NAME,
+            MERGE_FINISHED_FILE_NAME,
+            BPTREE_INDEX_FILE_NAME,
+        ] {
+            let file_path = dir_path.join(file_name);
+            if file_path.is_file() {
+                std::fs::remove_file(&file_path).map_err(|e| {
+                    error!("Failed to remove {}: {}", file_path.display(), e);
+                    Errors::RemoveFileError
+                })?;
+            }
+        }
+        fs2::FileExt::unlock(&lock_file).map_err(|e| {
+            warn!("Failed to unlock file lock: {}", e);
+            Errors::FailedToUnlockFileLock
+        })?;
+        Ok(())
+    }
+
+    /// 打开时因无法读取而跳过的旧数据文件id，只在开启skip_corrupt_files时出现
+    pub fn skipped_files(&self) -> Vec<u32> {
+        self.skipped_file_ids.lock().clone()
+    }
+
+    /// 打开时因CRC校验失败而跳过的记录的文件id和偏移，只在max_load_crc_errors大于0时出现
+    pub fn quarantined_records(&self) -> Vec<(u32, u64)> {
+        self.quarantined_records.lock().clone()
+    }
+
+    /// 获取get、put、merge的延迟统计，未开启track_latency时返回None
+    pub fn latency_report(&self) -> Option<LatencyReport> {
+        self.latency.as_ref().map(|latency| latency.report())
+    }
+
+    /// 本次打开是否是首次创建db
+    pub fn is_first_load(&self) -> bool {
+        self.is_first_load
+    }
+
     pub fn put(&self, key: Bytes, value: Bytes) -> Result<()> {
+        self.put_record(key, value, None, true)
+    }
+
+    /// 写入key/value，超过ttl后key过期，get和遍历都不再返回
+    ///
+    /// 过期时间以毫秒Unix时间戳保存在记录中，过期的记录在重新加载索引和merge时被清除
+    pub fn put_with_ttl(&self, key: Bytes, value: Bytes, ttl: Duration) -> Result<()> {
+        let expire_at = now_millis().saturating_add(ttl.as_millis() as u64);
+        self.put_record(key, value, Some(expire_at), true)
+    }
+
+    /// 写入key/value，无论sync_write和bytes_per_sync如何配置都不持久化活跃数据文件
+    ///
+    /// 写入的字节仍计入bytes_per_sync的累计值，连续写入后调用flush持久化
+    pub fn put_no_sync(&self, key: Bytes, value: Bytes) -> Result<()> {
+        self.put_record(key, value, None, false)
+    }
+
+    /// 写入key/value，expire_at为过期时间，allow_sync为false时跳过写入后的持久化
+    pub(crate) fn put_record(
+        &self,
+        key: Bytes,
+        value: Bytes,
+        expire_at: Option<u64>,
+        allow_sync: bool,
+    ) -> Result<()> {
+        let _timer = self.latency.as_ref().map(|latency| latency.put.start());
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
+        self.check_key(&key)?;
+        self.wait_index_ready()?;
+        let _merge_guard = self.wait_merge_if_blocking();
+        let index_key = self.index_key(&key);
+        check_index_budget(self.index.as_ref(), [index_key.as_slice()])?;
         let mut record = LogRecord {
             // 事务序列号为0，表示非事务提交的记录
             key: get_record_sequence_number_with_key(&key, NON_TRANSACTION_SEQ_NUMBER),
             value: value.to_vec(),
             rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at,
         };
+        let _index_guard = self.index_update_lock.read();
         // 写入活跃数据文件
-        let record_position = self.append_log_record(&mut record)?;
+        let record_position = self.append_log_record_with_sync(&mut record, allow_sync)?;
         // 更新内存索引
-        if !self.index.put(key.to_vec(), record_position) {
-            return Err(Errors::FailedToUpdateIndex);
+        update_index(
+            self.index.as_ref(),
+            &self.dead_bytes,
+            index_key.clone(),
+            LogRecordType::Normal,
+            record_position,
+        )?;
+        self.invalidate_cached(&index_key);
+        self.journal_ops([(
+            key.as_ref(),
+            value.as_ref(),
+            LogRecordType::Normal,
+            expire_at,
+        )])
+    }
+
+    /// 从reader读取len字节作为value写入，value分块写入数据文件，不需要在内存中保存完整的value
+    ///
+    /// reader提供的数据不足或读取失败时，丢弃已写入的部分，key的旧value保持不变。
+    /// 配置了value_codec时需要完整的value才能编码，会先读取到内存中再写入
+    pub fn put_stream(&self, key: Bytes, reader: &mut dyn Read, len: usize) -> Result<()> {
+        self.check_writable()?;
+        if self.options.value_codec.is_some() || self.ops_journal.is_some() {
+            let mut value = vec![0; len];
+            reader
+                .read_exact(&mut value)
+                .map_err(|_| Errors::ReadValueStreamError)?;
+            return self.put(key, value.into());
+        }
+        let _timer = self.latency.as_ref().map(|latency| latency.put.start());
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.check_key(&key)?;
+        self.wait_index_ready()?;
+        let _merge_guard = self.wait_merge_if_blocking();
+        let index_key = self.index_key(&key);
+        check_index_budget(self.index.as_ref(), [index_key.as_slice()])?;
+        self.check_disk_space()?;
+        let record = LogRecord {
+            key: get_record_sequence_number_with_key(&key, NON_TRANSACTION_SEQ_NUMBER),
+            value: vec![],
+            rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
+        };
+        let mut header = BytesMut::new();
+        let mut hasher = record.encode_header_into(len, &mut header);
+        let record_len = header.len() + len + 4;
+        if record_len as u64 > self.options.data_file_size {
+            return Err(Errors::ValueTooLarge);
+        }
+
+        let _index_guard = self.index_update_lock.read();
+        let mut active_file = self.active_file.write();
+        if active_file.get_write_offset() + record_len as u64 > self.options.data_file_size {
+            self.rotate_active_file(&mut active_file)?;
+        }
+        let write_offset = active_file.get_write_offset();
+        // 依次写入记录头和key、分块读取的value以及CRC，失败时截断已写入的部分
+        let res = (|| {
+            active_file.write(&header)?;
+            let mut chunk = vec![0; STREAM_CHUNK_SIZE.min(len)];
+            let mut remaining = len;
+            while remaining > 0 {
+                let n = remaining.min(chunk.len());
+                reader
+                    .read_exact(&mut chunk[..n])
+                    .map_err(|_| Errors::ReadValueStreamError)?;
+                hasher.update(&chunk[..n]);
+                active_file.write(&chunk[..n])?;
+                remaining -= n;
+            }
+            active_file.write(&hasher.finalize().to_be_bytes())
+        })();
+        if let Err(e) = res {
+            active_file.truncate(write_offset)?;
+            return Err(e);
         }
+        // 完整格式的记录，作为前缀压缩的重启点
+        active_file.record_restart(
+            write_offset,
+            &record.key,
+            self.options.key_prefix_restart_interval,
+        );
+        self.data_file_write_count
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        self.sync_after_write(&active_file, record_len)?;
+        let record_position = LogRecordPos {
+            file_id: active_file.get_file_id(),
+            offset: write_offset,
+            size: record_len as u32,
+        };
+        drop(active_file);
+
+        update_index(
+            self.index.as_ref(),
+            &self.dead_bytes,
+            index_key.clone(),
+            LogRecordType::Normal,
+            record_position,
+        )?;
+        self.invalidate_cached(&index_key);
         Ok(())
     }
 
     /// 获取指定key的value
     pub fn get(&self, key: Bytes) -> Result<Bytes> {
+        let _timer = self.latency.as_ref().map(|latency| latency.get.start());
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        let key = self.index_key(&key);
+        // 索引预热期间，先从尚未加载的数据文件中查找最新记录
+        if self.is_warming()
+            && let Some(record) = self.get_from_deferred_files(&key)?
+        {
+            return match record.rec_type {
+                LogRecordType::Deleted => Err(Errors::KeyNotFound),
+                _ if record.is_expired() => Err(Errors::KeyNotFound),
+                _ => Ok(self.decode_value(record)?.into()),
+            };
+        }
+        // 从内存索引获取位置，读取前数据文件被删除时，key可能已被移动到新位置，重新查询索引后重试
+        let mut retries = 0;
+        loop {
+            let Some(position) = self.index.get(key.clone()) else {
+                return Err(Errors::KeyNotFound);
+            };
+            match self.get_value_for_key(&position, &key) {
+                Err(Errors::DataFileNotFound)
+                    if retries < self.options.read_retries
+                        && self.index.get(key.clone()) != Some(position) =>
+                {
+                    retries += 1;
+                    self.read_retry_count
+                        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                }
+                Err(Errors::InvalidLogRecordCrc)
+                    if self.options.on_read_corruption == CorruptionPolicy::SkipToIndex
+                        && retries < self.options.read_retries =>
+                {
+                    warn!(
+                        "Corrupt record in data file {} at offset {}, looking up the index again",
+                        position.file_id, position.offset
+                    );
+                    if self.index.get(key.clone()) == Some(position) {
+                        return Err(Errors::InvalidLogRecordCrc);
+                    }
+                    retries += 1;
+                    self.read_retry_count
+                        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// 判断key是否存在，只读取记录头，不读取key和value，设置了TTL的key过期后返回false
+    pub fn exists(&self, key: Bytes) -> Result<bool> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        let key = self.index_key(&key);
+        // 索引预热期间，尚未加载的数据文件中可能有更新的记录
+        if self.is_warming()
+            && let Some(record) = self.get_from_deferred_files(&key)?
+        {
+            return Ok(record.rec_type != LogRecordType::Deleted && !record.is_expired());
+        }
+        match self.index.get(key) {
+            Some(position) => Ok(self.live_value_len(position)?.is_some()),
+            None => Ok(false),
+        }
+    }
+
+    /// 在后台线程中读取key，超过timeout仍未完成时返回ReadTimeout，用于存储可能很慢的场景，
+    /// 等待索引预热完成的时间也计入timeout
+    ///
+    /// 每次调用启动一个线程，超时后线程会继续完成读取，结果被丢弃。未结束的读取线程数
+    /// 达到max_pending_timeout_reads时不再启动新线程，直接返回TooManyPendingReads
+    pub fn get_timeout(&self, key: Bytes, timeout: Duration) -> Result<Bytes> {
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
-        // 从内存索引获取位置
-        let Some(position) = self.index.get(key.to_vec()) else {
+        let pending = self.pending_timeout_reads.clone();
+        if pending.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+            >= self.options.max_pending_timeout_reads
+        {
+            pending.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            return Err(Errors::TooManyPendingReads);
+        }
+        let key = self.index_key(&key);
+        let index_warmup = self.index_warmup.clone();
+        let index = self.index.clone();
+        let active_file = self.active_file.clone();
+        let older_files = self.older_files.clone();
+        let options = self.options.clone();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let read = || {
+                index_warmup.wait()?;
+                let Some(position) = index.get(key.clone()) else {
+                    return Err(Errors::KeyNotFound);
+                };
+                let record =
+                    read_record_at(&active_file, &older_files, &position, &mut BytesMut::new())?
+                        .0
+                        .record;
+                verify_record_key(&options, &record, &key)?;
+                if record.rec_type == LogRecordType::Deleted || record.is_expired() {
+                    return Err(Errors::KeyNotFound);
+                }
+                Ok(decode_record_value(&options, record)?.into())
+            };
+            let res = read();
+            pending.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            let _ = sender.send(res);
+        });
+        match receiver.recv_timeout(timeout) {
+            Ok(res) => res,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => Err(Errors::ReadTimeout),
+            // 读取线程panic
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                Err(Errors::ReadFromDataFileError)
+            }
+        }
+    }
+
+    /// 从读缓存获取指定key的value，未命中时读取并放入缓存，适合读多写少的场景
+    ///
+    /// 命中缓存时不再读取数据文件，只检查缓存的过期时间，缓存项在该key下一次通过put、delete
+    /// 或WriteBatch写入时失效。读取后索引仍指向读取的位置时才放入缓存，
+    /// 与写入并发的读取不会把旧值放入缓存。缓存最多保存read_cache_capacity个key
+    pub fn get_cached(&self, key: Bytes) -> Result<Bytes> {
+        let index_key = self.index_key(&key);
+        if let Some(cached) = self.read_cache.read().get(&index_key)
+            && cached
+                .expire_at
+                .is_none_or(|expire_at| expire_at > now_millis())
+        {
+            return Ok(cached.value.clone());
+        }
+        // 索引预热期间索引不完整，不使用缓存
+        if self.is_warming() || self.options.read_cache_capacity == 0 {
+            return self.get(key);
+        }
+        let Some(position) = self.index.get(index_key.clone()) else {
+            self.invalidate_cached(&index_key);
             return Err(Errors::KeyNotFound);
         };
-        self.get_value_by_position(&position)
+        let record = match self.read_record_by_position(&position) {
+            Ok((read_record, _)) => read_record.record,
+            // 数据文件被删除或记录损坏时，由get按配置重试，结果不放入缓存
+            Err(Errors::DataFileNotFound | Errors::InvalidLogRecordCrc) => return self.get(key),
+            Err(e) => return Err(e),
+        };
+        verify_record_key(&self.options, &record, &index_key)?;
+        if record.rec_type == LogRecordType::Deleted || record.is_expired() {
+            self.invalidate_cached(&index_key);
+            return Err(Errors::KeyNotFound);
+        }
+        let expire_at = record.expire_at;
+        let value: Bytes = self.decode_value(record)?.into();
+        // 写入在更新索引之后才使缓存失效，持有缓存的写锁检查索引，保证不会放入已被覆盖的value
+        let mut read_cache = self.read_cache.write();
+        if self.index.get(index_key.clone()) == Some(position) {
+            if read_cache.len() >= self.options.read_cache_capacity
+                && !read_cache.contains_key(&index_key)
+                && let Some(evicted) = read_cache.keys().next().cloned()
+            {
+                read_cache.remove(&evicted);
+            }
+            read_cache.insert(
+                index_key,
+                CachedValue {
+                    position,
+                    expire_at,
+                    value: value.clone(),
+                },
+            );
+        }
+        Ok(value)
     }
 
-    /// 获取指定位置的value
-    pub(crate) fn get_value_by_position(&self, position: &LogRecordPos) -> Result<Bytes> {
+    /// 批量读取多个key，按keys的顺序返回每个key的结果，与get相同，不存在的key返回KeyNotFound
+    ///
+    /// 只获取一次活跃文件和旧数据文件的读锁，在同一个锁范围内读取所有记录并复用读取缓冲区，
+    /// 期间切换活跃文件的写入会等待读取完成
+    pub fn get_batch(&self, keys: &[Bytes]) -> Vec<Result<Bytes>> {
+        // 索引预热期间需要扫描尚未加载的数据文件，逐个读取
+        if self.is_warming() {
+            return keys.iter().map(|key| self.get(key.clone())).collect();
+        }
         let active_file = self.active_file.read();
         let older_files = self.older_files.read();
-        let log_record = match active_file.get_file_id() == position.file_id {
-            true => active_file.read_log_record(position.offset)?.record,
-            false => {
-                let Some(data_file) = older_files.get(&position.file_id) else {
-                    return Err(Errors::DataFileNotFound);
-                };
-                data_file.read_log_record(position.offset)?.record
+        let mut scratch = BytesMut::new();
+        let mut get_one = |key: &Bytes| -> Result<Bytes> {
+            if key.is_empty() {
+                return Err(Errors::KeyIsEmpty);
+            }
+            let key = self.index_key(key);
+            let Some(position) = self.index.get(key.clone()) else {
+                return Err(Errors::KeyNotFound);
+            };
+            let data_file = match active_file.get_file_id() == position.file_id {
+                true => &*active_file,
+                false => older_files
+                    .get(&position.file_id)
+                    .ok_or(Errors::DataFileNotFound)?,
+            };
+            let record = read_record_in(data_file, &position, &mut scratch)?.record;
+            verify_record_key(&self.options, &record, &key)?;
+            if record.rec_type == LogRecordType::Deleted || record.is_expired() {
+                return Err(Errors::KeyNotFound);
             }
+            Ok(self.decode_value(record)?.into())
+        };
+        keys.iter().map(&mut get_one).collect()
+    }
+
+    /// 配置了block_writes_during_merge时，等待正在进行的merge完成，并在写入期间阻止新的merge
+    pub(crate) fn wait_merge_if_blocking(&self) -> Option<MutexGuard<'_, ()>> {
+        match self.options.block_writes_during_merge {
+            true => Some(self.merge_lock.lock()),
+            false => None,
+        }
+    }
+
+    /// 使key的读缓存失效
+    pub(crate) fn invalidate_cached(&self, key: &[u8]) {
+        self.read_cache.write().remove(key);
+    }
+
+    /// 获取指定key的value长度，只读取记录头，不读取value
+    ///
+    /// 配置了value_codec时，记录中保存的是编码后的value，需要读取并解码整条记录
+    pub fn value_size(&self, key: Bytes) -> Result<usize> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        // 校验key时需要读取整条记录
+        if self.options.value_codec.is_some() || self.options.verify_key_on_read {
+            return Ok(self.get(key)?.len());
+        }
+        let key = self.index_key(&key);
+        if self.is_warming()
+            && let Some(record) = self.get_from_deferred_files(&key)?
+        {
+            return match record.rec_type {
+                LogRecordType::Deleted => Err(Errors::KeyNotFound),
+                _ if record.is_expired() => Err(Errors::KeyNotFound),
+                _ => Ok(record.value.len()),
+            };
+        }
+        let Some(position) = self.index.get(key) else {
+            return Err(Errors::KeyNotFound);
+        };
+        self.live_value_len(position)?.ok_or(Errors::KeyNotFound)
+    }
+
+    /// 只读取索引位置处的记录头，返回value长度，记录已删除或已过期时返回None
+    fn live_value_len(&self, position: LogRecordPos) -> Result<Option<usize>> {
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read().clone();
+        let data_file = match active_file.get_file_id() == position.file_id {
+            true => &*active_file,
+            false => older_files
+                .get(&position.file_id)
+                .ok_or(Errors::DataFileNotFound)?,
+        };
+        Ok(match data_file.read_log_record_header(position.offset)? {
+            (LogRecordType::Deleted, _, _) => None,
+            (_, _, Some(expire_at)) if expire_at <= now_millis() => None,
+            (_, value_len, _) => Some(value_len),
+        })
+    }
+
+    /// 校验写入的key：开启utf8_keys时必须是合法的UTF-8，配置了load_keyspaces时必须属于已加载的keyspace
+    pub(crate) fn check_key(&self, key: &[u8]) -> Result<()> {
+        if self.options.utf8_keys && std::str::from_utf8(key).is_err() {
+            return Err(Errors::InvalidUtf8Key);
+        }
+        if !key_in_keyspaces(self.options.load_keyspaces.as_ref(), &self.index_key(key)) {
+            return Err(Errors::KeyspaceNotLoaded);
+        }
+        Ok(())
+    }
+
+    /// 配置了key_transform时返回变换后的key，索引、读缓存和删除记录跟踪都使用变换后的key
+    pub(crate) fn index_key(&self, key: &[u8]) -> Vec<u8> {
+        transform_key(self.options.key_transform.as_ref(), key.to_vec())
+    }
+
+    /// 只读打开时拒绝写入
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        match self.options.read_only {
+            true => Err(Errors::DatabaseIsReadOnly),
+            false => Ok(()),
+        }
+    }
+
+    /// 配置了min_free_bytes时，检查数据目录所在磁盘的可用空间
+    ///
+    /// 每DISK_SPACE_CHECK_INTERVAL次写入才重新获取一次可用空间，空间不足后每次写入都重新获取，
+    /// 释放空间后可以立即恢复写入
+    pub(crate) fn check_disk_space(&self) -> Result<()> {
+        let Some(min_free_bytes) = self.options.min_free_bytes else {
+            return Ok(());
+        };
+        let checks = self
+            .disk_space_checks
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let low = self
+            .disk_space_low
+            .load(std::sync::atomic::Ordering::SeqCst);
+        if low || checks.is_multiple_of(DISK_SPACE_CHECK_INTERVAL) {
+            let available = fs2::available_space(&self.options.dir_path).map_err(|e| {
+                error!("Failed to get available disk space: {}", e);
+                Errors::DiskSpaceCheckError
+            })?;
+            self.disk_space_low.store(
+                available < min_free_bytes,
+                std::sync::atomic::Ordering::SeqCst,
+            );
+        }
+        match self
+            .disk_space_low
+            .load(std::sync::atomic::Ordering::SeqCst)
+        {
+            true => Err(Errors::InsufficientDiskSpace),
+            false => Ok(()),
+        }
+    }
+
+    /// 配置了value_codec时，编码普通记录的value，已编码的记录不会重复编码
+    pub(crate) fn encode_value(&self, record: &mut LogRecord) {
+        let Some(codec) = &self.options.value_codec else {
+            return;
+        };
+        if record.rec_type != LogRecordType::Normal || record.value_encoded {
+            return;
+        }
+        let encoded = codec.encode(&record.value);
+        let mut value = Vec::with_capacity(encoded.len() + 1);
+        value.push(codec.id());
+        value.extend_from_slice(&encoded);
+        record.value = value;
+        record.value_encoded = true;
+    }
+
+    /// 解码记录的value，编码时使用的编解码器id与配置的不一致时返回错误
+    pub(crate) fn decode_value(&self, record: LogRecord) -> Result<Vec<u8>> {
+        decode_record_value(&self.options, record)
+    }
+
+    /// 获取指定key的value及其所在位置、CRC和来源文件，用于排查读取问题
+    pub fn get_debug(&self, key: Bytes) -> Result<ValueDebug> {
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        self.wait_index_ready()?;
+        let key = self.index_key(&key);
+        let Some(position) = self.index.get(key.clone()) else {
+            return Err(Errors::KeyNotFound);
         };
+        let (read_log_record, source) = self.read_record_by_position(&position)?;
+        verify_record_key(&self.options, &read_log_record.record, &key)?;
+        if read_log_record.record.rec_type == LogRecordType::Deleted
+            || read_log_record.record.is_expired()
+        {
+            return Err(Errors::KeyNotFound);
+        }
+        let crc = read_log_record.crc;
+        Ok(ValueDebug {
+            value: self.decode_value(read_log_record.record)?.into(),
+            position,
+            crc,
+            source,
+        })
+    }
+
+    /// 获取指定key的状态，区分存在、已删除和从未写入
+    ///
+    /// key存在时只读取一次索引；不存在时删除记录已从索引中移除，需要从最新的数据文件开始
+    /// 逐个扫描所有记录查找该key最后一条记录，代价与数据量成正比，只适合审计等低频场景。
+    /// merge会丢弃删除记录，merge前删除的key之后返回Absent
+    pub fn get_status(&self, key: Bytes) -> Result<KeyStatus> {
+        match self.get(key.clone()) {
+            Ok(value) => return Ok(KeyStatus::Present(value)),
+            Err(Errors::KeyNotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let index_key = self.index_key(&key);
+        for file_id in self.data_file_ids().into_iter().rev() {
+            let records = match self.read_file_records(file_id) {
+                Ok(records) => records,
+                // 扫描期间文件被merge删除
+                Err(Errors::DataFileNotFound) => continue,
+                Err(e) => return Err(e),
+            };
+            let last_record = records.into_iter().rev().find(|(record, _)| {
+                let (_, real_key) = parse_record_sequence_number_with_key(&record.key);
+                transform_key(self.options.key_transform.as_ref(), real_key) == index_key
+            });
+            if let Some((record, _)) = last_record {
+                return Ok(match record.rec_type {
+                    LogRecordType::Deleted => KeyStatus::Deleted,
+                    _ => KeyStatus::Absent,
+                });
+            }
+        }
+        Ok(KeyStatus::Absent)
+    }
+
+    /// 获取指定位置的value
+    pub(crate) fn get_value_by_position(&self, position: &LogRecordPos) -> Result<Bytes> {
+        let log_record = self.read_record_by_position(position)?.0.record;
         // 判断记录的类型
-        if log_record.rec_type == LogRecordType::Deleted {
+        if log_record.rec_type == LogRecordType::Deleted || log_record.is_expired() {
             return Err(Errors::KeyNotFound);
         }
-        Ok(log_record.value.into())
+        Ok(self.decode_value(log_record)?.into())
+    }
+
+    /// 获取索引中key对应位置的value，key为变换后的key，开启verify_key_on_read时校验记录的key
+    fn get_value_for_key(&self, position: &LogRecordPos, key: &[u8]) -> Result<Bytes> {
+        let log_record = self.read_record_by_position(position)?.0.record;
+        verify_record_key(&self.options, &log_record, key)?;
+        if log_record.rec_type == LogRecordType::Deleted || log_record.is_expired() {
+            return Err(Errors::KeyNotFound);
+        }
+        Ok(self.decode_value(log_record)?.into())
+    }
+
+    /// 读取指定位置的记录，同时返回记录所在的是活跃文件还是旧数据文件
+    fn read_record_by_position(
+        &self,
+        position: &LogRecordPos,
+    ) -> Result<(ReadLogRecord, FileSource)> {
+        self.read_record_with_buf(position, &mut BytesMut::new())
+    }
+
+    /// 使用调用方提供的缓冲区读取指定位置的记录，连续读取多条记录时可复用同一块内存
+    pub(crate) fn read_record_with_buf(
+        &self,
+        position: &LogRecordPos,
+        scratch: &mut BytesMut,
+    ) -> Result<(ReadLogRecord, FileSource)> {
+        read_record_at(&self.active_file, &self.older_files, position, scratch)
     }
 
     pub fn delete(&self, key: Bytes) -> Result<()> {
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
+        self.check_key(&key)?;
+        self.wait_index_ready()?;
+        let _merge_guard = self.wait_merge_if_blocking();
+        // 持有锁直到索引更新完成，并发删除同一个key时只写入一条删除记录
+        let mut tombstones = self.active_tombstones.lock();
+        let index_key = self.index_key(&key);
         // 从内存索引查找对应数据，不存在时直接返回
-        let Some(_) = self.index.get(key.to_vec()) else {
+        let Some(_) = self.index.get(index_key.clone()) else {
             return Err(Errors::KeyNotFound);
         };
         // 构造一条删除记录
@@ -231,23 +1034,158 @@ impl Engine {
             key: get_record_sequence_number_with_key(&key, NON_TRANSACTION_SEQ_NUMBER),
             value: vec![],
             rec_type: LogRecordType::Deleted,
+            value_encoded: false,
+            expire_at: None,
         };
-        self.append_log_record(&mut record)?;
+        let _index_guard = self.index_update_lock.read();
+        let record_position = self.append_log_record(&mut record)?;
         // 从内存索引中删除
-        if !self.index.delete(key.to_vec()) {
-            return Err(Errors::FailedToUpdateIndex);
-        }
-        Ok(())
+        update_index(
+            self.index.as_ref(),
+            &self.dead_bytes,
+            index_key.clone(),
+            LogRecordType::Deleted,
+            record_position,
+        )?;
+        tombstones.insert(record_position.file_id, index_key.clone());
+        self.invalidate_cached(&index_key);
+        self.journal_ops([(key.as_ref(), &[][..], LogRecordType::Deleted, None)])
     }
 
     pub fn sync(&self) -> Result<()> {
         self.active_file.read().sync()
     }
 
+    /// 持久化屏障：持久化活跃数据文件，并将bytes_per_sync的累计值置为0
+    ///
+    /// 与sync_write和bytes_per_sync的自动持久化无关，是用户显式保证持久化的接口，
+    /// 返回成功后之前的所有写入（包括put_no_sync）在崩溃后都不会丢失。
+    /// 切换活跃文件时旧文件已经持久化，只需要持久化当前的活跃文件
+    pub fn flush(&self) -> Result<()> {
+        let active_file = self.active_file.read();
+        active_file.sync()?;
+        self.bytes_write
+            .store(0, std::sync::atomic::Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// 持久化活跃数据文件和所有旧数据文件
+    ///
+    /// 某个文件持久化失败时继续持久化其余文件，最后返回PartialSyncFailure，按文件id列出每个失败的文件及其错误
+    pub fn sync_all(&self) -> Result<()> {
+        let mut failures = Vec::new();
+        {
+            let active_file = self.active_file.read();
+            let older_files = self.older_files.read().clone();
+            for data_file in older_files
+                .values()
+                .map(Arc::as_ref)
+                .chain(std::iter::once(&*active_file))
+            {
+                if let Err(e) = data_file.sync() {
+                    failures.push((data_file.get_file_id(), e));
+                }
+            }
+        }
+        if failures.is_empty() {
+            return Ok(());
+        }
+        failures.sort_unstable_by_key(|(file_id, _)| *file_id);
+        Err(Errors::PartialSyncFailure(failures))
+    }
+
+    /// 获取所有数据文件的id，按id升序排列
+    pub fn data_file_ids(&self) -> Vec<u32> {
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        let mut file_ids: Vec<u32> = older_files.keys().copied().collect();
+        file_ids.push(active_file.get_file_id());
+        file_ids.sort_unstable();
+        file_ids
+    }
+
+    /// 按写入顺序读取指定数据文件中的所有记录及其位置，供外部工具检查文件内容
+    pub fn read_file_records(&self, file_id: u32) -> Result<Vec<(LogRecord, LogRecordPos)>> {
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read().clone();
+        let data_file = match active_file.get_file_id() == file_id {
+            true => &*active_file,
+            false => older_files.get(&file_id).ok_or(Errors::DataFileNotFound)?,
+        };
+        let mut records = Vec::new();
+        let mut offset = 0;
+        loop {
+            let read_log_record = match data_file.read_log_record(offset) {
+                Ok(v) => v,
+                Err(Errors::ReadDataFileEof) => break,
+                Err(e) => return Err(e),
+            };
+            let position = LogRecordPos {
+                file_id,
+                offset,
+                size: read_log_record.size as u32,
+            };
+            records.push((read_log_record.record, position));
+            offset += read_log_record.size;
+        }
+        Ok(records)
+    }
+
+    /// 启动后台线程，每隔interval提交一次暂存的B+Tree索引更新
+    ///
+    /// 提交前先持久化活跃数据文件，保证提交后的索引不会指向崩溃后丢失的记录
+    fn start_index_flush(&self, interval: Duration, tree: Arc<BPlusTree>) {
+        let active_file = self.active_file.clone();
+        self.background.spawn(move |background| {
+            while !background.wait_stop(interval) {
+                if let Err(e) = tree.flush_with(|| active_file.read().sync()) {
+                    warn!("Failed to flush bptree index in background: {}", e);
+                }
+            }
+        });
+    }
+
+    /// 启动后台线程，每隔interval持久化一次活跃数据文件
+    fn start_timed_sync(&self, interval: Duration) {
+        let active_file = self.active_file.clone();
+        let sync_count = self.background_sync_count.clone();
+        self.background.spawn(move |background| {
+            while !background.wait_stop(interval) {
+                if let Err(e) = active_file.read().sync() {
+                    warn!("Failed to sync active file in background: {}", e);
+                }
+                sync_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        });
+    }
+
+    /// 关闭db：停止并等待所有后台线程退出，清空读缓存，持久化所有数据文件后释放文件锁
+    ///
+    /// 重复调用时直接返回
     pub fn close(&self) -> Result<()> {
-        if !self.options.dir_path.is_dir() {
+        if self.closed.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.background.shutdown();
+        self.read_cache.write().clear();
+        // 纯内存模式没有需要持久化的文件和文件锁
+        if self.options.in_memory || !self.options.dir_path.is_dir() {
             return Ok(());
         }
+        // 等待后台加载索引结束，加载失败不影响关闭
+        if let Err(e) = self.wait_index_ready() {
+            warn!("Index was not fully loaded: {}", e);
+        }
+        let Some(lock_file) = &self.lock_file else {
+            return Ok(());
+        };
+        // 只读打开时没有写入，不需要持久化
+        if self.options.read_only {
+            return fs2::FileExt::unlock(lock_file).map_err(|e| {
+                warn!("Failed to unlock file lock: {}", e);
+                Errors::FailedToUnlockFileLock
+            });
+        }
         // 写入事务序列号
         let sequence_number_file = DataFile::new_sequence_number_file(&self.options.dir_path)?;
         let record = LogRecord {
@@ -258,12 +1196,17 @@ impl Engine {
                 .to_string()
                 .into_bytes(),
             rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
         };
-        sequence_number_file.sync()?;
-
         sequence_number_file.write(&record.encode())?;
-        self.active_file.read().sync()?;
-        fs2::FileExt::unlock(&self.lock_file).map_err(|e| {
+        sequence_number_file.sync()?;
+        self.sync_all()?;
+        self.index.flush()?;
+        if let Some(journal) = &self.ops_journal {
+            journal.sync()?;
+        }
+        fs2::FileExt::unlock(lock_file).map_err(|e| {
             warn!("Failed to unlock file lock: {}", e);
             Errors::FailedToUnlockFileLock
         })?;
@@ -272,37 +1215,155 @@ impl Engine {
 
     /// 将记录追加写到活跃数据文件，返回写入到文件的起始位置
     pub(crate) fn append_log_record(&self, record: &mut LogRecord) -> Result<LogRecordPos> {
-        let dir_path = self.options.dir_path.as_path();
-        let encoded_record = record.encode();
-        let record_len = encoded_record.len();
-        // 获取当前活跃数据文件
-        let mut active_file = self.active_file.write();
-        // 活跃数据文件大小如果超过阈值，需要创建新文件
+        self.append_log_record_with_sync(record, true)
+    }
+
+    /// 追加写入一条记录，allow_sync为false时只累计写入字节数，不持久化
+    fn append_log_record_with_sync(
+        &self,
+        record: &mut LogRecord,
+        allow_sync: bool,
+    ) -> Result<LogRecordPos> {
+        self.check_writable()?;
+        self.check_disk_space()?;
+        self.encode_value(record);
+        let record_len = record.encoded_length();
+        // 单条记录超过数据文件大小，无论是否切换文件都无法容纳
+        if record_len as u64 > self.options.data_file_size {
+            return Err(Errors::ValueTooLarge);
+        }
+        // 获取当前活跃数据文件
+        let mut active_file = self.active_file.write();
+        // 活跃数据文件大小如果超过阈值，需要创建新文件，前缀压缩后的记录不会比record_len更长
         if active_file.get_write_offset() + record_len as u64 > self.options.data_file_size {
-            // 持久化活跃数据文件
-            active_file.sync()?;
-            let current_file_id = active_file.get_file_id();
-            let old_active_file = DataFile::new(dir_path, current_file_id, IOType::StandardFileIO)?;
-            self.older_files
-                .write()
-                .insert(current_file_id, old_active_file);
-            // 创建新的活跃数据文件
-            let new_active_file =
-                DataFile::new(dir_path, current_file_id + 1, IOType::StandardFileIO)?;
-            *active_file = new_active_file;
+            self.rotate_active_file(&mut active_file)?;
         }
+        // 编码到复用的写缓冲区，持有活跃文件写锁时才会访问，不存在竞争
+        // 前缀压缩依赖同一文件中上一条记录的key，需要在切换文件之后编码
+        let mut encoded_record = self.write_buf.lock();
+        encoded_record.clear();
+        active_file.encode_log_record(
+            record,
+            self.options.key_prefix_restart_interval,
+            &mut encoded_record,
+        );
+        let record_len = encoded_record.len();
         // 写入记录
         let write_offset = active_file.get_write_offset();
         active_file.write(&encoded_record)?;
+        active_file.commit_encoded();
+        self.data_file_write_count
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        match allow_sync {
+            true => self.sync_after_write(&active_file, record_len)?,
+            false => {
+                self.bytes_write
+                    .fetch_add(record_len, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+        // 返回写入位置
+        Ok(LogRecordPos {
+            file_id: active_file.get_file_id(),
+            offset: write_offset,
+            size: record_len as u32,
+        })
+    }
+
+    /// 将多条记录编码到同一个缓冲区后一次写入活跃数据文件，按顺序返回每条记录的位置
+    ///
+    /// 活跃文件剩余空间不足时，先写入已编码的部分，切换文件后继续编码剩余的记录
+    pub(crate) fn append_log_records(
+        &self,
+        records: &mut [LogRecord],
+    ) -> Result<Vec<LogRecordPos>> {
+        self.check_writable()?;
+        self.check_disk_space()?;
+        for record in records.iter_mut() {
+            self.encode_value(record);
+        }
+        if records
+            .iter()
+            .any(|record| record.encoded_length() as u64 > self.options.data_file_size)
+        {
+            return Err(Errors::ValueTooLarge);
+        }
+        let mut active_file = self.active_file.write();
+        let mut encoded_records = self.write_buf.lock();
+        let mut positions = Vec::with_capacity(records.len());
+        let mut records = records.iter().peekable();
+        while records.peek().is_some() {
+            encoded_records.clear();
+            let file_id = active_file.get_file_id();
+            let write_offset = active_file.get_write_offset();
+            while let Some(record) = records.peek() {
+                let offset = write_offset + encoded_records.len() as u64;
+                if offset + record.encoded_length() as u64 > self.options.data_file_size {
+                    break;
+                }
+                active_file.encode_log_record(
+                    record,
+                    self.options.key_prefix_restart_interval,
+                    &mut encoded_records,
+                );
+                positions.push(LogRecordPos {
+                    file_id,
+                    offset,
+                    size: (write_offset + encoded_records.len() as u64 - offset) as u32,
+                });
+                records.next();
+            }
+            if !encoded_records.is_empty() {
+                active_file.write(&encoded_records)?;
+                active_file.commit_encoded();
+                self.data_file_write_count
+                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                self.sync_after_write(&active_file, encoded_records.len())?;
+            }
+            if records.peek().is_some() {
+                self.rotate_active_file(&mut active_file)?;
+            }
+        }
+        Ok(positions)
+    }
+
+    /// 切换活跃数据文件，原活跃文件转为旧数据文件
+    fn rotate_active_file(&self, active_file: &mut DataFile) -> Result<()> {
+        let dir_path = self.options.dir_path.as_path();
+        // 先持久化活跃数据文件，再创建新文件，崩溃时旧文件的内容一定完整
+        active_file.sync()?;
+        let current_file_id = active_file.get_file_id();
+        let io_type = data_file_io_type(&self.options);
+        // 创建新的活跃数据文件，并持久化目录项，之后写入新文件的记录不会因目录项丢失而丢失
+        let mut new_active_file = DataFile::new(dir_path, current_file_id + 1, io_type)?;
+        new_active_file.set_max_record_size(self.options.data_file_size);
+        let old_active_file = match self.options.in_memory {
+            // 内存中的数据文件无法按文件名重新打开，原活跃文件直接转为旧数据文件
+            true => std::mem::replace(active_file, new_active_file),
+            false => {
+                let mut old_active_file = DataFile::new(dir_path, current_file_id, io_type)?;
+                old_active_file.set_max_record_size(self.options.data_file_size);
+                if self.options.sync_dir_on_rotate {
+                    fio::sync_dir(dir_path)?;
+                }
+                *active_file = new_active_file;
+                old_active_file
+            }
+        };
+        // 有读取持有快照时复制一份映射再修改，不影响正在进行的读取
+        Arc::make_mut(&mut self.older_files.write())
+            .insert(current_file_id, Arc::new(old_active_file));
+        Ok(())
+    }
 
+    /// 写入written字节后，根据配置项决定是否立刻持久化活跃数据文件
+    fn sync_after_write(&self, active_file: &DataFile, written: usize) -> Result<()> {
         let previous = self
             .bytes_write
-            .fetch_add(record_len, std::sync::atomic::Ordering::SeqCst);
-        // 根据配置项，决定是否立刻持久化活跃数据文件
-        let mut need_sync = self.options.sync_write;
+            .fetch_add(written, std::sync::atomic::Ordering::SeqCst);
+        let mut need_sync = self.sync_write.load(std::sync::atomic::Ordering::SeqCst);
         if !need_sync
             && self.options.bytes_per_sync > 0
-            && previous + record_len >= self.options.bytes_per_sync
+            && previous + written >= self.options.bytes_per_sync
         {
             need_sync = true;
         }
@@ -312,11 +1373,7 @@ impl Engine {
             self.bytes_write
                 .store(0, std::sync::atomic::Ordering::SeqCst);
         }
-        // 返回写入位置
-        Ok(LogRecordPos {
-            file_id: active_file.get_file_id(),
-            offset: write_offset,
-        })
+        Ok(())
     }
 
     /// 从数据文件加载索引
@@ -324,119 +1381,213 @@ impl Engine {
     /// 2. 将记录写入索引
     /// 3. 如果是删除记录，则从索引中删除
     /// 4. 如果是正常记录，则将记录写入索引
+    ///
+    /// 配置了max_recovery_duration且加载超时，剩余的数据文件交给后台线程继续加载
     fn load_index_from_data_files(&mut self) -> Result<usize> {
-        // 最新的事务序列号
-        let mut current_seq_number = NON_TRANSACTION_SEQ_NUMBER;
         if self.file_ids.is_empty() {
-            return Ok(current_seq_number);
+            return Ok(NON_TRANSACTION_SEQ_NUMBER);
         }
 
         let mut unmerged_file_id = 0;
-        let mut has_merge = false;
         let merge_finished_file_name = self.options.dir_path.join(MERGE_FINISHED_FILE_NAME);
-        // 如果merge完成文件存在，则从不用从已被merge的文件中加载索引
-        if merge_finished_file_name.is_file() {
+        // 如果merge完成文件和hint索引文件都存在，则从不用从已被merge的文件中加载索引，
+        // 没有hint文件时尝试从被merge文件末尾的内联hint加载，
+        // 索引只保存在内存中时不使用hint文件，从所有数据文件加载
+        if merge_finished_file_name.is_file() && !self.options.index_only_in_memory {
             let merge_finished_file = DataFile::new_merge_finished_file(&self.options.dir_path)?;
             let read_log_record = merge_finished_file.read_log_record(0)?;
-            unmerged_file_id = String::from_utf8(read_log_record.record.value)
+            let merged_file_id = String::from_utf8(read_log_record.record.value)
                 .unwrap()
                 .parse::<u32>()?;
-            has_merge = true;
+            if self.options.dir_path.join(HINT_FILE_NAME).is_file()
+                || self.load_index_from_inline_hints(merged_file_id)?
+            {
+                unmerged_file_id = merged_file_id;
+            }
         }
+        // 文件id小于unmerged_file_id，说明已经从hint索引文件中加载过索引，跳过
+        let file_ids: Vec<u32> = self
+            .file_ids
+            .iter()
+            .copied()
+            .filter(|file_id| *file_id >= unmerged_file_id)
+            .collect();
 
-        let mut transaction_records: HashMap<usize, Vec<TransactionRecord>> = HashMap::new();
-        let active_file = self.active_file.read();
-        let older_files = self.older_files.read();
-        for (i, file_id) in self.file_ids.iter().enumerate() {
-            // 文件id小于unmerged_file_id，说明已经从hint索引文件中加载过索引，跳过
-            if has_merge && *file_id < unmerged_file_id {
-                continue;
+        let deadline = self
+            .options
+            .max_recovery_duration
+            .map(|duration| Instant::now() + duration);
+        let mut state = IndexLoadState {
+            skip_corrupt_files: self.options.skip_corrupt_files,
+            batch_updates: self.options.batch_index_load,
+            key_transform: self.options.key_transform.clone(),
+            load_keyspaces: self.options.load_keyspaces.clone(),
+            max_crc_errors: self.options.max_load_crc_errors,
+            dir_path: self.options.dir_path.clone(),
+            ..Default::default()
+        };
+        // 一致性检查需要包含已从hint文件或内联hint加载到索引中的key
+        if self.options.check_consistency_on_open {
+            let mut live_keys = HashMap::new();
+            let mut index_iter = self.index.iterator(IteratorOptions::default())?;
+            while let Some((key, _)) = index_iter.next() {
+                live_keys.insert(key.clone(), true);
+            }
+            state.live_keys = Some(live_keys);
+        }
+        // 延迟加载索引时，所有文件都交给后台线程按顺序加载，保证较新的记录覆盖较旧的记录
+        let loaded = match self.options.lazy_index {
+            true => 0,
+            false => load_index_from_files(
+                self.index.as_ref(),
+                &self.dead_bytes,
+                &self.active_file.read(),
+                &self.older_files.read(),
+                &file_ids,
+                &mut state,
+                deadline,
+            )?,
+        };
+        self.skipped_file_ids
+            .lock()
+            .append(&mut state.skipped_file_ids);
+        self.quarantined_records
+            .lock()
+            .append(&mut state.quarantined_records);
+        if loaded == file_ids.len() {
+            state.check_consistency(self.index.as_ref())?;
+            return Ok(state.current_seq_number);
+        }
+
+        // 超出恢复时间预算或延迟加载索引，剩余文件交给后台线程加载，加载完成前engine处于预热状态
+        let deferred_file_ids = file_ids[loaded..].to_vec();
+        if !self.options.lazy_index {
+            warn!(
+                "Recovery budget exceeded, loading {} data files in background",
+                deferred_file_ids.len()
+            );
+        }
+        self.deferred_file_ids = deferred_file_ids.clone();
+        self.index_warmup = Arc::new(IndexWarmup::default());
+        let index = self.index.clone();
+        let dead_bytes = self.dead_bytes.clone();
+        let active_file = self.active_file.clone();
+        let older_files = self.older_files.clone();
+        let sequence_number = self.sequence_number.clone();
+        let index_warmup = self.index_warmup.clone();
+        let skipped_file_ids = self.skipped_file_ids.clone();
+        let quarantined_records = self.quarantined_records.clone();
+        let dir_path = self.options.dir_path.clone();
+        let use_mmap = self.options.use_mmap;
+        let read_only = self.options.read_only;
+        let seq_number = state.current_seq_number;
+        self.background.spawn(move |_| {
+            let res = load_index_from_files(
+                index.as_ref(),
+                &dead_bytes,
+                &active_file.read(),
+                &older_files.read(),
+                &deferred_file_ids,
+                &mut state,
+                None,
+            )
+            .and_then(|_| state.check_consistency(index.as_ref()))
+            .and_then(|_| {
+                skipped_file_ids.lock().append(&mut state.skipped_file_ids);
+                quarantined_records
+                    .lock()
+                    .append(&mut state.quarantined_records);
+                if state.current_seq_number > NON_TRANSACTION_SEQ_NUMBER {
+                    sequence_number.fetch_max(
+                        state.current_seq_number + 1,
+                        std::sync::atomic::Ordering::SeqCst,
+                    );
+                }
+                // 加载数据文件后，恢复标准文件IO
+                match use_mmap {
+                    true => reset_io_type(&dir_path, &active_file, &older_files),
+                    false => Ok(()),
+                }
+            });
+            // 截断活跃文件末尾不完整的记录，加载时持有的读锁已释放
+            let res = res.and_then(|_| match read_only {
+                true => Ok(()),
+                false => {
+                    let active_file = active_file.write();
+                    truncate_torn_tail(&active_file, active_file.get_write_offset())
+                }
+            });
+            if let Err(e) = &res {
+                error!("Failed to load index in background: {}", e);
             }
+            index_warmup.finish(res);
+        });
+        Ok(seq_number)
+    }
+
+    /// 索引是否仍在后台加载中
+    pub fn is_warming(&self) -> bool {
+        !self.index_warmup.is_ready()
+    }
+
+    /// 索引是否已加载完成，加载完成后读取不再扫描数据文件
+    pub fn index_ready(&self) -> bool {
+        !self.is_warming()
+    }
+
+    /// 等待后台索引加载完成，返回加载结果
+    pub fn wait_index_ready(&self) -> Result<()> {
+        self.index_warmup.wait()
+    }
+
+    /// 预热期间按顺序扫描尚未加载到索引的数据文件，查找key的最新记录，key为变换后的key
+    ///
+    /// 每次调用都会读取所有延迟加载的文件，只用于预热期间的读取
+    pub(crate) fn get_from_deferred_files(&self, key: &[u8]) -> Result<Option<LogRecord>> {
+        // 未加载的keyspace中的key不会加入索引，加载完成前也不返回
+        if !key_in_keyspaces(self.options.load_keyspaces.as_ref(), key) {
+            return Ok(None);
+        }
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read().clone();
+        let mut latest = None;
+        let mut transaction_records: HashMap<usize, LogRecord> = HashMap::new();
+        let mut scratch = BytesMut::new();
+        for file_id in &self.deferred_file_ids {
+            let data_file = match *file_id == active_file.get_file_id() {
+                true => &*active_file,
+                false => older_files.get(file_id).unwrap(),
+            };
             let mut offset = 0;
             loop {
-                let read_record_res = match *file_id == active_file.get_file_id() {
-                    true => active_file.read_log_record(offset),
-                    false => {
-                        let data_file = older_files.get(file_id).unwrap();
-                        data_file.read_log_record(offset)
-                    }
-                };
-                // 读取记录，和记录在data file中的大小
-                // key: 事务序列号+key
-                let (mut record, record_size) = match read_record_res {
-                    Ok(v) => (v.record, v.size),
-                    Err(e) => {
-                        if e == Errors::ReadDataFileEof {
-                            // 读取到文件末尾，退出循环,读取下一个文件
-                            break;
-                        }
-                        return Err(e);
+                let (mut record, record_size) =
+                    match data_file.read_log_record_with_buf(offset, &mut scratch) {
+                        Ok(v) => (v.record, v.size),
+                        Err(Errors::ReadDataFileEof) => break,
+                        Err(e) => return Err(e),
+                    };
+                offset += record_size;
+                let (seq_number, real_key) = parse_record_sequence_number_with_key(&record.key);
+                if record.rec_type == LogRecordType::TxnFinished {
+                    // 事务提交后，事务中的记录才生效
+                    if let Some(txn_record) = transaction_records.remove(&seq_number) {
+                        latest = Some(txn_record);
                     }
-                };
-                // 记录的位置信息
-                let record_pos = LogRecordPos {
-                    file_id: *file_id,
-                    offset,
-                };
-
-                let (seq_number, key) = parse_record_sequence_number_with_key(&record.key);
-                if seq_number == NON_TRANSACTION_SEQ_NUMBER {
-                    // 非事务提交的记录，更新索引
-                    self.update_index(key, record.rec_type, record_pos)?;
-                } else {
-                    match record.rec_type {
-                        LogRecordType::TxnFinished => {
-                            // 事务结束记录，一次性更新该事务的所有记录的索引
-                            let transaction_records =
-                                transaction_records.remove(&seq_number).unwrap();
-                            for txn_record in transaction_records {
-                                self.update_index(
-                                    txn_record.record.key,
-                                    txn_record.record.rec_type,
-                                    txn_record.position,
-                                )?;
-                            }
-                        }
-                        _ => {
-                            // 去掉事务序列号
-                            record.key = key;
-                            // 根据事务序列号，插入对应的分组,将其暂存到内存，知道读到对应的TxnFinished记录，才将该组记录插入索引
-                            transaction_records.entry(seq_number).or_default().push(
-                                TransactionRecord {
-                                    record,
-                                    position: record_pos,
-                                },
-                            );
-                        }
+                    continue;
+                }
+                if self.index_key(&real_key) != key {
+                    continue;
+                }
+                record.key = real_key;
+                match seq_number == NON_TRANSACTION_SEQ_NUMBER {
+                    true => latest = Some(record),
+                    false => {
+                        transaction_records.insert(seq_number, record);
                     }
                 }
-                current_seq_number = current_seq_number.max(seq_number);
-                // 更新偏移量
-                offset += record_size;
-            }
-            // 如果是最后一个文件，更新活跃数据文件的偏移量
-            if i == self.file_ids.len() - 1 {
-                active_file.set_write_offset(offset);
             }
         }
-        Ok(current_seq_number)
-    }
-
-    fn update_index(
-        &self,
-        key: Vec<u8>,
-        rec_type: LogRecordType,
-        record_pos: LogRecordPos,
-    ) -> Result<()> {
-        // 根据记录类型，更新索引
-        if !match rec_type {
-            LogRecordType::Normal => self.index.put(key, record_pos),
-            LogRecordType::Deleted => self.index.delete(key),
-            LogRecordType::TxnFinished => true,
-        } {
-            return Err(Errors::FailedToUpdateIndex);
-        }
-        Ok(())
+        Ok(latest)
     }
 
     fn load_sequence_number_from_file(&self) -> (bool, usize) {
@@ -457,19 +1608,10 @@ impl Engine {
             .unwrap()
             .parse::<usize>()
             .unwrap();
-        std::fs::remove_file(file_name).unwrap();
-        (true, seq_number)
-    }
-
-    /// 重置io管理器类型为标准文件io
-    fn reset_io_type(&self) -> Result<()> {
-        let mut active_file = self.active_file.write();
-        active_file.set_io_manager(&self.options.dir_path, IOType::StandardFileIO)?;
-        let mut older_files = self.older_files.write();
-        for (_, file) in older_files.iter_mut() {
-            file.set_io_manager(&self.options.dir_path, IOType::StandardFileIO)?;
+        if !self.options.read_only {
+            std::fs::remove_file(file_name).unwrap();
         }
-        Ok(())
+        (true, seq_number)
     }
 }
 
@@ -481,133 +1623,965 @@ impl Drop for Engine {
     }
 }
 
-fn check_options(opts: &Options) -> Result<()> {
-    let dir_path = opts.dir_path.to_str();
-    if dir_path.is_none() || dir_path.unwrap().is_empty() {
-        return Err(Errors::DirPathIsEmpty);
+/// 记录所在的数据文件
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FileSource {
+    /// 活跃数据文件
+    Active,
+    /// 旧数据文件
+    Older,
+}
+
+/// Engine::get_debug返回的value及其元信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueDebug {
+    pub value: Bytes,
+    /// 记录在数据文件中的位置
+    pub position: LogRecordPos,
+    /// 文件中保存的记录CRC
+    pub crc: u32,
+    /// 记录所在的数据文件
+    pub source: FileSource,
+}
+
+/// Engine::get_status返回的key状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyStatus {
+    /// key存在，包含当前的value
+    Present(Bytes),
+    /// key最后一条记录是删除记录
+    Deleted,
+    /// key从未写入，或者删除记录已被merge清除
+    Absent,
+}
+
+/// get_cached缓存的value，以及读取时记录的位置和过期时间
+pub(crate) struct CachedValue {
+    pub(crate) position: LogRecordPos,
+    pub(crate) expire_at: Option<u64>,
+    pub(crate) value: Bytes,
+}
+
+/// 活跃数据文件中已写入删除记录的key，活跃文件切换后清空
+#[derive(Default)]
+pub(crate) struct ActiveTombstones {
+    file_id: u32,
+    keys: HashSet<Vec<u8>>,
+}
+
+impl ActiveTombstones {
+    /// key在file_id对应的活跃文件中是否已有删除记录
+    pub(crate) fn contains(&self, file_id: u32, key: &[u8]) -> bool {
+        self.file_id == file_id && self.keys.contains(key)
     }
-    if opts.data_file_size == 0 {
-        return Err(Errors::DataFileSizeIsTooSmall);
+
+    pub(crate) fn insert(&mut self, file_id: u32, key: Vec<u8>) {
+        if self.file_id != file_id {
+            self.file_id = file_id;
+            self.keys.clear();
+        }
+        self.keys.insert(key);
     }
-    Ok(())
 }
 
-fn load_data_files(dir_path: &Path, use_mmap: bool) -> Result<Vec<DataFile>> {
-    let d_entries = std::fs::read_dir(dir_path).map_err(|_| Errors::FailedToReadDatabaseDir)?;
-    let mut file_ids = Vec::new();
-    let mut data_files = Vec::new();
+/// 从数据文件加载索引的中间状态，后台加载时随剩余文件一起转移到后台线程
+#[derive(Default)]
+struct IndexLoadState {
+    /// 尚未读到TxnFinished记录的事务记录
+    transaction_records: HashMap<usize, Vec<TransactionRecord>>,
+    /// 最新的事务序列号
+    current_seq_number: usize,
+    /// 是否跳过无法读取的旧数据文件
+    skip_corrupt_files: bool,
+    /// 跳过的旧数据文件id
+    skipped_file_ids: Vec<u32>,
+    /// 是否将一个文件中的索引更新暂存，在文件结束时一次加锁批量完成
+    batch_updates: bool,
+    /// 暂存的索引更新，key已去掉事务序列号
+    pending_updates: Vec<(Vec<u8>, LogRecordType, LogRecordPos)>,
+    /// 更新索引的次数，每次逐条或批量更新都需要获取一次索引的锁
+    index_lock_count: usize,
+    /// 更新索引前对key做的变换
+    key_transform: Option<KeyTransform>,
+    /// 只加载这些keyspace中的key，None表示加载所有key
+    load_keyspaces: Option<Vec<String>>,
+    /// 最多容忍的CRC校验失败的记录数
+    max_crc_errors: usize,
+    /// 已跳过的CRC校验失败的记录数
+    crc_errors: usize,
+    /// 跳过的损坏记录，文件id和偏移
+    quarantined_records: Vec<(u32, u64)>,
+    /// 数据目录，跳过的损坏记录写入其中的quarantine文件
+    dir_path: PathBuf,
+    /// 开启一致性检查时记录每个key的最新记录是否有效，false表示最新记录是删除记录
+    live_keys: Option<HashMap<Vec<u8>, bool>>,
+}
 
-    for entry in d_entries {
-        let entry = entry.map_err(|_| Errors::FailedToGetDirEntry)?;
-        let file_name = entry.file_name();
-        let file_name = file_name.to_str().unwrap();
-        // 判断文件名是否以.data结尾
-        if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
-            // 0000.data
-            let split_names: Vec<&str> = file_name.split(".").collect();
-            let file_id = split_names[0].parse::<u32>()?;
-            file_ids.push(file_id);
+impl IndexLoadState {
+    /// 开启一致性检查时，比较索引中的key数与有效记录的key数，不一致说明有索引更新被遗漏，返回IndexCorrupt
+    fn check_consistency(&self, index: &dyn Indexer) -> Result<()> {
+        let Some(live_keys) = self.live_keys.as_ref() else {
+            return Ok(());
+        };
+        let live_records = live_keys.values().filter(|live| **live).count();
+        let index_entries = index.count_prefix(&[])?;
+        if index_entries != live_records {
+            error!(
+                "Index consistency check failed: {} index entries, {} live records",
+                index_entries, live_records
+            );
+            return Err(Errors::IndexCorrupt {
+                index_entries,
+                live_records,
+            });
         }
+        Ok(())
     }
 
-    file_ids.sort();
+    /// 跳过offset处CRC校验失败的记录，返回记录大小，无法确定记录大小或未开启容忍时返回None
+    ///
+    /// 跳过的记录数超过max_crc_errors时返回TooManyCorruptRecords
+    fn skip_corrupt_record(&mut self, data_file: &DataFile, offset: u64) -> Result<Option<u64>> {
+        if self.max_crc_errors == 0 {
+            return Ok(None);
+        }
+        let Some(size) = data_file.corrupt_record_size(offset) else {
+            return Ok(None);
+        };
+        self.crc_errors += 1;
+        if self.crc_errors > self.max_crc_errors {
+            error!(
+                "Found more than {} corrupt records while loading data files",
+                self.max_crc_errors
+            );
+            return Err(Errors::TooManyCorruptRecords);
+        }
+        let file_id = data_file.get_file_id();
+        warn!(
+            "Quarantining corrupt record in data file {} at offset {}, size {}",
+            file_id, offset, size
+        );
+        append_quarantine_log(&self.dir_path, file_id, offset, size)?;
+        self.quarantined_records.push((file_id, offset));
+        Ok(Some(size))
+    }
 
-    // 打开数据文件
-    for file_id in &file_ids {
-        let mut io_type = IOType::StandardFileIO;
-        if use_mmap {
-            io_type = IOType::MmapIO;
+    /// 更新索引，批量更新时先暂存，配置了内存索引预算时需要逐条检查，不批量更新
+    fn update_index(
+        &mut self,
+        index: &dyn Indexer,
+        dead_bytes: &RwLock<HashMap<u32, u64>>,
+        key: Vec<u8>,
+        rec_type: LogRecordType,
+        record_pos: LogRecordPos,
+    ) -> Result<()> {
+        let key = match rec_type {
+            LogRecordType::TxnFinished => key,
+            _ => transform_key(self.key_transform.as_ref(), key),
+        };
+        if rec_type != LogRecordType::TxnFinished
+            && !key_in_keyspaces(self.load_keyspaces.as_ref(), &key)
+        {
+            return Ok(());
+        }
+        if rec_type != LogRecordType::TxnFinished
+            && let Some(live_keys) = self.live_keys.as_mut()
+        {
+            live_keys.insert(key.clone(), rec_type == LogRecordType::Normal);
+        }
+        if self.batch_updates && index.memory_budget_remaining().is_none() {
+            self.pending_updates.push((key, rec_type, record_pos));
+            return Ok(());
+        }
+        self.index_lock_count += 1;
+        update_index(index, dead_bytes, key, rec_type, record_pos)
+    }
+
+    /// 在一次加锁中按顺序应用暂存的索引更新，并统计可回收的字节数
+    fn flush_updates(&mut self, index: &dyn Indexer, dead_bytes: &RwLock<HashMap<u32, u64>>) {
+        if self.pending_updates.is_empty() {
+            return;
+        }
+        self.index_lock_count += 1;
+        let mut records = Vec::with_capacity(self.pending_updates.len());
+        let mut updates = Vec::with_capacity(self.pending_updates.len());
+        for (key, rec_type, record_pos) in self.pending_updates.drain(..) {
+            records.push((rec_type, record_pos));
+            match rec_type {
+                LogRecordType::Normal => updates.push((key, Some(record_pos))),
+                LogRecordType::Deleted => updates.push((key, None)),
+                LogRecordType::TxnFinished => {}
+            }
+        }
+        let mut old_positions = index.update_batch(updates).into_iter();
+        let mut dead_bytes = dead_bytes.write();
+        for (rec_type, record_pos) in records {
+            // 与逐条更新相同，被覆盖或删除的旧记录，以及删除记录和事务完成记录本身都可以回收
+            if rec_type != LogRecordType::TxnFinished
+                && let Some(Some(old_pos)) = old_positions.next()
+            {
+                *dead_bytes.entry(old_pos.file_id).or_default() += old_pos.size as u64;
+            }
+            if rec_type != LogRecordType::Normal {
+                *dead_bytes.entry(record_pos.file_id).or_default() += record_pos.size as u64;
+            }
         }
-        let data_file = DataFile::new(dir_path, *file_id, io_type)?;
-        data_files.push(data_file);
     }
-    Ok(data_files)
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::{
-        options::IndexType,
-        util::rand_kv::{get_test_key, get_test_value},
-    };
+/// 后台加载索引的状态
+pub(crate) struct IndexWarmup {
+    /// None表示仍在加载中
+    result: Mutex<Option<Result<()>>>,
+    cond: Condvar,
+}
 
-    use super::*;
-    #[test]
-    fn test_db_put() {
-        let engine_opts = Options {
-            dir_path: std::env::temp_dir().join("test_db_put"),
-            data_file_size: 8 * 1024 * 1024,
-            sync_write: false,
-            bytes_per_sync: 1000000,
-            index_type: IndexType::BTree,
-            use_mmap: false,
-        };
-        let engine_dir = engine_opts.dir_path.clone();
+impl Default for IndexWarmup {
+    fn default() -> Self {
+        Self {
+            result: Mutex::new(None),
+            cond: Condvar::new(),
+        }
+    }
+}
 
-        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+impl IndexWarmup {
+    /// 索引已加载完成
+    fn ready() -> Self {
+        Self {
+            result: Mutex::new(Some(Ok(()))),
+            cond: Condvar::new(),
+        }
+    }
 
-        // 单条写入
-        engine
-            .put(get_test_key(1), get_test_value(2))
-            .expect("Failed to put data");
-        let get_res = engine.get(get_test_key(1));
-        assert!(get_res.is_ok());
-        assert_eq!(get_res.unwrap(), get_test_value(2));
+    fn is_ready(&self) -> bool {
+        self.result.lock().is_some()
+    }
 
-        // 重复写入key
-        engine
-            .put(get_test_key(2), get_test_value(2))
-            .expect("Failed to put data");
-        engine
-            .put(get_test_key(2), get_test_value(22))
-            .expect("Failed to put data");
-        let get_res = engine.get(get_test_key(2));
-        assert!(get_res.is_ok());
-        assert_eq!(get_res.unwrap(), get_test_value(22));
+    fn finish(&self, res: Result<()>) {
+        *self.result.lock() = Some(res);
+        self.cond.notify_all();
+    }
 
-        // key为空
-        let put_res = engine.put(Bytes::new(), get_test_value(3));
-        assert_eq!(put_res, Err(Errors::KeyIsEmpty));
+    fn wait(&self) -> Result<()> {
+        let mut result = self.result.lock();
+        while result.is_none() {
+            self.cond.wait(&mut result);
+        }
+        result.clone().unwrap()
+    }
+}
 
-        // value为空
-        engine
-            .put(get_test_key(3), Bytes::new())
-            .expect("Failed to put data");
-        let get_res = engine.get(get_test_key(3));
-        assert!(get_res.is_ok());
-        assert!(get_res.unwrap().is_empty());
+/// 后台线程共享的停止信号，以及需要在关闭时等待退出的线程句柄
+#[derive(Default)]
+pub(crate) struct BackgroundTasks {
+    stopped: Mutex<bool>,
+    cond: Condvar,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
 
-        // 写入大量数据
-        for i in 0..1000000 {
-            let put_res = engine.put(get_test_key(i), get_test_value(i));
-            assert!(put_res.is_ok());
+impl BackgroundTasks {
+    /// 启动后台线程，线程内通过wait_stop或is_stopped感知停止信号
+    fn spawn<F>(self: &Arc<Self>, f: F)
+    where
+        F: FnOnce(Arc<BackgroundTasks>) + Send + 'static,
+    {
+        let background = self.clone();
+        let handle = std::thread::spawn(move || f(background));
+        self.handles.lock().push(handle);
+    }
+
+    /// 等待timeout或停止信号，返回是否已停止
+    fn wait_stop(&self, timeout: Duration) -> bool {
+        let mut stopped = self.stopped.lock();
+        if !*stopped {
+            self.cond.wait_for(&mut stopped, timeout);
         }
+        *stopped
+    }
 
-        // 重启数据库
-        std::mem::drop(engine);
-        let engine = Engine::open(engine_opts).expect("Failed to open engine");
-        engine
-            .put(get_test_key(33), get_test_value(33))
-            .expect("Failed to put data");
-        let get_res = engine.get(get_test_key(33));
-        assert!(get_res.is_ok());
-        assert_eq!(get_res.unwrap(), get_test_value(33));
-        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    fn is_stopped(&self) -> bool {
+        *self.stopped.lock()
     }
 
-    #[test]
-    fn test_db_get() {
-        let engine_opts = Options {
-            dir_path: std::env::temp_dir().join("test_db_get"),
-            data_file_size: 8 * 1024 * 1024,
-            sync_write: false,
-            bytes_per_sync: 1000000,
-            index_type: IndexType::BTree,
-            use_mmap: false,
-        };
-        let engine_dir = engine_opts.dir_path.clone();
+    /// 发出停止信号并等待所有后台线程退出
+    fn shutdown(&self) {
+        *self.stopped.lock() = true;
+        self.cond.notify_all();
+        let handles = std::mem::take(&mut *self.handles.lock());
+        for handle in handles {
+            if handle.join().is_err() {
+                error!("Background thread panicked");
+            }
+        }
+    }
+}
 
-        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+/// 按顺序加载数据文件中的记录到索引，返回已加载完成的文件数
+///
+/// 超过deadline且没有未完成的事务时停止，剩余文件可以带着state继续加载
+fn load_index_from_files(
+    index: &dyn Indexer,
+    dead_bytes: &RwLock<HashMap<u32, u64>>,
+    active_file: &DataFile,
+    older_files: &OlderFiles,
+    file_ids: &[u32],
+    state: &mut IndexLoadState,
+    deadline: Option<Instant>,
+) -> Result<usize> {
+    // 顺序扫描时复用同一块读缓冲区
+    let mut scratch = BytesMut::new();
+    for (i, file_id) in file_ids.iter().enumerate() {
+        // 超出时间预算，在文件边界处停止，事务跨文件时继续加载直到事务结束
+        if let Some(deadline) = deadline
+            && i > 0
+            && state.transaction_records.is_empty()
+            && Instant::now() > deadline
+        {
+            return Ok(i);
+        }
+        let is_active_file = *file_id == active_file.get_file_id();
+        let Some(data_file) = (match is_active_file {
+            true => Some(active_file),
+            false => older_files.get(file_id).map(Arc::as_ref),
+        }) else {
+            // 打开时已跳过的文件
+            continue;
+        };
+        let mut offset = 0;
+        loop {
+            // 读取记录，和记录在data file中的大小
+            // key: 事务序列号+key
+            let read_record = data_file.read_log_record_with_buf(offset, &mut scratch);
+            // CRC校验失败的记录在容忍范围内时跳过
+            if let Err(Errors::InvalidLogRecordCrc) = read_record
+                && let Some(size) = state.skip_corrupt_record(data_file, offset)?
+            {
+                offset += size;
+                continue;
+            }
+            let (mut record, record_size) = match read_record {
+                Ok(v) => (v.record, v.size),
+                // 读取到文件末尾，退出循环,读取下一个文件
+                Err(Errors::ReadDataFileEof) => break,
+                // 活跃文件末尾写入中断留下的不完整记录，打开时会被截断
+                Err(Errors::InvalidLogRecordCrc | Errors::InvalidLogRecordLength)
+                    if is_active_file && data_file.record_past_eof(offset) =>
+                {
+                    break;
+                }
+                // 旧数据文件损坏时跳过剩余部分，已读取的记录仍然有效，
+                // 更新版本写入的记录不是损坏，不能跳过
+                Err(e)
+                    if state.skip_corrupt_files
+                        && !is_active_file
+                        && !matches!(e, Errors::UnsupportedRecordVersion { .. }) =>
+                {
+                    warn!(
+                        "Skipping corrupt data file {} at offset {}: {}",
+                        file_id, offset, e
+                    );
+                    state.skipped_file_ids.push(*file_id);
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            // 记录的位置信息
+            let record_pos = LogRecordPos {
+                file_id: *file_id,
+                offset,
+                size: record_size as u32,
+            };
+
+            let (seq_number, key) = parse_record_sequence_number_with_key(&record.key);
+            if seq_number == NON_TRANSACTION_SEQ_NUMBER {
+                // 非事务提交的记录，更新索引，已过期的记录与删除记录一样从索引中移除key
+                let rec_type = match record.is_expired() {
+                    true => LogRecordType::Deleted,
+                    false => record.rec_type,
+                };
+                state.update_index(index, dead_bytes, key, rec_type, record_pos)?;
+            } else {
+                match record.rec_type {
+                    LogRecordType::TxnFinished => {
+                        // 事务结束记录，一次性更新该事务的所有记录的索引
+                        // 部分merge会保留事务结束记录，其对应的记录可能已被改写为非事务记录
+                        let transaction_records = state
+                            .transaction_records
+                            .remove(&seq_number)
+                            .unwrap_or_default();
+                        state.update_index(index, dead_bytes, key, record.rec_type, record_pos)?;
+                        for txn_record in transaction_records {
+                            state.update_index(
+                                index,
+                                dead_bytes,
+                                txn_record.record.key,
+                                txn_record.record.rec_type,
+                                txn_record.position,
+                            )?;
+                        }
+                    }
+                    _ => {
+                        // 去掉事务序列号
+                        record.key = key;
+                        // 根据事务序列号，插入对应的分组,将其暂存到内存，知道读到对应的TxnFinished记录，才将该组记录插入索引
+                        state
+                            .transaction_records
+                            .entry(seq_number)
+                            .or_default()
+                            .push(TransactionRecord {
+                                record,
+                                position: record_pos,
+                            });
+                    }
+                }
+            }
+            state.current_seq_number = state.current_seq_number.max(seq_number);
+            // 更新偏移量
+            offset += record_size;
+        }
+        state.flush_updates(index, dead_bytes);
+        // 如果是活跃数据文件，更新活跃数据文件的偏移量
+        if *file_id == active_file.get_file_id() {
+            active_file.set_write_offset(offset);
+        }
+    }
+    Ok(file_ids.len())
+}
+
+/// 从活跃文件或旧数据文件中读取指定位置的记录，同时返回记录所在的文件
+///
+/// 读取旧数据文件时只持有文件映射的快照，不持有活跃文件和旧数据文件的锁
+fn read_record_at(
+    active_file: &RwLock<DataFile>,
+    older_files: &RwLock<Arc<OlderFiles>>,
+    position: &LogRecordPos,
+    scratch: &mut BytesMut,
+) -> Result<(ReadLogRecord, FileSource)> {
+    {
+        let active_file = active_file.read();
+        if active_file.get_file_id() == position.file_id {
+            return Ok((
+                read_record_in(&active_file, position, scratch)?,
+                FileSource::Active,
+            ));
+        }
+    }
+    // 活跃文件切换时先加入旧数据文件再替换活跃文件，释放活跃文件的锁之后仍能在快照中找到
+    let older_files = older_files.read().clone();
+    let Some(data_file) = older_files.get(&position.file_id) else {
+        return Err(Errors::DataFileNotFound);
+    };
+    Ok((
+        read_record_in(data_file, position, scratch)?,
+        FileSource::Older,
+    ))
+}
+
+/// 读取数据文件中指定位置的记录
+fn read_record_in(
+    data_file: &DataFile,
+    position: &LogRecordPos,
+    scratch: &mut BytesMut,
+) -> Result<ReadLogRecord> {
+    // 索引中的位置超出文件范围，说明索引和数据文件不一致
+    let size = data_file.file_size();
+    if position.offset >= size {
+        return Err(Errors::PositionOutOfBounds {
+            file_id: position.file_id,
+            offset: position.offset,
+            size,
+        });
+    }
+    data_file.read_log_record_with_buf(position.offset, scratch)
+}
+
+/// 开启verify_key_on_read时，校验记录去掉序列号并变换后的key与请求的key一致
+fn verify_record_key(options: &Options, record: &LogRecord, key: &[u8]) -> Result<()> {
+    if !options.verify_key_on_read {
+        return Ok(());
+    }
+    let (_, real_key) = parse_record_sequence_number_with_key(&record.key);
+    match transform_key(options.key_transform.as_ref(), real_key) == key {
+        true => Ok(()),
+        false => Err(Errors::KeyMismatch),
+    }
+}
+
+/// 使用配置的编解码器解码记录的value，编码时使用的编解码器id与配置的不一致时返回错误
+fn decode_record_value(options: &Options, record: LogRecord) -> Result<Vec<u8>> {
+    if !record.value_encoded {
+        return Ok(record.value);
+    }
+    let Some((&id, value)) = record.value.split_first() else {
+        return Err(Errors::ValueDecodeError("missing codec id".to_string()));
+    };
+    match &options.value_codec {
+        Some(codec) if codec.id() == id => codec.decode(value),
+        _ => Err(Errors::UnknownValueCodec(id)),
+    }
+}
+
+/// key是否属于keyspaces中的某个keyspace，即以其名字为前缀，keyspaces为None时属于所有keyspace
+pub(crate) fn key_in_keyspaces(keyspaces: Option<&Vec<String>>, key: &[u8]) -> bool {
+    keyspaces.is_none_or(|keyspaces| {
+        keyspaces
+            .iter()
+            .any(|keyspace| key.starts_with(keyspace.as_bytes()))
+    })
+}
+
+/// 配置了key变换时返回变换后的key，否则原样返回
+pub(crate) fn transform_key(transform: Option<&KeyTransform>, key: Vec<u8>) -> Vec<u8> {
+    match transform {
+        Some(transform) => transform(&key),
+        None => key,
+    }
+}
+
+/// 根据记录类型更新索引，并统计可回收的字节数
+pub(crate) fn update_index(
+    index: &dyn Indexer,
+    dead_bytes: &RwLock<HashMap<u32, u64>>,
+    key: Vec<u8>,
+    rec_type: LogRecordType,
+    record_pos: LogRecordPos,
+) -> Result<()> {
+    let old_pos = match rec_type {
+        LogRecordType::TxnFinished => None,
+        _ => index.get(key.clone()),
+    };
+    if rec_type == LogRecordType::Normal && old_pos.is_none() {
+        check_index_budget(index, [key.as_slice()])?;
+    }
+    // 根据记录类型，更新索引，部分merge回收了key的旧value时删除记录对应的key可能不在索引中
+    if !match rec_type {
+        LogRecordType::Normal => index.put(key, record_pos),
+        LogRecordType::Deleted => old_pos.is_none() || index.delete(key),
+        LogRecordType::TxnFinished => true,
+    } {
+        return Err(Errors::FailedToUpdateIndex);
+    }
+    // 被覆盖或删除的旧记录，以及删除记录和事务完成记录本身，merge时都可以回收
+    let mut dead_bytes = dead_bytes.write();
+    if let Some(old_pos) = old_pos {
+        *dead_bytes.entry(old_pos.file_id).or_default() += old_pos.size as u64;
+    }
+    if rec_type != LogRecordType::Normal {
+        *dead_bytes.entry(record_pos.file_id).or_default() += record_pos.size as u64;
+    }
+    Ok(())
+}
+
+/// 重置io管理器类型为标准文件io
+fn reset_io_type(
+    dir_path: &Path,
+    active_file: &RwLock<DataFile>,
+    older_files: &RwLock<Arc<OlderFiles>>,
+) -> Result<()> {
+    let mut active_file = active_file.write();
+    active_file.set_io_manager(dir_path, IOType::StandardFileIO)?;
+    // 正在读取的快照仍使用原来的文件对象，读取完成后释放
+    let mut older_files = older_files.write();
+    let reopened = older_files
+        .values()
+        .map(|file| {
+            let reopened = file.reopen(dir_path, IOType::StandardFileIO)?;
+            Ok((file.get_file_id(), Arc::new(reopened)))
+        })
+        .collect::<Result<OlderFiles>>()?;
+    *older_files = Arc::new(reopened);
+    Ok(())
+}
+
+/// 截断活跃文件中write_offset之后写入中断留下的不完整记录，write_offset为最后一条完整记录的结束位置
+fn truncate_torn_tail(active_file: &DataFile, write_offset: u64) -> Result<()> {
+    let file_size = active_file.file_size();
+    if write_offset >= file_size {
+        active_file.set_write_offset(file_size);
+        return Ok(());
+    }
+    warn!(
+        "Truncating torn tail of data file {} from {} to {}",
+        active_file.get_file_id(),
+        file_size,
+        write_offset
+    );
+    active_file.truncate(write_offset)
+}
+
+/// 从头扫描数据文件，返回最后一条完整记录的结束位置
+///
+/// 记录是变长的，只能顺序解析才能确定记录边界，只有超出文件末尾的最后一条记录视为未写完的记录。
+/// 文件中间的损坏记录在容忍CRC错误时跳过，否则与IO错误、更新版本写入的记录一样返回错误而不截断，
+/// 避免丢失之后的有效记录
+fn last_valid_offset(data_file: &DataFile, skip_corrupt_records: bool) -> Result<u64> {
+    let file_size = data_file.file_size();
+    let mut scratch = BytesMut::new();
+    let mut offset = 0;
+    while offset < file_size {
+        match data_file.read_log_record_with_buf(offset, &mut scratch) {
+            Ok(read_record) => offset += read_record.size,
+            Err(
+                e @ (Errors::ReadDataFileEof
+                | Errors::InvalidLogRecordCrc
+                | Errors::InvalidLogRecordLength),
+            ) => {
+                // 超出文件末尾的记录，或者mmap预留的写入空间未截断时剩余的全0部分
+                if data_file.record_past_eof(offset)
+                    || (e == Errors::ReadDataFileEof && data_file.is_zero_tail(offset))
+                {
+                    break;
+                }
+                match data_file.corrupt_record_size(offset) {
+                    Some(size) if skip_corrupt_records => offset += size,
+                    // 记录长度都为0时按文件末尾处理，文件中间出现时说明文件损坏
+                    _ => match e {
+                        Errors::ReadDataFileEof => return Err(Errors::InvalidLogRecordCrc),
+                        e => return Err(e),
+                    },
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(offset)
+}
+
+/// 打开数据目录得到的文件锁和数据文件等
+#[derive(Default)]
+struct OpenedDir {
+    is_first_load: bool,
+    lock_file: Option<File>,
+    last_merge_stat: Option<MergeStat>,
+    data_files: Vec<DataFile>,
+    skipped_file_ids: Vec<u32>,
+}
+
+/// 创建或检查数据目录：加文件锁，处理B+Tree索引文件、格式版本和未完成的merge，打开所有数据文件
+fn open_dir(opts: &Options) -> Result<OpenedDir> {
+    let dir_path = opts.dir_path.as_path();
+    let mut is_first_load = false;
+    // 只读打开时不创建目录
+    if opts.read_only && !dir_path.is_dir() {
+        return Err(Errors::FailedToReadDatabaseDir);
+    }
+    if !dir_path.is_dir() {
+        // println!(
+        //     "Database dir not found, creating dir: {}",
+        //     dir_path.display()
+        // );
+        is_first_load = true;
+        std::fs::create_dir_all(dir_path).map_err(|e| {
+            warn!("Failed to create database dir: {}", e);
+            Errors::FailedToCreateDatabaseDir
+        })?;
+    }
+
+    // 判断db目录是否正被使用中，只读打开时目录不可写则不加锁
+    let lock_file = match lock_dir(dir_path) {
+        Err(Errors::FailedToCreateFileLock) if opts.read_only => {
+            warn!("Opening read-only database without file lock");
+            None
+        }
+        res => Some(res?),
+    };
+
+    // 目录中有B+Tree索引文件，却以其他索引类型打开，通常是配置错误
+    // 迁移时从数据文件重建索引，并删除之后不再更新的B+Tree索引文件；只读打开时不修改目录
+    // 以B+Tree打开没有索引文件的目录时会从数据文件重建索引（如repair之后），不视为不一致
+    let bptree_index_file = dir_path.join(BPTREE_INDEX_FILE_NAME);
+    if opts.index_type != IndexType::BPlusTree && bptree_index_file.is_file() {
+        if !opts.migrate_index && !opts.read_only {
+            return Err(Errors::IndexTypeMismatch);
+        }
+        if !opts.read_only {
+            remove_file_if_exists(&bptree_index_file)?;
+            remove_file_if_exists(&dir_path.join(BPTREE_DIRTY_FILE_NAME))?;
+        }
+    }
+    // 暂存的B+Tree索引更新提交前崩溃，索引文件落后于数据文件，删除后从数据文件重建
+    let bptree_dirty_file = dir_path.join(BPTREE_DIRTY_FILE_NAME);
+    if opts.index_type == IndexType::BPlusTree && bptree_dirty_file.is_file() {
+        warn!("B+Tree index was not flushed before shutdown, rebuilding from data files");
+        remove_file_if_exists(&bptree_index_file)?;
+        remove_file_if_exists(&bptree_dirty_file)?;
+    }
+
+    // 目录中没有数据文件、hint索引和B+Tree索引时也认为是首次加载，文件锁和无关文件不计入
+    let entries = std::fs::read_dir(dir_path).expect("Failed to read database dir");
+    if !entries
+        .filter_map(|e| e.ok())
+        .any(|e| is_db_file(&e.file_name().to_string_lossy()))
+    {
+        is_first_load = true;
+    }
+
+    // 校验数据库格式版本，首次创建时写入，只读打开时没有版本文件则跳过
+    if !opts.read_only || dir_path.join(VERSION_FILE_NAME).is_file() {
+        check_version(dir_path, opts.read_only)?;
+    }
+
+    // 加载merge目录,删除已merge的数据文件，将已merge的数据文件移动到当前db
+    // 只读打开时不修改目录，未加载的merge结果不影响原数据文件
+    if !opts.read_only {
+        load_merge_files(dir_path)?;
+    }
+    let last_merge_stat = load_merge_stat(dir_path);
+
+    let (data_files, skipped_file_ids) =
+        load_data_files(dir_path, opts.use_mmap, opts.skip_corrupt_files)?;
+    Ok(OpenedDir {
+        is_first_load,
+        lock_file,
+        last_merge_stat,
+        data_files,
+        skipped_file_ids,
+    })
+}
+
+/// 校验目录中记录的数据库格式版本和记录格式版本，版本文件不存在时写入当前版本
+///
+/// 版本高于当前版本时无法读取，返回错误；低于当前版本时可以读取，非只读打开时升级为当前版本，
+/// 之后写入的记录可能使用较低版本无法解析的格式
+fn check_version(dir_path: &Path, read_only: bool) -> Result<()> {
+    let exists = dir_path.join(VERSION_FILE_NAME).is_file();
+    let version_file = DataFile::new_version_file(dir_path)?;
+    if !exists {
+        return write_version_file(&version_file);
+    }
+    let record = version_file.read_log_record(0)?;
+    let found = String::from_utf8_lossy(&record.record.value).parse::<u32>()?;
+    if found > DB_FORMAT_VERSION {
+        return Err(Errors::IncompatibleVersion {
+            found,
+            expected: DB_FORMAT_VERSION,
+        });
+    }
+    // 版本1的版本文件中没有记录格式版本，当时的记录版本为0
+    let found_record_version = match version_file.read_log_record(record.size) {
+        Ok(record) => String::from_utf8_lossy(&record.record.value).parse::<u8>()?,
+        Err(Errors::ReadDataFileEof) => 0,
+        Err(e) => return Err(e),
+    };
+    if found_record_version > RECORD_FORMAT_VERSION {
+        return Err(Errors::UnsupportedRecordVersion {
+            version: found_record_version,
+        });
+    }
+    // 高于当前版本时已返回错误，不相等说明是较低版本
+    if !read_only && (found, found_record_version) != (DB_FORMAT_VERSION, RECORD_FORMAT_VERSION) {
+        return write_version_file(&version_file);
+    }
+    Ok(())
+}
+
+/// 写入当前的数据库格式版本和记录格式版本，覆盖版本文件原有的内容
+fn write_version_file(version_file: &DataFile) -> Result<()> {
+    version_file.truncate(0)?;
+    for (key, value) in [
+        (VERSION_KEY, DB_FORMAT_VERSION.to_string()),
+        (RECORD_VERSION_KEY, RECORD_FORMAT_VERSION.to_string()),
+    ] {
+        let record = LogRecord {
+            key: key.as_bytes().to_vec(),
+            value: value.into_bytes(),
+            rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
+        };
+        version_file.write(&record.encode())?;
+    }
+    version_file.sync()
+}
+
+/// 将加载时跳过的损坏记录追加到数据目录下的quarantine文件，每行为文件id、偏移和记录大小
+fn append_quarantine_log(dir_path: &Path, file_id: u32, offset: u64, size: u64) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir_path.join(QUARANTINE_FILE_NAME))
+        .map_err(|e| {
+            error!("Failed to open quarantine file: {}", e);
+            Errors::OpenFileError
+        })?;
+    writeln!(file, "{} {} {}", file_id, offset, size).map_err(|e| {
+        error!("Failed to write quarantine file: {}", e);
+        Errors::WriteToDataFileError
+    })
+}
+
+/// 是否是存有db数据的文件，用于判断是否首次加载，未知文件会记录日志后忽略
+fn is_db_file(file_name: &str) -> bool {
+    if file_name.ends_with(DATA_FILE_NAME_SUFFIX)
+        || file_name == HINT_FILE_NAME
+        || file_name == BPTREE_INDEX_FILE_NAME
+    {
+        return true;
+    }
+    if ![
+        FILE_LOCK_NAME,
+        BPTREE_DIRTY_FILE_NAME,
+        HINT_TMP_FILE_NAME,
+        INLINE_HINTS_FILE_NAME,
+        MERGE_FINISHED_FILE_NAME,
+        QUARANTINE_FILE_NAME,
+        SEQUENCE_NUMBER_FILE_NAME,
+        VERSION_FILE_NAME,
+    ]
+    .contains(&file_name)
+    {
+        warn!("Ignoring unknown file in database dir: {}", file_name);
+    }
+    false
+}
+
+/// 打开或创建文件锁，保证在db目录只有一个使用者
+fn lock_dir(dir_path: &Path) -> Result<File> {
+    let lock_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dir_path.join(FILE_LOCK_NAME))
+        .map_err(|e| {
+            warn!("Failed to create file lock: {}", e);
+            Errors::FailedToCreateFileLock
+        })?;
+    if lock_file.try_lock_exclusive().is_err() {
+        return Err(Errors::DatabaseIsUsing);
+    }
+    Ok(lock_file)
+}
+
+/// 新建数据文件使用的IO类型，纯内存模式只在内存中保存数据
+fn data_file_io_type(opts: &Options) -> IOType {
+    match opts.in_memory {
+        true => IOType::MemIO,
+        false => IOType::StandardFileIO,
+    }
+}
+
+fn check_options(opts: &Options) -> Result<()> {
+    let dir_path = opts.dir_path.to_str();
+    if !opts.in_memory && (dir_path.is_none() || dir_path.unwrap().is_empty()) {
+        return Err(Errors::DirPathIsEmpty);
+    }
+    if opts.data_file_size == 0 {
+        return Err(Errors::DataFileSizeIsTooSmall);
+    }
+    // 纯内存模式不读写磁盘，B+Tree索引、操作日志等依赖文件的配置都不支持
+    if opts.in_memory
+        && (opts.index_type == IndexType::BPlusTree
+            || opts.read_only
+            || opts.ops_journal.is_some()
+            || opts.min_free_bytes.is_some()
+            || opts.compact_on_open.is_some())
+    {
+        return Err(Errors::InMemoryUnsupported);
+    }
+    // 路径已存在但不是目录，例如误指向了某个数据文件
+    if !opts.in_memory && opts.dir_path.exists() && !opts.dir_path.is_dir() {
+        return Err(Errors::DirPathNotADirectory);
+    }
+    // B+Tree索引本身持久化在文件中
+    if opts.index_only_in_memory && opts.index_type == IndexType::BPlusTree {
+        return Err(Errors::IndexOnlyInMemoryUnsupported);
+    }
+    if opts.read_only && opts.index_type == IndexType::BPlusTree {
+        return Err(Errors::ReadOnlyUnsupported);
+    }
+    // B+Tree索引持久化了所有key，merge需要完整的索引
+    if opts.load_keyspaces.is_some()
+        && (opts.index_type == IndexType::BPlusTree || opts.compact_on_open.is_some())
+    {
+        return Err(Errors::PartialIndexUnsupported);
+    }
+    if !(0.0..=1.0).contains(&opts.data_file_merge_ratio) {
+        return Err(Errors::InvalidMergeRatio);
+    }
+    if opts.sort_on_merge && opts.merge_recent_first {
+        return Err(Errors::ConflictingMergeOrder);
+    }
+    Ok(())
+}
+
+/// 打开目录中的所有数据文件，返回数据文件和因无法打开而跳过的文件id
+///
+/// 只跳过旧数据文件，最新的数据文件会作为活跃文件继续写入，无法打开时仍然报错
+fn load_data_files(
+    dir_path: &Path,
+    use_mmap: bool,
+    skip_corrupt_files: bool,
+) -> Result<(Vec<DataFile>, Vec<u32>)> {
+    let inline_hints_file_id = inline_hints_file_id(dir_path);
+    let d_entries = std::fs::read_dir(dir_path).map_err(|_| Errors::FailedToReadDatabaseDir)?;
+    let mut file_ids = Vec::new();
+    let mut data_files = Vec::new();
+
+    for entry in d_entries {
+        let entry = entry.map_err(|_| Errors::FailedToGetDirEntry)?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_str().unwrap();
+        // 判断文件名是否以.data结尾
+        if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
+            // 0000.data
+            let split_names: Vec<&str> = file_name.split(".").collect();
+            let file_id = split_names[0].parse::<u32>()?;
+            file_ids.push(file_id);
+        }
+    }
+
+    file_ids.sort();
+
+    // 打开数据文件
+    let mut skipped_file_ids = Vec::new();
+    for (i, file_id) in file_ids.iter().enumerate() {
+        let mut io_type = IOType::StandardFileIO;
+        if use_mmap {
+            io_type = IOType::MmapIO;
+        }
+        // 只有merge时写入了内联hint的文件需要读取文件尾部
+        let data_file = DataFile::new(dir_path, *file_id, io_type).and_then(|mut data_file| {
+            if inline_hints_file_id.is_some_and(|id| *file_id < id) {
+                data_file.load_inline_hints()?;
+            }
+            Ok(data_file)
+        });
+        match data_file {
+            Ok(data_file) => data_files.push(data_file),
+            Err(e) if skip_corrupt_files && i + 1 < file_ids.len() => {
+                warn!("Skipping unreadable data file {}: {}", file_id, e);
+                skipped_file_ids.push(*file_id);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((data_files, skipped_file_ids))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data::log_record::RECORD_VERSION_SHIFT,
+        options::{IndexType, WriteBatchOptions},
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+    #[test]
+    fn test_db_put() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_put"),
+            data_file_size: 8 * 1024 * 1024,
+            sync_write: false,
+            bytes_per_sync: 1000000,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
 
         // 单条写入
         engine
@@ -628,32 +2602,25 @@ mod tests {
         assert!(get_res.is_ok());
         assert_eq!(get_res.unwrap(), get_test_value(22));
 
-        // 获取不存在的key
-        let get_res = engine.get(get_test_key(3));
-        assert_eq!(get_res, Err(Errors::KeyNotFound));
+        // key为空
+        let put_res = engine.put(Bytes::new(), get_test_value(3));
+        assert_eq!(put_res, Err(Errors::KeyIsEmpty));
 
-        // 删除后获取
+        // value为空
         engine
-            .delete(get_test_key(2))
-            .expect("Failed to delete data");
-        let get_res = engine.get(get_test_key(2));
-        assert_eq!(get_res, Err(Errors::KeyNotFound));
+            .put(get_test_key(3), Bytes::new())
+            .expect("Failed to put data");
+        let get_res = engine.get(get_test_key(3));
+        assert!(get_res.is_ok());
+        assert!(get_res.unwrap().is_empty());
 
         // 写入大量数据
-        for i in 10..1000000 {
+        for i in 0..1000000 {
             let put_res = engine.put(get_test_key(i), get_test_value(i));
             assert!(put_res.is_ok());
         }
-        // 从旧数据文件获取
-        let get_res = engine.get(get_test_key(100));
-        assert!(get_res.is_ok());
-        assert_eq!(get_res.unwrap(), get_test_value(100));
-        // 从活跃数据文件获取
-        let get_res = engine.get(get_test_key(999999));
-        assert!(get_res.is_ok());
-        assert_eq!(get_res.unwrap(), get_test_value(999999));
 
-        // 重启数据库后，获取
+        // 重启数据库
         std::mem::drop(engine);
         let engine = Engine::open(engine_opts).expect("Failed to open engine");
         engine
@@ -662,61 +2629,391 @@ mod tests {
         let get_res = engine.get(get_test_key(33));
         assert!(get_res.is_ok());
         assert_eq!(get_res.unwrap(), get_test_value(33));
-
-        // 从旧数据文件获取
-        let get_res = engine.get(get_test_key(100));
-        assert!(get_res.is_ok());
-        assert_eq!(get_res.unwrap(), get_test_value(100));
-        // 从活跃数据文件获取
-        let get_res = engine.get(get_test_key(999999));
-        assert!(get_res.is_ok());
-        assert_eq!(get_res.unwrap(), get_test_value(999999));
-
         std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
     }
 
     #[test]
-    fn test_db_delete() {
+    fn test_db_put_skiplist() {
         let engine_opts = Options {
-            dir_path: std::env::temp_dir().join("test_db_delete"),
+            dir_path: std::env::temp_dir().join("test_db_put_skiplist"),
             data_file_size: 8 * 1024 * 1024,
             sync_write: false,
             bytes_per_sync: 1000000,
-            index_type: IndexType::BTree,
+            index_type: IndexType::SkipList,
             use_mmap: false,
+            ..Default::default()
         };
         let engine_dir = engine_opts.dir_path.clone();
 
         let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
 
-        // 正常删除
+        // 单条写入
         engine
             .put(get_test_key(1), get_test_value(2))
             .expect("Failed to put data");
-        let del_res = engine.delete(get_test_key(1));
-        assert!(del_res.is_ok());
-        let get_res = engine.get(get_test_key(1));
-        assert_eq!(get_res, Err(Errors::KeyNotFound));
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(2)));
 
-        // 删除不存在的key
-        let del_res = engine.delete(get_test_key(2));
-        assert_eq!(del_res, Err(Errors::KeyNotFound));
+        // 重复写入key
+        engine
+            .put(get_test_key(2), get_test_value(2))
+            .expect("Failed to put data");
+        engine
+            .put(get_test_key(2), get_test_value(22))
+            .expect("Failed to put data");
+        assert_eq!(engine.get(get_test_key(2)), Ok(get_test_value(22)));
 
-        // 删除空key
-        let del_res = engine.delete(Bytes::new());
-        assert_eq!(del_res, Err(Errors::KeyIsEmpty));
+        // key为空
+        let put_res = engine.put(Bytes::new(), get_test_value(3));
+        assert_eq!(put_res, Err(Errors::KeyIsEmpty));
+
+        // value为空
+        engine
+            .put(get_test_key(3), Bytes::new())
+            .expect("Failed to put data");
+        assert_eq!(engine.get(get_test_key(3)), Ok(Bytes::new()));
 
         // 写入大量数据
-        for i in 10..1000000 {
-            let put_res = engine.put(get_test_key(i), get_test_value(i));
-            assert!(put_res.is_ok());
+        for i in 10..100000 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
         }
 
-        // 重启数据库后，删除
+        // 重启数据库，从数据文件重建跳表索引
         std::mem::drop(engine);
         let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(2)), Ok(get_test_value(22)));
+        for i in 10..100000 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
         engine
-            .delete(get_test_key(100))
+            .put(get_test_key(33), get_test_value(33))
+            .expect("Failed to put data");
+        assert_eq!(engine.get(get_test_key(33)), Ok(get_test_value(33)));
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_get() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_get"),
+            data_file_size: 8 * 1024 * 1024,
+            sync_write: false,
+            bytes_per_sync: 1000000,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+
+        // 单条写入
+        engine
+            .put(get_test_key(1), get_test_value(2))
+            .expect("Failed to put data");
+        let get_res = engine.get(get_test_key(1));
+        assert!(get_res.is_ok());
+        assert_eq!(get_res.unwrap(), get_test_value(2));
+
+        // 重复写入key
+        engine
+            .put(get_test_key(2), get_test_value(2))
+            .expect("Failed to put data");
+        engine
+            .put(get_test_key(2), get_test_value(22))
+            .expect("Failed to put data");
+        let get_res = engine.get(get_test_key(2));
+        assert!(get_res.is_ok());
+        assert_eq!(get_res.unwrap(), get_test_value(22));
+
+        // 获取不存在的key
+        let get_res = engine.get(get_test_key(3));
+        assert_eq!(get_res, Err(Errors::KeyNotFound));
+
+        // 删除后获取
+        engine
+            .delete(get_test_key(2))
+            .expect("Failed to delete data");
+        let get_res = engine.get(get_test_key(2));
+        assert_eq!(get_res, Err(Errors::KeyNotFound));
+
+        // 写入大量数据
+        for i in 10..1000000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        // 从旧数据文件获取
+        let get_res = engine.get(get_test_key(100));
+        assert!(get_res.is_ok());
+        assert_eq!(get_res.unwrap(), get_test_value(100));
+        // 从活跃数据文件获取
+        let get_res = engine.get(get_test_key(999999));
+        assert!(get_res.is_ok());
+        assert_eq!(get_res.unwrap(), get_test_value(999999));
+
+        // 重启数据库后，获取
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .put(get_test_key(33), get_test_value(33))
+            .expect("Failed to put data");
+        let get_res = engine.get(get_test_key(33));
+        assert!(get_res.is_ok());
+        assert_eq!(get_res.unwrap(), get_test_value(33));
+
+        // 从旧数据文件获取
+        let get_res = engine.get(get_test_key(100));
+        assert!(get_res.is_ok());
+        assert_eq!(get_res.unwrap(), get_test_value(100));
+        // 从活跃数据文件获取
+        let get_res = engine.get(get_test_key(999999));
+        assert!(get_res.is_ok());
+        assert_eq!(get_res.unwrap(), get_test_value(999999));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_get_batch() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_get_batch"),
+            data_file_size: 64 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        // 写入足够多的数据，部分key在旧数据文件中
+        for i in 0..3000 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        assert!(engine.data_file_ids().len() > 1);
+        engine
+            .delete(get_test_key(5))
+            .expect("Failed to delete data");
+
+        let keys = vec![
+            get_test_key(2999),
+            get_test_key(3000),
+            get_test_key(0),
+            get_test_key(5),
+            Bytes::new(),
+            get_test_key(1500),
+            get_test_key(0),
+        ];
+        let values = engine.get_batch(&keys);
+        assert_eq!(
+            values,
+            vec![
+                Ok(get_test_value(2999)),
+                Err(Errors::KeyNotFound),
+                Ok(get_test_value(0)),
+                Err(Errors::KeyNotFound),
+                Err(Errors::KeyIsEmpty),
+                Ok(get_test_value(1500)),
+                Ok(get_test_value(0)),
+            ]
+        );
+        // 与逐个get的结果一致
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(engine.get(key.clone()), value);
+        }
+        assert_eq!(engine.get_batch(&[]), vec![]);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_exists() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_exists"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        engine
+            .put(get_test_key(2), get_test_value(2))
+            .expect("Failed to put data");
+        // 存在的key
+        assert_eq!(engine.exists(get_test_key(1)), Ok(true));
+        // 不存在的key
+        assert_eq!(engine.exists(get_test_key(3)), Ok(false));
+        // 删除后的key
+        engine
+            .delete(get_test_key(2))
+            .expect("Failed to delete data");
+        assert_eq!(engine.exists(get_test_key(2)), Ok(false));
+        // 空key
+        assert_eq!(engine.exists(Bytes::new()), Err(Errors::KeyIsEmpty));
+
+        // 重启后从数据文件重建的索引结果相同
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.exists(get_test_key(1)), Ok(true));
+        assert_eq!(engine.exists(get_test_key(2)), Ok(false));
+
+        // 设置了TTL的key过期后与get一样视为不存在
+        engine
+            .put_with_ttl(
+                get_test_key(3),
+                get_test_value(3),
+                Duration::from_millis(50),
+            )
+            .expect("Failed to put data");
+        assert_eq!(engine.exists(get_test_key(3)), Ok(true));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(engine.get(get_test_key(3)), Err(Errors::KeyNotFound));
+        assert_eq!(engine.exists(get_test_key(3)), Ok(false));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_in_memory() {
+        let engine_dir = std::env::temp_dir().join("test_db_in_memory");
+        let _ = std::fs::remove_dir_all(&engine_dir);
+        let engine_opts = Options {
+            dir_path: engine_dir.clone(),
+            data_file_size: 64 * 1024,
+            index_type: IndexType::SkipList,
+            in_memory: true,
+            ..Default::default()
+        };
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert!(engine.is_first_load());
+
+        // 写入足够多的数据，切换出多个旧数据文件
+        for i in 0..5000 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        assert!(engine.data_file_ids().len() > 1);
+        // 从旧数据文件和活跃数据文件读取
+        assert_eq!(engine.get(get_test_key(0)), Ok(get_test_value(0)));
+        assert_eq!(engine.get(get_test_key(4999)), Ok(get_test_value(4999)));
+        // 覆盖写入
+        engine
+            .put(get_test_key(1), get_test_value(11))
+            .expect("Failed to put data");
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(11)));
+        // 删除
+        for i in 100..5000 {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        assert_eq!(engine.get(get_test_key(100)), Err(Errors::KeyNotFound));
+        assert_eq!(engine.exists(get_test_key(99)), Ok(true));
+        // 批量写入
+        let batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        batch
+            .delete(get_test_key(0))
+            .expect("Failed to delete in batch");
+        batch.commit().expect("Failed to commit batch");
+        assert_eq!(engine.get(get_test_key(0)), Err(Errors::KeyNotFound));
+        // 遍历
+        let mut expected: Vec<_> = (1..100).map(get_test_key).collect();
+        expected.sort();
+        assert_eq!(engine.list_keys(), Ok(expected.clone()));
+        let mut keys = Vec::new();
+        engine
+            .for_each(|key, value| {
+                assert_eq!(
+                    engine.get(Bytes::copy_from_slice(key)).as_deref(),
+                    Ok(value)
+                );
+                keys.push(Bytes::copy_from_slice(key));
+                Ok(true)
+            })
+            .expect("Failed to iterate");
+        assert_eq!(keys, expected);
+        // 不支持merge
+        assert_eq!(engine.merge(), Err(Errors::InMemoryUnsupported));
+        assert!(engine.sync_all().is_ok());
+        assert!(engine.close().is_ok());
+        std::mem::drop(engine);
+        // 没有创建目录和任何文件
+        assert!(!engine_dir.exists());
+
+        // 重新打开时是一个新的空db
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert!(engine.is_first_load());
+        assert_eq!(engine.list_keys(), Ok(vec![]));
+        std::mem::drop(engine);
+        assert!(!engine_dir.exists());
+
+        // 依赖文件的配置不支持
+        let res = Engine::open(Options {
+            index_type: IndexType::BPlusTree,
+            ..engine_opts.clone()
+        });
+        assert_eq!(res.err(), Some(Errors::InMemoryUnsupported));
+        let res = Engine::open(Options {
+            read_only: true,
+            ..engine_opts
+        });
+        assert_eq!(res.err(), Some(Errors::InMemoryUnsupported));
+        assert!(!engine_dir.exists());
+    }
+
+    #[test]
+    fn test_db_delete() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_delete"),
+            data_file_size: 8 * 1024 * 1024,
+            sync_write: false,
+            bytes_per_sync: 1000000,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+
+        // 正常删除
+        engine
+            .put(get_test_key(1), get_test_value(2))
+            .expect("Failed to put data");
+        let del_res = engine.delete(get_test_key(1));
+        assert!(del_res.is_ok());
+        let get_res = engine.get(get_test_key(1));
+        assert_eq!(get_res, Err(Errors::KeyNotFound));
+
+        // 删除不存在的key
+        let del_res = engine.delete(get_test_key(2));
+        assert_eq!(del_res, Err(Errors::KeyNotFound));
+
+        // 删除空key
+        let del_res = engine.delete(Bytes::new());
+        assert_eq!(del_res, Err(Errors::KeyIsEmpty));
+
+        // 写入大量数据
+        for i in 10..1000000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+
+        // 重启数据库后，删除
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .delete(get_test_key(100))
             .expect("Failed to delete data");
         let get_res = engine.get(get_test_key(100));
         assert_eq!(get_res, Err(Errors::KeyNotFound));
@@ -733,6 +3030,7 @@ mod tests {
             bytes_per_sync: 1000000,
             index_type: IndexType::BTree,
             use_mmap: true,
+            ..Default::default()
         };
         let engine_dir = engine_opts.dir_path.clone();
 
@@ -753,6 +3051,7 @@ mod tests {
             bytes_per_sync: 100,
             index_type: IndexType::BTree,
             use_mmap: false,
+            ..Default::default()
         };
         let engine_dir = engine_opts.dir_path.clone();
 
@@ -766,4 +3065,3327 @@ mod tests {
 
         std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
     }
+
+    #[test]
+    fn test_db_on_first_load() {
+        let load_count = Arc::new(AtomicUsize::new(0));
+        let hook_count = load_count.clone();
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_on_first_load"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            on_first_load: Some(Arc::new(move |engine: &Engine| {
+                hook_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                engine.put(get_test_key(1), get_test_value(1))
+            })),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let _ = std::fs::remove_dir_all(&engine_dir);
+
+        // 首次创建，执行回调
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert!(engine.is_first_load());
+        assert_eq!(load_count.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+
+        // 重新打开，不执行回调
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert!(!engine.is_first_load());
+        assert_eq!(load_count.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_on_first_load_ignore_unknown_files() {
+        let load_count = Arc::new(AtomicUsize::new(0));
+        let hook_count = load_count.clone();
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_on_first_load_ignore_unknown_files"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            on_first_load: Some(Arc::new(move |engine: &Engine| {
+                hook_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                engine.put(get_test_key(1), get_test_value(1))
+            })),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let _ = std::fs::remove_dir_all(&engine_dir);
+        std::fs::create_dir_all(&engine_dir).expect("Failed to create test directory");
+        std::fs::write(engine_dir.join("README.txt"), b"readme").expect("Failed to write file");
+
+        // 目录中只有无关文件，仍是首次加载
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert!(engine.is_first_load());
+        assert_eq!(load_count.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert!(!engine.is_first_load());
+        assert_eq!(load_count.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_incompatible_version() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_open_incompatible_version"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        std::mem::drop(engine);
+
+        // 相同版本可以正常打开
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+        std::mem::drop(engine);
+
+        // 模拟由其他版本创建的数据库
+        let write_versions = |versions: &[(&str, u32)]| {
+            let mut buf = Vec::new();
+            for (key, version) in versions {
+                let record = LogRecord {
+                    key: key.as_bytes().to_vec(),
+                    value: version.to_string().into_bytes(),
+                    rec_type: LogRecordType::Normal,
+                    value_encoded: false,
+                    expire_at: None,
+                };
+                buf.extend_from_slice(&record.encode());
+            }
+            std::fs::write(engine_dir.join(VERSION_FILE_NAME), buf)
+                .expect("Failed to write version file");
+        };
+        write_versions(&[(VERSION_KEY, DB_FORMAT_VERSION + 1)]);
+        assert_eq!(
+            Engine::open(engine_opts.clone()).err(),
+            Some(Errors::IncompatibleVersion {
+                found: DB_FORMAT_VERSION + 1,
+                expected: DB_FORMAT_VERSION,
+            })
+        );
+        // 记录格式版本高于当前版本
+        write_versions(&[
+            (VERSION_KEY, DB_FORMAT_VERSION),
+            (RECORD_VERSION_KEY, RECORD_FORMAT_VERSION as u32 + 1),
+        ]);
+        assert_eq!(
+            Engine::open(engine_opts.clone()).err(),
+            Some(Errors::UnsupportedRecordVersion {
+                version: RECORD_FORMAT_VERSION + 1
+            })
+        );
+
+        // 版本1的版本文件中没有记录格式版本，可以打开，打开后升级为当前版本
+        write_versions(&[(VERSION_KEY, 1)]);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+        std::mem::drop(engine);
+        let version_file = DataFile::new_version_file(&engine_dir).unwrap();
+        let record = version_file.read_log_record(0).unwrap();
+        assert_eq!(
+            record.record.value,
+            DB_FORMAT_VERSION.to_string().into_bytes()
+        );
+        assert_eq!(
+            version_file
+                .read_log_record(record.size)
+                .unwrap()
+                .record
+                .value,
+            RECORD_FORMAT_VERSION.to_string().into_bytes()
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_value_size() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_value_size"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for (i, len) in [0, 1, 127, 128, 4096, 100_000].into_iter().enumerate() {
+            let value = Bytes::from(vec![b'v'; len]);
+            engine
+                .put(get_test_key(i as u32), value)
+                .expect("Failed to put data");
+        }
+        for i in 0..6 {
+            let value = engine.get(get_test_key(i)).expect("Failed to get data");
+            assert_eq!(engine.value_size(get_test_key(i)), Ok(value.len()));
+        }
+
+        engine
+            .delete(get_test_key(1))
+            .expect("Failed to delete data");
+        assert_eq!(engine.value_size(get_test_key(1)), Err(Errors::KeyNotFound));
+        assert_eq!(
+            engine.value_size(get_test_key(100)),
+            Err(Errors::KeyNotFound)
+        );
+        assert_eq!(engine.value_size(Bytes::new()), Err(Errors::KeyIsEmpty));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_skip_corrupt_files() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_open_skip_corrupt_files"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let mut n = 0;
+        while engine.active_file.read().get_file_id() < 3 {
+            engine
+                .put(get_test_key(n), get_test_value(n))
+                .expect("Failed to put data");
+            n += 1;
+        }
+        let file_ids: Vec<u32> = (0..n)
+            .map(|i| engine.index.get(get_test_key(i).to_vec()).unwrap().file_id)
+            .collect();
+        std::mem::drop(engine);
+
+        // 破坏文件1的开头
+        let file_name = crate::data::data_file::create_data_file_name(&engine_dir, 1);
+        let mut content = std::fs::read(&file_name).expect("Failed to read data file");
+        content[..16].fill(0xff);
+        std::fs::write(&file_name, content).expect("Failed to write data file");
+
+        // 默认打开失败
+        assert!(Engine::open(engine_opts.clone()).is_err());
+
+        // 开启后跳过损坏的文件，其余key仍可读取
+        let engine = Engine::open(Options {
+            skip_corrupt_files: true,
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        assert_eq!(engine.skipped_files(), vec![1]);
+        for i in 0..n {
+            match file_ids[i as usize] {
+                1 => assert_eq!(engine.get(get_test_key(i)), Err(Errors::KeyNotFound)),
+                _ => assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i))),
+            }
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_latency_report() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_latency_report"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            track_latency: true,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..30 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in 0..20 {
+            engine.get(get_test_key(i)).expect("Failed to get data");
+        }
+        // 失败的调用也会被统计
+        assert!(engine.get(get_test_key(100)).is_err());
+        engine.merge().expect("Failed to merge");
+
+        let report = engine
+            .latency_report()
+            .expect("Latency tracking is enabled");
+        assert_eq!(report.put.count, 30);
+        assert_eq!(report.put.buckets.iter().sum::<u64>(), 30);
+        assert_eq!(report.get.count, 21);
+        assert_eq!(report.merge.count, 1);
+        std::mem::drop(engine);
+
+        // 未开启时不统计
+        let engine = Engine::open(Options {
+            track_latency: false,
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        assert!(engine.latency_report().is_none());
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_sequence_number_from_close_file() {
+        for index_type in [IndexType::BTree, IndexType::SkipList] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir().join(format!(
+                    "test_db_open_sequence_number_from_close_file_{:?}",
+                    index_type
+                )),
+                index_type,
+                use_mmap: false,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            let mut batch = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .expect("Failed to create write batch");
+            batch
+                .put(get_test_key(1), get_test_value(1))
+                .expect("Failed to put data");
+            assert_eq!(batch.commit_with_seq().expect("Failed to commit"), 1);
+            std::mem::drop(engine);
+
+            // close写入的序列号大于数据文件中的最大事务序列号
+            std::fs::remove_file(engine_dir.join(SEQUENCE_NUMBER_FILE_NAME))
+                .expect("Failed to remove sequence number file");
+            let sequence_number_file =
+                DataFile::new_sequence_number_file(&engine_dir).expect("Failed to open file");
+            let record = LogRecord {
+                key: SEQUENCE_NUMBER_KEY.as_bytes().to_vec(),
+                value: "100".as_bytes().to_vec(),
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            };
+            sequence_number_file
+                .write(&record.encode())
+                .expect("Failed to write sequence number");
+            sequence_number_file.sync().expect("Failed to sync");
+
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            let mut batch = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .expect("Failed to create write batch");
+            batch
+                .put(get_test_key(2), get_test_value(2))
+                .expect("Failed to put data");
+            assert!(batch.commit_with_seq().expect("Failed to commit") >= 100);
+            assert_eq!(
+                engine.get(get_test_key(1)).expect("Failed to get data"),
+                get_test_value(1)
+            );
+            std::mem::drop(engine);
+
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_db_key_prefix_compression() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_key_prefix_compression"),
+            data_file_size: 64 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            key_prefix_restart_interval: 8,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..2000 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in (0..2000).step_by(7) {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        for i in 2000..2010 {
+            batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        batch.commit().expect("Failed to commit");
+        assert!(engine.data_file_ids().len() > 1);
+
+        let check = |engine: &Engine| {
+            for i in 0..2010 {
+                match i < 2000 && i % 7 == 0 {
+                    true => assert!(engine.get(get_test_key(i)).is_err()),
+                    false => assert_eq!(
+                        engine.get(get_test_key(i)).expect("Failed to get data"),
+                        get_test_value(i)
+                    ),
+                }
+            }
+        };
+        check(&engine);
+        std::mem::drop(engine);
+
+        // 重新打开时顺序解码前缀压缩的记录重建索引
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        check(&engine);
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+
+        // 不开启前缀压缩也可以读取已压缩的数据文件
+        let engine = Engine::open(Options {
+            key_prefix_restart_interval: 0,
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        check(&engine);
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_sync_dir() {
+        for sync_dir_on_rotate in [true, false] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir()
+                    .join(format!("test_db_open_sync_dir_{}", sync_dir_on_rotate)),
+                data_file_size: 256,
+                index_type: IndexType::BTree,
+                use_mmap: false,
+                sync_dir_on_rotate,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            // 首次打开时创建初始数据文件
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            assert_eq!(engine.options.sync_dir_on_rotate, sync_dir_on_rotate);
+            assert!(
+                crate::data::data_file::create_data_file_name(&engine_dir, INITIAL_DATA_FILE_ID)
+                    .is_file()
+            );
+            for i in 0..50 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            assert!(engine.data_file_ids().len() > 1);
+            std::mem::drop(engine);
+
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            for i in 0..50 {
+                assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+            }
+            std::mem::drop(engine);
+
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_db_rotate_sync_dir() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_rotate_sync_dir"),
+            data_file_size: 256,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            sync_dir_on_rotate: true,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..100 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        let file_ids = engine.data_file_ids();
+        assert!(file_ids.len() > 1);
+        std::mem::drop(engine);
+
+        // 切换后的数据文件连续且都可以读取
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.data_file_ids(), file_ids);
+        assert!(file_ids.windows(2).all(|w| w[1] == w[0] + 1));
+        for i in 0..100 {
+            assert_eq!(
+                engine.get(get_test_key(i)).expect("Failed to get data"),
+                get_test_value(i)
+            );
+        }
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_delete_dedup_tombstones() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_delete_dedup_tombstones"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let count_tombstones = |engine: &Engine, key: &Bytes| {
+            let mut count = 0;
+            for file_id in engine.data_file_ids() {
+                for (record, _) in engine.read_file_records(file_id).unwrap() {
+                    let (_, real_key) = parse_record_sequence_number_with_key(&record.key);
+                    if record.rec_type == LogRecordType::Deleted && real_key == key.as_ref() {
+                        count += 1;
+                    }
+                }
+            }
+            count
+        };
+
+        // 连续删除两次
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        engine
+            .delete(get_test_key(1))
+            .expect("Failed to delete data");
+        assert_eq!(engine.delete(get_test_key(1)), Err(Errors::KeyNotFound));
+        assert_eq!(count_tombstones(&engine, &get_test_key(1)), 1);
+
+        // 两个批次在key存在时暂存删除，先后提交
+        engine
+            .put(get_test_key(2), get_test_value(2))
+            .expect("Failed to put data");
+        let batch1 = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        let batch2 = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        batch1
+            .delete(get_test_key(2))
+            .expect("Failed to delete data");
+        batch2
+            .delete(get_test_key(2))
+            .expect("Failed to delete data");
+        batch1.commit().expect("Failed to commit");
+        batch2.commit().expect("Failed to commit");
+        assert_eq!(count_tombstones(&engine, &get_test_key(2)), 1);
+        assert!(engine.get(get_test_key(2)).is_err());
+
+        // 重新写入后删除，需要写入新的删除记录
+        engine
+            .put(get_test_key(2), get_test_value(3))
+            .expect("Failed to put data");
+        engine
+            .delete(get_test_key(2))
+            .expect("Failed to delete data");
+        assert_eq!(count_tombstones(&engine, &get_test_key(2)), 2);
+
+        // 并发删除同一个key
+        engine
+            .put(get_test_key(3), get_test_value(3))
+            .expect("Failed to put data");
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _ = engine.delete(get_test_key(3));
+                });
+            }
+        });
+        assert_eq!(count_tombstones(&engine, &get_test_key(3)), 1);
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 1..=3 {
+            assert!(engine.get(get_test_key(i)).is_err());
+        }
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_rotate_at_exact_file_size() {
+        let record_len = LogRecord {
+            key: get_record_sequence_number_with_key(&get_test_key(0), NON_TRANSACTION_SEQ_NUMBER),
+            value: get_test_value(0).to_vec(),
+            rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
+        }
+        .encoded_length() as u64;
+        for use_mmap in [false, true] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir()
+                    .join(format!("test_db_rotate_at_exact_file_size_{}", use_mmap)),
+                // 三条记录恰好写满一个数据文件
+                data_file_size: record_len * 3,
+                index_type: IndexType::BTree,
+                use_mmap,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            for i in 0..3 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            // 写满时不切换，下一条记录才切换
+            assert_eq!(engine.data_file_ids(), vec![INITIAL_DATA_FILE_ID]);
+            assert_eq!(
+                engine.active_file.read().get_write_offset(),
+                engine_opts.data_file_size
+            );
+            std::mem::drop(engine);
+
+            // 重新打开写满的活跃文件后继续写入
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            assert_eq!(
+                engine.active_file.read().get_write_offset(),
+                engine_opts.data_file_size
+            );
+            for i in 0..3 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).expect("Failed to get data"),
+                    get_test_value(i)
+                );
+            }
+            engine
+                .put(get_test_key(3), get_test_value(3))
+                .expect("Failed to put data");
+            assert_eq!(
+                engine.data_file_ids(),
+                vec![INITIAL_DATA_FILE_ID, INITIAL_DATA_FILE_ID + 1]
+            );
+            let pos = engine.index.get(get_test_key(3).to_vec()).unwrap();
+            assert_eq!((pos.file_id, pos.offset), (INITIAL_DATA_FILE_ID + 1, 0));
+
+            // 批量一次写入时同样在恰好写满后切换
+            let mut batch = engine
+                .new_write_batch(WriteBatchOptions {
+                    single_write: true,
+                    ..Default::default()
+                })
+                .expect("Failed to create write batch");
+            batch
+                .put(get_test_key(4), get_test_value(4))
+                .expect("Failed to put data");
+            batch.commit().expect("Failed to commit");
+            std::mem::drop(engine);
+
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            for i in 0..5 {
+                assert_eq!(
+                    engine.get(get_test_key(i)).expect("Failed to get data"),
+                    get_test_value(i)
+                );
+            }
+            for file_id in engine.data_file_ids() {
+                let file_size = std::fs::metadata(crate::data::data_file::create_data_file_name(
+                    &engine_dir,
+                    file_id,
+                ))
+                .unwrap()
+                .len();
+                assert!(file_size <= record_len * 3);
+            }
+            std::mem::drop(engine);
+
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_db_get_status() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_get_status"),
+            data_file_size: 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        engine
+            .put(get_test_key(0), get_test_value(0))
+            .expect("Failed to put data");
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        engine
+            .delete(get_test_key(1))
+            .expect("Failed to delete data");
+
+        assert_eq!(
+            engine.get_status(get_test_key(0)),
+            Ok(KeyStatus::Present(get_test_value(0)))
+        );
+        assert_eq!(engine.get_status(get_test_key(1)), Ok(KeyStatus::Deleted));
+        assert_eq!(engine.get_status(get_test_key(2)), Ok(KeyStatus::Absent));
+        assert_eq!(engine.get_status(Bytes::new()), Err(Errors::KeyIsEmpty));
+
+        // 删除记录切换到旧数据文件并重启后仍能识别
+        let mut i = 3;
+        while engine.data_file_ids().len() < 3 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+            i += 1;
+        }
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.get_status(get_test_key(1)), Ok(KeyStatus::Deleted));
+        assert_eq!(engine.get_status(get_test_key(2)), Ok(KeyStatus::Absent));
+
+        // 重新写入后再次存在
+        engine
+            .put(get_test_key(1), get_test_value(10))
+            .expect("Failed to put data");
+        assert_eq!(
+            engine.get_status(get_test_key(1)),
+            Ok(KeyStatus::Present(get_test_value(10)))
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_get_debug() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_get_debug"),
+            data_file_size: 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .put(get_test_key(0), get_test_value(0))
+            .expect("Failed to put data");
+
+        let debug = engine
+            .get_debug(get_test_key(0))
+            .expect("Failed to get data");
+        assert_eq!(debug.value, get_test_value(0));
+        assert_eq!(debug.source, FileSource::Active);
+        assert_eq!(
+            debug.position,
+            engine.index.get(get_test_key(0).to_vec()).unwrap()
+        );
+        let record = LogRecord {
+            key: get_record_sequence_number_with_key(&get_test_key(0), NON_TRANSACTION_SEQ_NUMBER),
+            value: get_test_value(0).to_vec(),
+            rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
+        };
+        assert_eq!(debug.crc, record.get_crc());
+
+        // 切换活跃文件后来自旧数据文件
+        let mut i = 1;
+        while engine.data_file_ids().len() < 2 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+            i += 1;
+        }
+        let debug = engine
+            .get_debug(get_test_key(0))
+            .expect("Failed to get data");
+        assert_eq!(debug.value, get_test_value(0));
+        assert_eq!(debug.source, FileSource::Older);
+        assert_eq!(
+            engine
+                .get_debug(get_test_key(i - 1))
+                .expect("Failed to get data")
+                .source,
+            FileSource::Active
+        );
+
+        engine
+            .delete(get_test_key(0))
+            .expect("Failed to delete data");
+        assert_eq!(engine.get_debug(get_test_key(0)), Err(Errors::KeyNotFound));
+        assert_eq!(engine.get_debug(Bytes::new()), Err(Errors::KeyIsEmpty));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_utf8_keys() {
+        let invalid_key = Bytes::from_static(&[0xff, 0xfe, 0x00]);
+        for utf8_keys in [false, true] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir().join(format!("test_db_utf8_keys_{}", utf8_keys)),
+                index_type: IndexType::BTree,
+                use_mmap: false,
+                utf8_keys,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+            // 合法的UTF-8 key
+            engine
+                .put("键-key".into(), get_test_value(0))
+                .expect("Failed to put data");
+            engine
+                .delete("键-key".into())
+                .expect("Failed to delete data");
+            let mut batch = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .expect("Failed to create write batch");
+            batch
+                .put("键-key".into(), get_test_value(1))
+                .expect("Failed to put data");
+            batch.commit().expect("Failed to commit");
+            assert_eq!(engine.get("键-key".into()), Ok(get_test_value(1)));
+
+            // 非UTF-8 key
+            let put_res = engine.put(invalid_key.clone(), get_test_value(2));
+            let mut batch = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .expect("Failed to create write batch");
+            let batch_put_res = batch.put(invalid_key.clone(), get_test_value(3));
+            match utf8_keys {
+                true => {
+                    assert_eq!(put_res, Err(Errors::InvalidUtf8Key));
+                    assert_eq!(batch_put_res, Err(Errors::InvalidUtf8Key));
+                    assert_eq!(
+                        engine.delete(invalid_key.clone()),
+                        Err(Errors::InvalidUtf8Key)
+                    );
+                    assert!(engine.get(invalid_key.clone()).is_err());
+                }
+                false => {
+                    assert!(put_res.is_ok());
+                    assert!(batch_put_res.is_ok());
+                    batch.commit().expect("Failed to commit");
+                    assert_eq!(engine.get(invalid_key.clone()), Ok(get_test_value(3)));
+                    engine
+                        .delete(invalid_key.clone())
+                        .expect("Failed to delete data");
+                }
+            }
+
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_db_put_value_too_large() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_put_value_too_large"),
+            data_file_size: 64,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+        let put_res = engine.put(get_test_key(1), Bytes::from(vec![b'a'; 64]));
+        assert_eq!(put_res, Err(Errors::ValueTooLarge));
+        assert_eq!(engine.get(get_test_key(1)), Err(Errors::KeyNotFound));
+        // 没有为超大记录切换数据文件
+        assert!(engine.older_files.read().is_empty());
+        assert_eq!(engine.active_file.read().get_write_offset(), 0);
+
+        // 能放入数据文件的记录正常写入
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_dir_path_is_file() {
+        let file_path = std::env::temp_dir().join("test_db_open_dir_path_is_file");
+        std::fs::write(&file_path, b"not a dir").expect("Failed to create file");
+        let engine_opts = Options {
+            dir_path: file_path.clone(),
+            ..Default::default()
+        };
+        let engine_res = Engine::open(engine_opts);
+        assert_eq!(engine_res.err(), Some(Errors::DirPathNotADirectory));
+        std::fs::remove_file(file_path).expect("Failed to remove test file");
+    }
+
+    #[test]
+    fn test_db_open_go_bitcask_format() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_open_go_bitcask_format"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            format: Format::GoBitcask,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        std::fs::create_dir_all(&engine_dir).expect("Failed to create test directory");
+        // Go版本bitcask写入：put hello => world, put foo => bar, delete foo
+        let mut blob = Vec::new();
+        for (key, value, crc) in [
+            (&b"hello"[..], &b"world"[..], 0x3a771143u32),
+            (b"foo", b"bar", 0x76ff8caa),
+            (b"foo", b"", 0),
+        ] {
+            blob.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            blob.extend_from_slice(&(value.len() as u64).to_be_bytes());
+            blob.extend_from_slice(key);
+            blob.extend_from_slice(value);
+            blob.extend_from_slice(&crc.to_be_bytes());
+            blob.extend_from_slice(&0u64.to_be_bytes());
+        }
+        std::fs::write(engine_dir.join("000000000.data"), blob).expect("Failed to write data");
+
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.get("hello".into()), Ok("world".into()));
+        assert_eq!(engine.get("foo".into()), Err(Errors::KeyNotFound));
+        // 新记录写入原生格式的活跃文件
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        assert_eq!(engine.active_file.read().get_file_id(), 1);
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+
+        // merge后数据全部转换为原生格式，可以按原生格式重新打开
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(Options {
+            format: Format::Native,
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        assert_eq!(engine.get("hello".into()), Ok("world".into()));
+        assert_eq!(engine.get("foo".into()), Err(Errors::KeyNotFound));
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_set_sync_write() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_set_sync_write"),
+            sync_write: false,
+            bytes_per_sync: 0,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        let bytes_write = || engine.bytes_write.load(std::sync::atomic::Ordering::SeqCst);
+
+        // 未开启时不持久化，累计写入量持续增加
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        assert!(bytes_write() > 0);
+
+        // 开启后每次写入都持久化，累计写入量被清零
+        engine.set_sync_write(true);
+        engine
+            .put(get_test_key(2), get_test_value(2))
+            .expect("Failed to put data");
+        assert_eq!(bytes_write(), 0);
+
+        // 关闭后恢复为不持久化
+        engine.set_sync_write(false);
+        engine
+            .put(get_test_key(3), get_test_value(3))
+            .expect("Failed to put data");
+        assert!(bytes_write() > 0);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_use_mmap() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_use_mmap"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        // 每次打开切换use_mmap，写入后重新打开都能读取之前的所有数据
+        for (round, use_mmap) in [false, true, false, true].into_iter().enumerate() {
+            let engine = Engine::open(Options {
+                use_mmap,
+                ..engine_opts.clone()
+            })
+            .expect("Failed to open engine");
+            let round = round as u32;
+            for i in 0..round * 100 {
+                assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+            }
+            for i in round * 100..(round + 1) * 100 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            for i in 0..(round + 1) * 100 {
+                assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+            }
+        }
+        assert!(Engine::open(engine_opts).unwrap().data_file_ids().len() > 1);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_put_with_ttl() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_put_with_ttl"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        engine
+            .put(get_test_key(0), get_test_value(0))
+            .expect("Failed to put data");
+        // key 1的旧value没有过期时间，过期后不能重新出现
+        engine
+            .put(get_test_key(1), get_test_value(100))
+            .expect("Failed to put data");
+        engine
+            .put_with_ttl(
+                get_test_key(1),
+                get_test_value(1),
+                Duration::from_millis(50),
+            )
+            .expect("Failed to put data");
+        engine
+            .put_with_ttl(
+                get_test_key(2),
+                get_test_value(2),
+                Duration::from_secs(3600),
+            )
+            .expect("Failed to put data");
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+        assert_eq!(
+            engine.value_size(get_test_key(1)),
+            Ok(get_test_value(1).len())
+        );
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(engine.get(get_test_key(1)), Err(Errors::KeyNotFound));
+        assert_eq!(engine.value_size(get_test_key(1)), Err(Errors::KeyNotFound));
+        assert_eq!(engine.get(get_test_key(2)), Ok(get_test_value(2)));
+        let mut keys = Vec::new();
+        engine
+            .for_each(|key, _| {
+                keys.push(Bytes::copy_from_slice(key));
+                Ok(true)
+            })
+            .expect("Failed to iterate");
+        assert_eq!(keys, vec![get_test_key(0), get_test_key(2)]);
+
+        // 重新加载索引时清除过期的key
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(1)), Err(Errors::KeyNotFound));
+        assert_eq!(
+            engine.list_keys().unwrap(),
+            vec![get_test_key(0), get_test_key(2)]
+        );
+
+        // merge后过期的记录不再保留
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(1)), Err(Errors::KeyNotFound));
+        assert_eq!(engine.get(get_test_key(2)), Ok(get_test_value(2)));
+        assert_eq!(engine.list_keys().unwrap().len(), 2);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_load_keyspaces() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_load_keyspaces"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let user_key = |i: u32| Bytes::from(format!("user:{}", i));
+        let order_key = |i: u32| Bytes::from(format!("order:{}", i));
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..100 {
+            engine
+                .put(user_key(i), get_test_value(i))
+                .expect("Failed to put data");
+            engine
+                .put(order_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        // merge后部分key从hint文件加载
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 100..200 {
+            engine
+                .put(user_key(i), get_test_value(i))
+                .expect("Failed to put data");
+            engine
+                .put(order_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        std::mem::drop(engine);
+
+        let partial_opts = Options {
+            load_keyspaces: Some(vec!["user:".to_string()]),
+            ..engine_opts.clone()
+        };
+        let engine = Engine::open(partial_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 200);
+        for i in 0..200 {
+            assert_eq!(engine.get(user_key(i)), Ok(get_test_value(i)));
+            assert_eq!(engine.get(order_key(i)), Err(Errors::KeyNotFound));
+        }
+        // 只能写入已加载的keyspace，索引不完整时不能merge
+        assert_eq!(
+            engine.put(order_key(0), get_test_value(1000)),
+            Err(Errors::KeyspaceNotLoaded)
+        );
+        assert_eq!(engine.delete(order_key(0)), Err(Errors::KeyspaceNotLoaded));
+        engine
+            .put(user_key(0), get_test_value(1000))
+            .expect("Failed to put data");
+        assert_eq!(engine.merge(), Err(Errors::PartialIndexUnsupported));
+        std::mem::drop(engine);
+
+        assert_eq!(
+            Engine::open(Options {
+                index_type: IndexType::BPlusTree,
+                ..partial_opts
+            })
+            .err(),
+            Some(Errors::PartialIndexUnsupported)
+        );
+
+        // 加载所有keyspace时，未加载的key没有丢失
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 400);
+        assert_eq!(engine.get(user_key(0)), Ok(get_test_value(1000)));
+        assert_eq!(engine.get(order_key(0)), Ok(get_test_value(0)));
+        assert_eq!(engine.get(order_key(199)), Ok(get_test_value(199)));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_index_flush_interval() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_index_flush_interval"),
+            data_file_size: 16 * 1024,
+            index_type: IndexType::BPlusTree,
+            use_mmap: false,
+            index_flush_interval: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let crash_dir = std::env::temp_dir().join("test_db_index_flush_interval_crash");
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..200 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in 0..20 {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        // 索引更新暂存在内存中，尚未提交
+        assert!(engine_dir.join(BPTREE_DIRTY_FILE_NAME).is_file());
+        assert_eq!(engine.get(get_test_key(0)), Err(Errors::KeyNotFound));
+        assert_eq!(engine.get(get_test_key(100)), Ok(get_test_value(100)));
+
+        // 不关闭engine直接复制数据目录，模拟索引提交前崩溃，打开时从数据文件重建索引
+        std::fs::create_dir_all(&crash_dir).expect("Failed to create test directory");
+        for entry in std::fs::read_dir(&engine_dir).expect("Failed to read directory") {
+            let path = entry.expect("Failed to read directory entry").path();
+            std::fs::copy(&path, crash_dir.join(path.file_name().unwrap()))
+                .expect("Failed to copy file");
+        }
+        let crashed = Engine::open(Options {
+            dir_path: crash_dir.clone(),
+            index_flush_interval: None,
+            ..engine_opts.clone()
+        })
+        .expect("Failed to open engine");
+        assert!(!crash_dir.join(BPTREE_DIRTY_FILE_NAME).exists());
+        assert_eq!(crashed.list_keys().unwrap().len(), 180);
+        for i in 0..200 {
+            let expected = match i < 20 {
+                true => Err(Errors::KeyNotFound),
+                false => Ok(get_test_value(i)),
+            };
+            assert_eq!(crashed.get(get_test_key(i)), expected);
+        }
+        std::mem::drop(crashed);
+
+        // 关闭时提交暂存的索引更新
+        std::mem::drop(engine);
+        assert!(!engine_dir.join(BPTREE_DIRTY_FILE_NAME).exists());
+        let engine = Engine::open(Options {
+            index_flush_interval: Some(Duration::from_millis(20)),
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 180);
+        assert_eq!(engine.get(get_test_key(199)), Ok(get_test_value(199)));
+
+        // 后台线程定时提交
+        engine
+            .put(get_test_key(0), get_test_value(1000))
+            .expect("Failed to put data");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while engine_dir.join(BPTREE_DIRTY_FILE_NAME).exists() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!engine_dir.join(BPTREE_DIRTY_FILE_NAME).exists());
+        assert_eq!(engine.get(get_test_key(0)), Ok(get_test_value(1000)));
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(crash_dir).expect("Failed to remove test directory");
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_future_record_version() {
+        for index_type in [IndexType::BTree, IndexType::BPlusTree] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir().join(format!(
+                    "test_db_open_future_record_version_{:?}",
+                    index_type
+                )),
+                index_type,
+                use_mmap: false,
+                skip_corrupt_files: true,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            for i in 0..10 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            std::mem::drop(engine);
+
+            // 追加一条版本号高于当前支持版本的记录
+            let record = LogRecord {
+                key: get_record_sequence_number_with_key(
+                    &get_test_key(10),
+                    NON_TRANSACTION_SEQ_NUMBER,
+                ),
+                value: get_test_value(10).to_vec(),
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            };
+            let mut encoded = record.encode();
+            encoded[0] |= (RECORD_FORMAT_VERSION + 1) << RECORD_VERSION_SHIFT;
+            let file_path = crate::data::data_file::create_data_file_name(&engine_dir, 0);
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&file_path)
+                .expect("Failed to open data file");
+            file.write_all(&encoded).expect("Failed to write data file");
+            let file_size = std::fs::metadata(&file_path).unwrap().len();
+
+            // 返回明确的错误，而不是CRC校验失败或截断为未写完的记录
+            assert_eq!(
+                Engine::open(engine_opts.clone()).err(),
+                Some(Errors::UnsupportedRecordVersion {
+                    version: RECORD_FORMAT_VERSION + 1
+                })
+            );
+            assert_eq!(std::fs::metadata(&file_path).unwrap().len(), file_size);
+
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_db_put_no_sync_flush() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_put_no_sync_flush"),
+            sync_write: true,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let crash_dir = std::env::temp_dir().join("test_db_put_no_sync_flush_crash");
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let bytes_write = || engine.bytes_write.load(std::sync::atomic::Ordering::SeqCst);
+
+        // 开启sync_write时put_no_sync也不持久化，累计写入量持续增加
+        for i in 0..100 {
+            engine
+                .put_no_sync(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        assert!(bytes_write() > 0);
+        engine.flush().expect("Failed to flush");
+        assert_eq!(bytes_write(), 0);
+
+        // 不关闭engine直接复制数据目录，模拟flush之后崩溃
+        std::fs::create_dir_all(&crash_dir).expect("Failed to create test directory");
+        for entry in std::fs::read_dir(&engine_dir).expect("Failed to read directory") {
+            let path = entry.expect("Failed to read directory entry").path();
+            std::fs::copy(&path, crash_dir.join(path.file_name().unwrap()))
+                .expect("Failed to copy file");
+        }
+        let crashed = Engine::open(Options {
+            dir_path: crash_dir.clone(),
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        for i in 0..100 {
+            assert_eq!(crashed.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        std::mem::drop(crashed);
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(crash_dir).expect("Failed to remove test directory");
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_bytes_per_sync_after_restart() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_bytes_per_sync_after_restart"),
+            sync_write: false,
+            bytes_per_sync: 1000,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let bytes_write =
+            |engine: &Engine| engine.bytes_write.load(std::sync::atomic::Ordering::SeqCst);
+        engine
+            .put(get_test_key(0), get_test_value(0))
+            .expect("Failed to put data");
+        let record_len = bytes_write(&engine);
+        assert!(record_len > 0 && record_len < 1000);
+        // 关闭时持久化所有数据，未达到阈值的累计值不会带到下次打开
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(bytes_write(&engine), 0);
+        // 重启后与新建的db相同，本次打开后累计写入达到阈值时持久化，重复写入同一条记录保证每次写入的大小相同
+        let puts_to_sync = 1000_usize.div_ceil(record_len);
+        for _ in 1..puts_to_sync {
+            engine
+                .put(get_test_key(0), get_test_value(0))
+                .expect("Failed to put data");
+        }
+        assert_eq!(bytes_write(&engine), (puts_to_sync - 1) * record_len);
+        engine
+            .put(get_test_key(0), get_test_value(0))
+            .expect("Failed to put data");
+        assert_eq!(bytes_write(&engine), 0);
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_repair() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_repair"),
+            index_type: IndexType::BPlusTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..100 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        engine
+            .delete(get_test_key(0))
+            .expect("Failed to delete data");
+
+        // db正在使用时不能修复
+        assert_eq!(Engine::repair(&engine_dir), Err(Errors::DatabaseIsUsing));
+        std::mem::drop(engine);
+
+        // 破坏辅助文件
+        std::fs::write(engine_dir.join(BPTREE_INDEX_FILE_NAME), b"corrupted")
+            .expect("Failed to corrupt bptree index");
+        std::fs::write(engine_dir.join(HINT_FILE_NAME), b"corrupted")
+            .expect("Failed to corrupt hint file");
+
+        Engine::repair(&engine_dir).expect("Failed to repair");
+        assert!(!engine_dir.join(HINT_FILE_NAME).exists());
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(0)), Err(Errors::KeyNotFound));
+        for i in 1..100 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        // 修复后可以继续写入
+        engine
+            .put(get_test_key(100), get_test_value(100))
+            .expect("Failed to put data");
+        assert_eq!(engine.get(get_test_key(100)), Ok(get_test_value(100)));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_truncates_torn_tail() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_open_truncates_torn_tail"),
+            index_type: IndexType::BPlusTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..10 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        let file_id = engine.active_file.read().get_file_id();
+        std::mem::drop(engine);
+
+        // 模拟写入中断，活跃文件末尾留下不完整的记录
+        let file_name = crate::data::data_file::create_data_file_name(&engine_dir, file_id);
+        let clean_size = std::fs::metadata(&file_name).unwrap().len();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&file_name)
+            .expect("Failed to open data file");
+        std::io::Write::write_all(&mut file, &[1, 20, 40, b'g', b'a', b'r'])
+            .expect("Failed to append garbage");
+        std::mem::drop(file);
+
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.active_file.read().get_write_offset(), clean_size);
+        assert_eq!(std::fs::metadata(&file_name).unwrap().len(), clean_size);
+        // 新记录写在完整记录之后
+        engine
+            .put(get_test_key(10), get_test_value(10))
+            .expect("Failed to put data");
+        assert_eq!(
+            engine.index.get(get_test_key(10).to_vec()).unwrap().offset,
+            clean_size
+        );
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..=10 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        std::mem::drop(engine);
+
+        // 模拟mmap写入时预留的空间未截断，活跃文件末尾全部为0
+        let clean_size = std::fs::metadata(&file_name).unwrap().len();
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&file_name)
+            .expect("Failed to open data file");
+        file.set_len(clean_size + 4096)
+            .expect("Failed to extend data file");
+        std::mem::drop(file);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.active_file.read().get_write_offset(), clean_size);
+        assert_eq!(std::fs::metadata(&file_name).unwrap().len(), clean_size);
+        for i in 0..=10 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_keeps_corrupt_middle_records() {
+        // 写入10个key后在活跃文件的中间破坏key 3的记录，或在末尾追加不完整的记录
+        let write_active_file = |name: &str, index_type: IndexType, max_load_crc_errors: usize| {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir().join(name),
+                index_type,
+                use_mmap: false,
+                max_load_crc_errors,
+                ..Default::default()
+            };
+            let _ = std::fs::remove_dir_all(&engine_opts.dir_path);
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            for i in 0..10 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            let position = engine.index.get(get_test_key(3).to_vec()).unwrap();
+            std::mem::drop(engine);
+            let file_name = crate::data::data_file::create_data_file_name(
+                &engine_opts.dir_path,
+                position.file_id,
+            );
+            (engine_opts, file_name, position)
+        };
+        let corrupt = |file_name: &PathBuf, position: LogRecordPos| {
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(file_name)
+                .expect("Failed to open data file");
+            std::os::unix::fs::FileExt::write_at(
+                &file,
+                b"x",
+                position.offset + position.size as u64 - 5,
+            )
+            .expect("Failed to corrupt data file");
+        };
+
+        // 文件中间的损坏记录不是未写完的记录，打开失败且不截断文件
+        let (engine_opts, file_name, position) = write_active_file(
+            "test_db_open_keeps_corrupt_middle_records_strict",
+            IndexType::BPlusTree,
+            0,
+        );
+        let file_size = std::fs::metadata(&file_name).unwrap().len();
+        corrupt(&file_name, position);
+        assert_eq!(
+            Engine::open(engine_opts.clone()).err(),
+            Some(Errors::InvalidLogRecordCrc)
+        );
+        assert_eq!(std::fs::metadata(&file_name).unwrap().len(), file_size);
+        std::fs::remove_dir_all(&engine_opts.dir_path).expect("Failed to remove test directory");
+
+        // 容忍CRC错误时跳过损坏的记录，之后的记录仍然有效，新记录追加在文件末尾
+        let (engine_opts, file_name, position) = write_active_file(
+            "test_db_open_keeps_corrupt_middle_records_tolerant",
+            IndexType::BTree,
+            1,
+        );
+        let file_size = std::fs::metadata(&file_name).unwrap().len();
+        corrupt(&file_name, position);
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.active_file.read().get_write_offset(), file_size);
+        assert_eq!(engine.get(get_test_key(3)), Err(Errors::KeyNotFound));
+        for i in 4..10 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(&engine_opts.dir_path).expect("Failed to remove test directory");
+
+        // 从数据文件重建索引时同样只截断末尾不完整的记录
+        let (engine_opts, file_name, _) = write_active_file(
+            "test_db_open_keeps_corrupt_middle_records_torn",
+            IndexType::BTree,
+            0,
+        );
+        let file_size = std::fs::metadata(&file_name).unwrap().len();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&file_name)
+            .expect("Failed to open data file");
+        std::io::Write::write_all(&mut file, &[1, 20, 40, b'g', b'a', b'r'])
+            .expect("Failed to append garbage");
+        std::mem::drop(file);
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(std::fs::metadata(&file_name).unwrap().len(), file_size);
+        for i in 0..10 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(&engine_opts.dir_path).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_read_only() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_open_read_only"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let read_only_opts = Options {
+            read_only: true,
+            ..engine_opts.clone()
+        };
+        // 只读打开不创建目录，不支持B+Tree索引
+        assert_eq!(
+            Engine::open(read_only_opts.clone()).err(),
+            Some(Errors::FailedToReadDatabaseDir)
+        );
+        assert_eq!(
+            Engine::open(Options {
+                index_type: IndexType::BPlusTree,
+                ..read_only_opts.clone()
+            })
+            .err(),
+            Some(Errors::ReadOnlyUnsupported)
+        );
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..200 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        engine
+            .delete(get_test_key(0))
+            .expect("Failed to delete data");
+        std::mem::drop(engine);
+
+        // 尽量模拟只读目录，root用户不受权限限制，此时仍可以创建文件锁
+        let set_read_only = |read_only: bool| {
+            for entry in std::fs::read_dir(&engine_dir).unwrap() {
+                let path = entry.unwrap().path();
+                let mut perms = std::fs::metadata(&path).unwrap().permissions();
+                perms.set_readonly(read_only);
+                std::fs::set_permissions(&path, perms).unwrap();
+            }
+            let mut perms = std::fs::metadata(&engine_dir).unwrap().permissions();
+            perms.set_readonly(read_only);
+            std::fs::set_permissions(&engine_dir, perms).unwrap();
+        };
+        let snapshot = || {
+            let mut files: Vec<_> = std::fs::read_dir(&engine_dir)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    (path.clone(), std::fs::read(path).unwrap())
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        let files = snapshot();
+        set_read_only(true);
+
+        let engine = Engine::open(read_only_opts);
+        set_read_only(false);
+        let engine = engine.expect("Failed to open read-only engine");
+        assert_eq!(engine.get(get_test_key(0)), Err(Errors::KeyNotFound));
+        for i in 1..200 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        assert_eq!(
+            engine.put(get_test_key(0), get_test_value(0)),
+            Err(Errors::DatabaseIsReadOnly)
+        );
+        assert_eq!(
+            engine.delete(get_test_key(1)),
+            Err(Errors::DatabaseIsReadOnly)
+        );
+        assert_eq!(engine.merge(), Err(Errors::DatabaseIsReadOnly));
+        std::mem::drop(engine);
+        // 只读打开不会修改目录中的文件，文件锁除外
+        let lock_path = engine_dir.join(FILE_LOCK_NAME);
+        assert_eq!(
+            snapshot()
+                .into_iter()
+                .filter(|(path, _)| *path != lock_path)
+                .collect::<Vec<_>>(),
+            files
+                .into_iter()
+                .filter(|(path, _)| *path != lock_path)
+                .collect::<Vec<_>>()
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_index_type_mismatch() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_open_index_type_mismatch"),
+            index_type: IndexType::BPlusTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..100 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        std::mem::drop(engine);
+
+        let btree_opts = Options {
+            index_type: IndexType::BTree,
+            ..engine_opts.clone()
+        };
+        assert_eq!(
+            Engine::open(btree_opts.clone()).err(),
+            Some(Errors::IndexTypeMismatch)
+        );
+        // 只读打开时从数据文件重建索引，不删除B+Tree索引文件
+        let engine = Engine::open(Options {
+            read_only: true,
+            ..btree_opts.clone()
+        })
+        .expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+        std::mem::drop(engine);
+        assert!(engine_dir.join(BPTREE_INDEX_FILE_NAME).is_file());
+
+        // 迁移到BTree索引，删除B+Tree索引文件
+        let engine = Engine::open(Options {
+            migrate_index: true,
+            ..btree_opts.clone()
+        })
+        .expect("Failed to open engine");
+        assert!(!engine_dir.join(BPTREE_INDEX_FILE_NAME).exists());
+        for i in 0..100 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        engine
+            .put(get_test_key(100), get_test_value(100))
+            .expect("Failed to put data");
+        std::mem::drop(engine);
+        let engine = Engine::open(btree_opts).expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(100)), Ok(get_test_value(100)));
+        std::mem::drop(engine);
+
+        // 以B+Tree打开没有索引文件的目录时从数据文件重建索引
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..=100 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_close_flushes_bptree_index() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_close_flushes_bptree_index"),
+            index_type: IndexType::BPlusTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in 0..100 {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        assert_eq!(engine.index.flush(), Ok(()));
+        engine.close().expect("Failed to close engine");
+        std::mem::drop(engine);
+
+        // B+Tree索引文件存在时不扫描数据文件，索引完全来自持久化的B+Tree
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 400);
+        for i in 0..500 {
+            let expected = match i < 100 {
+                true => Err(Errors::KeyNotFound),
+                false => Ok(get_test_value(i)),
+            };
+            assert_eq!(engine.get(get_test_key(i)), expected);
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_index_memory_budget() {
+        let budget = (0..50)
+            .map(|i| crate::index::index_entry_size(&get_test_key(i)))
+            .sum();
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_index_memory_budget"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            index_memory_budget: Some(budget),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..50 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        assert_eq!(
+            engine.put(get_test_key(50), get_test_value(50)),
+            Err(Errors::IndexBudgetExceeded)
+        );
+        assert_eq!(engine.get(get_test_key(50)), Err(Errors::KeyNotFound));
+        // 覆盖已有的key不占用新的内存
+        engine
+            .put(get_test_key(10), get_test_value(100))
+            .expect("Failed to put data");
+        // 删除key后释放内存
+        engine
+            .delete(get_test_key(0))
+            .expect("Failed to delete data");
+        engine
+            .delete(get_test_key(1))
+            .expect("Failed to delete data");
+        engine
+            .put(get_test_key(50), get_test_value(50))
+            .expect("Failed to put data");
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        for i in 51..53 {
+            batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        assert_eq!(batch.commit(), Err(Errors::IndexBudgetExceeded));
+        assert_eq!(engine.get(get_test_key(51)), Err(Errors::KeyNotFound));
+        std::mem::drop(batch);
+        std::mem::drop(engine);
+
+        // 加载索引时超出预算
+        assert_eq!(
+            Engine::open(Options {
+                index_memory_budget: Some(budget / 2),
+                ..engine_opts.clone()
+            })
+            .err(),
+            Some(Errors::IndexBudgetExceeded)
+        );
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 49);
+        assert_eq!(engine.get(get_test_key(10)), Ok(get_test_value(100)));
+        assert_eq!(engine.get(get_test_key(50)), Ok(get_test_value(50)));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_get_timeout() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_get_timeout"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..10 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        engine
+            .delete(get_test_key(0))
+            .expect("Failed to delete data");
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            engine.get_timeout(get_test_key(1), timeout),
+            Ok(get_test_value(1))
+        );
+        assert_eq!(
+            engine.get_timeout(get_test_key(0), timeout),
+            Err(Errors::KeyNotFound)
+        );
+        assert_eq!(
+            engine.get_timeout(Bytes::new(), timeout),
+            Err(Errors::KeyIsEmpty)
+        );
+
+        // 持有活跃文件的写锁，模拟读取一直无法完成的慢存储
+        let active_file = engine.active_file.write();
+        let start = Instant::now();
+        assert_eq!(
+            engine.get_timeout(get_test_key(1), Duration::from_millis(50)),
+            Err(Errors::ReadTimeout)
+        );
+        assert!(start.elapsed() < timeout);
+        std::mem::drop(active_file);
+        assert_eq!(
+            engine.get_timeout(get_test_key(2), timeout),
+            Ok(get_test_value(2))
+        );
+
+        // 等待索引预热的时间计入timeout
+        *engine.index_warmup.result.lock() = None;
+        let start = Instant::now();
+        assert_eq!(
+            engine.get_timeout(get_test_key(1), Duration::from_millis(50)),
+            Err(Errors::ReadTimeout)
+        );
+        assert!(start.elapsed() < timeout);
+        engine.index_warmup.finish(Ok(()));
+        std::mem::drop(engine);
+
+        // 未结束的读取线程数达到上限时直接返回错误
+        let engine = Engine::open(Options {
+            max_pending_timeout_reads: 2,
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        let active_file = engine.active_file.write();
+        for _ in 0..2 {
+            assert_eq!(
+                engine.get_timeout(get_test_key(1), Duration::from_millis(10)),
+                Err(Errors::ReadTimeout)
+            );
+        }
+        assert_eq!(
+            engine.get_timeout(get_test_key(1), timeout),
+            Err(Errors::TooManyPendingReads)
+        );
+        std::mem::drop(active_file);
+        while engine
+            .pending_timeout_reads
+            .load(std::sync::atomic::Ordering::SeqCst)
+            > 0
+        {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            engine.get_timeout(get_test_key(1), timeout),
+            Ok(get_test_value(1))
+        );
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_batch_index_load() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_batch_index_load"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i % 300), get_test_value(i))
+                .expect("Failed to put data");
+            if i % 7 == 0 {
+                engine
+                    .delete(get_test_key(i % 300))
+                    .expect("Failed to delete data");
+            }
+            if i % 50 == 0 {
+                let mut batch = engine
+                    .new_write_batch(WriteBatchOptions::default())
+                    .expect("Failed to create write batch");
+                for j in 0..10 {
+                    batch
+                        .put(get_test_key(1000 + i + j), get_test_value(j))
+                        .expect("Failed to put data");
+                }
+                batch
+                    .delete(get_test_key(1000 + i))
+                    .expect("Failed to delete data");
+                batch.commit().expect("Failed to commit");
+            }
+        }
+        let file_ids = engine.data_file_ids();
+        assert!(file_ids.len() > 2);
+        std::mem::drop(engine);
+
+        // 分别逐条和批量加载索引，比较加锁次数和加载结果
+        let load = |batch_updates: bool| {
+            let mut older_files = HashMap::new();
+            for file_id in &file_ids[..file_ids.len() - 1] {
+                older_files.insert(
+                    *file_id,
+                    Arc::new(DataFile::new(&engine_dir, *file_id, IOType::StandardFileIO).unwrap()),
+                );
+            }
+            let active_file = DataFile::new(
+                &engine_dir,
+                *file_ids.last().unwrap(),
+                IOType::StandardFileIO,
+            )
+            .unwrap();
+            let index =
+                new_indexer(IndexType::BTree, &engine_dir).expect("Failed to create indexer");
+            let dead_bytes = RwLock::new(HashMap::new());
+            let mut state = IndexLoadState {
+                batch_updates,
+                ..Default::default()
+            };
+            let loaded = load_index_from_files(
+                index.as_ref(),
+                &dead_bytes,
+                &active_file,
+                &older_files,
+                &file_ids,
+                &mut state,
+                None,
+            )
+            .expect("Failed to load index");
+            assert_eq!(loaded, file_ids.len());
+            let entries: Vec<_> = index
+                .list_keys()
+                .unwrap()
+                .into_iter()
+                .map(|key| (key.clone(), index.get(key.to_vec())))
+                .collect();
+            (
+                entries,
+                dead_bytes.into_inner(),
+                state.index_lock_count,
+                state.current_seq_number,
+            )
+        };
+        let (entries, dead_bytes, lock_count, seq_number) = load(false);
+        let (batch_entries, batch_dead_bytes, batch_lock_count, batch_seq_number) = load(true);
+        assert_eq!(batch_entries, entries);
+        assert_eq!(batch_dead_bytes, dead_bytes);
+        assert_eq!(batch_seq_number, seq_number);
+        // 每个文件只加锁一次
+        assert_eq!(batch_lock_count, file_ids.len());
+        assert!(lock_count > batch_lock_count * 10);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), entries.len());
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_check_consistency_on_open() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_check_consistency_on_open"),
+            data_file_size: 32 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            check_consistency_on_open: true,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i % 200), get_test_value(i))
+                .expect("Failed to put data");
+            if i % 7 == 0 {
+                engine
+                    .delete(get_test_key(i % 200))
+                    .expect("Failed to delete data");
+            }
+        }
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        for i in 0..10 {
+            batch
+                .put(get_test_key(1000 + i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        batch
+            .delete(get_test_key(1))
+            .expect("Failed to delete data");
+        batch.commit().expect("Failed to commit");
+        engine.merge().expect("Failed to merge");
+        // merge后继续写入，重启时同时从hint文件和数据文件加载索引
+        for i in 0..50 {
+            engine
+                .put(get_test_key(300 + i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        engine
+            .delete(get_test_key(2))
+            .expect("Failed to delete data");
+        let keys = engine.list_keys().unwrap().len();
+        std::mem::drop(engine);
+
+        // 索引与数据文件一致，检查通过
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), keys);
+        let file_ids = engine.data_file_ids();
+        std::mem::drop(engine);
+
+        // 加载后删除一个key的索引，模拟遗漏的索引更新，检查能发现不一致
+        let mut older_files = HashMap::new();
+        for file_id in &file_ids[..file_ids.len() - 1] {
+            older_files.insert(
+                *file_id,
+                Arc::new(DataFile::new(&engine_dir, *file_id, IOType::StandardFileIO).unwrap()),
+            );
+        }
+        let active_file = DataFile::new(
+            &engine_dir,
+            *file_ids.last().unwrap(),
+            IOType::StandardFileIO,
+        )
+        .unwrap();
+        let index = new_indexer(IndexType::BTree, &engine_dir).expect("Failed to create indexer");
+        let dead_bytes = RwLock::new(HashMap::new());
+        let mut state = IndexLoadState {
+            live_keys: Some(HashMap::new()),
+            ..Default::default()
+        };
+        load_index_from_files(
+            index.as_ref(),
+            &dead_bytes,
+            &active_file,
+            &older_files,
+            &file_ids,
+            &mut state,
+            None,
+        )
+        .expect("Failed to load index");
+        assert!(state.check_consistency(index.as_ref()).is_ok());
+        assert!(index.delete(get_test_key(1000).to_vec()));
+        assert_eq!(
+            state.check_consistency(index.as_ref()),
+            Err(Errors::IndexCorrupt {
+                index_entries: keys - 1,
+                live_records: keys,
+            })
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_empty_active_file() {
+        for use_mmap in [false, true] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir()
+                    .join(format!("test_db_open_empty_active_file_{}", use_mmap)),
+                data_file_size: 4 * 1024,
+                index_type: IndexType::BTree,
+                use_mmap,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            for i in 0..200 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            let last_file_id = *engine.data_file_ids().last().unwrap();
+            std::mem::drop(engine);
+
+            // 模拟切换活跃文件后还没有写入就崩溃，最新的数据文件为空
+            let empty_file_id = last_file_id + 1;
+            std::fs::File::create(crate::data::data_file::create_data_file_name(
+                &engine_dir,
+                empty_file_id,
+            ))
+            .expect("Failed to create empty data file");
+
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            {
+                let active_file = engine.active_file.read();
+                assert_eq!(active_file.get_file_id(), empty_file_id);
+                assert_eq!(active_file.get_write_offset(), 0);
+            }
+            for i in 0..200 {
+                assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+            }
+            engine
+                .put(get_test_key(200), get_test_value(200))
+                .expect("Failed to put data");
+            let pos = engine.index.get(get_test_key(200).to_vec()).unwrap();
+            assert_eq!((pos.file_id, pos.offset), (empty_file_id, 0));
+            std::mem::drop(engine);
+
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            for i in 0..=200 {
+                assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+            }
+            std::mem::drop(engine);
+
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_db_sync_all() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_sync_all"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..1000 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        // 写入分布在多个数据文件中
+        assert!(engine.older_files.read().len() > 1);
+        engine.sync_all().expect("Failed to sync all");
+
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..1000 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_sync_all_partial_failure() {
+        use crate::fio::{IOManager, new_io_manager};
+
+        /// 持久化总是失败，其余操作交给真实文件
+        struct FailingSyncIO(Box<dyn IOManager>);
+
+        impl IOManager for FailingSyncIO {
+            fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+                self.0.read(buf, offset)
+            }
+            fn write(&self, buf: &[u8]) -> Result<usize> {
+                self.0.write(buf)
+            }
+            fn sync(&self) -> Result<()> {
+                Err(Errors::SyncFileError)
+            }
+            fn size(&self) -> u64 {
+                self.0.size()
+            }
+            fn truncate(&self, len: u64) -> Result<()> {
+                self.0.truncate(len)
+            }
+        }
+
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_sync_all_partial_failure"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..1000 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        assert!(engine.older_files.read().len() > 2);
+        for file_id in [2, 0] {
+            let file_path = crate::data::data_file::create_data_file_name(&engine_dir, file_id);
+            let io_manager = new_io_manager(&file_path, IOType::StandardFileIO)
+                .expect("Failed to create io manager");
+            let mut older_files = engine.older_files.write();
+            let data_file = Arc::make_mut(&mut older_files).get_mut(&file_id).unwrap();
+            Arc::get_mut(data_file)
+                .unwrap()
+                .replace_io_manager(Box::new(FailingSyncIO(io_manager)));
+        }
+
+        // 所有失败的文件都会列出，而不只是第一个
+        assert_eq!(
+            engine.sync_all(),
+            Err(Errors::PartialSyncFailure(vec![
+                (0, Errors::SyncFileError),
+                (2, Errors::SyncFileError),
+            ]))
+        );
+        // 失败的文件不影响其余文件的读取
+        for i in 0..1000 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_get_position_out_of_bounds() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_get_position_out_of_bounds"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        let size = engine.active_file.read().file_size();
+        let position = LogRecordPos {
+            file_id: 0,
+            offset: size + 100,
+            size: 0,
+        };
+        assert_eq!(
+            engine.get_value_by_position(&position),
+            Err(Errors::PositionOutOfBounds {
+                file_id: 0,
+                offset: size + 100,
+                size,
+            })
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_verify_key_on_read() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_verify_key_on_read"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        engine
+            .put(get_test_key(2), get_test_value(2))
+            .expect("Failed to put data");
+        // 让key 1的索引指向key 2的记录，不校验时读到错误的value
+        let wrong_position = engine.index.get(get_test_key(2).to_vec()).unwrap();
+        engine.index.put(get_test_key(1).to_vec(), wrong_position);
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(2)));
+        std::mem::drop(engine);
+
+        let engine = Engine::open(Options {
+            verify_key_on_read: true,
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+        engine.index.put(get_test_key(1).to_vec(), wrong_position);
+        assert_eq!(engine.get(get_test_key(1)), Err(Errors::KeyMismatch));
+        assert_eq!(
+            engine.get_timeout(get_test_key(1), Duration::from_secs(5)),
+            Err(Errors::KeyMismatch)
+        );
+        assert_eq!(engine.value_size(get_test_key(1)), Err(Errors::KeyMismatch));
+        assert_eq!(
+            engine.get_debug(get_test_key(1)).map(|debug| debug.value),
+            Err(Errors::KeyMismatch)
+        );
+        // 索引正确的key不受影响
+        assert_eq!(engine.get(get_test_key(2)), Ok(get_test_value(2)));
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_get_cached() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_get_cached"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        assert_eq!(engine.get_cached(get_test_key(1)), Ok(get_test_value(1)));
+        assert_eq!(
+            engine
+                .read_cache
+                .read()
+                .get(get_test_key(1).as_ref())
+                .map(|cached| cached.position),
+            engine.index.get(get_test_key(1).to_vec())
+        );
+
+        // 绕过put直接更新索引，缓存未失效，仍返回旧值
+        let mut record = LogRecord {
+            key: get_record_sequence_number_with_key(&get_test_key(1), NON_TRANSACTION_SEQ_NUMBER),
+            value: get_test_value(2).to_vec(),
+            rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
+        };
+        let position = engine
+            .append_log_record(&mut record)
+            .expect("Failed to append log record");
+        engine.index.put(get_test_key(1).to_vec(), position);
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(2)));
+        assert_eq!(engine.get_cached(get_test_key(1)), Ok(get_test_value(1)));
+
+        // 再次写入该key后缓存失效
+        engine
+            .put(get_test_key(1), get_test_value(3))
+            .expect("Failed to put data");
+        assert_eq!(engine.get_cached(get_test_key(1)), Ok(get_test_value(3)));
+        engine
+            .delete(get_test_key(1))
+            .expect("Failed to delete data");
+        assert_eq!(engine.get_cached(get_test_key(1)), Err(Errors::KeyNotFound));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_get_cached_expiry_and_capacity() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_get_cached_expiry_and_capacity"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            read_cache_capacity: 2,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+        // 缓存项过期后不再返回
+        engine
+            .put_with_ttl(
+                get_test_key(0),
+                get_test_value(0),
+                Duration::from_millis(50),
+            )
+            .expect("Failed to put data");
+        assert_eq!(engine.get_cached(get_test_key(0)), Ok(get_test_value(0)));
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(engine.get_cached(get_test_key(0)), Err(Errors::KeyNotFound));
+        assert!(engine.read_cache.read().is_empty());
+
+        // 缓存的key数不超过read_cache_capacity
+        for i in 1..10 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+            assert_eq!(engine.get_cached(get_test_key(i)), Ok(get_test_value(i)));
+            assert!(engine.read_cache.read().len() <= 2);
+        }
+        for i in 1..10 {
+            assert_eq!(engine.get_cached(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_read_file_records() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_read_file_records"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..200 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        let file_ids = engine.data_file_ids();
+        assert!(file_ids.len() > 1);
+        assert!(file_ids.windows(2).all(|w| w[0] < w[1]));
+
+        // 按文件id依次读取，记录顺序与写入顺序一致
+        let mut i = 0;
+        for file_id in file_ids {
+            for (record, position) in engine
+                .read_file_records(file_id)
+                .expect("Failed to read file records")
+            {
+                let (_, key) = parse_record_sequence_number_with_key(&record.key);
+                assert_eq!(key, get_test_key(i).to_vec());
+                assert_eq!(record.value, get_test_value(i).to_vec());
+                assert_eq!(engine.index.get(key), Some(position));
+                i += 1;
+            }
+        }
+        assert_eq!(i, 200);
+        assert_eq!(
+            engine.read_file_records(1000).err(),
+            Some(Errors::DataFileNotFound)
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_close_stops_background_threads() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_close_stops_background_threads"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            sync_interval: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+        assert_eq!(engine.get_cached(get_test_key(1)), Ok(get_test_value(1)));
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(
+            engine
+                .background_sync_count
+                .load(std::sync::atomic::Ordering::SeqCst)
+                > 0
+        );
+
+        engine.close().expect("Failed to close engine");
+        assert!(engine.background.is_stopped());
+        assert!(engine.background.handles.lock().is_empty());
+        assert!(engine.read_cache.read().is_empty());
+
+        // 关闭后后台线程不再执行
+        let sync_count = engine
+            .background_sync_count
+            .load(std::sync::atomic::Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            engine
+                .background_sync_count
+                .load(std::sync::atomic::Ordering::SeqCst),
+            sync_count
+        );
+        // 重复关闭直接返回
+        assert_eq!(engine.close(), Ok(()));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_block_writes_during_merge() {
+        for block in [true, false] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir()
+                    .join(format!("test_db_block_writes_during_merge_{}", block)),
+                index_type: IndexType::BTree,
+                use_mmap: false,
+                block_writes_during_merge: block,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+            // 持有merge锁，模拟正在进行的merge
+            let merge_guard = engine.merge_lock.lock();
+            let put_done = AtomicBool::new(false);
+            std::thread::scope(|s| {
+                let handle = s.spawn(|| {
+                    engine
+                        .put(get_test_key(1), get_test_value(1))
+                        .expect("Failed to put data");
+                    put_done.store(true, std::sync::atomic::Ordering::SeqCst);
+                });
+                std::thread::sleep(Duration::from_millis(100));
+                assert_eq!(put_done.load(std::sync::atomic::Ordering::SeqCst), !block);
+                drop(merge_guard);
+                handle.join().expect("Failed to join thread");
+            });
+            assert!(put_done.load(std::sync::atomic::Ordering::SeqCst));
+            assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_db_open_recovery_budget() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_open_recovery_budget"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        // 较新的文件中覆盖和删除
+        engine
+            .put(get_test_key(0), get_test_value(1000))
+            .expect("Failed to put data");
+        engine
+            .delete(get_test_key(1))
+            .expect("Failed to delete data");
+        assert!(engine.older_files.read().len() > 2);
+        std::mem::drop(engine);
+
+        // 时间预算为0，加载完第一个文件后，剩余文件在后台加载
+        let engine = Engine::open(Options {
+            max_recovery_duration: Some(std::time::Duration::ZERO),
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        assert!(!engine.deferred_file_ids.is_empty());
+        let record = engine
+            .get_from_deferred_files(&get_test_key(0))
+            .expect("Failed to scan deferred files")
+            .expect("Key not found in deferred files");
+        assert_eq!(record.value, get_test_value(1000));
+        let record = engine
+            .get_from_deferred_files(&get_test_key(1))
+            .expect("Failed to scan deferred files")
+            .expect("Key not found in deferred files");
+        assert_eq!(record.rec_type, LogRecordType::Deleted);
+
+        // 无论后台是否加载完成，读取结果都是最新的
+        assert_eq!(engine.get(get_test_key(0)), Ok(get_test_value(1000)));
+        assert_eq!(engine.get(get_test_key(1)), Err(Errors::KeyNotFound));
+        assert_eq!(engine.get(get_test_key(499)), Ok(get_test_value(499)));
+
+        engine.wait_index_ready().expect("Failed to load index");
+        assert!(!engine.is_warming());
+        for i in 2..500 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        engine
+            .put(get_test_key(500), get_test_value(500))
+            .expect("Failed to put data");
+        assert_eq!(engine.get(get_test_key(500)), Ok(get_test_value(500)));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_open_lazy_index() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_open_lazy_index"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        // 较新的文件中覆盖、删除和事务写入
+        for i in (0..500).step_by(7) {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+        }
+        for i in (0..500).step_by(11) {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        for i in 490..510 {
+            batch
+                .put(get_test_key(i), get_test_value(i + 2000))
+                .expect("Failed to put data");
+        }
+        batch.commit().expect("Failed to commit");
+        let file_ids = engine.data_file_ids();
+        assert!(file_ids.len() > 3);
+        std::mem::drop(engine);
+
+        let expected = |i: u32| match (i % 11, i % 7) {
+            _ if i >= 490 => Ok(get_test_value(i + 2000)),
+            (0, _) => Err(Errors::KeyNotFound),
+            (_, 0) => Ok(get_test_value(i + 1000)),
+            _ => Ok(get_test_value(i)),
+        };
+
+        // 打开时不加载任何文件，所有文件在后台加载
+        let engine = Engine::open(Options {
+            lazy_index: true,
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        assert_eq!(engine.deferred_file_ids, file_ids);
+        // 后台加载完成前，直接扫描数据文件得到最新记录
+        let record = engine
+            .get_from_deferred_files(&get_test_key(7))
+            .expect("Failed to scan deferred files")
+            .expect("Key not found in deferred files");
+        assert_eq!(record.value, get_test_value(1007));
+        let record = engine
+            .get_from_deferred_files(&get_test_key(11))
+            .expect("Failed to scan deferred files")
+            .expect("Key not found in deferred files");
+        assert_eq!(record.rec_type, LogRecordType::Deleted);
+        let record = engine
+            .get_from_deferred_files(&get_test_key(505))
+            .expect("Failed to scan deferred files")
+            .expect("Key not found in deferred files");
+        assert_eq!(record.value, get_test_value(2505));
+
+        // 无论后台是否加载完成，读取结果都是最新的
+        for i in (0..510).step_by(13) {
+            assert_eq!(engine.get(get_test_key(i)), expected(i));
+        }
+
+        engine.wait_index_ready().expect("Failed to load index");
+        assert!(engine.index_ready());
+        for i in 0..510 {
+            assert_eq!(engine.get(get_test_key(i)), expected(i));
+        }
+        engine
+            .put(get_test_key(510), get_test_value(510))
+            .expect("Failed to put data");
+        assert_eq!(engine.get(get_test_key(510)), Ok(get_test_value(510)));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_iter_after_failed_warmup() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_iter_after_failed_warmup"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .put(get_test_key(1), get_test_value(1))
+            .expect("Failed to put data");
+
+        // 后台加载索引失败时，迭代器返回加载的错误，而不是遍历不完整的索引
+        engine.index_warmup.finish(Err(Errors::InvalidLogRecordCrc));
+        assert_eq!(
+            engine.iter(IteratorOptions::default()).err(),
+            Some(Errors::InvalidLogRecordCrc)
+        );
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_value_codec() {
+        struct XorCodec(u8);
+        impl crate::options::ValueCodec for XorCodec {
+            fn id(&self) -> u8 {
+                self.0
+            }
+            fn encode(&self, value: &[u8]) -> Vec<u8> {
+                value.iter().map(|b| b ^ 0x5a).collect()
+            }
+            fn decode(&self, value: &[u8]) -> Result<Vec<u8>> {
+                Ok(value.iter().map(|b| b ^ 0x5a).collect())
+            }
+        }
+
+        let mut engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_value_codec"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        // 配置编解码器之前写入的记录不经过编码
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        engine
+            .put(get_test_key(1000), get_test_value(1000))
+            .expect("Failed to put data");
+        std::mem::drop(engine);
+
+        engine_opts.value_codec = Some(Arc::new(XorCodec(1)));
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..100 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions {
+                single_write: true,
+                ..Default::default()
+            })
+            .expect("Failed to create write batch");
+        for i in 100..150 {
+            batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        batch.commit().expect("Failed to commit");
+
+        // 数据文件中保存的是编码后的value
+        let mut stored = 0;
+        for file_id in engine.data_file_ids() {
+            for (record, _) in engine.read_file_records(file_id).unwrap() {
+                if record.rec_type != LogRecordType::Normal {
+                    continue;
+                }
+                let (_, key) = parse_record_sequence_number_with_key(&record.key);
+                if key == get_test_key(1000).to_vec() {
+                    assert!(!record.value_encoded);
+                    continue;
+                }
+                let i = std::str::from_utf8(&key["bitcask_test_key_".len()..])
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let plain = get_test_value(i);
+                assert!(record.value_encoded);
+                assert_ne!(record.value, plain.to_vec());
+                stored += 1;
+            }
+        }
+        assert_eq!(stored, 150);
+
+        // 读取、扫描和value长度返回解码后的value，未编码的旧记录可以同时读取
+        for i in (0..150).chain([1000]) {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+            assert_eq!(
+                engine.value_size(get_test_key(i)),
+                Ok(get_test_value(i).len())
+            );
+        }
+        let mut scanned = 0;
+        engine
+            .scan(|k, v| {
+                assert_eq!(engine.get(k), Ok(v));
+                scanned += 1;
+                true
+            })
+            .expect("Failed to scan");
+        assert_eq!(scanned, 151);
+
+        // merge后重新打开，记录仍然保持编码
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in (0..150).chain([1000]) {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        std::mem::drop(engine);
+
+        // 使用其他编解码器时无法读取已编码的记录
+        engine_opts.value_codec = Some(Arc::new(XorCodec(2)));
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(
+            engine.get(get_test_key(0)),
+            Err(Errors::UnknownValueCodec(1))
+        );
+        assert_eq!(engine.get(get_test_key(1000)), Ok(get_test_value(1000)));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_get_retry_on_removed_file() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_get_retry_on_removed_file"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .put(get_test_key(0), get_test_value(0))
+            .expect("Failed to put data");
+
+        // 模拟merge：把key写入新的数据文件并更新索引，再删除旧的数据文件，同时并发读取
+        let stop = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while !stop.load(std::sync::atomic::Ordering::SeqCst) {
+                        assert_eq!(engine.get(get_test_key(0)), Ok(get_test_value(0)));
+                    }
+                });
+            }
+            let record = LogRecord {
+                key: get_record_sequence_number_with_key(
+                    &get_test_key(0),
+                    NON_TRANSACTION_SEQ_NUMBER,
+                ),
+                value: get_test_value(0).to_vec(),
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            }
+            .encode();
+            let start = Instant::now();
+            let mut file_id = 1000;
+            while engine
+                .read_retry_count
+                .load(std::sync::atomic::Ordering::SeqCst)
+                == 0
+                && start.elapsed() < Duration::from_secs(10)
+            {
+                let data_file = DataFile::new(&engine_dir, file_id, IOType::StandardFileIO)
+                    .expect("Failed to create data file");
+                data_file.write(&record).expect("Failed to write data");
+                Arc::make_mut(&mut engine.older_files.write()).insert(file_id, Arc::new(data_file));
+                let old_position = engine.index.get(get_test_key(0).to_vec()).unwrap();
+                engine.index.put(
+                    get_test_key(0).to_vec(),
+                    LogRecordPos {
+                        file_id,
+                        offset: 0,
+                        size: record.len() as u32,
+                    },
+                );
+                if old_position.file_id >= 1000 {
+                    Arc::make_mut(&mut engine.older_files.write()).remove(&old_position.file_id);
+                }
+                file_id += 1;
+            }
+            stop.store(true, std::sync::atomic::Ordering::SeqCst);
+        });
+        assert!(
+            engine
+                .read_retry_count
+                .load(std::sync::atomic::Ordering::SeqCst)
+                > 0
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_max_load_crc_errors() {
+        // 写入50个key后破坏前corrupt个key的记录
+        let write_corrupted = |name: &str, corrupt: u32, max_load_crc_errors: usize| {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir().join(name),
+                data_file_size: 4 * 1024,
+                index_type: IndexType::BTree,
+                use_mmap: false,
+                max_load_crc_errors,
+                ..Default::default()
+            };
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            for i in 0..50 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            let positions: Vec<_> = (0..corrupt)
+                .map(|i| engine.index.get(get_test_key(i).to_vec()).unwrap())
+                .collect();
+            std::mem::drop(engine);
+            for position in positions {
+                let file_path = crate::data::data_file::create_data_file_name(
+                    &engine_opts.dir_path,
+                    position.file_id,
+                );
+                let file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(file_path)
+                    .expect("Failed to open data file");
+                std::os::unix::fs::FileExt::write_at(
+                    &file,
+                    b"x",
+                    position.offset + position.size as u64 - 5,
+                )
+                .expect("Failed to corrupt data file");
+            }
+            engine_opts
+        };
+
+        // 损坏的记录数在容忍范围内，跳过损坏的记录并写入quarantine文件
+        let engine_opts = write_corrupted("test_db_max_load_crc_errors_tolerant", 3, 10);
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..3 {
+            assert_eq!(engine.get(get_test_key(i)), Err(Errors::KeyNotFound));
+        }
+        for i in 3..50 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        assert_eq!(engine.quarantined_records().len(), 3);
+        let quarantine = std::fs::read_to_string(engine_dir.join(QUARANTINE_FILE_NAME))
+            .expect("Failed to read quarantine file");
+        assert_eq!(quarantine.lines().count(), 3);
+        // merge时丢弃跳过的损坏记录
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert!(engine.quarantined_records().is_empty());
+        assert_eq!(engine.list_keys().unwrap().len(), 47);
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+
+        // 损坏的记录数超过容忍数量时打开失败
+        let engine_opts = write_corrupted("test_db_max_load_crc_errors_strict", 5, 2);
+        let engine_dir = engine_opts.dir_path.clone();
+        assert_eq!(
+            Engine::open(engine_opts.clone()).err(),
+            Some(Errors::TooManyCorruptRecords)
+        );
+        // 默认不容忍损坏的记录
+        assert_eq!(
+            Engine::open(Options {
+                max_load_crc_errors: 0,
+                ..engine_opts
+            })
+            .err(),
+            Some(Errors::InvalidLogRecordCrc)
+        );
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_on_read_corruption() {
+        // 写入两个key后破坏第一个key的value的最后一个字节
+        let open_corrupted = |name: &str, policy: CorruptionPolicy| {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir().join(name),
+                data_file_size: 4 * 1024,
+                index_type: IndexType::BTree,
+                use_mmap: false,
+                on_read_corruption: policy,
+                ..Default::default()
+            };
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            for i in 0..2 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            let position = engine.index.get(get_test_key(0).to_vec()).unwrap();
+            let file_path =
+                crate::data::data_file::create_data_file_name(&engine.options.dir_path, 0);
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(file_path)
+                .expect("Failed to open data file");
+            std::os::unix::fs::FileExt::write_at(
+                &file,
+                b"x",
+                position.offset + position.size as u64 - 5,
+            )
+            .expect("Failed to corrupt data file");
+            (engine, position)
+        };
+
+        let (engine, _) =
+            open_corrupted("test_db_on_read_corruption_error", CorruptionPolicy::Error);
+        assert_eq!(
+            engine.get(get_test_key(0)),
+            Err(Errors::InvalidLogRecordCrc)
+        );
+        assert_eq!(engine.get(get_test_key(1)), Ok(get_test_value(1)));
+        let engine_dir = engine.options.dir_path.clone();
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+
+        // 索引位置没有变化时无法恢复，仍返回错误
+        let (engine, corrupt_position) = open_corrupted(
+            "test_db_on_read_corruption_skip",
+            CorruptionPolicy::SkipToIndex,
+        );
+        assert_eq!(
+            engine.get(get_test_key(0)),
+            Err(Errors::InvalidLogRecordCrc)
+        );
+        assert_eq!(
+            engine
+                .read_retry_count
+                .load(std::sync::atomic::Ordering::SeqCst),
+            0
+        );
+
+        // 模拟merge：key被写入新的数据文件，读取到损坏的记录后重新查询索引，从新位置读取
+        let engine_dir = engine.options.dir_path.clone();
+        let stop = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while !stop.load(std::sync::atomic::Ordering::SeqCst) {
+                        match engine.get(get_test_key(0)) {
+                            Ok(value) => assert_eq!(value, get_test_value(0)),
+                            Err(e) => assert_eq!(e, Errors::InvalidLogRecordCrc),
+                        }
+                    }
+                });
+            }
+            let record = LogRecord {
+                key: get_record_sequence_number_with_key(
+                    &get_test_key(0),
+                    NON_TRANSACTION_SEQ_NUMBER,
+                ),
+                value: get_test_value(0).to_vec(),
+                rec_type: LogRecordType::Normal,
+                value_encoded: false,
+                expire_at: None,
+            }
+            .encode();
+            let data_file = DataFile::new(&engine_dir, 1000, IOType::StandardFileIO)
+                .expect("Failed to create data file");
+            data_file.write(&record).expect("Failed to write data");
+            Arc::make_mut(&mut engine.older_files.write()).insert(1000, Arc::new(data_file));
+            let good_position = LogRecordPos {
+                file_id: 1000,
+                offset: 0,
+                size: record.len() as u32,
+            };
+            let start = Instant::now();
+            while engine
+                .read_retry_count
+                .load(std::sync::atomic::Ordering::SeqCst)
+                == 0
+                && start.elapsed() < Duration::from_secs(10)
+            {
+                engine.index.put(get_test_key(0).to_vec(), corrupt_position);
+                engine.index.put(get_test_key(0).to_vec(), good_position);
+            }
+            stop.store(true, std::sync::atomic::Ordering::SeqCst);
+        });
+        assert!(
+            engine
+                .read_retry_count
+                .load(std::sync::atomic::Ordering::SeqCst)
+                > 0
+        );
+        assert_eq!(engine.get(get_test_key(0)), Ok(get_test_value(0)));
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_put_stream() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_put_stream"),
+            data_file_size: 8 * 1024 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            key_prefix_restart_interval: 4,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..10 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        let value: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        engine
+            .put_stream(
+                get_test_key(5),
+                &mut std::io::Cursor::new(&value),
+                value.len(),
+            )
+            .expect("Failed to put stream");
+        for i in 10..20 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        assert_eq!(engine.get(get_test_key(5)), Ok(Bytes::from(value.clone())));
+
+        // reader数据不足时写入失败，已写入的部分被丢弃，旧value不变
+        let res = engine.put_stream(
+            get_test_key(6),
+            &mut std::io::Cursor::new(&value[..1000]),
+            value.len(),
+        );
+        assert_eq!(res, Err(Errors::ReadValueStreamError));
+        assert_eq!(engine.get(get_test_key(6)), Ok(get_test_value(6)));
+        engine
+            .put(get_test_key(20), get_test_value(20))
+            .expect("Failed to put data");
+
+        // 超过数据文件大小
+        let res = engine.put_stream(
+            get_test_key(7),
+            &mut std::io::Cursor::new(&value),
+            9 * 1024 * 1024,
+        );
+        assert_eq!(res, Err(Errors::ValueTooLarge));
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.get(get_test_key(5)), Ok(Bytes::from(value)));
+        for i in (0..21).filter(|i| *i != 5) {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_key_transform() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_key_transform"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            key_transform: Some(Arc::new(|key: &[u8]| key.to_ascii_lowercase())),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        engine
+            .put(Bytes::from("key"), Bytes::from("value"))
+            .expect("Failed to put data");
+        assert_eq!(engine.get(Bytes::from("KEY")), Ok(Bytes::from("value")));
+        assert_eq!(engine.get(Bytes::from("Key")), Ok(Bytes::from("value")));
+        assert_eq!(engine.value_size(Bytes::from("KEY")), Ok(5));
+
+        // 变换后相同的key覆盖旧值，迭代时返回最后一次写入的原始key
+        engine
+            .put(Bytes::from("Other"), Bytes::from("v1"))
+            .expect("Failed to put data");
+        engine
+            .put(Bytes::from("OTHER"), Bytes::from("v2"))
+            .expect("Failed to put data");
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        batch
+            .put(Bytes::from("Batch"), Bytes::from("v3"))
+            .expect("Failed to put data");
+        batch.commit().expect("Failed to commit");
+        let expected = vec![
+            (Bytes::from("Batch"), Bytes::from("v3")),
+            (Bytes::from("key"), Bytes::from("value")),
+            (Bytes::from("OTHER"), Bytes::from("v2")),
+        ];
+        let keys: Vec<Bytes> = expected.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(engine.list_keys().unwrap(), keys);
+        let mut entries = Vec::new();
+        engine
+            .for_each(|key, value| {
+                entries.push((Bytes::copy_from_slice(key), Bytes::copy_from_slice(value)));
+                Ok(true)
+            })
+            .expect("Failed to iterate");
+        assert_eq!(entries, expected);
+        assert_eq!(engine.get_prefix_map(Bytes::new()).unwrap(), expected);
+        assert_eq!(
+            engine.get_prefix_map(Bytes::from("OTH")).unwrap(),
+            vec![(Bytes::from("OTHER"), Bytes::from("v2"))]
+        );
+        assert_eq!(engine.count_prefix(Bytes::from("BA")), Ok(1));
+
+        engine
+            .delete(Bytes::from("BATCH"))
+            .expect("Failed to delete data");
+        assert_eq!(engine.get(Bytes::from("batch")), Err(Errors::KeyNotFound));
+        std::mem::drop(engine);
+
+        // 重启后从数据文件加载索引，key同样经过变换
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.get(Bytes::from("KEY")), Ok(Bytes::from("value")));
+        assert_eq!(engine.get(Bytes::from("Batch")), Err(Errors::KeyNotFound));
+        assert_eq!(
+            engine.list_keys().unwrap(),
+            vec![Bytes::from("key"), Bytes::from("OTHER")]
+        );
+
+        // merge后从hint文件加载索引
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.get(Bytes::from("other")), Ok(Bytes::from("v2")));
+        assert_eq!(
+            engine.list_keys().unwrap(),
+            vec![Bytes::from("key"), Bytes::from("OTHER")]
+        );
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_db_min_free_bytes() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_db_min_free_bytes"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            min_free_bytes: Some(1),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..100 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        std::mem::drop(engine);
+
+        // 任何磁盘都达不到的阈值，写入被拒绝，已有数据仍可读取
+        let engine = Engine::open(Options {
+            min_free_bytes: Some(u64::MAX),
+            ..engine_opts
+        })
+        .expect("Failed to open engine");
+        assert_eq!(
+            engine.put(get_test_key(100), get_test_value(100)),
+            Err(Errors::InsufficientDiskSpace)
+        );
+        assert_eq!(
+            engine.delete(get_test_key(0)),
+            Err(Errors::InsufficientDiskSpace)
+        );
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        batch
+            .put(get_test_key(101), get_test_value(101))
+            .expect("Failed to put data");
+        assert_eq!(batch.commit(), Err(Errors::InsufficientDiskSpace));
+        assert_eq!(
+            engine.put_stream(get_test_key(102), &mut &b"value"[..], 5),
+            Err(Errors::InsufficientDiskSpace)
+        );
+        assert_eq!(engine.get(get_test_key(100)), Err(Errors::KeyNotFound));
+        assert_eq!(engine.get(get_test_key(0)), Ok(get_test_value(0)));
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
 }
@@ -4,7 +4,7 @@ use thiserror::Error;
 pub type Result<T> = std::result::Result<T, Errors>;
 
 #[allow(clippy::enum_variant_names)]
-#[derive(Error, Debug, Eq, PartialEq)]
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
 pub enum Errors {
     #[error("Failed to read from data file")]
     ReadFromDataFileError,
@@ -15,6 +15,9 @@ pub enum Errors {
     #[error("Failed to sync file")]
     SyncFileError,
 
+    #[error("Failed to sync directory")]
+    SyncDirError,
+
     #[error("Failed to open file")]
     OpenFileError,
 
@@ -33,6 +36,9 @@ pub enum Errors {
     #[error("Dir path is empty")]
     DirPathIsEmpty,
 
+    #[error("Dir path exists but is not a directory")]
+    DirPathNotADirectory,
+
     #[error("Data file size is too small")]
     DataFileSizeIsTooSmall,
 
@@ -54,6 +60,9 @@ pub enum Errors {
     #[error("Invalid log record crc")]
     InvalidLogRecordCrc,
 
+    #[error("Invalid log record length")]
+    InvalidLogRecordLength,
+
     #[error("Batch size exceeded")]
     BatchSizeExceeded,
 
@@ -74,4 +83,110 @@ pub enum Errors {
 
     #[error("Failed to unlock file lock")]
     FailedToUnlockFileLock,
+
+    #[error("Failed to rename file")]
+    RenameFileError,
+
+    #[error("Failed to remove file")]
+    RemoveFileError,
+
+    #[error("Log record is larger than data file size")]
+    ValueTooLarge,
+
+    #[error("Incompatible database version: found {found}, expected {expected}")]
+    IncompatibleVersion { found: u32, expected: u32 },
+
+    #[error("Unsupported record format version: {version}")]
+    UnsupportedRecordVersion { version: u8 },
+
+    #[error("Failed to truncate file")]
+    TruncateFileError,
+
+    #[error("Invalid hint record")]
+    InvalidHintRecord,
+
+    #[error("Key is not valid UTF-8")]
+    InvalidUtf8Key,
+
+    #[error("index_only_in_memory is not supported by the B+Tree index")]
+    IndexOnlyInMemoryUnsupported,
+
+    #[error("read_only is not supported by the B+Tree index")]
+    ReadOnlyUnsupported,
+
+    #[error("Index type does not match the index files in the database dir")]
+    IndexTypeMismatch,
+
+    #[error("Index memory budget exceeded")]
+    IndexBudgetExceeded,
+
+    #[error("Read timed out")]
+    ReadTimeout,
+
+    #[error("Too many timed out reads are still pending")]
+    TooManyPendingReads,
+
+    #[error("Database is opened read-only")]
+    DatabaseIsReadOnly,
+
+    #[error("Failed to read value from stream")]
+    ReadValueStreamError,
+
+    #[error("Unknown value codec: {0}")]
+    UnknownValueCodec(u8),
+
+    #[error("Failed to decode value: {0}")]
+    ValueDecodeError(String),
+
+    #[error("Position out of bounds: file {file_id}, offset {offset}, size {size}")]
+    PositionOutOfBounds {
+        file_id: u32,
+        offset: u64,
+        size: u64,
+    },
+
+    #[error("Insufficient disk space")]
+    InsufficientDiskSpace,
+
+    #[error("Failed to get available disk space")]
+    DiskSpaceCheckError,
+
+    #[error("Record key does not match the requested key")]
+    KeyMismatch,
+
+    #[error("Failed to sync {} data files: {:?}", .0.len(), .0)]
+    PartialSyncFailure(Vec<(u32, Errors)>),
+
+    #[error("Key does not belong to a loaded keyspace")]
+    KeyspaceNotLoaded,
+
+    #[error("Operation requires the full index but only some keyspaces are loaded")]
+    PartialIndexUnsupported,
+
+    #[error("Option or operation is not supported by an in-memory engine")]
+    InMemoryUnsupported,
+
+    #[error("sort_on_merge and merge_recent_first cannot be enabled together")]
+    ConflictingMergeOrder,
+
+    #[error("Index operation failed")]
+    IndexOperationFailed,
+
+    #[error("Index has {index_entries} entries but data files have {live_records} live records")]
+    IndexCorrupt {
+        index_entries: usize,
+        live_records: usize,
+    },
+
+    #[error("Too many corrupt records found while loading data files")]
+    TooManyCorruptRecords,
+
+    #[error("Reclaimable ratio has not reached the merge threshold")]
+    MergeRatioUnreached,
+
+    #[error("Merge ratio must be between 0 and 1")]
+    InvalidMergeRatio,
+
+    #[error("Not enough disk space to merge: {required} bytes required, {available} available")]
+    InsufficientSpaceForMerge { required: u64, available: u64 },
 }
@@ -7,38 +7,45 @@ use crate::errors::Errors;
 use crate::errors::Result;
 
 use log::error;
-use parking_lot::RwLock;
+use parking_lot::Mutex;
 
 pub struct FileIo {
-    fd: Arc<RwLock<File>>,
+    /// read_at是按位置读取，并发读取不需要加锁
+    fd: Arc<File>,
+    /// 写入互斥锁，保证追加写入串行执行
+    write_lock: Mutex<()>,
 }
 
 impl IOManager for FileIo {
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
-        let read_guard = self.fd.read();
-        read_guard.read_at(buf, offset).map_err(|e| {
+        self.fd.read_at(buf, offset).map_err(|e| {
             error!("Failed to read from file: {}", e);
             Errors::ReadFromDataFileError
         })
     }
     fn write(&self, buf: &[u8]) -> Result<usize> {
-        let mut write_guard = self.fd.write();
+        let _write_guard = self.write_lock.lock();
         use std::io::Write;
-        write_guard.write(buf).map_err(|e| {
+        (&*self.fd).write(buf).map_err(|e| {
             error!("Failed to write to file: {}", e);
             Errors::WriteToDataFileError
         })
     }
     fn sync(&self) -> Result<()> {
-        let read_guard = self.fd.read();
-        read_guard.sync_all().map_err(|e| {
+        self.fd.sync_all().map_err(|e| {
             error!("Failed to sync file: {}", e);
             Errors::SyncFileError
         })
     }
     fn size(&self) -> u64 {
-        let read_guard = self.fd.read();
-        read_guard.metadata().unwrap().len()
+        self.fd.metadata().unwrap().len()
+    }
+    fn truncate(&self, len: u64) -> Result<()> {
+        let _write_guard = self.write_lock.lock();
+        self.fd.set_len(len).map_err(|e| {
+            error!("Failed to truncate file: {}", e);
+            Errors::TruncateFileError
+        })
     }
 }
 
@@ -51,8 +58,20 @@ impl FileIo {
             .open(file_path)
         {
             Ok(file) => Ok(Self {
-                fd: Arc::new(RwLock::new(file)),
+                fd: Arc::new(file),
+                write_lock: Mutex::new(()),
             }),
+            // 只读文件系统或没有写权限时，已存在的文件以只读方式打开，写入时返回错误
+            Err(e) if is_read_only_error(&e) && file_path.is_file() => {
+                let file = File::open(file_path).map_err(|e| {
+                    error!("Failed to open file: {}", e);
+                    Errors::OpenFileError
+                })?;
+                Ok(Self {
+                    fd: Arc::new(file),
+                    write_lock: Mutex::new(()),
+                })
+            }
             Err(e) => {
                 error!("Failed to open file: {}", e);
                 Err(Errors::OpenFileError)
@@ -61,6 +80,14 @@ impl FileIo {
     }
 }
 
+/// 是否是因为只读文件系统或没有写权限导致的打开失败
+pub(super) fn is_read_only_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::ReadOnlyFilesystem | std::io::ErrorKind::PermissionDenied
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
@@ -126,4 +153,36 @@ mod tests {
 
         std::fs::remove_file(file_path).unwrap();
     }
+
+    #[test]
+    fn test_file_io_concurrent_read() {
+        let file_path = PathBuf::from("/tmp/d.data");
+        let file = FileIo::new(&file_path).unwrap();
+        for i in 0..100u32 {
+            file.write(&i.to_be_bytes()).unwrap();
+        }
+
+        // 多个线程并发按位置读取，同时追加写入
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let mut buf = [0u8; 4];
+                    for _ in 0..1000 {
+                        for i in 0..100u32 {
+                            assert_eq!(file.read(&mut buf, i as u64 * 4).unwrap(), 4);
+                            assert_eq!(u32::from_be_bytes(buf), i);
+                        }
+                    }
+                });
+            }
+            s.spawn(|| {
+                for i in 100..200u32 {
+                    file.write(&i.to_be_bytes()).unwrap();
+                }
+            });
+        });
+        assert_eq!(file.size(), 200 * 4);
+
+        std::fs::remove_file(file_path).unwrap();
+    }
 }
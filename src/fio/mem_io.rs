@@ -0,0 +1,82 @@
+use parking_lot::RwLock;
+
+use super::IOManager;
+use crate::errors::{Errors, Result};
+
+/// 数据只保存在内存中的IOManager，不创建文件，进程退出后数据丢失
+#[derive(Default)]
+pub struct MemIO {
+    /// 已写入的数据，并发读取共享读锁，追加写入和截断时加写锁
+    buf: RwLock<Vec<u8>>,
+}
+
+impl MemIO {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl IOManager for MemIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let data = self.buf.read();
+        let end = offset + buf.len() as u64;
+        if end > data.len() as u64 {
+            return Err(Errors::ReadDataFileEof);
+        }
+        buf.copy_from_slice(&data[offset as usize..end as usize]);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.buf.write().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.buf.read().len() as u64
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        self.buf.write().truncate(len as usize);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mem_io() {
+        let mem_io = MemIO::new();
+        assert_eq!(mem_io.size(), 0);
+        // 无数据
+        let mut buf = vec![0; 6];
+        assert_eq!(mem_io.read(&mut buf, 0), Err(Errors::ReadDataFileEof));
+
+        assert_eq!(mem_io.write(b"hello, world"), Ok(12));
+        assert_eq!(mem_io.write(b"aabbcc"), Ok(6));
+        assert_eq!(mem_io.size(), 18);
+        let mut buf = vec![0; 12];
+        assert_eq!(mem_io.read(&mut buf, 0), Ok(12));
+        assert_eq!(buf, b"hello, world");
+        let mut buf = vec![0; 6];
+        assert_eq!(mem_io.read(&mut buf, 12), Ok(6));
+        assert_eq!(buf, b"aabbcc");
+        // 超出已写入的范围
+        assert_eq!(mem_io.read(&mut buf, 13), Err(Errors::ReadDataFileEof));
+        assert!(mem_io.sync().is_ok());
+
+        // 截断后继续追加写入
+        assert!(mem_io.truncate(12).is_ok());
+        assert_eq!(mem_io.size(), 12);
+        assert_eq!(mem_io.write(b"hello"), Ok(5));
+        let mut buf = vec![0; 5];
+        assert_eq!(mem_io.read(&mut buf, 12), Ok(5));
+        assert_eq!(buf, b"hello");
+    }
+}
@@ -1,66 +1,180 @@
 #![allow(unused_variables)]
 #![allow(dead_code)]
 
-use std::{path::Path, sync::Arc};
+use std::{fs::File, path::Path, sync::Arc};
 
 use log::error;
 use parking_lot::Mutex;
 
-use super::IOManager;
+use super::{IOManager, file_io::is_read_only_error};
 use crate::errors::{Errors, Result};
+
+/// 映射空间不足时扩展的最小长度
+const MMAP_MIN_CAPACITY: u64 = 4 * 1024;
+
 pub struct MmapIO {
-    map: Arc<Mutex<memmap2::Mmap>>,
+    /// 映射的文件，映射空间不足时按倍数扩展文件长度再重新映射
+    file: File,
+    map: Arc<Mutex<MmapState>>,
+}
+
+/// 文件映射和已写入的字节数
+///
+/// 映射长度不小于已写入的字节数，多出的部分是预留的写入空间，持久化和关闭时截断文件去掉预留空间
+struct MmapState {
+    region: MmapRegion,
+    len: u64,
+}
+
+/// 文件映射，只读打开的文件只能读取
+enum MmapRegion {
+    ReadOnly(memmap2::Mmap),
+    Writable(memmap2::MmapMut),
+}
+
+impl MmapRegion {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            MmapRegion::ReadOnly(mmap) => mmap,
+            MmapRegion::Writable(mmap) => mmap,
+        }
+    }
 }
 
 impl MmapIO {
     pub fn new(file_path: &Path) -> Result<Self> {
-        match std::fs::OpenOptions::new()
+        let (file, writable) = match std::fs::OpenOptions::new()
             .create(true)
             .read(true)
             .write(true)
             .truncate(false)
             .open(file_path)
         {
-            Ok(f) => {
-                let mmap = unsafe {
-                    memmap2::MmapOptions::new()
-                        .map(&f)
-                        .expect("Failed to mmap file")
-                };
-                Ok(Self {
-                    map: Arc::new(Mutex::new(mmap)),
-                })
+            Ok(f) => (f, true),
+            // 只读文件系统或没有写权限时，已存在的文件以只读方式映射
+            Err(e) if is_read_only_error(&e) && file_path.is_file() => {
+                match std::fs::File::open(file_path) {
+                    Ok(f) => (f, false),
+                    Err(e) => {
+                        error!("Failed to open file: {}", e);
+                        return Err(Errors::OpenFileError);
+                    }
+                }
             }
             Err(e) => {
                 error!("Failed to open file: {}", e);
-                Err(Errors::OpenFileError)
+                return Err(Errors::OpenFileError);
             }
+        };
+        let region = match writable {
+            true => map_writable(&file),
+            false => unsafe { memmap2::MmapOptions::new().map(&file) }.map(MmapRegion::ReadOnly),
         }
+        .map_err(|e| {
+            error!("Failed to mmap file: {}", e);
+            Errors::OpenFileError
+        })?;
+        let len = region.as_slice().len() as u64;
+        Ok(Self {
+            file,
+            map: Arc::new(Mutex::new(MmapState { region, len })),
+        })
+    }
+
+    /// 修改文件长度并重新映射，只读映射返回错误
+    fn resize(&self, region: &mut MmapRegion, len: u64, err: Errors) -> Result<()> {
+        if let MmapRegion::ReadOnly(_) = region {
+            return Err(err);
+        }
+        // 持有映射的锁，修改长度后到重新映射之前不会有读取访问旧的映射
+        self.file.set_len(len).map_err(|e| {
+            error!("Failed to resize file: {}", e);
+            err.clone()
+        })?;
+        *region = map_writable(&self.file).map_err(|e| {
+            error!("Failed to mmap file: {}", e);
+            err
+        })?;
+        Ok(())
     }
 }
 
+/// 以可写方式映射整个文件
+fn map_writable(file: &File) -> std::io::Result<MmapRegion> {
+    unsafe { memmap2::MmapOptions::new().map_mut(file) }.map(MmapRegion::Writable)
+}
+
 impl IOManager for MmapIO {
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
-        let mmap = self.map.lock();
+        let state = self.map.lock();
         let end = offset + buf.len() as u64;
-        if end > mmap.len() as u64 {
+        if end > state.len {
             return Err(Errors::ReadDataFileEof);
         }
-        buf.copy_from_slice(&mmap[offset as usize..end as usize]);
+        buf.copy_from_slice(&state.region.as_slice()[offset as usize..end as usize]);
         Ok(buf.len())
     }
 
     fn write(&self, buf: &[u8]) -> Result<usize> {
-        unimplemented!()
+        let mut state = self.map.lock();
+        // 追加写入，映射空间不足时至少扩展为原来的两倍，避免每次写入都重新映射
+        let offset = state.len;
+        let end = offset + buf.len() as u64;
+        let capacity = state.region.as_slice().len() as u64;
+        if end > capacity {
+            let capacity = end.max(capacity * 2).max(MMAP_MIN_CAPACITY);
+            self.resize(&mut state.region, capacity, Errors::WriteToDataFileError)?;
+        }
+        if let MmapRegion::Writable(mmap) = &mut state.region {
+            mmap[offset as usize..end as usize].copy_from_slice(buf);
+        }
+        state.len = end;
+        Ok(buf.len())
     }
 
     fn size(&self) -> u64 {
-        let mmap = self.map.lock();
-        mmap.len() as u64
+        self.map.lock().len
     }
 
     fn sync(&self) -> Result<()> {
-        unimplemented!()
+        let mut state = self.map.lock();
+        let MmapRegion::Writable(mmap) = &state.region else {
+            return Ok(());
+        };
+        mmap.flush().map_err(|e| {
+            error!("Failed to flush mmap: {}", e);
+            Errors::SyncFileError
+        })?;
+        // 去掉预留的写入空间，文件长度与已写入的字节数一致
+        if state.region.as_slice().len() as u64 > state.len {
+            let len = state.len;
+            self.resize(&mut state.region, len, Errors::SyncFileError)?;
+        }
+        // 持久化文件长度等元数据
+        self.file.sync_all().map_err(|e| {
+            error!("Failed to sync file: {}", e);
+            Errors::SyncFileError
+        })
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        let mut state = self.map.lock();
+        self.resize(&mut state.region, len, Errors::TruncateFileError)?;
+        state.len = len;
+        Ok(())
+    }
+}
+
+impl Drop for MmapIO {
+    /// 关闭时截断文件去掉预留的写入空间
+    fn drop(&mut self) {
+        let state = self.map.lock();
+        if let MmapRegion::Writable(mmap) = &state.region
+            && mmap.len() as u64 > state.len
+            && let Err(e) = self.file.set_len(state.len)
+        {
+            error!("Failed to truncate mmap file: {}", e);
+        }
     }
 }
 
@@ -106,4 +220,55 @@ mod tests {
 
         std::fs::remove_file(file_path).unwrap();
     }
+
+    #[test]
+    fn test_mmap_io_write() {
+        let file_path = std::env::temp_dir().join("test_mmap_io_write.data");
+        let _ = std::fs::remove_file(&file_path);
+        let records: Vec<Vec<u8>> = (0..10)
+            .map(|i| format!("bitcask_record_{}", i).into_bytes())
+            .collect();
+        let mmap_io = MmapIO::new(&file_path).expect("Failed to create mmap io");
+        assert_eq!(mmap_io.size(), 0);
+        let mut offsets = Vec::new();
+        for record in &records {
+            offsets.push(mmap_io.size());
+            assert_eq!(mmap_io.write(record), Ok(record.len()));
+        }
+        let total: usize = records.iter().map(Vec::len).sum();
+        assert_eq!(mmap_io.size(), total as u64);
+        for (record, offset) in records.iter().zip(&offsets) {
+            let mut buf = vec![0; record.len()];
+            assert_eq!(mmap_io.read(&mut buf, *offset), Ok(record.len()));
+            assert_eq!(&buf, record);
+        }
+        // 映射空间按倍数扩展，文件中有预留的写入空间，持久化时截断
+        assert!(std::fs::metadata(&file_path).unwrap().len() >= MMAP_MIN_CAPACITY);
+        assert!(mmap_io.sync().is_ok());
+        assert_eq!(std::fs::metadata(&file_path).unwrap().len(), total as u64);
+        assert_eq!(mmap_io.write(b"hello"), Ok(5));
+        std::mem::drop(mmap_io);
+
+        // 关闭时同样截断，重新打开后数据仍然存在，文件长度不包含多余的空间
+        let total = total + 5;
+        offsets.push(total as u64 - 5);
+        assert_eq!(std::fs::metadata(&file_path).unwrap().len(), total as u64);
+        let mmap_io = MmapIO::new(&file_path).expect("Failed to create mmap io");
+        assert_eq!(mmap_io.size(), total as u64);
+        for (record, offset) in records.iter().zip(&offsets) {
+            let mut buf = vec![0; record.len()];
+            assert_eq!(mmap_io.read(&mut buf, *offset), Ok(record.len()));
+            assert_eq!(&buf, record);
+        }
+
+        // 截断后继续追加写入
+        assert!(mmap_io.truncate(offsets[1]).is_ok());
+        assert_eq!(mmap_io.size(), offsets[1]);
+        assert_eq!(mmap_io.write(b"hello"), Ok(5));
+        let mut buf = vec![0; 5];
+        assert_eq!(mmap_io.read(&mut buf, offsets[1]), Ok(5));
+        assert_eq!(buf, b"hello");
+
+        std::fs::remove_file(file_path).expect("Failed to remove test file");
+    }
 }
@@ -1,13 +1,19 @@
 #![allow(dead_code)]
 
 mod file_io;
+mod mem_io;
 mod mmap;
 use std::path::Path;
 
 use file_io::FileIo;
+use log::error;
+use mem_io::MemIO;
 use mmap::MmapIO;
 
-use crate::{errors::Result, options::IOType};
+use crate::{
+    errors::{Errors, Result},
+    options::IOType,
+};
 
 /// Abstract IOManager, for different file systems.
 /// Only support file based storage at present.
@@ -16,12 +22,51 @@ pub trait IOManager: Send + Sync {
     fn write(&self, buf: &[u8]) -> Result<usize>;
     fn sync(&self) -> Result<()>;
     fn size(&self) -> u64;
+    /// 截断文件到len字节，用于丢弃未写完的记录
+    fn truncate(&self, len: u64) -> Result<()>;
 }
 
 /// Create a new IOManager
+///
+/// MemIO不使用file_path，每次创建都是新的空文件
 pub fn new_io_manager(file_path: &Path, io_type: IOType) -> Result<Box<dyn IOManager + 'static>> {
     match io_type {
         IOType::StandardFileIO => Ok(Box::new(FileIo::new(file_path)?)),
         IOType::MmapIO => Ok(Box::new(MmapIO::new(file_path)?)),
+        IOType::MemIO => Ok(Box::new(MemIO::new())),
+    }
+}
+
+/// 持久化目录项，保证新建或重命名的文件在崩溃后仍然存在
+///
+/// 只在Unix上生效，其他平台不支持打开目录，直接返回
+pub fn sync_dir(dir_path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let dir = std::fs::File::open(dir_path).map_err(|e| {
+            error!("Failed to open directory: {}", e);
+            Errors::SyncDirError
+        })?;
+        dir.sync_all().map_err(|e| {
+            error!("Failed to sync directory: {}", e);
+            Errors::SyncDirError
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_dir() {
+        let dir_path = std::env::temp_dir().join("test_fio_sync_dir");
+        std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
+        std::fs::write(dir_path.join("a.data"), b"hello").expect("Failed to write file");
+        assert!(sync_dir(&dir_path).is_ok());
+        std::fs::remove_dir_all(&dir_path).expect("Failed to remove test directory");
+        #[cfg(unix)]
+        assert_eq!(sync_dir(&dir_path), Err(Errors::SyncDirError));
     }
 }
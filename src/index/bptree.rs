@@ -1,123 +1,290 @@
-use std::{path::Path, sync::Arc};
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    collections::BTreeMap,
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use bytes::Bytes;
-use jammdb::DB;
+use jammdb::{DB, Data};
+use log::error;
+use parking_lot::RwLock;
 
 use crate::{
     data::log_record::{LogRecordPos, decode_log_record_pos},
-    errors::Result,
+    errors::{Errors, Result},
+    fio,
     options::IteratorOptions,
 };
 
-use super::{IndexIterator, Indexer};
+use super::{IndexIterator, Indexer, KeyRange, key_range};
 
-const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
+pub(crate) const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
+/// 暂存的索引更新尚未提交时存在该文件，打开时存在说明索引文件落后于数据文件，需要重建
+pub(crate) const BPTREE_DIRTY_FILE_NAME: &str = "bptree-index.dirty";
 const BPTREE_INDEX_BUCKET_NAME: &str = "bitcask-index";
+/// jammdb操作失败时日志中的操作名称
+const TX_ERROR: &str = "create bptree index transaction";
+const BUCKET_ERROR: &str = "get bptree index bucket";
+const COMMIT_ERROR: &str = "commit bptree index transaction";
 pub struct BPlusTree {
     tree: Arc<DB>,
+    /// 索引文件路径，flush时持久化该文件
+    tree_path: PathBuf,
+    /// 是否将索引更新暂存在内存中，flush时在一个事务中提交
+    buffered: bool,
+    /// 暂存的索引更新，None表示删除
+    pending: RwLock<BTreeMap<Vec<u8>, Option<LogRecordPos>>>,
+    /// 提交的写事务数，只用于测试
+    #[cfg(test)]
+    commit_count: AtomicUsize,
 }
 
 impl BPlusTree {
-    pub fn new(dir_path: &Path) -> Self {
+    pub fn new(dir_path: &Path) -> Result<Self> {
         let tree_path = dir_path.join(BPTREE_INDEX_FILE_NAME);
-        let tree = DB::open(tree_path).expect("Failed to open bptree index file");
-        let tx = tree
-            .tx(true)
-            .expect("Failed to create bptree index transaction");
+        // 文件内容损坏时jammdb可能在校验页类型时panic，转换为错误返回
+        let tree = std::panic::catch_unwind(|| DB::open(&tree_path))
+            .map_err(|_| {
+                error!("Bptree index file is corrupt: {}", tree_path.display());
+                Errors::IndexOperationFailed
+            })?
+            .map_err(index_error("open bptree index file"))?;
+        let tx = tree.tx(true).map_err(index_error(TX_ERROR))?;
         tx.get_or_create_bucket(BPTREE_INDEX_BUCKET_NAME)
-            .expect("Failed to create bptree index bucket");
-        tx.commit()
-            .expect("Failed to commit bptree index transaction");
-        Self {
+            .map_err(index_error(BUCKET_ERROR))?;
+        tx.commit().map_err(index_error(COMMIT_ERROR))?;
+        Ok(Self {
             tree: Arc::new(tree),
+            tree_path,
+            buffered: false,
+            pending: Default::default(),
+            #[cfg(test)]
+            commit_count: AtomicUsize::new(0),
+        })
+    }
+
+    /// 索引更新暂存在内存中，调用flush时才在一个事务中提交，减少写放大
+    ///
+    /// 提交前崩溃时索引文件落后于数据文件，打开时根据BPTREE_DIRTY_FILE_NAME从数据文件重建索引
+    pub fn with_buffered_writes(dir_path: &Path) -> Result<Self> {
+        Ok(Self {
+            buffered: true,
+            ..Self::new(dir_path)?
+        })
+    }
+
+    /// 提交暂存的索引更新，before_commit在获取暂存区的锁之后、提交之前调用，
+    /// 用于先持久化暂存的索引指向的数据文件，期间的写入会等待提交完成
+    pub(crate) fn flush_with<F>(&self, before_commit: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let mut pending = self.pending.write();
+        if !pending.is_empty() {
+            before_commit()?;
+            let tx = self.tree.tx(true).map_err(index_error(TX_ERROR))?;
+            let bucket = tx
+                .get_or_create_bucket(BPTREE_INDEX_BUCKET_NAME)
+                .map_err(index_error(BUCKET_ERROR))?;
+            for (key, pos) in pending.iter() {
+                match pos {
+                    Some(pos) => {
+                        bucket
+                            .put(key.clone(), pos.encode())
+                            .map_err(index_error("put bptree index"))?;
+                    }
+                    None => match bucket.delete(key) {
+                        Ok(_) | Err(jammdb::Error::KeyValueMissing) => {}
+                        Err(e) => return Err(index_error("delete bptree index")(e)),
+                    },
+                }
+            }
+            tx.commit().map_err(index_error(COMMIT_ERROR))?;
+            #[cfg(test)]
+            self.commit_count.fetch_add(1, Ordering::SeqCst);
         }
+        let file = std::fs::File::open(&self.tree_path).map_err(|e| {
+            error!("Failed to open bptree index file: {}", e);
+            Errors::OpenFileError
+        })?;
+        file.sync_all().map_err(|e| {
+            error!("Failed to sync bptree index file: {}", e);
+            Errors::SyncFileError
+        })?;
+        // 索引文件持久化后才删除dirty文件
+        if !pending.is_empty() {
+            pending.clear();
+            std::fs::remove_file(self.dirty_file_path()).map_err(|e| {
+                error!("Failed to remove bptree dirty file: {}", e);
+                Errors::RemoveFileError
+            })?;
+        }
+        Ok(())
     }
-}
 
-impl Indexer for BPlusTree {
-    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> bool {
-        let tx = self
-            .tree
-            .tx(true)
-            .expect("Failed to create bptree index transaction");
+    /// 提交的写事务数
+    #[cfg(test)]
+    pub(crate) fn commit_count(&self) -> usize {
+        self.commit_count.load(Ordering::SeqCst)
+    }
+
+    /// 索引文件所在目录下的dirty文件
+    fn dirty_file_path(&self) -> PathBuf {
+        self.tree_path.with_file_name(BPTREE_DIRTY_FILE_NAME)
+    }
+
+    /// 暂存一条索引更新，暂存区为空时先创建dirty文件
+    fn buffer_update(&self, key: Vec<u8>, pos: Option<LogRecordPos>) -> Result<()> {
+        let mut pending = self.pending.write();
+        if pending.is_empty() {
+            let dirty_file_path = self.dirty_file_path();
+            std::fs::File::create(&dirty_file_path).map_err(|e| {
+                error!("Failed to create bptree dirty file: {}", e);
+                Errors::IndexOperationFailed
+            })?;
+            if let Some(dir_path) = dirty_file_path.parent() {
+                fio::sync_dir(dir_path)?;
+            }
+        }
+        pending.insert(key, pos);
+        Ok(())
+    }
+
+    fn try_put(&self, key: Vec<u8>, pos: LogRecordPos) -> Result<()> {
+        if self.buffered {
+            return self.buffer_update(key, Some(pos));
+        }
+        let tx = self.tree.tx(true).map_err(index_error(TX_ERROR))?;
         let bucket = tx
             .get_or_create_bucket(BPTREE_INDEX_BUCKET_NAME)
-            .expect("Failed to get bptree index bucket");
+            .map_err(index_error(BUCKET_ERROR))?;
         bucket
             .put(key, pos.encode())
-            .expect("Failed to put bptree index");
-        tx.commit()
-            .expect("Failed to commit bptree index transaction");
-        true
+            .map_err(index_error("put bptree index"))?;
+        tx.commit().map_err(index_error(COMMIT_ERROR))?;
+        #[cfg(test)]
+        self.commit_count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
     }
 
-    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
-        let tx = self
-            .tree
-            .tx(false)
-            .expect("Failed to create bptree index transaction");
+    fn try_get(&self, key: &[u8]) -> Result<Option<LogRecordPos>> {
+        if let Some(pos) = self.pending.read().get(key) {
+            return Ok(*pos);
+        }
+        let tx = self.tree.tx(false).map_err(index_error(TX_ERROR))?;
         let bucket = tx
             .get_bucket(BPTREE_INDEX_BUCKET_NAME)
-            .expect("Failed to get bptree index bucket");
+            .map_err(index_error(BUCKET_ERROR))?;
         bucket
-            .get_kv(&key)
+            .get_kv(key)
             .map(|kv| decode_log_record_pos(kv.value()))
+            .transpose()
     }
 
-    fn delete(&self, key: Vec<u8>) -> bool {
-        let tx = self
-            .tree
-            .tx(true)
-            .expect("Failed to create bptree index transaction");
+    /// 删除key，返回key删除前是否存在
+    fn try_delete(&self, key: Vec<u8>) -> Result<bool> {
+        if self.buffered {
+            let existed = self.try_get(&key)?.is_some();
+            if existed {
+                self.buffer_update(key, None)?;
+            }
+            return Ok(existed);
+        }
+        let tx = self.tree.tx(true).map_err(index_error(TX_ERROR))?;
         let bucket = tx
             .get_or_create_bucket(BPTREE_INDEX_BUCKET_NAME)
-            .expect("Failed to get bptree index bucket");
-        if let Err(e) = bucket.delete(key) {
-            if e == jammdb::Error::KeyValueMissing {
-                return false;
-            }
+            .map_err(index_error(BUCKET_ERROR))?;
+        match bucket.delete(key) {
+            Ok(_) => {}
+            Err(jammdb::Error::KeyValueMissing) => return Ok(false),
+            Err(e) => return Err(index_error("delete bptree index")(e)),
         }
-        tx.commit()
-            .expect("Failed to commit bptree index transaction");
-        true
+        tx.commit().map_err(index_error(COMMIT_ERROR))?;
+        #[cfg(test)]
+        self.commit_count.fetch_add(1, Ordering::SeqCst);
+        Ok(true)
     }
 
-    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
-        let tx = self
-            .tree
-            .tx(false)
-            .expect("Failed to create bptree index transaction");
+    /// 索引中range范围内的key和位置，按key升序，包括暂存的更新
+    fn items(&self, range: KeyRange<'_>) -> Result<Vec<(Vec<u8>, LogRecordPos)>> {
+        let tx = self.tree.tx(false).map_err(index_error(TX_ERROR))?;
         let bucket = tx
             .get_bucket(BPTREE_INDEX_BUCKET_NAME)
-            .expect("Failed to get bptree index bucket");
+            .map_err(index_error(BUCKET_ERROR))?;
+        let items = bucket.range(range).filter_map(|data| match data {
+            Data::KeyValue(kv) => {
+                Some(decode_log_record_pos(kv.value()).map(|pos| (kv.key().to_vec(), pos)))
+            }
+            Data::Bucket(_) => None,
+        });
+        let pending = self.pending.read();
+        if pending.is_empty() {
+            return items.collect();
+        }
+        let mut items = items.collect::<Result<BTreeMap<_, _>>>()?;
+        for (key, pos) in pending.range::<[u8], _>(range) {
+            match pos {
+                Some(pos) => items.insert(key.clone(), *pos),
+                None => items.remove(key),
+            };
+        }
+        Ok(items.into_iter().collect())
+    }
+}
+
+/// Indexer的put、get和delete没有返回错误的方式，失败时已记录日志，分别返回false、None和false
+impl Indexer for BPlusTree {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> bool {
+        self.try_put(key, pos).is_ok()
+    }
 
-        let mut items = bucket
-            .kv_pairs()
-            .map(|kv| (kv.key().to_vec(), decode_log_record_pos(kv.value())))
-            .collect::<Vec<_>>();
+    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+        self.try_get(&key).ok().flatten()
+    }
+
+    fn delete(&self, key: Vec<u8>) -> bool {
+        self.try_delete(key).unwrap_or(false)
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+        let mut items = match key_range(&options) {
+            Some(range) => self.items(range)?,
+            None => Vec::new(),
+        };
         if options.reverse {
             items.reverse();
         }
-        Box::new(BPlusTreeIterator {
+        Ok(Box::new(BPlusTreeIterator {
             items,
             idx: 0,
             options,
-        })
+        }))
     }
 
     fn list_keys(&self) -> Result<Vec<Bytes>> {
-        let tx = self
-            .tree
-            .tx(false)
-            .expect("Failed to create bptree index transaction");
-        let bucket = tx
-            .get_bucket(BPTREE_INDEX_BUCKET_NAME)
-            .expect("Failed to get bptree index bucket");
-        Ok(bucket
-            .kv_pairs()
-            .map(|kv| kv.key().to_vec().into())
+        Ok(self
+            .items((Bound::Unbounded, Bound::Unbounded))?
+            .into_iter()
+            .map(|(key, _)| key.into())
             .collect())
     }
+
+    /// 在一个事务中提交暂存的索引更新并删除dirty文件，
+    /// 每次写入的事务提交时jammdb已持久化，这里再次持久化整个索引文件
+    fn flush(&self) -> Result<()> {
+        self.flush_with(|| Ok(()))
+    }
+}
+
+/// 记录jammdb操作失败的日志，转换为IndexOperationFailed
+fn index_error(op: &str) -> impl FnOnce(jammdb::Error) -> Errors + '_ {
+    move |e| {
+        error!("Failed to {}: {}", op, e);
+        Errors::IndexOperationFailed
+    }
 }
 
 pub struct BPlusTreeIterator {
@@ -161,18 +328,24 @@ impl IndexIterator for BPlusTreeIterator {
 
 #[cfg(test)]
 mod tests {
+    use crate::{
+        db::Engine,
+        options::{IndexType, Options},
+    };
+
     use super::*;
 
     #[test]
     fn test_bptree_put() {
         let dir_path = std::env::temp_dir().join("test_bptree_put");
         std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
-        let bpt = BPlusTree::new(&dir_path);
+        let bpt = BPlusTree::new(&dir_path).expect("Failed to open bptree index");
         bpt.put(
             "hello".into(),
             LogRecordPos {
                 file_id: 1,
                 offset: 1,
+                size: 0,
             },
         );
         bpt.put(
@@ -180,6 +353,7 @@ mod tests {
             LogRecordPos {
                 file_id: 2,
                 offset: 2,
+                size: 0,
             },
         );
         bpt.put(
@@ -187,6 +361,7 @@ mod tests {
             LogRecordPos {
                 file_id: 3,
                 offset: 3,
+                size: 0,
             },
         );
         std::fs::remove_dir_all(&dir_path).expect("Failed to remove test directory");
@@ -196,7 +371,7 @@ mod tests {
     fn test_bptree_get() {
         let dir_path = std::env::temp_dir().join("test_bptree_get");
         std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
-        let bpt = BPlusTree::new(&dir_path);
+        let bpt = BPlusTree::new(&dir_path).expect("Failed to open bptree index");
 
         let empty_get_res = bpt.get("hello".into());
         assert_eq!(empty_get_res, None);
@@ -206,6 +381,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 1,
+                size: 0,
             },
         );
         bpt.put(
@@ -213,6 +389,7 @@ mod tests {
             LogRecordPos {
                 file_id: 2,
                 offset: 2,
+                size: 0,
             },
         );
         bpt.put(
@@ -220,6 +397,7 @@ mod tests {
             LogRecordPos {
                 file_id: 3,
                 offset: 3,
+                size: 0,
             },
         );
 
@@ -229,6 +407,7 @@ mod tests {
             Some(LogRecordPos {
                 file_id: 3,
                 offset: 3,
+                size: 0,
             })
         );
 
@@ -239,7 +418,7 @@ mod tests {
     fn test_bptree_delete() {
         let dir_path = std::env::temp_dir().join("test_bptree_delete");
         std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
-        let bpt = BPlusTree::new(&dir_path);
+        let bpt = BPlusTree::new(&dir_path).expect("Failed to open bptree index");
 
         let empty_delete_res = bpt.delete("hello".into());
         assert!(!empty_delete_res);
@@ -249,6 +428,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 1,
+                size: 0,
             },
         );
         bpt.put(
@@ -256,6 +436,7 @@ mod tests {
             LogRecordPos {
                 file_id: 2,
                 offset: 2,
+                size: 0,
             },
         );
         bpt.put(
@@ -263,6 +444,7 @@ mod tests {
             LogRecordPos {
                 file_id: 3,
                 offset: 3,
+                size: 0,
             },
         );
 
@@ -279,7 +461,7 @@ mod tests {
     fn test_bptree_list_keys() {
         let dir_path = std::env::temp_dir().join("test_bptree_list_keys");
         std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
-        let bpt = BPlusTree::new(&dir_path);
+        let bpt = BPlusTree::new(&dir_path).expect("Failed to open bptree index");
 
         let empty_list_keys_res = bpt.list_keys();
         assert_eq!(empty_list_keys_res, Ok(vec![]));
@@ -289,6 +471,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 1,
+                size: 0,
             },
         );
         bpt.put(
@@ -296,6 +479,7 @@ mod tests {
             LogRecordPos {
                 file_id: 2,
                 offset: 2,
+                size: 0,
             },
         );
         bpt.put(
@@ -303,6 +487,7 @@ mod tests {
             LogRecordPos {
                 file_id: 3,
                 offset: 3,
+                size: 0,
             },
         );
 
@@ -316,13 +501,14 @@ mod tests {
     fn test_bptree_iterator() {
         let dir_path = std::env::temp_dir().join("test_bptree_iterator");
         std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
-        let bpt = BPlusTree::new(&dir_path);
+        let bpt = BPlusTree::new(&dir_path).expect("Failed to open bptree index");
 
         bpt.put(
             "hello".into(),
             LogRecordPos {
                 file_id: 1,
                 offset: 1,
+                size: 0,
             },
         );
         bpt.put(
@@ -330,6 +516,7 @@ mod tests {
             LogRecordPos {
                 file_id: 2,
                 offset: 2,
+                size: 0,
             },
         );
         bpt.put(
@@ -337,10 +524,13 @@ mod tests {
             LogRecordPos {
                 file_id: 3,
                 offset: 3,
+                size: 0,
             },
         );
 
-        let mut iter = bpt.iterator(IteratorOptions::default());
+        let mut iter = bpt
+            .iterator(IteratorOptions::default())
+            .expect("Failed to create index iterator");
         assert_eq!(
             iter.next(),
             Some((
@@ -348,6 +538,7 @@ mod tests {
                 &LogRecordPos {
                     file_id: 3,
                     offset: 3,
+                    size: 0,
                 }
             ))
         );
@@ -358,6 +549,7 @@ mod tests {
                 &LogRecordPos {
                     file_id: 1,
                     offset: 1,
+                    size: 0,
                 }
             ))
         );
@@ -368,6 +560,7 @@ mod tests {
                 &LogRecordPos {
                     file_id: 2,
                     offset: 2,
+                    size: 0,
                 }
             ))
         );
@@ -375,4 +568,110 @@ mod tests {
 
         std::fs::remove_dir_all(&dir_path).expect("Failed to remove test directory");
     }
+
+    #[test]
+    fn test_bptree_buffered_writes() {
+        let pos = |i: u64| LogRecordPos {
+            file_id: 0,
+            offset: i,
+            size: 0,
+        };
+        let mut commit_counts = Vec::new();
+        for buffered in [false, true] {
+            let dir_path =
+                std::env::temp_dir().join(format!("test_bptree_buffered_writes_{}", buffered));
+            std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
+            let bpt = match buffered {
+                true => {
+                    BPlusTree::with_buffered_writes(&dir_path).expect("Failed to open bptree index")
+                }
+                false => BPlusTree::new(&dir_path).expect("Failed to open bptree index"),
+            };
+            for i in 0..100 {
+                bpt.put(format!("key-{:03}", i).into_bytes(), pos(i));
+            }
+            for i in 0..10 {
+                assert!(bpt.delete(format!("key-{:03}", i).into_bytes()));
+            }
+            assert!(!bpt.delete("missing".into()));
+            // 提交前可以读取暂存的更新
+            assert_eq!(dir_path.join(BPTREE_DIRTY_FILE_NAME).is_file(), buffered);
+            assert_eq!(bpt.get("key-000".into()), None);
+            assert_eq!(bpt.get("key-050".into()), Some(pos(50)));
+            assert_eq!(bpt.list_keys().unwrap().len(), 90);
+            let mut iter = bpt
+                .iterator(IteratorOptions::default())
+                .expect("Failed to create index iterator");
+            assert_eq!(
+                iter.next().map(|(k, _)| k.clone()),
+                Some(b"key-010".to_vec())
+            );
+
+            bpt.flush().expect("Failed to flush bptree index");
+            assert!(!dir_path.join(BPTREE_DIRTY_FILE_NAME).exists());
+            commit_counts.push(bpt.commit_count());
+            std::mem::drop(bpt);
+
+            // 提交后重新打开，索引内容一致
+            let bpt = BPlusTree::new(&dir_path).expect("Failed to open bptree index");
+            assert_eq!(bpt.get("key-000".into()), None);
+            assert_eq!(bpt.get("key-099".into()), Some(pos(99)));
+            assert_eq!(bpt.list_keys().unwrap().len(), 90);
+            std::fs::remove_dir_all(&dir_path).expect("Failed to remove test directory");
+        }
+        // 逐条提交110次，暂存后只提交一次
+        assert_eq!(commit_counts, vec![110, 1]);
+    }
+
+    #[test]
+    fn test_bptree_corrupt_index_file() {
+        let dir_path = std::env::temp_dir().join("test_bptree_corrupt_index_file");
+        let _ = std::fs::remove_dir_all(&dir_path);
+        std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
+        // 索引文件内容损坏时返回错误而不是panic
+        std::fs::write(dir_path.join(BPTREE_INDEX_FILE_NAME), vec![0xab; 8192])
+            .expect("Failed to write index file");
+        assert_eq!(
+            BPlusTree::new(&dir_path).err(),
+            Some(Errors::IndexOperationFailed)
+        );
+        assert_eq!(
+            BPlusTree::with_buffered_writes(&dir_path).err(),
+            Some(Errors::IndexOperationFailed)
+        );
+        // 以B+Tree索引打开数据库时同样返回错误
+        let res = Engine::open(Options {
+            dir_path: dir_path.clone(),
+            index_type: IndexType::BPlusTree,
+            ..Default::default()
+        });
+        assert_eq!(res.err(), Some(Errors::IndexOperationFailed));
+        std::fs::remove_dir_all(&dir_path).expect("Failed to remove test directory");
+
+        // 索引中保存的位置损坏时同样返回错误
+        std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
+        let bpt = BPlusTree::new(&dir_path).expect("Failed to open bptree index");
+        let pos = LogRecordPos {
+            file_id: 1,
+            offset: 10,
+            size: 20,
+        };
+        assert!(bpt.put(b"aa".to_vec(), pos));
+        assert!(bpt.put(b"bb".to_vec(), pos));
+        let tx = bpt.tree.tx(true).unwrap();
+        let bucket = tx.get_bucket(BPTREE_INDEX_BUCKET_NAME).unwrap();
+        bucket.put(b"bb".to_vec(), vec![0x80]).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(bpt.get(b"aa".to_vec()), Some(pos));
+        assert_eq!(bpt.get(b"bb".to_vec()), None);
+        assert_eq!(bpt.try_get(b"bb"), Err(Errors::IndexOperationFailed));
+        assert_eq!(
+            bpt.iterator(IteratorOptions::default()).err(),
+            Some(Errors::IndexOperationFailed)
+        );
+        assert_eq!(bpt.list_keys(), Err(Errors::IndexOperationFailed));
+        assert_eq!(bpt.count_prefix(&[]), Err(Errors::IndexOperationFailed));
+        std::mem::drop(bpt);
+        std::fs::remove_dir_all(dir_path).expect("Failed to remove test directory");
+    }
 }
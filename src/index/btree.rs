@@ -1,10 +1,10 @@
 use bytes::Bytes;
 use parking_lot::RwLock;
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
 
 use crate::{data::log_record::LogRecordPos, errors::Result, options::IteratorOptions};
 
-use super::{IndexIterator, Indexer};
+use super::{IndexIterator, Indexer, key_range};
 
 /// Btree Indexer
 #[derive(Default)]
@@ -29,25 +29,52 @@ impl Indexer for BTree {
         write_guard.remove(&key).is_some()
     }
 
-    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+    fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
         let read_guard = self.tree.read();
-        let mut items = read_guard
-            .iter()
-            .map(|(k, p)| (k.clone(), *p))
-            .collect::<Vec<_>>();
+        // 只复制start和end范围内的key
+        let mut items = match key_range(&options) {
+            Some(range) => read_guard
+                .range::<[u8], _>(range)
+                .map(|(k, p)| (k.clone(), *p))
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
         if options.reverse {
             items.reverse();
         }
-        Box::new(BTreeIterator {
+        Ok(Box::new(BTreeIterator {
             items,
             idx: 0,
             options,
-        })
+        }))
     }
 
     fn list_keys(&self) -> Result<Vec<Bytes>> {
         Ok(self.tree.read().keys().map(|k| k.clone().into()).collect())
     }
+
+    fn update_batch(
+        &self,
+        updates: Vec<(Vec<u8>, Option<LogRecordPos>)>,
+    ) -> Vec<Option<LogRecordPos>> {
+        let mut write_guard = self.tree.write();
+        updates
+            .into_iter()
+            .map(|(key, pos)| match pos {
+                Some(pos) => write_guard.insert(key, pos),
+                None => write_guard.remove(&key),
+            })
+            .collect()
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> Result<usize> {
+        Ok(self
+            .tree
+            .read()
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .count())
+    }
 }
 
 impl BTree {
@@ -109,6 +136,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         ),);
         assert!(bt.put(
@@ -116,6 +144,7 @@ mod tests {
             LogRecordPos {
                 file_id: 11,
                 offset: 11,
+                size: 0,
             },
         ),);
     }
@@ -128,6 +157,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         bt.put(
@@ -135,13 +165,15 @@ mod tests {
             LogRecordPos {
                 file_id: 11,
                 offset: 11,
+                size: 0,
             },
         );
         assert_eq!(
             bt.get("".as_bytes().into()),
             Some(LogRecordPos {
                 file_id: 1,
-                offset: 10
+                offset: 10,
+                size: 0,
             })
         );
         assert_eq!(
@@ -149,6 +181,7 @@ mod tests {
             Some(LogRecordPos {
                 file_id: 11,
                 offset: 11,
+                size: 0,
             })
         );
     }
@@ -161,6 +194,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         bt.put(
@@ -168,6 +202,7 @@ mod tests {
             LogRecordPos {
                 file_id: 11,
                 offset: 11,
+                size: 0,
             },
         );
         assert!(bt.delete("".as_bytes().into()));
@@ -177,6 +212,7 @@ mod tests {
             Some(LogRecordPos {
                 file_id: 11,
                 offset: 11,
+                size: 0,
             })
         );
     }
@@ -186,7 +222,9 @@ mod tests {
         let bt = BTree::new();
 
         // 空 iterator
-        let mut iter = bt.iterator(IteratorOptions::default());
+        let mut iter = bt
+            .iterator(IteratorOptions::default())
+            .expect("Failed to create index iterator");
         iter.seek("a".into());
         assert!(iter.next().is_none());
 
@@ -196,9 +234,12 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
-        let mut iter = bt.iterator(IteratorOptions::default());
+        let mut iter = bt
+            .iterator(IteratorOptions::default())
+            .expect("Failed to create index iterator");
         iter.seek("a".into());
         assert_eq!(
             iter.next(),
@@ -207,6 +248,7 @@ mod tests {
                 &LogRecordPos {
                     file_id: 1,
                     offset: 10,
+                    size: 0,
                 }
             ))
         );
@@ -217,6 +259,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         bt.put(
@@ -224,6 +267,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         bt.put(
@@ -231,6 +275,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         bt.put(
@@ -238,6 +283,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         bt.put(
@@ -245,6 +291,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         bt.put(
@@ -252,6 +299,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         bt.put(
@@ -259,10 +307,13 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
 
-        let mut iter = bt.iterator(IteratorOptions::default());
+        let mut iter = bt
+            .iterator(IteratorOptions::default())
+            .expect("Failed to create index iterator");
         while let Some((k, _)) = iter.next() {
             // println!("{}", String::from_utf8(k.clone()).unwrap());
             assert!(!k.is_empty());
@@ -275,19 +326,23 @@ mod tests {
         }
 
         // reverse iterator
-        let mut iter = bt.iterator(IteratorOptions {
-            reverse: true,
-            ..Default::default()
-        });
+        let mut iter = bt
+            .iterator(IteratorOptions {
+                reverse: true,
+                ..Default::default()
+            })
+            .expect("Failed to create index iterator");
         while let Some((k, _)) = iter.next() {
             // println!("{}", String::from_utf8(k.clone()).unwrap());
             assert!(!k.is_empty());
         }
         // prefix iterator
-        let mut iter = bt.iterator(IteratorOptions {
-            prefix: "b".into(),
-            ..Default::default()
-        });
+        let mut iter = bt
+            .iterator(IteratorOptions {
+                prefix: "b".into(),
+                ..Default::default()
+            })
+            .expect("Failed to create index iterator");
         while let Some((k, _)) = iter.next() {
             println!("{}", String::from_utf8(k.clone()).unwrap());
             assert!(k.starts_with(b"b"));
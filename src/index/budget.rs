@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::Bytes;
+
+use crate::{data::log_record::LogRecordPos, errors::Result, options::IteratorOptions};
+
+use super::{IndexIterator, Indexer};
+
+/// 估算索引中一个key占用的内存：key本身、Vec头部和记录位置
+pub(crate) fn index_entry_size(key: &[u8]) -> usize {
+    key.len() + std::mem::size_of::<Vec<u8>>() + std::mem::size_of::<LogRecordPos>()
+}
+
+/// 统计内存索引估算大小的索引，配置了index_memory_budget时包装内存索引
+pub(crate) struct BudgetIndexer {
+    inner: Box<dyn Indexer>,
+    budget: usize,
+    used: AtomicUsize,
+}
+
+impl BudgetIndexer {
+    pub(crate) fn new(inner: Box<dyn Indexer>, budget: usize) -> Self {
+        Self {
+            inner,
+            budget,
+            used: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Indexer for BudgetIndexer {
+    fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> bool {
+        let size = match self.inner.get(key.clone()) {
+            Some(_) => 0,
+            None => index_entry_size(&key),
+        };
+        if !self.inner.put(key, pos) {
+            return false;
+        }
+        self.used.fetch_add(size, Ordering::SeqCst);
+        true
+    }
+
+    fn get(&self, key: Vec<u8>) -> Option<LogRecordPos> {
+        self.inner.get(key)
+    }
+
+    fn delete(&self, key: Vec<u8>) -> bool {
+        let size = index_entry_size(&key);
+        if !self.inner.delete(key) {
+            return false;
+        }
+        self.used.fetch_sub(size, Ordering::SeqCst);
+        true
+    }
+
+    fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>> {
+        self.inner.iterator(options)
+    }
+
+    fn list_keys(&self) -> Result<Vec<Bytes>> {
+        self.inner.list_keys()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn count_prefix(&self, prefix: &[u8]) -> Result<usize> {
+        self.inner.count_prefix(prefix)
+    }
+
+    fn memory_budget_remaining(&self) -> Option<usize> {
+        Some(self.budget.saturating_sub(self.used.load(Ordering::SeqCst)))
+    }
+}
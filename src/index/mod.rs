@@ -2,15 +2,19 @@
 
 mod bptree;
 mod btree;
+mod budget;
 mod skiplist;
 
-use std::path::Path;
+pub(crate) use bptree::{BPTREE_DIRTY_FILE_NAME, BPTREE_INDEX_FILE_NAME, BPlusTree};
+pub(crate) use budget::{BudgetIndexer, index_entry_size};
+
+use std::{ops::Bound, path::Path};
 
 use bytes::Bytes;
 
 use crate::{
     data::log_record::LogRecordPos,
-    errors::Result,
+    errors::{Errors, Result},
     options::{IndexType, IteratorOptions},
 };
 
@@ -19,15 +23,106 @@ pub trait Indexer: Send + Sync {
     fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> bool;
     fn get(&self, key: Vec<u8>) -> Option<LogRecordPos>;
     fn delete(&self, key: Vec<u8>) -> bool;
-    fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
+    fn iterator(&self, options: IteratorOptions) -> Result<Box<dyn IndexIterator>>;
     fn list_keys(&self) -> Result<Vec<Bytes>>;
+
+    /// 按顺序写入（pos为Some）或删除（pos为None）多个key，返回每个key更新前的位置
+    ///
+    /// 默认逐个更新，内存索引可以在一次加锁中完成
+    fn update_batch(
+        &self,
+        updates: Vec<(Vec<u8>, Option<LogRecordPos>)>,
+    ) -> Vec<Option<LogRecordPos>> {
+        updates
+            .into_iter()
+            .map(|(key, pos)| {
+                let old_pos = self.get(key.clone());
+                match pos {
+                    Some(pos) => {
+                        self.put(key, pos);
+                    }
+                    None if old_pos.is_some() => {
+                        self.delete(key);
+                    }
+                    None => {}
+                }
+                old_pos
+            })
+            .collect()
+    }
+
+    /// 持久化索引，只有保存在文件中的索引需要实现
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// 内存预算的剩余字节数，没有预算限制时为None
+    fn memory_budget_remaining(&self) -> Option<usize> {
+        None
+    }
+
+    /// 统计以prefix开头的key的数量，默认通过迭代器逐个比较
+    fn count_prefix(&self, prefix: &[u8]) -> Result<usize> {
+        let mut iter = self.iterator(IteratorOptions::default())?;
+        iter.seek(prefix.to_vec());
+        let mut count = 0;
+        while let Some((key, _)) = iter.next() {
+            if !key.starts_with(prefix) {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// 索引中key的区间，依次为下界和上界
+pub(crate) type KeyRange<'a> = (Bound<&'a [u8]>, Bound<&'a [u8]>);
+
+/// 迭代选项中start和end表示的key范围，start大于end等范围为空时返回None
+pub(crate) fn key_range(options: &IteratorOptions) -> Option<KeyRange<'_>> {
+    let start = match &options.start {
+        Some(start) => Bound::Included(start.as_slice()),
+        None => Bound::Unbounded,
+    };
+    let end = match &options.end {
+        Some(end) if options.end_inclusive => Bound::Included(end.as_slice()),
+        Some(end) => Bound::Excluded(end.as_slice()),
+        None => Bound::Unbounded,
+    };
+    if let (Some(start), Some(end)) = (&options.start, &options.end)
+        && (start > end || (start == end && !options.end_inclusive))
+    {
+        return None;
+    }
+    Some((start, end))
 }
 
-pub fn new_indexer(idx_type: IndexType, dir_path: &Path) -> Box<dyn Indexer> {
-    match idx_type {
+/// 创建索引，B+Tree索引文件无法打开时返回IndexOperationFailed
+pub fn new_indexer(idx_type: IndexType, dir_path: &Path) -> Result<Box<dyn Indexer>> {
+    Ok(match idx_type {
         IndexType::BTree => Box::new(btree::BTree::new()),
         IndexType::SkipList => Box::new(skiplist::SkipList::new()),
-        IndexType::BPlusTree => Box::new(bptree::BPlusTree::new(dir_path)),
+        IndexType::BPlusTree => Box::new(bptree::BPlusTree::new(dir_path)?),
+    })
+}
+
+/// 写入keys前检查内存索引的预算，已在索引中的key不占用新的内存，超出预算时返回IndexBudgetExceeded
+pub(crate) fn check_index_budget<'a, I>(index: &dyn Indexer, keys: I) -> Result<()>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let Some(remaining) = index.memory_budget_remaining() else {
+        return Ok(());
+    };
+    let needed: usize = keys
+        .into_iter()
+        .filter(|key| index.get(key.to_vec()).is_none())
+        .map(index_entry_size)
+        .sum();
+    match needed > remaining {
+        true => Err(Errors::IndexBudgetExceeded),
+        false => Ok(()),
     }
 }
 
@@ -39,3 +134,124 @@ pub trait IndexIterator: Send + Sync {
     /// 获取下一个记录，如果迭代器已经到达末尾，则返回None
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_indexer() {
+        let dir_path = std::env::temp_dir().join("test_new_indexer");
+        std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
+        for idx_type in [IndexType::BTree, IndexType::SkipList, IndexType::BPlusTree] {
+            let index = new_indexer(idx_type, &dir_path).expect("Failed to create indexer");
+            let pos = LogRecordPos {
+                file_id: 1,
+                offset: 10,
+                size: 0,
+            };
+            assert!(index.put("hello".into(), pos));
+            assert_eq!(index.get("hello".into()), Some(pos));
+            assert_eq!(index.get("world".into()), None);
+        }
+
+        std::fs::remove_dir_all(&dir_path).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_index_iterator_key_range() {
+        let dir_path = std::env::temp_dir().join("test_index_iterator_key_range");
+        std::fs::create_dir_all(&dir_path).expect("Failed to create test directory");
+        // 只有偶数编号的key，范围的边界可以不在索引中
+        let key = |i: u32| format!("key-{:02}", i).into_bytes();
+        let all_keys: Vec<Vec<u8>> = (0..20).step_by(2).map(key).collect();
+        let range_opts =
+            |start: Option<u32>, end: Option<u32>, end_inclusive, reverse| IteratorOptions {
+                reverse,
+                start: start.map(key),
+                end: end.map(key),
+                end_inclusive,
+                ..Default::default()
+            };
+        let cases = [
+            (Some(4), Some(10), false),
+            (Some(5), Some(11), false),
+            (Some(4), Some(10), true),
+            (Some(5), None, false),
+            (None, Some(7), false),
+            (None, None, false),
+            (Some(10), Some(4), false),
+            (Some(10), Some(10), false),
+            (Some(10), Some(10), true),
+        ];
+        let buffered_dir = dir_path.join("buffered");
+        std::fs::create_dir_all(&buffered_dir).expect("Failed to create test directory");
+        let mut indexers: Vec<(&str, Box<dyn Indexer>)> = [
+            ("btree", IndexType::BTree),
+            ("skiplist", IndexType::SkipList),
+            ("bptree", IndexType::BPlusTree),
+        ]
+        .into_iter()
+        .map(|(name, idx_type)| {
+            let index = new_indexer(idx_type, &dir_path).expect("Failed to create indexer");
+            (name, index)
+        })
+        .collect();
+        // 暂存的更新同样按范围遍历
+        indexers.push((
+            "buffered bptree",
+            Box::new(
+                BPlusTree::with_buffered_writes(&buffered_dir)
+                    .expect("Failed to open bptree index"),
+            ),
+        ));
+        for (name, index) in indexers {
+            for (i, key) in all_keys.iter().enumerate() {
+                index.put(
+                    key.clone(),
+                    LogRecordPos {
+                        file_id: 0,
+                        offset: i as u64,
+                        size: 0,
+                    },
+                );
+            }
+            for (start, end, end_inclusive) in cases {
+                let opts = range_opts(start, end, end_inclusive, false);
+                let mut expected: Vec<Vec<u8>> = all_keys
+                    .iter()
+                    .filter(|k| opts.start.as_ref().is_none_or(|start| *k >= start))
+                    .filter(|k| {
+                        opts.end.as_ref().is_none_or(|end| match end_inclusive {
+                            true => *k <= end,
+                            false => *k < end,
+                        })
+                    })
+                    .cloned()
+                    .collect();
+                for reverse in [false, true] {
+                    let mut iter = index
+                        .iterator(range_opts(start, end, end_inclusive, reverse))
+                        .expect("Failed to create index iterator");
+                    let mut keys = Vec::new();
+                    while let Some((key, _)) = iter.next() {
+                        keys.push(key.clone());
+                    }
+                    assert_eq!(keys, expected, "{} {:?}", name, (start, end, reverse));
+                    expected.reverse();
+                }
+            }
+
+            // 逆序时在范围内seek，从不大于seek key的最大key开始
+            let mut iter = index
+                .iterator(range_opts(Some(4), Some(14), false, true))
+                .expect("Failed to create index iterator");
+            iter.seek(key(9));
+            assert_eq!(iter.next().map(|(k, _)| k.clone()), Some(key(8)));
+            iter.seek(key(20));
+            assert_eq!(iter.next().map(|(k, _)| k.clone()), Some(key(12)));
+        }
+
+        std::fs::remove_dir_all(&dir_path).expect("Failed to remove test directory");
+    }
+}
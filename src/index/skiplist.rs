@@ -1,10 +1,10 @@
-use std::sync::Arc;
+use std::{ops::Bound, sync::Arc};
 
 use crossbeam_skiplist::SkipMap;
 
-use crate::{data::log_record::LogRecordPos, options::IteratorOptions};
+use crate::{data::log_record::LogRecordPos, errors::Result, options::IteratorOptions};
 
-use super::Indexer;
+use super::{Indexer, key_range};
 
 pub struct SkipList {
     skip_list: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
@@ -40,20 +40,35 @@ impl Indexer for SkipList {
             .collect())
     }
 
-    fn iterator(&self, options: crate::options::IteratorOptions) -> Box<dyn super::IndexIterator> {
-        let mut items = self
+    fn count_prefix(&self, prefix: &[u8]) -> Result<usize> {
+        Ok(self
             .skip_list
-            .iter()
-            .map(|entry| (entry.key().clone(), *entry.value()))
-            .collect::<Vec<_>>();
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|entry| entry.key().starts_with(prefix))
+            .count())
+    }
+
+    fn iterator(
+        &self,
+        options: crate::options::IteratorOptions,
+    ) -> Result<Box<dyn super::IndexIterator>> {
+        // 只复制start和end范围内的key
+        let mut items = match key_range(&options) {
+            Some(range) => self
+                .skip_list
+                .range::<[u8], _>(range)
+                .map(|entry| (entry.key().clone(), *entry.value()))
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
         if options.reverse {
             items.reverse();
         }
-        Box::new(SkipListIterator {
+        Ok(Box::new(SkipListIterator {
             items,
             idx: 0,
             options,
-        })
+        }))
     }
 }
 
@@ -105,7 +120,9 @@ mod tests {
         let skl = SkipList::new();
 
         // 空 iterator
-        let mut iter = skl.iterator(IteratorOptions::default());
+        let mut iter = skl
+            .iterator(IteratorOptions::default())
+            .expect("Failed to create index iterator");
         iter.seek("a".into());
         assert!(iter.next().is_none());
 
@@ -115,9 +132,12 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
-        let mut iter = skl.iterator(IteratorOptions::default());
+        let mut iter = skl
+            .iterator(IteratorOptions::default())
+            .expect("Failed to create index iterator");
         iter.seek("a".into());
         assert_eq!(
             iter.next(),
@@ -126,6 +146,7 @@ mod tests {
                 &LogRecordPos {
                     file_id: 1,
                     offset: 10,
+                    size: 0,
                 }
             ))
         );
@@ -136,6 +157,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         skl.put(
@@ -143,6 +165,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         skl.put(
@@ -150,6 +173,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         skl.put(
@@ -157,6 +181,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         skl.put(
@@ -164,6 +189,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         skl.put(
@@ -171,6 +197,7 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
         skl.put(
@@ -178,10 +205,13 @@ mod tests {
             LogRecordPos {
                 file_id: 1,
                 offset: 10,
+                size: 0,
             },
         );
 
-        let mut iter = skl.iterator(IteratorOptions::default());
+        let mut iter = skl
+            .iterator(IteratorOptions::default())
+            .expect("Failed to create index iterator");
         while let Some((k, _)) = iter.next() {
             // println!("{}", String::from_utf8(k.clone()).unwrap());
             assert!(!k.is_empty());
@@ -195,19 +225,23 @@ mod tests {
 
         skl.delete("aaa".into());
         // reverse iterator
-        let mut iter = skl.iterator(IteratorOptions {
-            reverse: true,
-            ..Default::default()
-        });
+        let mut iter = skl
+            .iterator(IteratorOptions {
+                reverse: true,
+                ..Default::default()
+            })
+            .expect("Failed to create index iterator");
         while let Some((k, _)) = iter.next() {
             // println!("{}", String::from_utf8(k.clone()).unwrap());
             assert!(!k.is_empty());
         }
         // prefix iterator
-        let mut iter = skl.iterator(IteratorOptions {
-            prefix: "b".into(),
-            ..Default::default()
-        });
+        let mut iter = skl
+            .iterator(IteratorOptions {
+                prefix: "b".into(),
+                ..Default::default()
+            })
+            .expect("Failed to create index iterator");
         while let Some((k, _)) = iter.next() {
             // println!("{}", String::from_utf8(k.clone()).unwrap());
             assert!(k.starts_with(b"b"));
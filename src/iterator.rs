@@ -2,33 +2,244 @@
 #![allow(unused_variables)]
 use std::sync::Arc;
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use parking_lot::RwLock;
 
-use crate::{db::Engine, errors::Result, index::IndexIterator, options::IteratorOptions};
+use crate::{
+    batch::parse_record_sequence_number_with_key,
+    data::log_record::{LogRecord, LogRecordPos, LogRecordType},
+    db::Engine,
+    errors::Result,
+    index::IndexIterator,
+    options::IteratorOptions,
+};
 
 pub struct Iterator<'a> {
     index_iter: Arc<RwLock<Box<dyn IndexIterator>>>,
     engine: &'a Engine,
 }
 
+/// 按数据文件的物理顺序遍历有效记录的迭代器，同时返回记录的位置，
+/// 由Engine::iterate_values_with_positions创建
+pub struct PositionedEntries<'a> {
+    engine: &'a Engine,
+    /// 尚未读取的数据文件id
+    file_ids: std::vec::IntoIter<u32>,
+    /// 当前数据文件中尚未处理的记录
+    records: std::vec::IntoIter<(LogRecord, LogRecordPos)>,
+}
+
 impl Engine {
-    pub fn iter(&self, opts: IteratorOptions) -> Iterator {
-        Iterator {
-            index_iter: Arc::new(RwLock::new(self.index.iterator(opts))),
+    /// 索引在后台加载时，等待加载完成后再创建迭代器，后台加载失败时返回加载的错误，
+    /// 读取B+Tree索引失败时返回IndexOperationFailed
+    pub fn iter(&self, mut opts: IteratorOptions) -> Result<Iterator<'_>> {
+        self.wait_index_ready()?;
+        opts.prefix = self.index_prefix(&opts.prefix);
+        opts.start = opts.start.map(|start| self.index_key(&start));
+        opts.end = opts.end.map(|end| self.index_key(&end));
+        Ok(Iterator {
+            index_iter: Arc::new(RwLock::new(self.index.iterator(opts)?)),
             engine: self,
-        }
+        })
     }
 
+    /// 配置了key_transform时，索引中只有变换后的key，需要从数据文件中读取原始key
     pub fn list_keys(&self) -> Result<Vec<Bytes>> {
-        self.index.list_keys()
+        self.wait_index_ready()?;
+        if self.options.key_transform.is_none() {
+            return self.index.list_keys();
+        }
+        let mut index_iter = self.index.iterator(IteratorOptions::default())?;
+        let mut scratch = BytesMut::new();
+        let mut keys = Vec::new();
+        while let Some((_, pos)) = index_iter.next() {
+            let record = self.read_record_with_buf(pos, &mut scratch)?.0.record;
+            let (_, key) = parse_record_sequence_number_with_key(&record.key);
+            keys.push(key.into());
+        }
+        Ok(keys)
+    }
+
+    /// 统计key以prefix开头的数量，只遍历索引，不读取value，空前缀统计全部key
+    pub fn count_prefix(&self, prefix: Bytes) -> Result<usize> {
+        self.wait_index_ready()?;
+        self.index.count_prefix(&self.index_prefix(&prefix))
+    }
+
+    /// 前缀查询在索引中按变换后的key匹配，空前缀匹配全部key，不做变换
+    fn index_prefix(&self, prefix: &[u8]) -> Vec<u8> {
+        match prefix.is_empty() {
+            true => vec![],
+            false => self.index_key(prefix),
+        }
+    }
+
+    /// 读取索引中一个位置对应的key和value，配置了key_transform时返回记录中的原始key
+    ///
+    /// 记录已删除或已过期时返回None，由调用方跳过
+    fn read_entry(
+        &self,
+        key: &[u8],
+        pos: &LogRecordPos,
+        scratch: &mut BytesMut,
+    ) -> Result<Option<(Bytes, Bytes)>> {
+        let record = self.read_record_with_buf(pos, scratch)?.0.record;
+        if record.rec_type == LogRecordType::Deleted || record.is_expired() {
+            return Ok(None);
+        }
+        let key = match self.options.key_transform {
+            Some(_) => parse_record_sequence_number_with_key(&record.key).1,
+            None => key.to_vec(),
+        };
+        Ok(Some((key.into(), self.decode_value(record)?.into())))
+    }
+
+    /// 获取key以prefix开头的所有key/value，按key升序返回，没有匹配的key时返回空
+    ///
+    /// 先从索引中收集记录位置，再按文件id和偏移的顺序读取value，同一文件中的记录集中顺序读取
+    pub fn get_prefix_map(&self, prefix: Bytes) -> Result<Vec<(Bytes, Bytes)>> {
+        self.wait_index_ready()?;
+        let prefix = self.index_prefix(&prefix);
+        let mut index_iter = self.index.iterator(IteratorOptions::default())?;
+        index_iter.seek(prefix.clone());
+        let mut entries = Vec::new();
+        while let Some((key, pos)) = index_iter.next() {
+            // 索引有序，第一个不匹配的key之后不会再有匹配的key
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            entries.push((key.clone(), *pos));
+        }
+        let mut read_order: Vec<usize> = (0..entries.len()).collect();
+        read_order.sort_by_key(|&i| (entries[i].1.file_id, entries[i].1.offset));
+        let mut scratch = BytesMut::new();
+        let mut key_values = vec![None; entries.len()];
+        for i in read_order {
+            key_values[i] = self.read_entry(&entries[i].0, &entries[i].1, &mut scratch)?;
+        }
+        // 跳过已过期的key
+        Ok(key_values.into_iter().flatten().collect())
+    }
+
+    /// 按数据文件顺序读取所有有效的key/value，返回false时停止，不保证key的顺序
+    ///
+    /// 逐个文件顺序读取记录，只保留索引仍指向该位置的记录，全量扫描时比按索引顺序随机读取更快。
+    /// 配置了scan_prefetch时，后台线程在处理当前文件的同时预读之后的数据文件
+    pub fn scan<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(Bytes, Bytes) -> bool,
+    {
+        self.wait_index_ready()?;
+        let file_ids = self.data_file_ids();
+        if self.options.scan_prefetch == 0 {
+            for file_id in file_ids {
+                if !self.scan_records(self.read_file_records(file_id)?, &mut f)? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        std::thread::scope(|scope| {
+            // 通道容量限制预读的文件数，消费者提前停止时通道关闭，预读线程随之退出
+            let (sender, receiver) = std::sync::mpsc::sync_channel(self.options.scan_prefetch);
+            scope.spawn(move || {
+                for file_id in file_ids {
+                    let records = self.read_file_records(file_id);
+                    #[cfg(test)]
+                    self.scan_prefetch_count
+                        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let failed = records.is_err();
+                    if sender.send(records).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+            for records in receiver {
+                if !self.scan_records(records?, &mut f)? {
+                    break;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// 对一个数据文件中的有效记录调用f，f返回false时返回false
+    fn scan_records<F>(&self, records: Vec<(LogRecord, LogRecordPos)>, f: &mut F) -> Result<bool>
+    where
+        F: FnMut(Bytes, Bytes) -> bool,
+    {
+        for (record, pos) in records {
+            if let Some((key, value)) = self.live_entry(record, &pos)?
+                && !f(key, value)
+            {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 索引仍指向pos且未过期的正常记录返回其key/value，已被覆盖、删除或过期的记录返回None
+    fn live_entry(&self, record: LogRecord, pos: &LogRecordPos) -> Result<Option<(Bytes, Bytes)>> {
+        if record.rec_type != LogRecordType::Normal || record.is_expired() {
+            return Ok(None);
+        }
+        let (_, key) = parse_record_sequence_number_with_key(&record.key);
+        let Some(index_pos) = self.index.get(self.index_key(&key)) else {
+            return Ok(None);
+        };
+        if index_pos.file_id != pos.file_id || index_pos.offset != pos.offset {
+            return Ok(None);
+        }
+        Ok(Some((key.into(), self.decode_value(record)?.into())))
+    }
+
+    /// 按数据文件的物理顺序遍历所有有效的key/value及其在数据文件中的位置，用于构建外部索引
+    ///
+    /// 与scan一样逐个文件顺序读取，只返回索引仍指向该位置的记录，每次只在内存中保留一个文件的记录
+    pub fn iterate_values_with_positions(&self) -> Result<PositionedEntries<'_>> {
+        self.wait_index_ready()?;
+        Ok(PositionedEntries {
+            engine: self,
+            file_ids: self.data_file_ids().into_iter(),
+            records: Vec::new().into_iter(),
+        })
+    }
+
+    /// 按key升序遍历所有key/value，以切片传给f，f返回false时停止，返回错误时停止并返回该错误
+    ///
+    /// 与fold不同，读取数据文件失败时返回错误而不是panic，读取记录时复用同一块缓冲区
+    pub fn for_each<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<bool>,
+    {
+        self.wait_index_ready()?;
+        let mut index_iter = self.index.iterator(IteratorOptions::default())?;
+        let mut scratch = BytesMut::new();
+        while let Some((key, pos)) = index_iter.next() {
+            let record = self.read_record_with_buf(pos, &mut scratch)?.0.record;
+            if record.rec_type == LogRecordType::Deleted || record.is_expired() {
+                continue;
+            }
+            let original_key;
+            let key = match self.options.key_transform {
+                Some(_) => {
+                    original_key = parse_record_sequence_number_with_key(&record.key).1;
+                    &original_key
+                }
+                None => key,
+            };
+            if !f(key, &self.decode_value(record)?)? {
+                break;
+            }
+        }
+        Ok(())
     }
 
     pub fn fold<F>(&self, f: F) -> Result<()>
     where
         F: Fn(Bytes, Bytes) -> bool,
     {
-        let mut iter = self.iter(IteratorOptions::default());
+        let mut iter = self.iter(IteratorOptions::default())?;
         while let Some((k, v)) = iter.next() {
             if !f(k, v) {
                 break;
@@ -44,33 +255,62 @@ impl Iterator<'_> {
         self.index_iter.write().rewind();
     }
 
-    /// 定位到第一个大于（或小于）等于key的记录
+    /// 定位到第一个大于（或小于）等于key的记录，配置了key_transform时按变换后的key定位
     fn seek(&mut self, key: Vec<u8>) {
-        self.index_iter.write().seek(key);
+        self.index_iter.write().seek(self.engine.index_key(&key));
     }
 
-    /// 获取下一个记录，如果迭代器已经到达末尾，则返回None
+    /// 获取下一个记录，跳过已过期的key，如果迭代器已经到达末尾，则返回None
     fn next(&mut self) -> Option<(Bytes, Bytes)> {
         let mut write_guard = self.index_iter.write();
-        if let Some((key, pos)) = write_guard.next() {
-            let value = self
+        while let Some((key, pos)) = write_guard.next() {
+            let entry = self
                 .engine
-                .get_value_by_position(pos)
+                .read_entry(key, pos, &mut BytesMut::new())
                 .expect("Failed to get value from data file");
-            return Some((key.clone().into(), value));
+            if entry.is_some() {
+                return entry;
+            }
         }
         None
     }
 }
 
+impl std::iter::Iterator for PositionedEntries<'_> {
+    type Item = Result<(Bytes, Bytes, LogRecordPos)>;
+
+    /// 读取数据文件失败时返回错误，之后不再返回记录
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for (record, pos) in self.records.by_ref() {
+                match self.engine.live_entry(record, &pos) {
+                    Ok(Some((key, value))) => return Some(Ok((key, value, pos))),
+                    Ok(None) => continue,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            let file_id = self.file_ids.next()?;
+            match self.engine.read_file_records(file_id) {
+                Ok(records) => self.records = records.into_iter(),
+                Err(e) => {
+                    self.file_ids = Vec::new().into_iter();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use crate::{
-        options::{IndexType, Options},
+        options::{IndexType, Options, WriteBatchOptions},
         util::rand_kv::{get_test_key, get_test_value},
     };
 
+    use std::sync::atomic::Ordering;
+
     use super::*;
 
     #[test]
@@ -83,12 +323,15 @@ mod tests {
             bytes_per_sync: 100,
             index_type: IndexType::BTree,
             use_mmap: true,
+            ..Default::default()
         };
         let engine_dir = engine_opts.dir_path.clone();
         let engine = Engine::open(engine_opts).expect("Failed to open engine");
 
         // Empty
-        let mut iter = engine.iter(iter_opts.clone());
+        let mut iter = engine
+            .iter(iter_opts.clone())
+            .expect("Failed to create iterator");
         iter.seek("hello".into());
         assert!(iter.next().is_none());
 
@@ -108,7 +351,9 @@ mod tests {
         engine
             .put(get_test_key(4), get_test_value(4))
             .expect("Failed to put data");
-        let mut iter = engine.iter(iter_opts.clone());
+        let mut iter = engine
+            .iter(iter_opts.clone())
+            .expect("Failed to create iterator");
         iter.seek(get_test_key(0).to_vec());
         let result = iter.next();
         assert_eq!(result, Some((get_test_key(0), get_test_value(0))));
@@ -151,7 +396,9 @@ mod tests {
         engine
             .put("z".into(), get_test_value(5))
             .expect("Failed to put data");
-        let mut iter = engine.iter(iter_opts.clone());
+        let mut iter = engine
+            .iter(iter_opts.clone())
+            .expect("Failed to create iterator");
         iter.seek("aaa".into());
         while let Some((key, value)) = iter.next() {
             // println!(
@@ -173,6 +420,7 @@ mod tests {
             bytes_per_sync: 100,
             index_type: IndexType::BTree,
             use_mmap: true,
+            ..Default::default()
         };
         let engine_dir = engine_opts.dir_path.clone();
         let engine = Engine::open(engine_opts).expect("Failed to open engine");
@@ -211,9 +459,10 @@ mod tests {
         let opts = IteratorOptions {
             reverse: false,
             prefix: "aa".into(),
+            ..Default::default()
         };
 
-        let mut iter = engine.iter(opts);
+        let mut iter = engine.iter(opts).expect("Failed to create iterator");
         iter.seek("aa".into());
         while let Some((key, value)) = iter.next() {
             // println!(
@@ -234,6 +483,7 @@ mod tests {
             bytes_per_sync: 100,
             index_type: IndexType::BTree,
             use_mmap: true,
+            ..Default::default()
         };
         engine_opts.dir_path = std::env::temp_dir().join("test_iterator_reverse");
 
@@ -276,7 +526,7 @@ mod tests {
             reverse: true,
             ..Default::default()
         };
-        let mut iter = engine.iter(opts);
+        let mut iter = engine.iter(opts).expect("Failed to create iterator");
         iter.seek("z".into());
         while let Some((key, value)) = iter.next() {
             // println!(
@@ -289,6 +539,74 @@ mod tests {
         std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
     }
 
+    #[test]
+    fn test_iterator_range() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_iterator_range"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        let key = |i: u32| Bytes::from(format!("key-{:02}", i));
+        for i in 0..20 {
+            engine
+                .put(key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        engine.delete(key(7)).expect("Failed to delete data");
+
+        let collect = |opts: IteratorOptions| {
+            let mut iter = engine.iter(opts).expect("Failed to create iterator");
+            let mut entries = Vec::new();
+            while let Some(entry) = iter.next() {
+                entries.push(entry);
+            }
+            entries
+        };
+        // [key-05, key-12)，已删除的key-07不在结果中
+        let expected: Vec<_> = [5, 6, 8, 9, 10, 11]
+            .into_iter()
+            .map(|i| (key(i), get_test_value(i)))
+            .collect();
+        let opts = IteratorOptions {
+            start: Some(key(5).to_vec()),
+            end: Some(key(12).to_vec()),
+            ..Default::default()
+        };
+        assert_eq!(collect(opts.clone()), expected);
+        // 逆序时从end之前的最大key开始，到start为止
+        let reversed: Vec<_> = expected.iter().rev().cloned().collect();
+        assert_eq!(
+            collect(IteratorOptions {
+                reverse: true,
+                ..opts.clone()
+            }),
+            reversed
+        );
+        // 包括end
+        let entries = collect(IteratorOptions {
+            reverse: true,
+            end_inclusive: true,
+            ..opts
+        });
+        assert_eq!(entries.first(), Some(&(key(12), get_test_value(12))));
+        assert_eq!(entries.last(), Some(&(key(5), get_test_value(5))));
+        // 与prefix同时使用时两个条件都需要满足
+        let entries = collect(IteratorOptions {
+            prefix: "key-1".into(),
+            end: Some(key(13).to_vec()),
+            ..Default::default()
+        });
+        assert_eq!(
+            entries.into_iter().map(|(k, _)| k).collect::<Vec<_>>(),
+            vec![key(10), key(11), key(12)]
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
     #[test]
     fn test_list_keys() {
         let mut engine_opts = Options {
@@ -298,6 +616,7 @@ mod tests {
             bytes_per_sync: 100,
             index_type: IndexType::BTree,
             use_mmap: true,
+            ..Default::default()
         };
         engine_opts.dir_path = std::env::temp_dir().join("test_iterator_list_keys");
 
@@ -319,6 +638,408 @@ mod tests {
         std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
     }
 
+    #[test]
+    fn test_iterator_skip_expired() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_iterator_skip_expired"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let _ = std::fs::remove_dir_all(&engine_dir);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+        engine
+            .put("user:1".into(), get_test_value(1))
+            .expect("Failed to put data");
+        engine
+            .put_with_ttl(
+                "user:2".into(),
+                get_test_value(2),
+                std::time::Duration::from_millis(50),
+            )
+            .expect("Failed to put data");
+        engine
+            .put("user:3".into(), get_test_value(3))
+            .expect("Failed to put data");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        // 已过期的key不返回，也不影响其他key
+        assert_eq!(
+            engine.get_prefix_map("user:".into()),
+            Ok(vec![
+                (Bytes::from("user:1"), get_test_value(1)),
+                (Bytes::from("user:3"), get_test_value(3)),
+            ])
+        );
+        for reverse in [false, true] {
+            let mut iter = engine
+                .iter(IteratorOptions {
+                    reverse,
+                    ..Default::default()
+                })
+                .expect("Failed to create iterator");
+            let mut keys = Vec::new();
+            while let Some((key, _)) = iter.next() {
+                keys.push(key);
+            }
+            if reverse {
+                keys.reverse();
+            }
+            assert_eq!(keys, vec![Bytes::from("user:1"), Bytes::from("user:3")]);
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_get_prefix_map() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_iterator_get_prefix_map"),
+            data_file_size: 128,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+        // 乱序写入嵌套前缀的key，数据分布在多个文件中
+        let keys = [
+            "user:2:name",
+            "user:1:name",
+            "user:10:name",
+            "user:1:email",
+            "user:2:email",
+            "user",
+            "order:1",
+            "user:1:address:city",
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            engine
+                .put(Bytes::from(*key), get_test_value(i as u32))
+                .expect("Failed to put data");
+        }
+        // 覆盖写入后value位于更新的文件中
+        engine
+            .put("user:1:name".into(), "alice".into())
+            .expect("Failed to put data");
+        engine
+            .delete("user:2:email".into())
+            .expect("Failed to delete data");
+        assert!(engine.data_file_ids().len() > 1);
+
+        let result = engine
+            .get_prefix_map("user:1:".into())
+            .expect("Failed to get prefix map");
+        assert_eq!(
+            result,
+            vec![
+                (Bytes::from("user:1:address:city"), get_test_value(7)),
+                (Bytes::from("user:1:email"), get_test_value(3)),
+                (Bytes::from("user:1:name"), Bytes::from("alice")),
+            ]
+        );
+
+        let result = engine
+            .get_prefix_map("user:".into())
+            .expect("Failed to get prefix map");
+        let result_keys: Vec<_> = result.iter().map(|(k, _)| k.clone()).collect();
+        assert_eq!(
+            result_keys,
+            vec![
+                Bytes::from("user:10:name"),
+                Bytes::from("user:1:address:city"),
+                Bytes::from("user:1:email"),
+                Bytes::from("user:1:name"),
+                Bytes::from("user:2:name"),
+            ]
+        );
+        for (key, value) in &result {
+            assert_eq!(engine.get(key.clone()).expect("Failed to get data"), value);
+        }
+
+        // 空前缀返回全部数据
+        assert_eq!(
+            engine
+                .get_prefix_map(Bytes::new())
+                .expect("Failed to get prefix map")
+                .len(),
+            7
+        );
+        // 没有匹配的前缀
+        assert!(
+            engine
+                .get_prefix_map("product:".into())
+                .expect("Failed to get prefix map")
+                .is_empty()
+        );
+        assert!(
+            engine
+                .get_prefix_map("zzz".into())
+                .expect("Failed to get prefix map")
+                .is_empty()
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_count_prefix() {
+        for index_type in [IndexType::BTree, IndexType::SkipList, IndexType::BPlusTree] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir()
+                    .join(format!("test_iterator_count_prefix_{:?}", index_type)),
+                data_file_size: 4 * 1024,
+                index_type,
+                use_mmap: false,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            for i in 0..300 {
+                engine
+                    .put(Bytes::from(format!("user:{}:name", i)), get_test_value(i))
+                    .expect("Failed to put data");
+                if i % 3 == 0 {
+                    engine
+                        .put(Bytes::from(format!("order:{}", i)), get_test_value(i))
+                        .expect("Failed to put data");
+                }
+            }
+            for i in 0..50 {
+                engine
+                    .delete(Bytes::from(format!("user:{}:name", i * 2)))
+                    .expect("Failed to delete data");
+            }
+
+            for prefix in [
+                "", "user:", "user:1", "user:10", "order:", "order:9", "u", "zzz",
+            ] {
+                let prefix = Bytes::from(prefix);
+                assert_eq!(
+                    engine.count_prefix(prefix.clone()),
+                    Ok(engine.get_prefix_map(prefix).unwrap().len())
+                );
+            }
+            assert_eq!(engine.count_prefix(Bytes::new()), Ok(350));
+            assert_eq!(engine.count_prefix("user:".into()), Ok(250));
+
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_scan() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_iterator_scan"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        // 覆盖写入、删除和事务写入
+        for i in (0..500).step_by(3) {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+        }
+        for i in (0..500).step_by(5) {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        for i in 490..510 {
+            batch
+                .put(get_test_key(i), get_test_value(i + 2000))
+                .expect("Failed to put data");
+        }
+        batch.commit().expect("Failed to commit");
+        assert!(engine.data_file_ids().len() > 1);
+
+        let mut scanned = std::collections::HashMap::new();
+        engine
+            .scan(|k, v| {
+                assert!(scanned.insert(k, v).is_none());
+                true
+            })
+            .expect("Failed to scan");
+
+        let mut expected = std::collections::HashMap::new();
+        let mut iter = engine
+            .iter(IteratorOptions::default())
+            .expect("Failed to create iterator");
+        while let Some((k, v)) = iter.next() {
+            expected.insert(k, v);
+        }
+        assert_eq!(scanned.len(), engine.list_keys().unwrap().len());
+        assert_eq!(scanned, expected);
+
+        // 回调返回false时停止
+        let mut count = 0;
+        engine
+            .scan(|_, _| {
+                count += 1;
+                count < 10
+            })
+            .expect("Failed to scan");
+        assert_eq!(count, 10);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_scan_prefetch() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_iterator_scan_prefetch"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            scan_prefetch: 2,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in (0..500).step_by(3) {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+        }
+        for i in (0..500).step_by(5) {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        let file_count = engine.data_file_ids().len();
+        assert!(file_count > 3);
+
+        // 处理第一条记录时阻塞，预读线程应当已经读取了之后的文件
+        let mut scanned = std::collections::HashMap::new();
+        engine
+            .scan(|k, v| {
+                if scanned.is_empty() {
+                    let start = std::time::Instant::now();
+                    while engine.scan_prefetch_count.load(Ordering::SeqCst) < 2 {
+                        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+                        std::thread::sleep(std::time::Duration::from_millis(1));
+                    }
+                }
+                assert!(scanned.insert(k, v).is_none());
+                true
+            })
+            .expect("Failed to scan");
+        assert_eq!(
+            engine.scan_prefetch_count.load(Ordering::SeqCst),
+            file_count
+        );
+
+        let mut expected = std::collections::HashMap::new();
+        let mut iter = engine
+            .iter(IteratorOptions::default())
+            .expect("Failed to create iterator");
+        while let Some((k, v)) = iter.next() {
+            expected.insert(k, v);
+        }
+        assert_eq!(scanned, expected);
+
+        // 提前停止时预读线程随之退出
+        let mut count = 0;
+        engine
+            .scan(|_, _| {
+                count += 1;
+                count < 10
+            })
+            .expect("Failed to scan");
+        assert_eq!(count, 10);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_iterate_values_with_positions() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_iterate_values_with_positions"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+
+        // 多个key共用同一个value，用于构建value到key的倒排索引
+        let group_value = |i: u32| Bytes::from(format!("group-{}", i % 7));
+        for i in 0..300 {
+            engine
+                .put(get_test_key(i), group_value(i))
+                .expect("Failed to put data");
+        }
+        for i in (0..300).step_by(4) {
+            engine
+                .put(get_test_key(i), group_value(i + 1))
+                .expect("Failed to put data");
+        }
+        for i in (0..300).step_by(5) {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        assert!(engine.data_file_ids().len() > 1);
+
+        let mut value_to_keys: std::collections::HashMap<Bytes, Vec<Bytes>> =
+            std::collections::HashMap::new();
+        let mut last_pos: Option<LogRecordPos> = None;
+        let mut count = 0;
+        for entry in engine
+            .iterate_values_with_positions()
+            .expect("Failed to iterate")
+        {
+            let (key, value, pos) = entry.expect("Failed to read entry");
+            // 按物理顺序返回，位置与索引一致
+            if let Some(last) = last_pos {
+                assert!((last.file_id, last.offset) < (pos.file_id, pos.offset));
+            }
+            last_pos = Some(pos);
+            assert_eq!(engine.index.get(key.to_vec()), Some(pos));
+            value_to_keys.entry(value).or_default().push(key);
+            count += 1;
+        }
+        assert_eq!(count, engine.list_keys().unwrap().len());
+
+        // 倒排索引与直接读取的结果一致
+        for (value, keys) in &value_to_keys {
+            for key in keys {
+                assert_eq!(engine.get(key.clone()).as_ref(), Ok(value));
+            }
+        }
+        for i in 0..300 {
+            let found = value_to_keys
+                .values()
+                .any(|keys| keys.contains(&get_test_key(i)));
+            assert_eq!(found, i % 5 != 0);
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
     #[test]
     fn test_fold() {
         let mut engine_opts = Options {
@@ -328,6 +1049,7 @@ mod tests {
             bytes_per_sync: 100,
             index_type: IndexType::BTree,
             use_mmap: true,
+            ..Default::default()
         };
         engine_opts.dir_path = std::env::temp_dir().join("test_iterator_fold");
 
@@ -352,4 +1074,95 @@ mod tests {
             .unwrap();
         std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
     }
+
+    #[test]
+    fn test_for_each() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_iterator_for_each"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..200 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in (0..200).step_by(4) {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+
+        // 汇总所有value的长度，key按升序传入
+        let mut total = 0;
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        engine
+            .for_each(|k, v| {
+                total += v.len();
+                keys.push(k.to_vec());
+                Ok(true)
+            })
+            .expect("Failed to iterate");
+        let expected: usize = (0..200)
+            .filter(|i| i % 4 != 0)
+            .map(|i| get_test_value(i).len())
+            .sum();
+        assert_eq!(total, expected);
+        assert_eq!(keys.len(), 150);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        // 返回false时停止
+        let mut count = 0;
+        engine
+            .for_each(|_, _| {
+                count += 1;
+                Ok(count < 10)
+            })
+            .expect("Failed to iterate");
+        assert_eq!(count, 10);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_for_each_error() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_iterator_for_each_error"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..200 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+
+        // 回调返回的错误原样返回，并停止遍历
+        let mut count = 0;
+        let res = engine.for_each(|_, _| {
+            count += 1;
+            match count {
+                5 => Err(crate::errors::Errors::KeyIsEmpty),
+                _ => Ok(true),
+            }
+        });
+        assert_eq!(res, Err(crate::errors::Errors::KeyIsEmpty));
+        assert_eq!(count, 5);
+
+        // 读取数据文件失败时返回错误而不是panic
+        let file_id = *engine.older_files.read().keys().min().unwrap();
+        std::sync::Arc::make_mut(&mut engine.older_files.write()).remove(&file_id);
+        let res = engine.for_each(|_, _| Ok(true));
+        assert_eq!(res, Err(crate::errors::Errors::DataFileNotFound));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
 }
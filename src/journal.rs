@@ -0,0 +1,243 @@
+#![allow(dead_code)]
+
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::{
+    data::{
+        data_file::DataFile,
+        log_record::{LogRecord, LogRecordType},
+    },
+    db::Engine,
+    errors::{Errors, Result},
+};
+
+/// 操作日志中每条记录value开头的时间戳长度，单位微秒，大端
+const JOURNAL_TIMESTAMP_SIZE: usize = 8;
+
+impl Engine {
+    /// 将成功的写入操作追加到操作日志，未配置ops_journal时直接返回
+    ///
+    /// 每个操作是一条记录，key为原始key，value为时间戳加上写入的value，删除操作只有时间戳，
+    /// 设置了TTL的写入在记录中保存过期时间
+    pub(crate) fn journal_ops<'a, I>(&self, ops: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a [u8], &'a [u8], LogRecordType, Option<u64>)>,
+    {
+        let Some(journal) = &self.ops_journal else {
+            return Ok(());
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut encoded = Vec::new();
+        for (key, value, rec_type, expire_at) in ops {
+            let mut journal_value = BytesMut::with_capacity(JOURNAL_TIMESTAMP_SIZE + value.len());
+            journal_value.put_u64(timestamp);
+            journal_value.extend_from_slice(value);
+            let record = LogRecord {
+                key: key.to_vec(),
+                value: journal_value.to_vec(),
+                rec_type,
+                value_encoded: false,
+                expire_at,
+            };
+            encoded.extend(record.encode());
+        }
+        // 批量提交的多个操作一次写入
+        journal.write(&encoded)?;
+        Ok(())
+    }
+
+    /// 按顺序将操作日志中的操作重放到target，返回重放的操作数
+    ///
+    /// 设置了TTL的写入按记录的过期时间写入，删除不存在的key不视为错误，日志中的记录损坏时返回错误
+    pub fn replay_journal(path: &Path, target: &Engine) -> Result<usize> {
+        if !path.is_file() {
+            return Err(Errors::OpenFileError);
+        }
+        let journal = DataFile::new_ops_journal_file(path)?;
+        let mut offset = 0;
+        let mut count = 0;
+        loop {
+            let read_record = match journal.read_log_record(offset) {
+                Ok(read_record) => read_record,
+                Err(Errors::ReadDataFileEof) => break,
+                Err(e) => return Err(e),
+            };
+            let record = read_record.record;
+            if record.value.len() < JOURNAL_TIMESTAMP_SIZE {
+                return Err(Errors::InvalidLogRecordLength);
+            }
+            let mut value = Bytes::from(record.value);
+            let _timestamp = value.get_u64();
+            match record.rec_type {
+                // 重放时保留原过期时间，已过期的写入同样覆盖之前的value
+                LogRecordType::Normal => {
+                    target.put_record(record.key.into(), value, record.expire_at, true)?
+                }
+                LogRecordType::Deleted => match target.delete(record.key.into()) {
+                    Ok(()) | Err(Errors::KeyNotFound) => {}
+                    Err(e) => return Err(e),
+                },
+                LogRecordType::TxnFinished => {}
+            }
+            offset += read_record.size;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        options::{IndexType, Options, WriteBatchOptions},
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_replay_journal() {
+        let journal_path = std::env::temp_dir().join("test_replay_journal.journal");
+        let _ = std::fs::remove_file(&journal_path);
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_replay_journal"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ops_journal: Some(journal_path.clone()),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..100 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in 0..20 {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+            engine
+                .delete(get_test_key(i + 20))
+                .expect("Failed to delete data");
+        }
+        // 失败的删除不会记录
+        assert_eq!(engine.delete(get_test_key(20)), Err(Errors::KeyNotFound));
+        let mut batch = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .expect("Failed to create write batch");
+        for i in 100..110 {
+            batch
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        batch
+            .delete(get_test_key(50))
+            .expect("Failed to delete data");
+        batch.commit().expect("Failed to commit");
+        engine
+            .put_stream(get_test_key(200), &mut &b"streamed"[..], 8)
+            .expect("Failed to put stream");
+        std::mem::drop(engine);
+
+        // 重新打开后继续追加到同一个操作日志
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .delete(get_test_key(60))
+            .expect("Failed to delete data");
+
+        let target_opts = Options {
+            dir_path: std::env::temp_dir().join("test_replay_journal_target"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let target_dir = target_opts.dir_path.clone();
+        let target = Engine::open(target_opts).expect("Failed to open engine");
+        assert_eq!(
+            Engine::replay_journal(&journal_path, &target),
+            Ok(100 + 40 + 11 + 1 + 1)
+        );
+
+        let keys = engine.list_keys().unwrap();
+        assert_eq!(target.list_keys().unwrap(), keys);
+        for key in keys {
+            assert_eq!(target.get(key.clone()), engine.get(key));
+        }
+        assert_eq!(target.get(get_test_key(200)), Ok(Bytes::from("streamed")));
+        assert_eq!(target.get(get_test_key(60)), Err(Errors::KeyNotFound));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        std::fs::remove_dir_all(target_dir).expect("Failed to remove test directory");
+        std::fs::remove_file(journal_path).expect("Failed to remove journal file");
+    }
+
+    #[test]
+    fn test_replay_journal_ttl() {
+        let journal_path = std::env::temp_dir().join("test_replay_journal_ttl.journal");
+        let _ = std::fs::remove_file(&journal_path);
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_replay_journal_ttl"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ops_journal: Some(journal_path.clone()),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine
+            .put(get_test_key(0), get_test_value(0))
+            .expect("Failed to put data");
+        engine
+            .put_with_ttl(
+                get_test_key(0),
+                get_test_value(1),
+                std::time::Duration::from_millis(50),
+            )
+            .expect("Failed to put data");
+        engine
+            .put_with_ttl(
+                get_test_key(1),
+                get_test_value(1),
+                std::time::Duration::from_secs(3600),
+            )
+            .expect("Failed to put data");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        std::mem::drop(engine);
+
+        // 重放后保留过期时间，已过期的写入覆盖之前的value
+        let target_opts = Options {
+            dir_path: std::env::temp_dir().join("test_replay_journal_ttl_target"),
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let target_dir = target_opts.dir_path.clone();
+        let target = Engine::open(target_opts).expect("Failed to open engine");
+        assert_eq!(Engine::replay_journal(&journal_path, &target), Ok(3));
+        assert_eq!(target.get(get_test_key(0)), Err(Errors::KeyNotFound));
+        assert_eq!(target.get(get_test_key(1)), Ok(get_test_value(1)));
+        let position = target.index.get(get_test_key(1).to_vec()).unwrap();
+        assert!(
+            target
+                .read_file_records(position.file_id)
+                .unwrap()
+                .iter()
+                .any(|(record, _)| record.expire_at.is_some())
+        );
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        std::fs::remove_dir_all(target_dir).expect("Failed to remove test directory");
+        std::fs::remove_file(journal_path).expect("Failed to remove journal file");
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// 直方图的桶数，第i个桶统计耗时在[2^(i-1), 2^i)微秒内的操作，最后一个桶包含所有更慢的操作
+const BUCKET_COUNT: usize = 32;
+
+/// 按2的幂划分桶的延迟直方图，记录时只有原子加法
+#[derive(Default)]
+pub(crate) struct LatencyHistogram {
+    buckets: [AtomicU64; BUCKET_COUNT],
+    count: AtomicU64,
+    total_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let idx = ((u64::BITS - micros.leading_zeros()) as usize).min(BUCKET_COUNT - 1);
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// 开始计时，返回的计时器在drop时记录耗时
+    pub(crate) fn start(&self) -> LatencyTimer<'_> {
+        LatencyTimer {
+            histogram: self,
+            start: Instant::now(),
+        }
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            count: self.count.load(Ordering::Relaxed),
+            total: Duration::from_micros(self.total_micros.load(Ordering::Relaxed)),
+            buckets: self
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+}
+
+pub(crate) struct LatencyTimer<'a> {
+    histogram: &'a LatencyHistogram,
+    start: Instant,
+}
+
+impl Drop for LatencyTimer<'_> {
+    fn drop(&mut self) {
+        self.histogram.record(self.start.elapsed());
+    }
+}
+
+/// get、put、merge的延迟统计
+#[derive(Default)]
+pub(crate) struct LatencyTracker {
+    pub(crate) get: LatencyHistogram,
+    pub(crate) put: LatencyHistogram,
+    pub(crate) merge: LatencyHistogram,
+}
+
+impl LatencyTracker {
+    pub(crate) fn report(&self) -> LatencyReport {
+        LatencyReport {
+            get: self.get.snapshot(),
+            put: self.put.snapshot(),
+            merge: self.merge.snapshot(),
+        }
+    }
+}
+
+/// 某一类操作的延迟直方图快照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// 操作次数
+    pub count: u64,
+    /// 总耗时
+    pub total: Duration,
+    /// 各个桶的操作次数，第i个桶统计耗时在[2^(i-1), 2^i)微秒内的操作
+    pub buckets: Vec<u64>,
+}
+
+/// Engine::latency_report返回的延迟报告
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyReport {
+    pub get: HistogramSnapshot,
+    pub put: HistogramSnapshot,
+    pub merge: HistogramSnapshot,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_histogram_record() {
+        let histogram = LatencyHistogram::default();
+        histogram.record(Duration::ZERO);
+        histogram.record(Duration::from_micros(1));
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_secs(1_000_000));
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 4);
+        assert_eq!(snapshot.buckets[0], 1);
+        assert_eq!(snapshot.buckets[1], 1);
+        assert_eq!(snapshot.buckets[2], 1);
+        assert_eq!(snapshot.buckets[BUCKET_COUNT - 1], 1);
+    }
+}
@@ -5,6 +5,8 @@ mod errors;
 mod fio;
 mod index;
 mod iterator;
+mod journal;
+mod latency;
 mod merge;
 mod options;
 mod util;
@@ -1,9 +1,16 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
 
-use std::path::{Path, PathBuf};
+use std::{
+    ffi::OsString,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use log::error;
+use bytes::{Bytes, BytesMut};
+use log::{error, warn};
 
 use crate::{
     batch::{
@@ -12,141 +19,859 @@ use crate::{
     },
     data::{
         data_file::{
-            DataFile, HINT_FILE_NAME, MERGE_FINISHED_FILE_NAME, SEQUENCE_NUMBER_FILE_NAME,
+            DATA_FILE_NAME_SUFFIX, DataFile, HINT_FILE_NAME, HINT_RAW_FORMAT_MARK,
+            HINT_TMP_FILE_NAME, INLINE_HINTS_FILE_NAME, MERGE_FINISHED_FILE_NAME,
+            MERGE_PROGRESS_FILE_NAME, SEQUENCE_NUMBER_FILE_NAME, VERSION_FILE_NAME,
             create_data_file_name,
         },
-        log_record::{LogRecord, LogRecordType, decode_log_record_pos},
+        log_record::{LogRecord, LogRecordPos, LogRecordType, decode_log_record_pos},
     },
-    db::{Engine, FILE_LOCK_NAME},
+    db::{Engine, FILE_LOCK_NAME, key_in_keyspaces, update_index},
     errors::{Errors, Result},
-    options::{IOType, Options},
+    index::{BPTREE_INDEX_FILE_NAME, check_index_budget},
+    options::{Format, IOType, IteratorOptions, Options},
 };
 
 const MERGE_DIR_SUFFIX: &str = "merge";
+/// merge目录中记录源db路径的文件，防止加载属于其他db的merge目录
+const MERGE_SOURCE_FILE_NAME: &str = "merge-source";
 const MERGE_FINISHED_KEY: &str = "merge.finished";
+/// merge进度文件的起始记录，value为本次merge的数据文件id上界
+const MERGE_PROGRESS_START_KEY: &str = "merge.start";
+/// merge进度文件的检查点记录，value为处理完的文件id、hint文件大小、输出文件id和写偏移
+const MERGE_PROGRESS_FILE_KEY: &str = "merge.file";
+/// 部分merge完成的标识，value为被merge的数据文件id列表
+const MERGE_FINISHED_FILES_KEY: &str = "merge.finished.files";
+/// merge完成文件中的统计记录，value为MergeStat的编码
+const MERGE_STAT_KEY: &str = "merge.stat";
+
+/// 一次merge的统计信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeStat {
+    /// merge完成的时间
+    pub finished_at: SystemTime,
+    /// merge耗时
+    pub duration: Duration,
+    /// 回收的字节数，即被merge的数据文件与merge后数据文件的大小之差
+    pub reclaimed_bytes: u64,
+    /// 被merge的数据文件数
+    pub files_before: usize,
+    /// merge后的数据文件数
+    pub files_after: usize,
+}
+
+impl MergeStat {
+    /// 编码为 完成时间(毫秒),耗时(微秒),回收字节数,merge前文件数,merge后文件数
+    fn encode(&self) -> Vec<u8> {
+        let finished_at = self
+            .finished_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        format!(
+            "{},{},{},{},{}",
+            finished_at.as_millis(),
+            self.duration.as_micros(),
+            self.reclaimed_bytes,
+            self.files_before,
+            self.files_after
+        )
+        .into_bytes()
+    }
+
+    fn decode(value: &[u8]) -> Option<Self> {
+        let value = std::str::from_utf8(value).ok()?;
+        let fields: Vec<&str> = value.split(',').collect();
+        if fields.len() != 5 {
+            return None;
+        }
+        Some(Self {
+            finished_at: UNIX_EPOCH + Duration::from_millis(fields[0].parse().ok()?),
+            duration: Duration::from_micros(fields[1].parse().ok()?),
+            reclaimed_bytes: fields[2].parse().ok()?,
+            files_before: fields[3].parse().ok()?,
+            files_after: fields[4].parse().ok()?,
+        })
+    }
+}
+
+/// merge的进度，每处理完一个数据文件通过merge_with_progress的回调报告一次
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeProgress {
+    /// 已处理完的数据文件数
+    pub files_processed: usize,
+    /// 本次需要处理的数据文件数，从中断处继续时不包括已处理完的文件
+    pub files_total: usize,
+    /// 已重写的记录数，配置了sort_on_merge或merge_recent_first时为已收集的有效记录数，所有文件处理完后再统一写入
+    pub records_rewritten: usize,
+}
+
+/// 一个数据文件中的有效记录：去掉序列号的key、记录和记录偏移
+type MergeRecords = Vec<(Vec<u8>, LogRecord, u64)>;
 
 impl Engine {
+    /// 最近一次merge的统计信息，从未merge过时返回None
+    ///
+    /// 完整merge的统计保存在merge完成文件中，重启后仍然可以获取；
+    /// 配置了merge_dead_ratio的部分merge只在内存中记录
+    pub fn last_merge_stat(&self) -> Option<MergeStat> {
+        self.last_merge_stat.read().clone()
+    }
+
     /// merge 数据目录，处理无效数据，并生成hint索引文件
+    ///
+    /// 可回收字节的比例低于data_file_merge_ratio时返回MergeRatioUnreached，
+    /// 磁盘可用空间放不下所有有效数据时返回InsufficientSpaceForMerge
     pub fn merge(&self) -> Result<()> {
+        self.merge_with_progress(|_| {})
+    }
+
+    /// 与merge相同，每处理完一个数据文件调用一次cb报告进度
+    pub fn merge_with_progress(&self, cb: impl Fn(MergeProgress)) -> Result<()> {
+        let _timer = self.latency.as_ref().map(|latency| latency.merge.start());
         // 如果正在merge，直接返回，因为只允许单进程merge
+        self.check_writable()?;
         let lock = self.merge_lock.try_lock();
         if lock.is_none() {
             return Err(Errors::MergeInProgress);
         }
+        // 只加载了部分keyspace时，不在索引中的key也可能有效
+        if self.options.load_keyspaces.is_some() {
+            return Err(Errors::PartialIndexUnsupported);
+        }
+        // merge通过数据目录下的merge目录完成，纯内存模式没有数据目录
+        if self.options.in_memory {
+            return Err(Errors::InMemoryUnsupported);
+        }
+        // 索引加载完成后才能判断记录是否有效
+        self.wait_index_ready()?;
+        // 可回收的数据太少时不merge，避免无意义的IO
+        if self.options.data_file_merge_ratio > 0.0
+            && self.reclaimable_ratio()? < self.options.data_file_merge_ratio
+        {
+            return Err(Errors::MergeRatioUnreached);
+        }
+        self.check_merge_space()?;
+        let start = Instant::now();
+        if let Some(ratio) = self.options.merge_dead_ratio {
+            return self.merge_dead_files(ratio, start, &cb);
+        }
 
         let merge_dir = create_merge_dir(&self.options.dir_path);
-        if merge_dir.is_dir() {
-            std::fs::remove_dir_all(&merge_dir).map_err(|_| Errors::RemoveDirError)?;
-        }
-        std::fs::create_dir_all(&merge_dir).map_err(|_| Errors::FailedToCreateDatabaseDir)?;
-        // 获取需要merge的数据文件
-        let merge_files = self.ratate_merge_files()?;
+        // 按key或更新时间排序、内联hint时，hint记录在最后统一写入，无法按文件记录进度
+        let reorder = self.options.sort_on_merge || self.options.merge_recent_first;
+        let resumable = !reorder && !self.options.inline_hints;
+        // 上次merge中断时，从最后一个检查点继续，跳过已处理完的数据文件
+        let progress = match !resumable {
+            true => None,
+            false if !merge_source_matches(&self.options.dir_path, &merge_dir) => None,
+            false => load_merge_progress(&merge_dir)?,
+        };
+        let (merge_files, non_merge_file_id, progress_file) = match progress {
+            Some(progress) => {
+                progress.truncate_outputs(&merge_dir)?;
+                let merge_files = self.resume_merge_files(&progress)?;
+                let progress_file = DataFile::new_merge_progress_file(&merge_dir)?;
+                (merge_files, progress.non_merge_file_id, Some(progress_file))
+            }
+            None => {
+                if merge_dir.is_dir() {
+                    std::fs::remove_dir_all(&merge_dir).map_err(|_| Errors::RemoveDirError)?;
+                }
+                std::fs::create_dir_all(&merge_dir)
+                    .map_err(|_| Errors::FailedToCreateDatabaseDir)?;
+                write_merge_source(&self.options.dir_path, &merge_dir)?;
+                // 获取需要merge的数据文件
+                let merge_files = self.ratate_merge_files()?;
+                // 原engine的当前活跃数据文件未merge
+                let non_merge_file_id = merge_files.last().unwrap().get_file_id() + 1;
+                let progress_file = match !resumable {
+                    true => None,
+                    false => {
+                        let progress_file = DataFile::new_merge_progress_file(&merge_dir)?;
+                        write_merge_progress_record(
+                            &progress_file,
+                            MERGE_PROGRESS_START_KEY,
+                            non_merge_file_id.to_string(),
+                        )?;
+                        Some(progress_file)
+                    }
+                };
+                (merge_files, non_merge_file_id, progress_file)
+            }
+        };
 
         // 创建merge engine，依次打开每个数据文件并读取记录，构建hint索引文件
         let opts = Options {
             data_file_size: self.options.data_file_size,
             dir_path: merge_dir.clone(),
             index_type: self.options.index_type,
+            key_prefix_restart_interval: self.options.key_prefix_restart_interval,
             ..Default::default()
         };
         let merge_engine = Engine::open(opts)?;
 
-        // 创建hint索引文件,写入hint索引
-        let hint_file = DataFile::new_hint_file(&merge_dir)?;
-        for data_file in &merge_files {
-            let mut offset = 0;
-            loop {
-                let (mut log_record, size) = match data_file.read_log_record(offset) {
-                    Ok(v) => (v.record, v.size),
-                    Err(e) => {
-                        if e == Errors::ReadDataFileEof {
-                            // 读取到文件末尾，退出循环,读取下一个文件
-                            break;
+        // 先写入临时hint索引文件，全部写完并持久化后再rename，避免崩溃时留下不完整的hint文件
+        // 索引只保存在内存中时不生成hint文件，打开时从merge后的数据文件重建索引，
+        // 内联hint时在merge后的数据文件末尾写入hint
+        let hint_file = match self.options.index_only_in_memory || self.options.inline_hints {
+            true => None,
+            false => Some(DataFile::new_hint_tmp_file(&merge_dir)?),
+        };
+        let hint_crc = self.options.hint_crc;
+        if let Some(hint_file) = &hint_file
+            && !hint_crc
+            && hint_file.file_size() == 0
+        {
+            hint_file.write(&[HINT_RAW_FORMAT_MARK])?;
+        }
+        let mut scratch = BytesMut::new();
+        // 需要排序时，先只收集有效记录的key和位置，写入时再读取value，避免在内存中保存所有value
+        let mut sorted_records = Vec::new();
+        let mut handle_record = |file_idx: usize,
+                                 real_key: Vec<u8>,
+                                 log_record: LogRecord,
+                                 offset: u64| match reorder {
+            true => {
+                sorted_records.push((real_key, file_idx, offset));
+                Ok(())
+            }
+            false => write_merge_record(
+                &merge_engine,
+                hint_file.as_ref(),
+                hint_crc,
+                real_key,
+                log_record,
+            ),
+        };
+        // 多线程时每次并行读取merge_threads个文件中的有效记录，再按文件顺序写入，输出与单线程一致
+        let merge_threads = self.options.merge_threads.max(1);
+        let mut records_rewritten = 0;
+        for (chunk_idx, chunk) in merge_files.chunks(merge_threads).enumerate() {
+            let mut collected = match merge_threads {
+                1 => None,
+                _ => Some(self.collect_merge_records(chunk)?),
+            };
+            for (i, data_file) in chunk.iter().enumerate() {
+                let file_idx = chunk_idx * merge_threads + i;
+                match &mut collected {
+                    None => self.read_merge_file(
+                        data_file,
+                        &mut scratch,
+                        |real_key, log_record, offset| {
+                            records_rewritten += 1;
+                            handle_record(file_idx, real_key, log_record, offset)
+                        },
+                    )?,
+                    Some(collected) => {
+                        for (real_key, log_record, offset) in std::mem::take(&mut collected[i]) {
+                            records_rewritten += 1;
+                            handle_record(file_idx, real_key, log_record, offset)?;
                         }
-                        return Err(e);
                     }
-                };
-                let (_, real_key) = parse_record_sequence_number_with_key(&log_record.key);
-                if let Some(idx_pos) = self.index.get(real_key.clone()) {
-                    // 如果索引位置对应的文件id和偏移量都匹配，则是有效记录
-                    if idx_pos.file_id == data_file.get_file_id() && idx_pos.offset == offset {
-                        // 去除key中事务id
-                        log_record.key = get_record_sequence_number_with_key(
-                            &real_key,
-                            NON_TRANSACTION_SEQ_NUMBER,
-                        );
-                        // 写入数据文件
-                        let record_pos = merge_engine.append_log_record(&mut log_record)?;
-                        // 写入hint索引文件
-                        hint_file.write_hint_record(real_key, record_pos)?;
+                }
+                // 数据文件处理完成，持久化输出并记录检查点
+                if let Some(progress_file) = &progress_file {
+                    merge_engine.sync()?;
+                    if let Some(hint_file) = &hint_file {
+                        hint_file.sync()?;
                     }
+                    let active_file = merge_engine.active_file.read();
+                    write_merge_progress_record(
+                        progress_file,
+                        MERGE_PROGRESS_FILE_KEY,
+                        format!(
+                            "{},{},{},{}",
+                            data_file.get_file_id(),
+                            hint_file
+                                .as_ref()
+                                .map_or(0, |hint_file| hint_file.file_size()),
+                            active_file.get_file_id(),
+                            active_file.get_write_offset()
+                        ),
+                    )?;
                 }
-                offset += size;
+                cb(MergeProgress {
+                    files_processed: file_idx + 1,
+                    files_total: merge_files.len(),
+                    records_rewritten,
+                });
             }
         }
+        match self.options.merge_recent_first {
+            // 数据文件按id、文件内按偏移的顺序写入，位置越靠后的记录更新越晚，倒序写入时最近更新的在前
+            true => sorted_records.sort_unstable_by_key(|r| std::cmp::Reverse((r.1, r.2))),
+            // 按key排序后写入，merge后的数据文件和hint文件都按key有序
+            false => sorted_records.sort_unstable_by(|a, b| a.0.cmp(&b.0)),
+        }
+        for (real_key, file_idx, offset) in sorted_records {
+            let log_record = merge_files[file_idx]
+                .read_log_record_with_buf(offset, &mut scratch)?
+                .record;
+            write_merge_record(
+                &merge_engine,
+                hint_file.as_ref(),
+                hint_crc,
+                real_key,
+                log_record,
+            )?;
+        }
 
         // 持久化merge engine
         merge_engine.sync()?;
-        // 持久化hint索引文件
-        hint_file.sync()?;
+        if self.options.inline_hints && !self.options.index_only_in_memory {
+            let active_file = merge_engine.active_file.read();
+            let older_files = merge_engine.older_files.read();
+            for data_file in older_files
+                .values()
+                .map(Arc::as_ref)
+                .chain(std::iter::once(&*active_file))
+            {
+                write_inline_hints(data_file)?;
+            }
+            write_inline_hints_file_id(&merge_dir, non_merge_file_id)?;
+        }
+        if let Some(hint_file) = &hint_file {
+            // 持久化hint索引文件
+            hint_file.sync()?;
+            // 原子替换为正式的hint索引文件
+            std::fs::rename(
+                merge_dir.join(HINT_TMP_FILE_NAME),
+                merge_dir.join(HINT_FILE_NAME),
+            )
+            .map_err(|e| {
+                error!("Failed to rename hint file: {}", e);
+                Errors::RenameFileError
+            })?;
+        }
+
+        // 被merge的数据文件在加载merge目录之前仍保留在db目录中
+        let (files_before, bytes_before) = self.data_files_stat(non_merge_file_id);
+        let (files_after, bytes_after) = merge_engine.data_files_stat(u32::MAX);
+        let stat = MergeStat {
+            finished_at: SystemTime::now(),
+            duration: start.elapsed(),
+            reclaimed_bytes: bytes_before.saturating_sub(bytes_after),
+            files_before,
+            files_after,
+        };
 
-        // 原engine的当前活跃数据文件未merge
-        let non_merge_file_id = merge_files.last().unwrap().get_file_id() + 1;
-        // 创建标识merge完成的文件
+        // 创建标识merge完成的文件，之后追加本次merge的统计信息
         let merge_finished_file = DataFile::new_merge_finished_file(&merge_dir)?;
         let merge_finished_record = LogRecord {
             key: MERGE_FINISHED_KEY.as_bytes().to_vec(),
             value: non_merge_file_id.to_string().into_bytes(),
             rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
+        };
+        let merge_stat_record = LogRecord {
+            key: MERGE_STAT_KEY.as_bytes().to_vec(),
+            value: stat.encode(),
+            rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
         };
-        let encoded_record = merge_finished_record.encode();
+        let mut encoded_record = merge_finished_record.encode();
+        encoded_record.extend(merge_stat_record.encode());
         merge_finished_file.write(&encoded_record)?;
         // 持久化标识merge完成的文件
         merge_finished_file.sync()?;
+        *self.last_merge_stat.write() = Some(stat);
+
+        Ok(())
+    }
+
+    /// 将key在[start, end)范围内、不在活跃文件中的有效记录重写到活跃文件，
+    /// 使其旧位置可以被回收，返回旧记录的总字节数
+    ///
+    /// 只处理范围内的key，比完整merge的粒度更细，旧位置在之后的merge中回收
+    pub fn compact_range(&self, start: Bytes, end: Bytes) -> Result<u64> {
+        self.check_writable()?;
+        self.wait_index_ready()?;
+        let _merge_guard = self.wait_merge_if_blocking();
+        // 先收集范围内的key，重写时需要更新索引，索引中是变换后的key，范围同样变换后再比较
+        let start = self.index_key(&start);
+        let end = self.index_key(&end);
+        let mut positions = Vec::new();
+        {
+            let mut index_iter = self.index.iterator(IteratorOptions::default())?;
+            index_iter.seek(start);
+            while let Some((key, pos)) = index_iter.next() {
+                if *key >= end {
+                    break;
+                }
+                positions.push((key.clone(), *pos));
+            }
+        }
+        let mut reclaimed = 0;
+        let mut scratch = BytesMut::new();
+        for (key, pos) in positions {
+            if pos.file_id == self.active_file.read().get_file_id() {
+                continue;
+            }
+            let mut record = self.read_record_with_buf(&pos, &mut scratch)?.0.record;
+            if record.rec_type == LogRecordType::Deleted {
+                continue;
+            }
+            // 持有写锁直到索引更新完成，检查之后key不会再被并发写入
+            let _index_guard = self.index_update_lock.write();
+            // 读取之后key已被改写，不再需要重写
+            if self.index.get(key.clone()) != Some(pos) {
+                continue;
+            }
+            // 原样写入原始key和value，已编码的value不会重复编码
+            let (_, original_key) = parse_record_sequence_number_with_key(&record.key);
+            record.key =
+                get_record_sequence_number_with_key(&original_key, NON_TRANSACTION_SEQ_NUMBER);
+            let new_pos = self.append_log_record(&mut record)?;
+            update_index(
+                self.index.as_ref(),
+                &self.dead_bytes,
+                key,
+                LogRecordType::Normal,
+                new_pos,
+            )?;
+            reclaimed += pos.size as u64;
+        }
+        Ok(reclaimed)
+    }
+
+    /// 所有数据文件中已失效、可以被merge回收的字节数
+    pub fn reclaimable_bytes(&self) -> Result<u64> {
+        self.wait_index_ready()?;
+        Ok(self.dead_bytes.read().values().sum())
+    }
+
+    /// 索引中所有有效记录在数据文件中占用的字节数，即merge后数据文件的估算大小
+    pub(crate) fn live_data_size(&self) -> Result<u64> {
+        let mut index_iter = self.index.iterator(IteratorOptions::default())?;
+        let mut size = 0;
+        while let Some((_, pos)) = index_iter.next() {
+            size += pos.size as u64;
+        }
+        Ok(size)
+    }
+
+    /// merge前检查磁盘可用空间能否放下所有有效数据，配置了min_free_bytes时还需保留该空间，
+    /// 避免merge写到一半因磁盘写满而失败
+    fn check_merge_space(&self) -> Result<()> {
+        let required = self
+            .live_data_size()?
+            .saturating_add(self.options.min_free_bytes.unwrap_or(0));
+        let available = fs2::available_space(&self.options.dir_path).map_err(|e| {
+            error!("Failed to get available disk space: {}", e);
+            Errors::DiskSpaceCheckError
+        })?;
+        if available < required {
+            error!(
+                "Not enough disk space to merge: {} bytes required, {} available",
+                required, available
+            );
+            return Err(Errors::InsufficientSpaceForMerge {
+                required,
+                available,
+            });
+        }
+        Ok(())
+    }
+
+    /// 所有数据文件占用的磁盘空间，包括可以被merge回收的部分
+    pub fn disk_size(&self) -> u64 {
+        self.data_files_stat(u32::MAX).1
+    }
+
+    /// 所有有效记录占用的字节数，与disk_size之差即为可回收的字节数
+    pub fn logical_size(&self) -> Result<u64> {
+        self.wait_index_ready()?;
+        self.live_data_size()
+    }
+
+    /// 可回收字节占所有数据文件总大小的比例，没有数据时为0
+    pub(crate) fn reclaimable_ratio(&self) -> Result<f32> {
+        let reclaimable = self.reclaimable_bytes()?;
+        let total = self.disk_size();
+        match total {
+            0 => Ok(0.0),
+            _ => Ok(reclaimable as f32 / total as f32),
+        }
+    }
+
+    /// 文件id小于max_file_id的非空数据文件数及其总大小
+    fn data_files_stat(&self, max_file_id: u32) -> (usize, u64) {
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        older_files
+            .values()
+            .map(Arc::as_ref)
+            .chain(std::iter::once(&*active_file))
+            .filter(|data_file| data_file.get_file_id() < max_file_id && data_file.file_size() > 0)
+            .fold((0, 0), |(count, size), data_file| {
+                (count + 1, size + data_file.file_size())
+            })
+    }
+
+    /// 获取中断的merge中尚未处理完的数据文件
+    fn resume_merge_files(&self, progress: &MergeCheckpoint) -> Result<Vec<DataFile>> {
+        let older_files = self.older_files.read();
+        let mut merge_file_ids: Vec<u32> = older_files
+            .keys()
+            .copied()
+            .filter(|file_id| {
+                *file_id < progress.non_merge_file_id && !progress.merged_file_ids.contains(file_id)
+            })
+            .collect();
+        merge_file_ids.sort();
+        let mut merge_files = Vec::new();
+        for f_id in merge_file_ids {
+            let mut data_file =
+                DataFile::new(&self.options.dir_path, f_id, IOType::StandardFileIO)?;
+            let older_file = older_files.get(&f_id).unwrap();
+            data_file.set_format(older_file.get_format());
+            data_file.set_inline_hints_offset(older_file.get_inline_hints_offset());
+            merge_files.push(data_file);
+        }
+        Ok(merge_files)
+    }
+
+    /// 只merge可回收字节占比不低于ratio的旧数据文件，其余文件保持不变
+    ///
+    /// 有效记录按文件id顺序写入一个新文件，新文件使用被merge文件中最大的id，
+    /// 保证加载索引时其中的记录仍然晚于未被merge的更旧文件中的记录
+    ///
+    /// 存在更旧的未被merge的文件时，其中可能有已删除key的旧value，仍然有效的删除记录需要保留
+    fn merge_dead_files(
+        &self,
+        ratio: f32,
+        start: Instant,
+        cb: &dyn Fn(MergeProgress),
+    ) -> Result<()> {
+        let mut merge_file_ids = Vec::new();
+        let mut bytes_before = 0;
+        let mut oldest_unmerged_file_id = None;
+        {
+            let older_files = self.older_files.read();
+            let dead_bytes = self.dead_bytes.read();
+            for data_file in older_files.values() {
+                let file_size = data_file.file_size();
+                let dead = dead_bytes
+                    .get(&data_file.get_file_id())
+                    .copied()
+                    .unwrap_or_default();
+                // Go版本bitcask格式的文件只读，不参与部分merge
+                if data_file.get_format() == Format::Native
+                    && file_size > 0
+                    && dead as f64 >= file_size as f64 * ratio as f64
+                {
+                    merge_file_ids.push(data_file.get_file_id());
+                    bytes_before += file_size;
+                } else {
+                    let file_id = data_file.get_file_id();
+                    oldest_unmerged_file_id =
+                        Some(oldest_unmerged_file_id.map_or(file_id, |id: u32| id.min(file_id)));
+                }
+            }
+        }
+        if merge_file_ids.is_empty() {
+            return Ok(());
+        }
+        merge_file_ids.sort_unstable();
+
+        let merge_dir = create_merge_dir(&self.options.dir_path);
+        if merge_dir.is_dir() {
+            std::fs::remove_dir_all(&merge_dir).map_err(|_| Errors::RemoveDirError)?;
+        }
+        std::fs::create_dir_all(&merge_dir).map_err(|_| Errors::FailedToCreateDatabaseDir)?;
+        write_merge_source(&self.options.dir_path, &merge_dir)?;
+        let output_file = DataFile::new(
+            &merge_dir,
+            *merge_file_ids.last().unwrap(),
+            IOType::StandardFileIO,
+        )?;
+        let mut scratch = BytesMut::new();
+        let mut records_rewritten = 0;
+        for (file_idx, file_id) in merge_file_ids.iter().enumerate() {
+            let mut data_file =
+                DataFile::new(&self.options.dir_path, *file_id, IOType::StandardFileIO)?;
+            if let Some(older_file) = self.older_files.read().get(file_id) {
+                data_file.set_inline_hints_offset(older_file.get_inline_hints_offset());
+            }
+            let mut offset = 0;
+            loop {
+                let (mut log_record, size) =
+                    match data_file.read_log_record_with_buf(offset, &mut scratch) {
+                        Ok(v) => (v.record, v.size),
+                        Err(Errors::ReadDataFileEof) => break,
+                        Err(e) => return Err(e),
+                    };
+                let (_, real_key) = parse_record_sequence_number_with_key(&log_record.key);
+                match log_record.rec_type {
+                    // 保留事务完成记录，未被merge的更旧文件中可能有该事务的记录
+                    LogRecordType::TxnFinished => {
+                        output_file.write(&log_record.encode())?;
+                        records_rewritten += 1;
+                    }
+                    // key仍处于删除状态，且更旧的未被merge的文件中可能有它的value，保留删除记录，
+                    // 事务中的删除记录保留原key，与保留的事务完成记录一起生效
+                    LogRecordType::Deleted
+                        if oldest_unmerged_file_id.is_some_and(|id| id < *file_id)
+                            && self.index.get(self.index_key(&real_key)).is_none() =>
+                    {
+                        output_file.write(&log_record.encode())?;
+                        records_rewritten += 1;
+                    }
+                    _ => {
+                        if let Some(idx_pos) = self.index.get(self.index_key(&real_key))
+                            && idx_pos.file_id == *file_id
+                            && idx_pos.offset == offset
+                        {
+                            // 已过期的记录不再写入，更旧的未被merge的文件中可能有它的旧value，
+                            // 此时写入删除记录代替，避免重新加载后旧value重新生效
+                            if log_record.is_expired() {
+                                if oldest_unmerged_file_id.is_none_or(|id| id > *file_id) {
+                                    offset += size;
+                                    continue;
+                                }
+                                log_record.value.clear();
+                                log_record.rec_type = LogRecordType::Deleted;
+                                log_record.value_encoded = false;
+                                log_record.expire_at = None;
+                            }
+                            log_record.key = get_record_sequence_number_with_key(
+                                &real_key,
+                                NON_TRANSACTION_SEQ_NUMBER,
+                            );
+                            output_file.write(&log_record.encode())?;
+                            records_rewritten += 1;
+                        }
+                    }
+                }
+                offset += size;
+            }
+            cb(MergeProgress {
+                files_processed: file_idx + 1,
+                files_total: merge_file_ids.len(),
+                records_rewritten,
+            });
+        }
+        output_file.sync()?;
+
+        // 创建标识merge完成的文件，记录被merge的文件id
+        let merge_finished_file = DataFile::new_merge_finished_file(&merge_dir)?;
+        let merge_finished_record = LogRecord {
+            key: MERGE_FINISHED_FILES_KEY.as_bytes().to_vec(),
+            value: merge_file_ids
+                .iter()
+                .map(|file_id| file_id.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+                .into_bytes(),
+            rec_type: LogRecordType::Normal,
+            value_encoded: false,
+            expire_at: None,
+        };
+        merge_finished_file.write(&merge_finished_record.encode())?;
+        merge_finished_file.sync()?;
+
+        *self.last_merge_stat.write() = Some(MergeStat {
+            finished_at: SystemTime::now(),
+            duration: start.elapsed(),
+            reclaimed_bytes: bytes_before.saturating_sub(output_file.file_size()),
+            files_before: merge_file_ids.len(),
+            files_after: 1,
+        });
+        Ok(())
+    }
 
+    /// 顺序读取数据文件，对索引仍指向的有效记录调用f，参数为去掉序列号的key、记录和记录偏移
+    fn read_merge_file<F>(
+        &self,
+        data_file: &DataFile,
+        scratch: &mut BytesMut,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Vec<u8>, LogRecord, u64) -> Result<()>,
+    {
+        let mut offset = 0;
+        loop {
+            let (log_record, size) = match data_file.read_log_record_with_buf(offset, scratch) {
+                Ok(v) => (v.record, v.size),
+                // 读取到文件末尾，退出循环,读取下一个文件
+                Err(Errors::ReadDataFileEof) => break,
+                // 打开时已跳过的损坏记录，merge时丢弃
+                Err(Errors::InvalidLogRecordCrc)
+                    if self
+                        .quarantined_records
+                        .lock()
+                        .contains(&(data_file.get_file_id(), offset)) =>
+                {
+                    match data_file.corrupt_record_size(offset) {
+                        Some(size) => {
+                            offset += size;
+                            continue;
+                        }
+                        None => return Err(Errors::InvalidLogRecordCrc),
+                    }
+                }
+                Err(e) => return Err(e),
+            };
+            let (_, real_key) = parse_record_sequence_number_with_key(&log_record.key);
+            // 如果索引位置对应的文件id和偏移量都匹配，则是有效记录，已过期的记录不再写入
+            if let Some(idx_pos) = self.index.get(self.index_key(&real_key))
+                && idx_pos.file_id == data_file.get_file_id()
+                && idx_pos.offset == offset
+                && !log_record.is_expired()
+            {
+                f(real_key, log_record, offset)?;
+            }
+            offset += size;
+        }
         Ok(())
     }
 
+    /// 每个数据文件一个线程，并行读取文件中的有效记录，按文件顺序返回
+    fn collect_merge_records(&self, data_files: &[DataFile]) -> Result<Vec<MergeRecords>> {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = data_files
+                .iter()
+                .map(|data_file| {
+                    scope.spawn(move || {
+                        let mut records = Vec::new();
+                        self.read_merge_file(
+                            data_file,
+                            &mut BytesMut::new(),
+                            |real_key, log_record, offset| {
+                                records.push((real_key, log_record, offset));
+                                Ok(())
+                            },
+                        )?;
+                        Ok(records)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Merge thread panicked"))
+                .collect()
+        })
+    }
+
     fn ratate_merge_files(&self) -> Result<Vec<DataFile>> {
-        let mut merge_file_ids = self.older_files.write().keys().copied().collect::<Vec<_>>();
+        // 持有活跃文件的锁之后再获取旧数据文件列表，避免并发写入切换的文件被遗漏
         let mut active_file = self.active_file.write();
+        let mut merge_file_ids = self.older_files.read().keys().copied().collect::<Vec<_>>();
         active_file.sync()?;
         let active_file_id = active_file.get_file_id();
         // 创建新的活跃数据文件，处理写入,将当前活跃数据文件转化为旧数据文件加入到merge列表
-        let new_active_file = DataFile::new(
+        let mut new_active_file = DataFile::new(
             &self.options.dir_path,
             active_file_id + 1,
             IOType::StandardFileIO,
         )?;
+        new_active_file.set_max_record_size(self.options.data_file_size);
         *active_file = new_active_file;
-        let older_file = DataFile::new(
+        let mut older_file = DataFile::new(
             &self.options.dir_path,
             active_file_id,
             IOType::StandardFileIO,
         )?;
-        self.older_files.write().insert(active_file_id, older_file);
+        older_file.set_max_record_size(self.options.data_file_size);
+        Arc::make_mut(&mut self.older_files.write()).insert(active_file_id, Arc::new(older_file));
         merge_file_ids.push(active_file_id);
         merge_file_ids.sort();
+        let older_files = self.older_files.read();
         let mut merge_files = Vec::new();
         for f_id in merge_file_ids {
-            merge_files.push(DataFile::new(
-                &self.options.dir_path,
-                f_id,
-                IOType::StandardFileIO,
-            )?);
+            let mut data_file =
+                DataFile::new(&self.options.dir_path, f_id, IOType::StandardFileIO)?;
+            // 保持与旧数据文件相同的记录格式和内联hint位置
+            if let Some(older_file) = older_files.get(&f_id) {
+                data_file.set_format(older_file.get_format());
+                data_file.set_inline_hints_offset(older_file.get_inline_hints_offset());
+            }
+            merge_files.push(data_file);
         }
         Ok(merge_files)
     }
 
+    /// 开启inline_hints时，从id小于merged_file_id的被merge数据文件末尾的内联hint加载索引
+    ///
+    /// 有被merge的文件没有内联hint时不加载，返回false，之后从数据文件加载这些文件的索引
+    pub(crate) fn load_index_from_inline_hints(&self, merged_file_id: u32) -> Result<bool> {
+        if !self.options.inline_hints {
+            return Ok(false);
+        }
+        let older_files = self.older_files.read();
+        let merged_files: Vec<&DataFile> = older_files
+            .values()
+            .map(Arc::as_ref)
+            .filter(|data_file| data_file.get_file_id() < merged_file_id)
+            .collect();
+        if !merged_files
+            .iter()
+            .all(|data_file| data_file.has_inline_hints())
+        {
+            return Ok(false);
+        }
+        for data_file in merged_files {
+            for (key, record_pos) in data_file.read_inline_hints()? {
+                let key = self.index_key(&key);
+                if !key_in_keyspaces(self.options.load_keyspaces.as_ref(), &key) {
+                    continue;
+                }
+                check_index_budget(self.index.as_ref(), [key.as_slice()])?;
+                self.index.put(key, record_pos);
+            }
+        }
+        Ok(true)
+    }
+
     pub fn load_index_from_hint_file(&self) -> Result<()> {
+        // 残留的临时hint文件说明上次写入未完成，不可信，直接删除，
+        // 有进度文件说明是继续中断merge的merge目录，临时hint文件已截断到检查点，需要保留
+        let hint_tmp_file_name = self.options.dir_path.join(HINT_TMP_FILE_NAME);
+        if hint_tmp_file_name.is_file()
+            && !self.options.read_only
+            && !self
+                .options
+                .dir_path
+                .join(MERGE_PROGRESS_FILE_NAME)
+                .is_file()
+        {
+            std::fs::remove_file(&hint_tmp_file_name).map_err(|e| {
+                error!("Failed to remove hint tmp file: {}", e);
+                Errors::RemoveFileError
+            })?;
+        }
         let hint_file_name = self.options.dir_path.join(HINT_FILE_NAME);
         if !hint_file_name.is_file() {
             return Ok(());
         }
         let hint_file = DataFile::new_hint_file(&self.options.dir_path)?;
+        let mut scratch = BytesMut::new();
+        // 根据文件首字节判断hint记录是否带CRC，与当前配置无关
+        if hint_file.is_raw_hint_file()? {
+            let mut offset = 1;
+            loop {
+                let (key, record_position, size) =
+                    match hint_file.read_hint_record_raw(offset, &mut scratch) {
+                        Ok(v) => v,
+                        Err(Errors::ReadDataFileEof) => break,
+                        Err(e) => return Err(e),
+                    };
+                let key = self.index_key(&key);
+                offset += size;
+                if !key_in_keyspaces(self.options.load_keyspaces.as_ref(), &key) {
+                    continue;
+                }
+                check_index_budget(self.index.as_ref(), [key.as_slice()])?;
+                self.index.put(key, record_position);
+            }
+            return Ok(());
+        }
         let mut offset = 0;
         loop {
-            let (record, size) = match hint_file.read_log_record(offset) {
+            let (record, size) = match hint_file.read_log_record_with_buf(offset, &mut scratch) {
                 Ok(v) => (v.record, v.size),
                 Err(e) => {
                     if e == Errors::ReadDataFileEof {
@@ -156,26 +881,116 @@ impl Engine {
                 }
             };
             // hint文件中存储的记录格式为：key+LogRecordPos
-            let record_position = decode_log_record_pos(&record.value);
-            self.index.put(record.key, record_position);
+            let record_position =
+                decode_log_record_pos(&record.value).map_err(|_| Errors::InvalidHintRecord)?;
+            let key = self.index_key(&record.key);
             offset += size;
+            if !key_in_keyspaces(self.options.load_keyspaces.as_ref(), &key) {
+                continue;
+            }
+            check_index_budget(self.index.as_ref(), [key.as_slice()])?;
+            self.index.put(key, record_position);
         }
 
         Ok(())
     }
 }
 
+/// 读取merge后数据文件中的所有记录，将它们的位置作为内联hint写在文件末尾并持久化
+fn write_inline_hints(data_file: &DataFile) -> Result<()> {
+    if data_file.file_size() == 0 {
+        return Ok(());
+    }
+    let mut scratch = BytesMut::new();
+    let mut hints = Vec::new();
+    let mut offset = 0;
+    loop {
+        let read_record = match data_file.read_log_record_with_buf(offset, &mut scratch) {
+            Ok(read_record) => read_record,
+            Err(Errors::ReadDataFileEof) => break,
+            Err(e) => return Err(e),
+        };
+        // merge后的文件中只有有效的非事务记录
+        let (_, real_key) = parse_record_sequence_number_with_key(&read_record.record.key);
+        let record_pos = LogRecordPos {
+            file_id: data_file.get_file_id(),
+            offset,
+            size: read_record.size as u32,
+        };
+        hints.push((real_key, record_pos));
+        offset += read_record.size;
+    }
+    data_file.write_inline_hints(&hints)?;
+    data_file.sync()
+}
+
+/// 将有效记录写入merge engine的数据文件，并写入对应的hint索引
+fn write_merge_record(
+    merge_engine: &Engine,
+    hint_file: Option<&DataFile>,
+    hint_crc: bool,
+    real_key: Vec<u8>,
+    mut log_record: LogRecord,
+) -> Result<()> {
+    // 去除key中事务id
+    log_record.key = get_record_sequence_number_with_key(&real_key, NON_TRANSACTION_SEQ_NUMBER);
+    // 写入数据文件
+    let record_pos = merge_engine.append_log_record(&mut log_record)?;
+    let Some(hint_file) = hint_file else {
+        return Ok(());
+    };
+    // 写入hint索引文件
+    match hint_crc {
+        true => hint_file.write_hint_record(real_key, record_pos),
+        false => hint_file.write_hint_record_raw(real_key, record_pos),
+    }
+}
+
 fn create_merge_dir(dir_path: &Path) -> PathBuf {
     let dir_str = dir_path.to_str().unwrap();
     format!("{}-{}", dir_str, MERGE_DIR_SUFFIX).into()
 }
 
+/// db目录的规范路径，用于标识merge目录所属的db
+fn merge_source(dir_path: &Path) -> Vec<u8> {
+    let source = std::fs::canonicalize(dir_path).unwrap_or_else(|_| dir_path.to_path_buf());
+    source.as_os_str().as_encoded_bytes().to_vec()
+}
+
+/// 在merge目录中记录源db路径
+fn write_merge_source(dir_path: &Path, merge_dir: &Path) -> Result<()> {
+    std::fs::write(
+        merge_dir.join(MERGE_SOURCE_FILE_NAME),
+        merge_source(dir_path),
+    )
+    .map_err(|e| {
+        error!("Failed to write merge source file: {}", e);
+        Errors::WriteToDataFileError
+    })
+}
+
+/// merge目录是否属于该db，没有源路径记录的旧版本merge目录视为匹配
+fn merge_source_matches(dir_path: &Path, merge_dir: &Path) -> bool {
+    match std::fs::read(merge_dir.join(MERGE_SOURCE_FILE_NAME)) {
+        Ok(source) => source == merge_source(dir_path),
+        Err(_) => true,
+    }
+}
+
 /// 加载merge目录，读取merge完成文件，删除已merge的数据文件，将已merge的数据文件移动到当前db
 pub(crate) fn load_merge_files(dir_path: &Path) -> Result<()> {
     let merge_dir = create_merge_dir(dir_path);
     if !merge_dir.is_dir() {
         return Ok(());
     }
+    // merge目录属于其他db时忽略，不合并其中的数据
+    if !merge_source_matches(dir_path, &merge_dir) {
+        warn!(
+            "Ignore merge dir {} of another database",
+            merge_dir.display()
+        );
+        return Ok(());
+    }
     let dentries = std::fs::read_dir(&merge_dir).map_err(|_| {
         error!("Failed to read merge dir: {}", merge_dir.display());
         Errors::FailedToReadDatabaseDir
@@ -199,10 +1014,25 @@ pub(crate) fn load_merge_files(dir_path: &Path) -> Result<()> {
         if file_name.ends_with(FILE_LOCK_NAME) {
             continue;
         }
+        if file_name.ends_with(HINT_TMP_FILE_NAME) {
+            continue;
+        }
+        if file_name.ends_with(VERSION_FILE_NAME) {
+            continue;
+        }
+        if file_name.ends_with(MERGE_PROGRESS_FILE_NAME) {
+            continue;
+        }
+        if file_name.ends_with(MERGE_SOURCE_FILE_NAME) {
+            continue;
+        }
         merged_file_names.push(file_name_os);
     }
 
-    // 如果merge未完成，则删除merge目录
+    // 如果merge未完成，则删除merge目录，有进度记录时保留，下次merge从检查点继续
+    if !merge_finished && merge_dir.join(MERGE_PROGRESS_FILE_NAME).is_file() {
+        return Ok(());
+    }
     if !merge_finished {
         std::fs::remove_dir_all(&merge_dir).map_err(|_| {
             error!("Failed to remove merge dir: {}", merge_dir.display());
@@ -214,6 +1044,14 @@ pub(crate) fn load_merge_files(dir_path: &Path) -> Result<()> {
     // 如果merge完成，则读取merge完成文件，其中存储未merge的文件id，小于该id的均被merge
     let merge_finished_file = DataFile::new_merge_finished_file(&merge_dir)?;
     let merge_finished_record = merge_finished_file.read_log_record(0)?;
+    if merge_finished_record.record.key == MERGE_FINISHED_FILES_KEY.as_bytes() {
+        return load_partial_merge_files(
+            dir_path,
+            &merge_dir,
+            &merge_finished_record.record.value,
+            merged_file_names,
+        );
+    }
     let unmerge_file_id = String::from_utf8(merge_finished_record.record.value.clone())
         .unwrap()
         .parse::<u32>()
@@ -225,6 +1063,14 @@ pub(crate) fn load_merge_files(dir_path: &Path) -> Result<()> {
             std::fs::remove_file(file_name).unwrap();
         }
     }
+    // 索引只保存在内存中时merge不生成hint文件，db中旧的hint文件指向已被删除的数据文件
+    if !merge_dir.join(HINT_FILE_NAME).is_file() {
+        remove_file_if_exists(&dir_path.join(HINT_FILE_NAME))?;
+    }
+    // 本次merge未写入内联hint，旧的记录对应的数据文件已被删除
+    if !merge_dir.join(INLINE_HINTS_FILE_NAME).is_file() {
+        remove_file_if_exists(&dir_path.join(INLINE_HINTS_FILE_NAME))?;
+    }
     // 将已merge的文件移动到当前db
     for file_name in merged_file_names {
         let src = merge_dir.join(&file_name);
@@ -235,3 +1081,1580 @@ pub(crate) fn load_merge_files(dir_path: &Path) -> Result<()> {
     std::fs::remove_dir_all(merge_dir).unwrap();
     Ok(())
 }
+
+/// 中断的merge的进度，来自最后一个检查点
+struct MergeCheckpoint {
+    /// 本次merge的数据文件id上界，不包含
+    non_merge_file_id: u32,
+    /// 已处理完的数据文件id
+    merged_file_ids: Vec<u32>,
+    /// 检查点时临时hint索引文件的大小
+    hint_size: u64,
+    /// 检查点时merge engine活跃数据文件的id和写偏移
+    output_file_id: u32,
+    output_offset: u64,
+}
+
+impl MergeCheckpoint {
+    /// 解析检查点记录：处理完的文件id、hint文件大小、输出文件id和写偏移
+    fn apply_checkpoint(&mut self, value: &str) -> Option<()> {
+        let fields: Vec<&str> = value.split(',').collect();
+        if fields.len() != 4 {
+            return None;
+        }
+        self.merged_file_ids.push(fields[0].parse().ok()?);
+        self.hint_size = fields[1].parse().ok()?;
+        self.output_file_id = fields[2].parse().ok()?;
+        self.output_offset = fields[3].parse().ok()?;
+        Some(())
+    }
+
+    /// 截断检查点之后写入的输出，这些输出对应的数据文件会被重新处理
+    fn truncate_outputs(&self, merge_dir: &Path) -> Result<()> {
+        let mut file_id = self.output_file_id + 1;
+        while create_data_file_name(merge_dir, file_id).is_file() {
+            remove_file_if_exists(&create_data_file_name(merge_dir, file_id))?;
+            file_id += 1;
+        }
+        truncate_file(
+            &create_data_file_name(merge_dir, self.output_file_id),
+            self.output_offset,
+        )?;
+        truncate_file(&merge_dir.join(HINT_TMP_FILE_NAME), self.hint_size)
+    }
+}
+
+/// 读取merge目录中的进度文件，merge已完成或没有进度时返回None
+fn load_merge_progress(merge_dir: &Path) -> Result<Option<MergeCheckpoint>> {
+    if !merge_dir.join(MERGE_PROGRESS_FILE_NAME).is_file()
+        || merge_dir.join(MERGE_FINISHED_FILE_NAME).is_file()
+    {
+        return Ok(None);
+    }
+    let progress_file = DataFile::new_merge_progress_file(merge_dir)?;
+    let mut progress = None;
+    let mut offset = 0;
+    // 崩溃时可能留下不完整的记录，只使用完整的检查点
+    while let Ok(read_log_record) = progress_file.read_log_record(offset) {
+        let value = String::from_utf8_lossy(&read_log_record.record.value).to_string();
+        let parsed = match read_log_record.record.key == MERGE_PROGRESS_START_KEY.as_bytes() {
+            true => value.parse().ok().map(|non_merge_file_id| {
+                progress = Some(MergeCheckpoint {
+                    non_merge_file_id,
+                    merged_file_ids: Vec::new(),
+                    hint_size: 0,
+                    output_file_id: 0,
+                    output_offset: 0,
+                });
+            }),
+            false => progress
+                .as_mut()
+                .and_then(|progress| progress.apply_checkpoint(&value)),
+        };
+        // 无法解析的进度不可信，重新开始merge
+        if parsed.is_none() {
+            return Ok(None);
+        }
+        offset += read_log_record.size;
+    }
+    // 截断进度文件中不完整的记录，后续检查点追加在完整记录之后
+    if progress.is_some() {
+        truncate_file(&merge_dir.join(MERGE_PROGRESS_FILE_NAME), offset)?;
+    }
+    Ok(progress)
+}
+
+fn write_merge_progress_record(progress_file: &DataFile, key: &str, value: String) -> Result<()> {
+    let record = LogRecord {
+        key: key.as_bytes().to_vec(),
+        value: value.into_bytes(),
+        rec_type: LogRecordType::Normal,
+        value_encoded: false,
+        expire_at: None,
+    };
+    progress_file.write(&record.encode())?;
+    progress_file.sync()
+}
+
+fn truncate_file(file_name: &Path, len: u64) -> Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(file_name)
+        .map_err(|e| {
+            error!("Failed to open {}: {}", file_name.display(), e);
+            Errors::TruncateFileError
+        })?;
+    file.set_len(len).map_err(|e| {
+        error!("Failed to truncate {}: {}", file_name.display(), e);
+        Errors::TruncateFileError
+    })
+}
+
+/// 加载部分merge的结果：删除被merge的数据文件，移入merge后的数据文件
+///
+/// hint索引和B+Tree索引中可能有指向被merge文件的位置，一并删除，打开时从数据文件重建索引
+fn load_partial_merge_files(
+    dir_path: &Path,
+    merge_dir: &Path,
+    merged_file_ids: &[u8],
+    merged_file_names: Vec<OsString>,
+) -> Result<()> {
+    for file_id in String::from_utf8_lossy(merged_file_ids).split(',') {
+        let file_id = file_id.parse::<u32>()?;
+        remove_file_if_exists(&create_data_file_name(dir_path, file_id))?;
+    }
+    for file_name in [
+        HINT_FILE_NAME,
+        MERGE_FINISHED_FILE_NAME,
+        BPTREE_INDEX_FILE_NAME,
+    ] {
+        remove_file_if_exists(&dir_path.join(file_name))?;
+    }
+    for file_name in merged_file_names {
+        if !file_name.to_string_lossy().ends_with(DATA_FILE_NAME_SUFFIX) {
+            continue;
+        }
+        std::fs::rename(merge_dir.join(&file_name), dir_path.join(&file_name)).map_err(|e| {
+            error!("Failed to move merged file: {}", e);
+            Errors::RenameFileError
+        })?;
+    }
+    std::fs::remove_dir_all(merge_dir).map_err(|_| Errors::RemoveDirError)?;
+    Ok(())
+}
+
+/// 记录merge后带内联hint的数据文件id上界并持久化，merge完成后随数据文件移动到db目录
+fn write_inline_hints_file_id(merge_dir: &Path, file_id: u32) -> Result<()> {
+    let file_name = merge_dir.join(INLINE_HINTS_FILE_NAME);
+    std::fs::write(&file_name, file_id.to_string())
+        .and_then(|_| File::open(&file_name)?.sync_all())
+        .map_err(|e| {
+            error!("Failed to write inline hints file: {}", e);
+            Errors::WriteToDataFileError
+        })
+}
+
+/// 读取db目录中带内联hint的数据文件id上界，小于该id的文件是merge时写入了内联hint的文件
+///
+/// 部分merge不修改该记录，其生成的文件没有内联hint，读取文件尾部时不会匹配
+pub(crate) fn inline_hints_file_id(dir_path: &Path) -> Option<u32> {
+    std::fs::read_to_string(dir_path.join(INLINE_HINTS_FILE_NAME))
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// 读取db目录中merge完成文件保存的最近一次merge的统计信息
+pub(crate) fn load_merge_stat(dir_path: &Path) -> Option<MergeStat> {
+    if !dir_path.join(MERGE_FINISHED_FILE_NAME).is_file() {
+        return None;
+    }
+    let merge_finished_file = DataFile::new_merge_finished_file(dir_path).ok()?;
+    let mut offset = 0;
+    while let Ok(read_log_record) = merge_finished_file.read_log_record(offset) {
+        if read_log_record.record.key == MERGE_STAT_KEY.as_bytes() {
+            return MergeStat::decode(&read_log_record.record.value);
+        }
+        offset += read_log_record.size;
+    }
+    None
+}
+
+pub(crate) fn remove_file_if_exists(file_name: &Path) -> Result<()> {
+    if file_name.is_file() {
+        std::fs::remove_file(file_name).map_err(|e| {
+            error!("Failed to remove {}: {}", file_name.display(), e);
+            Errors::RemoveFileError
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        options::IndexType,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_merge_ignore_partial_hint_file() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_ignore_partial_hint_file"),
+            data_file_size: 32 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..1000 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+
+        // 模拟写入hint文件过程中崩溃，残留不完整的临时hint文件
+        std::fs::write(engine_dir.join(HINT_TMP_FILE_NAME), b"partial hint")
+            .expect("Failed to write hint tmp file");
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert!(engine_dir.join(HINT_FILE_NAME).is_file());
+        assert!(!engine_dir.join(HINT_TMP_FILE_NAME).exists());
+        for i in 0..1000 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_sort_on_merge() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_sort_on_merge"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            sort_on_merge: true,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        // 逆序写入，并覆盖部分key
+        for i in (0..300).rev() {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in (0..300).step_by(3) {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+        }
+        engine.merge().expect("Failed to merge");
+
+        // merge目录中的数据文件按key有序
+        let merge_dir = create_merge_dir(&engine_dir);
+        let mut keys = Vec::new();
+        let mut file_id = 0;
+        while create_data_file_name(&merge_dir, file_id).is_file() {
+            let data_file = DataFile::new(&merge_dir, file_id, IOType::StandardFileIO)
+                .expect("Failed to open data file");
+            let mut offset = 0;
+            while let Ok(read_log_record) = data_file.read_log_record(offset) {
+                let (_, key) = parse_record_sequence_number_with_key(&read_log_record.record.key);
+                keys.push(key);
+                offset += read_log_record.size;
+            }
+            file_id += 1;
+        }
+        assert_eq!(keys.len(), 300);
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..300 {
+            let expected = match i % 3 {
+                0 => get_test_value(i + 1000),
+                _ => get_test_value(i),
+            };
+            assert_eq!(engine.get(get_test_key(i)), Ok(expected));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_recent_first() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_recent_first"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            merge_recent_first: true,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        // 按写入顺序记录key，之后覆盖和删除部分key
+        let mut writes = Vec::new();
+        for i in 0..300 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+            writes.push(get_test_key(i));
+        }
+        for i in (0..300).step_by(7).rev() {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+            writes.push(get_test_key(i));
+        }
+        for i in (0..300).step_by(11) {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        engine.merge().expect("Failed to merge");
+
+        // merge目录中的数据文件按最近一次更新的时间倒序
+        let mut expected = Vec::new();
+        for key in writes.into_iter().rev() {
+            if !expected.contains(&key) && engine.get(key.clone()).is_ok() {
+                expected.push(key);
+            }
+        }
+        let merge_dir = create_merge_dir(&engine_dir);
+        let mut keys = Vec::new();
+        let mut file_id = 0;
+        while create_data_file_name(&merge_dir, file_id).is_file() {
+            let data_file = DataFile::new(&merge_dir, file_id, IOType::StandardFileIO)
+                .expect("Failed to open data file");
+            let mut offset = 0;
+            while let Ok(read_log_record) = data_file.read_log_record(offset) {
+                let (_, key) = parse_record_sequence_number_with_key(&read_log_record.record.key);
+                keys.push(Bytes::from(key));
+                offset += read_log_record.size;
+            }
+            file_id += 1;
+        }
+        assert_eq!(keys, expected);
+
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..300 {
+            let expected = match i {
+                _ if i % 11 == 0 => Err(Errors::KeyNotFound),
+                _ if i % 7 == 0 => Ok(get_test_value(i + 1000)),
+                _ => Ok(get_test_value(i)),
+            };
+            assert_eq!(engine.get(get_test_key(i)), expected);
+        }
+        std::mem::drop(engine);
+
+        // 不能同时按key排序
+        let res = Engine::open(Options {
+            sort_on_merge: true,
+            ..engine_opts
+        });
+        assert_eq!(res.err(), Some(Errors::ConflictingMergeOrder));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_dir_of_another_db() {
+        let other_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_dir_other_db"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let other_dir = other_opts.dir_path.clone();
+        let other = Engine::open(other_opts).expect("Failed to open engine");
+        for i in 0..300 {
+            other
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        other.merge().expect("Failed to merge");
+        std::mem::drop(other);
+
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_dir_of_another_db"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 1000..1050 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        std::mem::drop(engine);
+
+        // 把其他db的merge目录放到当前db的merge目录位置
+        let merge_dir = create_merge_dir(&engine_dir);
+        std::fs::rename(create_merge_dir(&other_dir), &merge_dir)
+            .expect("Failed to rename merge dir");
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert!(merge_dir.is_dir());
+        assert_eq!(engine.list_keys().unwrap().len(), 50);
+        assert_eq!(engine.get(get_test_key(0)), Err(Errors::KeyNotFound));
+        for i in 1000..1050 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        // 当前db自己的merge正常加载
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(Options {
+            dir_path: engine_dir.clone(),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        })
+        .expect("Failed to open engine");
+        assert!(!merge_dir.exists());
+        assert_eq!(engine.list_keys().unwrap().len(), 50);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        std::fs::remove_dir_all(other_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_stat() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_stat"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.last_merge_stat(), None);
+        // 每个key覆盖写入多次，大部分数据可以回收
+        for round in 0..5 {
+            for i in 0..100 {
+                engine
+                    .put(get_test_key(i), get_test_value(i + round * 1000))
+                    .expect("Failed to put data");
+            }
+        }
+        let data_files_size = || {
+            std::fs::read_dir(&engine_dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.to_string_lossy().ends_with(DATA_FILE_NAME_SUFFIX))
+                .map(|path| std::fs::metadata(path).unwrap().len())
+                .sum::<u64>()
+        };
+        let size_before = data_files_size();
+        let files_before = engine.data_file_ids().len();
+        engine.merge().expect("Failed to merge");
+
+        let stat = engine.last_merge_stat().expect("Missing merge stat");
+        assert_eq!(stat.files_before, files_before);
+        assert!(stat.files_after < stat.files_before);
+        assert!(stat.reclaimed_bytes > size_before / 2);
+
+        // 重启后加载merge结果，统计信息与merge时一致
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(stat.reclaimed_bytes, size_before - data_files_size());
+        let loaded = engine.last_merge_stat().expect("Missing merge stat");
+        assert_eq!(loaded.duration.as_micros(), stat.duration.as_micros());
+        assert_eq!(loaded.reclaimed_bytes, stat.reclaimed_bytes);
+        assert_eq!(loaded.files_before, stat.files_before);
+        assert_eq!(loaded.files_after, stat.files_after);
+        assert_eq!(
+            loaded
+                .finished_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            stat.finished_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis()
+        );
+        for i in 0..100 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i + 4000)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_index_only_in_memory() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_index_only_in_memory"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            index_only_in_memory: true,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..300 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in (0..300).step_by(3) {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+        }
+        for i in (0..300).step_by(5) {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        engine.merge().expect("Failed to merge");
+        assert!(!create_merge_dir(&engine_dir).join(HINT_FILE_NAME).exists());
+        // merge之后继续写入
+        for i in 300..350 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        std::mem::drop(engine);
+
+        // 每次打开都从数据文件重建完整的索引，目录中没有hint和B+Tree索引文件
+        for _ in 0..2 {
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            assert!(!engine_dir.join(HINT_FILE_NAME).exists());
+            assert!(!engine_dir.join(BPTREE_INDEX_FILE_NAME).exists());
+            assert!(!create_merge_dir(&engine_dir).exists());
+            for i in 0..350 {
+                let expected = match (i % 5, i % 3) {
+                    _ if i >= 300 => Ok(get_test_value(i)),
+                    (0, _) => Err(Errors::KeyNotFound),
+                    (_, 0) => Ok(get_test_value(i + 1000)),
+                    _ => Ok(get_test_value(i)),
+                };
+                assert_eq!(engine.get(get_test_key(i)), expected);
+            }
+        }
+
+        // B+Tree索引本身持久化在文件中，不支持只在内存中保存索引
+        let res = Engine::open(Options {
+            index_type: IndexType::BPlusTree,
+            ..engine_opts
+        });
+        assert_eq!(res.err(), Some(Errors::IndexOnlyInMemoryUnsupported));
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_threads() {
+        let mut outputs = Vec::new();
+        for merge_threads in [1, 4] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir()
+                    .join(format!("test_merge_threads_{}", merge_threads)),
+                data_file_size: 4 * 1024,
+                index_type: IndexType::BTree,
+                use_mmap: false,
+                merge_threads,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            for i in 0..1000 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            for i in (0..1000).step_by(3) {
+                engine
+                    .put(get_test_key(i), get_test_value(i + 1000))
+                    .expect("Failed to put data");
+            }
+            for i in (0..1000).step_by(7) {
+                engine
+                    .delete(get_test_key(i))
+                    .expect("Failed to delete data");
+            }
+            assert!(engine.data_file_ids().len() > merge_threads);
+            engine.merge().expect("Failed to merge");
+
+            // 记录merge目录中所有文件的内容，源路径记录随db目录不同，
+            // merge完成文件只比较第一条记录，之后的统计信息包含时间
+            let merge_dir = create_merge_dir(&engine_dir);
+            let mut files: Vec<_> = std::fs::read_dir(&merge_dir)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    let name = path.file_name().unwrap().to_string_lossy().to_string();
+                    let mut content = std::fs::read(&path).unwrap();
+                    if name == MERGE_FINISHED_FILE_NAME {
+                        let merge_finished_file = DataFile::new_merge_finished_file(&merge_dir)
+                            .expect("Failed to open merge finished file");
+                        let size = merge_finished_file.read_log_record(0).unwrap().size;
+                        content.truncate(size as usize);
+                    }
+                    (name, content)
+                })
+                .filter(|(name, _)| name != FILE_LOCK_NAME && name != MERGE_SOURCE_FILE_NAME)
+                .collect();
+            files.sort();
+            outputs.push(files);
+
+            std::mem::drop(engine);
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            for i in 0..1000 {
+                let expected = match (i % 7, i % 3) {
+                    (0, _) => Err(Errors::KeyNotFound),
+                    (_, 0) => Ok(get_test_value(i + 1000)),
+                    _ => Ok(get_test_value(i)),
+                };
+                assert_eq!(engine.get(get_test_key(i)), expected);
+            }
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+        // 并行与单线程merge的输出完全一致
+        assert!(outputs[0].len() > 2);
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
+    #[test]
+    fn test_merge_hint_crc() {
+        let mut hint_sizes = Vec::new();
+        for hint_crc in [true, false] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir().join(format!("test_merge_hint_crc_{}", hint_crc)),
+                data_file_size: 32 * 1024,
+                index_type: IndexType::BTree,
+                use_mmap: false,
+                hint_crc,
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            for i in 0..1000 {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+            }
+            engine.merge().expect("Failed to merge");
+            let hint_file_name = create_merge_dir(&engine_dir).join(HINT_FILE_NAME);
+            hint_sizes.push(std::fs::metadata(hint_file_name).unwrap().len());
+            std::mem::drop(engine);
+
+            // 重启后从hint文件加载索引
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            assert!(engine_dir.join(HINT_FILE_NAME).is_file());
+            for i in 0..1000 {
+                assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+            }
+
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+        // 不带CRC的hint文件更小
+        assert!(hint_sizes[1] < hint_sizes[0]);
+    }
+
+    #[test]
+    fn test_logical_size() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_logical_size"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.disk_size(), 0);
+        assert_eq!(engine.logical_size(), Ok(0));
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        // 没有覆盖和删除时所有记录都有效
+        assert_eq!(engine.logical_size(), Ok(engine.disk_size()));
+
+        for i in 0..200 {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+        }
+        for i in 400..500 {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        let logical_size = engine.logical_size().unwrap();
+        assert!(logical_size < engine.disk_size());
+        assert_eq!(
+            engine.disk_size() - logical_size,
+            engine.reclaimable_bytes().unwrap()
+        );
+        std::mem::drop(engine);
+
+        // 重新打开后统计一致，merge后不再有可回收的空间
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.logical_size(), Ok(logical_size));
+        assert_eq!(
+            engine.disk_size() - logical_size,
+            engine.reclaimable_bytes().unwrap()
+        );
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.logical_size(), Ok(engine.disk_size()));
+        assert_eq!(engine.reclaimable_bytes(), Ok(0));
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_with_progress() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_with_progress"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in 0..100 {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        // merge时活跃文件也会被merge
+        let files_total = engine.data_file_ids().len();
+        assert!(files_total > 1);
+
+        let events = std::cell::RefCell::new(Vec::new());
+        engine
+            .merge_with_progress(|progress| events.borrow_mut().push(progress))
+            .expect("Failed to merge");
+        let events = events.into_inner();
+        assert_eq!(events.len(), files_total);
+        for (i, progress) in events.iter().enumerate() {
+            assert_eq!(progress.files_processed, i + 1);
+            assert_eq!(progress.files_total, files_total);
+        }
+        assert!(
+            events
+                .windows(2)
+                .all(|w| w[0].records_rewritten <= w[1].records_rewritten)
+        );
+        let last = events.last().unwrap();
+        assert_eq!(last.files_processed, last.files_total);
+        assert_eq!(last.records_rewritten, 400);
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 400);
+        assert_eq!(engine.get(get_test_key(499)), Ok(get_test_value(499)));
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_ratio_threshold() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_ratio_threshold"),
+            data_file_size: 16 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            data_file_merge_ratio: 0.4,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..1000 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        // 少量覆盖，可回收的比例低于阈值，不merge
+        for i in 0..100 {
+            engine
+                .put(get_test_key(i), get_test_value(i + 10000))
+                .expect("Failed to put data");
+        }
+        assert!(engine.reclaimable_ratio().unwrap() < 0.4);
+        assert_eq!(engine.merge(), Err(Errors::MergeRatioUnreached));
+        assert!(engine.last_merge_stat().is_none());
+        assert!(!create_merge_dir(&engine_dir).exists());
+
+        // 覆盖和删除足够多的key后，可回收的比例达到阈值，正常merge
+        for i in 100..600 {
+            engine
+                .put(get_test_key(i), get_test_value(i + 10000))
+                .expect("Failed to put data");
+        }
+        for i in 900..1000 {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        assert!(engine.reclaimable_ratio().unwrap() >= 0.4);
+        engine.merge().expect("Failed to merge");
+        assert!(engine.last_merge_stat().is_some());
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert_eq!(engine.list_keys().unwrap().len(), 900);
+        for i in 0..600 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i + 10000)));
+        }
+        for i in 600..900 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+        // merge后没有可回收的数据
+        assert_eq!(engine.merge(), Err(Errors::MergeRatioUnreached));
+        std::mem::drop(engine);
+
+        assert_eq!(
+            Engine::open(Options {
+                data_file_merge_ratio: 1.5,
+                ..engine_opts
+            })
+            .err(),
+            Some(Errors::InvalidMergeRatio)
+        );
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_insufficient_space() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_insufficient_space"),
+            data_file_size: 16 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        // 上次运行残留的数据目录和已完成的merge目录打开时会加载merge统计，需要从空目录开始
+        let _ = std::fs::remove_dir_all(&engine_dir);
+        let _ = std::fs::remove_dir_all(create_merge_dir(&engine_dir));
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in 0..300 {
+            engine
+                .put(get_test_key(i), get_test_value(i + 10000))
+                .expect("Failed to put data");
+        }
+        // 按索引中的有效记录估算，不包括被覆盖的记录
+        let live: u64 = (0..500)
+            .map(|i| engine.index.get(get_test_key(i).to_vec()).unwrap().size as u64)
+            .sum();
+        assert_eq!(engine.live_data_size(), Ok(live));
+        let (_, total) = engine.data_files_stat(u32::MAX);
+        assert!(live < total);
+        std::mem::drop(engine);
+
+        // 需要保留的空间超过磁盘可用空间，merge被拒绝，不创建merge目录
+        let engine = Engine::open(Options {
+            min_free_bytes: Some(u64::MAX),
+            ..engine_opts.clone()
+        })
+        .expect("Failed to open engine");
+        assert!(matches!(
+            engine.merge(),
+            Err(Errors::InsufficientSpaceForMerge { .. })
+        ));
+        assert!(!create_merge_dir(&engine_dir).exists());
+        assert!(engine.last_merge_stat().is_none());
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        engine.merge().expect("Failed to merge");
+        for i in 0..500 {
+            let expected = match i < 300 {
+                true => get_test_value(i + 10000),
+                false => get_test_value(i),
+            };
+            assert_eq!(engine.get(get_test_key(i)), Ok(expected));
+        }
+        std::fs::remove_dir_all(create_merge_dir(&engine_dir))
+            .expect("Failed to remove merge directory");
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_dead_ratio() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_dead_ratio"),
+            data_file_size: 16 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            merge_dead_ratio: Some(0.5),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        // 写满文件0
+        let mut n = 0;
+        while engine.active_file.read().get_file_id() == 0 {
+            engine
+                .put(get_test_key(n), get_test_value(n))
+                .expect("Failed to put data");
+            n += 1;
+        }
+        // 覆盖文件0中的大部分key，文件0几乎全部可回收，文件1几乎全部有效
+        for i in 5..n {
+            engine
+                .put(get_test_key(i), get_test_value(i + 10000))
+                .expect("Failed to put data");
+        }
+        let mut m = n;
+        while engine.active_file.read().get_file_id() < 2 {
+            engine
+                .put(get_test_key(m), get_test_value(m))
+                .expect("Failed to put data");
+            m += 1;
+        }
+        let file0 = std::fs::read(create_data_file_name(&engine_dir, 0)).unwrap();
+        let file1 = std::fs::read(create_data_file_name(&engine_dir, 1)).unwrap();
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+
+        // 只有文件0被merge，文件1保持不变
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        let merged_file0 = std::fs::read(create_data_file_name(&engine_dir, 0)).unwrap();
+        assert!(merged_file0.len() < file0.len() / 10);
+        assert_eq!(
+            std::fs::read(create_data_file_name(&engine_dir, 1)).unwrap(),
+            file1
+        );
+        for i in 0..m {
+            let expected = match i >= 5 && i < n {
+                true => get_test_value(i + 10000),
+                false => get_test_value(i),
+            };
+            assert_eq!(engine.get(get_test_key(i)), Ok(expected));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_compact_range() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_compact_range"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let key = |i: u32| bytes::Bytes::from(format!("key_{:04}", i));
+        for i in 0..200 {
+            engine
+                .put(key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        engine.delete(key(60)).expect("Failed to delete data");
+        let positions: Vec<_> = (0..200)
+            .map(|i| engine.index.get(key(i).to_vec()))
+            .collect();
+        assert!(positions.iter().flatten().any(|pos| pos.file_id == 0));
+
+        let active_file_id = engine.active_file.read().get_file_id();
+        let reclaimed = engine
+            .compact_range(key(50), key(100))
+            .expect("Failed to compact range");
+        let mut expected = 0;
+        for i in 0..200 {
+            let pos = engine.index.get(key(i).to_vec());
+            if i == 60 {
+                assert_eq!(pos, None);
+                continue;
+            }
+            let old_pos = positions[i as usize].unwrap();
+            match (50..100).contains(&i) && old_pos.file_id != active_file_id {
+                // 范围内的key被重写到活跃文件，写满时会切换到新的活跃文件
+                true => {
+                    assert!(pos.unwrap().file_id >= active_file_id);
+                    expected += old_pos.size as u64;
+                }
+                false => assert_eq!(pos, Some(old_pos)),
+            }
+            assert_eq!(engine.get(key(i)), Ok(get_test_value(i)));
+        }
+        assert!(expected > 0);
+        assert_eq!(reclaimed, expected);
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..200 {
+            let expected = match i {
+                60 => Err(Errors::KeyNotFound),
+                _ => Ok(get_test_value(i)),
+            };
+            assert_eq!(engine.get(key(i)), expected);
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_compact_range_key_transform() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_compact_range_key_transform"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            key_transform: Some(Arc::new(|key: &[u8]| key.to_ascii_lowercase())),
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        let key = |i: u32| bytes::Bytes::from(format!("KEY_{:04}", i));
+        for i in 0..200 {
+            engine
+                .put(key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        let active_file_id = engine.active_file.read().get_file_id();
+        assert!((10..20).all(|i| {
+            engine.index.get(engine.index_key(&key(i))).unwrap().file_id != active_file_id
+        }));
+
+        // 范围与索引中的key一样经过变换
+        let reclaimed = engine
+            .compact_range(key(10), key(20))
+            .expect("Failed to compact range");
+        assert!(reclaimed > 0);
+        for i in 0..200 {
+            // 范围内的key被重写到活跃文件
+            let pos = engine.index.get(engine.index_key(&key(i))).unwrap();
+            if (10..20).contains(&i) {
+                assert!(pos.file_id >= active_file_id);
+            }
+            assert_eq!(engine.get(key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_compact_range_concurrent_put() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_compact_range_concurrent_put"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        let key = |i: u32| bytes::Bytes::from(format!("key_{:04}", i));
+        let value = |i: u32, round: u32| bytes::Bytes::from(format!("value_{}_{}", i, round));
+        let count = 2000;
+        for i in 0..count {
+            engine.put(key(i), value(i, 0)).expect("Failed to put data");
+        }
+
+        // 重写范围内的key时并发写入新value，新value不能被重写的旧value覆盖
+        let done = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..count {
+                    engine.put(key(i), value(i, 1)).expect("Failed to put data");
+                }
+                done.store(true, std::sync::atomic::Ordering::SeqCst);
+            });
+            while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                engine
+                    .compact_range(key(0), key(count))
+                    .expect("Failed to compact range");
+            }
+        });
+        for i in 0..count {
+            assert_eq!(engine.get(key(i)), Ok(value(i, 1)));
+        }
+        std::mem::drop(engine);
+
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..count {
+            assert_eq!(engine.get(key(i)), Ok(value(i, 1)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_dead_ratio_drops_expired_records() {
+        // 写入新key直到活跃文件id达到file_id，返回下一个key的序号
+        let fill_until = |engine: &Engine, file_id: u32, mut i: u32| {
+            while engine.active_file.read().get_file_id() < file_id {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+                i += 1;
+            }
+            i
+        };
+        let ttl_key = bytes::Bytes::from("ttl_key");
+        for with_old_value in [false, true] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir().join(format!(
+                    "test_merge_dead_ratio_drops_expired_records_{}",
+                    with_old_value
+                )),
+                data_file_size: 4 * 1024,
+                index_type: IndexType::BTree,
+                use_mmap: false,
+                merge_dead_ratio: Some(0.5),
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            // 文件0中可能有key的旧value，文件1写入会过期的value，只有文件1满足merge条件
+            if with_old_value {
+                engine
+                    .put(ttl_key.clone(), get_test_value(0))
+                    .expect("Failed to put data");
+            }
+            let n = fill_until(&engine, 1, 0);
+            engine
+                .put_with_ttl(
+                    ttl_key.clone(),
+                    get_test_value(1),
+                    Duration::from_millis(50),
+                )
+                .expect("Failed to put data");
+            let m = fill_until(&engine, 2, n);
+            for i in n..m {
+                engine
+                    .put(get_test_key(i), get_test_value(i + 10000))
+                    .expect("Failed to put data");
+            }
+            std::thread::sleep(Duration::from_millis(100));
+            engine.merge().expect("Failed to merge");
+            std::mem::drop(engine);
+
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            // merge后的文件中没有过期的value，旧value也不会重新生效
+            let has_value = engine
+                .read_file_records(1)
+                .expect("Failed to read file records")
+                .iter()
+                .any(|(record, _)| {
+                    parse_record_sequence_number_with_key(&record.key).1 == ttl_key.to_vec()
+                        && record.rec_type == LogRecordType::Normal
+                });
+            assert!(!has_value);
+            assert_eq!(engine.get(ttl_key.clone()), Err(Errors::KeyNotFound));
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_merge_dead_ratio_keeps_deleted_keys() {
+        // 写入新key直到活跃文件id达到file_id，返回下一个key的序号
+        let fill_until = |engine: &Engine, file_id: u32, mut i: u32| {
+            while engine.active_file.read().get_file_id() < file_id {
+                engine
+                    .put(get_test_key(i), get_test_value(i))
+                    .expect("Failed to put data");
+                i += 1;
+            }
+            i
+        };
+        let tombstone_key = bytes::Bytes::from("tombstone_key");
+        for merge_tombstone_file in [false, true] {
+            let engine_opts = Options {
+                dir_path: std::env::temp_dir().join(format!(
+                    "test_merge_dead_ratio_keeps_deleted_keys_{}",
+                    merge_tombstone_file
+                )),
+                data_file_size: 4 * 1024,
+                index_type: IndexType::BTree,
+                use_mmap: false,
+                merge_dead_ratio: Some(0.5),
+                ..Default::default()
+            };
+            let engine_dir = engine_opts.dir_path.clone();
+            let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+            // 文件0写入key的value，文件1写入删除记录
+            engine
+                .put(tombstone_key.clone(), get_test_value(0))
+                .expect("Failed to put data");
+            let n = fill_until(&engine, 1, 0);
+            engine
+                .delete(tombstone_key.clone())
+                .expect("Failed to delete data");
+            let m = fill_until(&engine, 2, n);
+            // 覆盖其中一个文件的其余key，使只有该文件满足merge条件
+            let overwritten = match merge_tombstone_file {
+                true => n..m,
+                false => 0..n,
+            };
+            for i in overwritten.clone() {
+                engine
+                    .put(get_test_key(i), get_test_value(i + 10000))
+                    .expect("Failed to put data");
+            }
+            let file0 = std::fs::read(create_data_file_name(&engine_dir, 0)).unwrap();
+            let file1 = std::fs::read(create_data_file_name(&engine_dir, 1)).unwrap();
+            engine.merge().expect("Failed to merge");
+            std::mem::drop(engine);
+
+            let engine = Engine::open(engine_opts).expect("Failed to open engine");
+            let (merged, unmerged, unmerged_content) = match merge_tombstone_file {
+                true => (1, 0, file0),
+                false => (0, 1, file1),
+            };
+            assert_eq!(
+                std::fs::read(create_data_file_name(&engine_dir, unmerged)).unwrap(),
+                unmerged_content
+            );
+            assert!(create_data_file_name(&engine_dir, merged).is_file());
+            // 删除的key在重新打开后仍然是删除状态
+            assert_eq!(engine.get(tombstone_key.clone()), Err(Errors::KeyNotFound));
+            for i in 0..m {
+                let expected = match overwritten.contains(&i) {
+                    true => get_test_value(i + 10000),
+                    false => get_test_value(i),
+                };
+                assert_eq!(engine.get(get_test_key(i)), Ok(expected));
+            }
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+        }
+    }
+
+    #[test]
+    fn test_merge_resume_from_progress() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_resume_from_progress"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let merge_dir = create_merge_dir(&engine_dir);
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        for i in 0..250 {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+        }
+        engine.merge().expect("Failed to merge");
+
+        // 模拟处理完两个数据文件后崩溃：没有完成标识，hint文件仍是临时文件，进度只到第二个检查点
+        std::fs::remove_file(merge_dir.join(MERGE_FINISHED_FILE_NAME))
+            .expect("Failed to remove merge finished file");
+        std::fs::rename(
+            merge_dir.join(HINT_FILE_NAME),
+            merge_dir.join(HINT_TMP_FILE_NAME),
+        )
+        .expect("Failed to rename hint file");
+        let progress_file =
+            DataFile::new_merge_progress_file(&merge_dir).expect("Failed to open progress file");
+        let mut offset = 0;
+        for _ in 0..3 {
+            offset += progress_file.read_log_record(offset).unwrap().size;
+        }
+        truncate_file(&merge_dir.join(MERGE_PROGRESS_FILE_NAME), offset).unwrap();
+        std::mem::drop(engine);
+
+        // 重启后保留merge目录
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert!(merge_dir.is_dir());
+        let progress = load_merge_progress(&merge_dir).unwrap().unwrap();
+        assert_eq!(progress.merged_file_ids.len(), 2);
+        for i in 0..10 {
+            engine
+                .put(get_test_key(i), get_test_value(i + 2000))
+                .expect("Failed to put data");
+        }
+
+        // 从检查点继续merge
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert!(!merge_dir.exists());
+        assert!(engine_dir.join(HINT_FILE_NAME).is_file());
+        for i in 0..500 {
+            let expected = match i {
+                0..10 => get_test_value(i + 2000),
+                10..250 => get_test_value(i + 1000),
+                _ => get_test_value(i),
+            };
+            assert_eq!(engine.get(get_test_key(i)), Ok(expected));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_resume_keeps_hint_entries() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_resume_keeps_hint_entries"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let _ = std::fs::remove_dir_all(&engine_dir);
+        let merge_dir = create_merge_dir(&engine_dir);
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+        // 只覆盖后写入的key，前两个数据文件中的记录仍然有效，只能从hint文件中找到
+        for i in 250..500 {
+            engine
+                .put(get_test_key(i), get_test_value(i + 1000))
+                .expect("Failed to put data");
+        }
+        engine.merge().expect("Failed to merge");
+
+        // 模拟处理完两个数据文件后崩溃
+        std::fs::remove_file(merge_dir.join(MERGE_FINISHED_FILE_NAME))
+            .expect("Failed to remove merge finished file");
+        std::fs::rename(
+            merge_dir.join(HINT_FILE_NAME),
+            merge_dir.join(HINT_TMP_FILE_NAME),
+        )
+        .expect("Failed to rename hint file");
+        let progress_file =
+            DataFile::new_merge_progress_file(&merge_dir).expect("Failed to open progress file");
+        let mut offset = 0;
+        for _ in 0..3 {
+            offset += progress_file.read_log_record(offset).unwrap().size;
+        }
+        truncate_file(&merge_dir.join(MERGE_PROGRESS_FILE_NAME), offset).unwrap();
+        std::mem::drop(engine);
+
+        // 从检查点继续merge，重启后前两个数据文件中的key仍然可读
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert!(!merge_dir.exists());
+        for i in 0..500 {
+            let expected = match i {
+                0..250 => get_test_value(i),
+                _ => get_test_value(i + 1000),
+            };
+            assert_eq!(engine.get(get_test_key(i)), Ok(expected));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_compact_on_open() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_compact_on_open"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        // 每个key覆盖写入多次，大部分数据可以回收
+        for round in 0..5 {
+            for i in 0..100 {
+                engine
+                    .put(get_test_key(i), get_test_value(i + round * 1000))
+                    .expect("Failed to put data");
+            }
+        }
+        assert!(engine.reclaimable_ratio().unwrap() > 0.5);
+        let reclaimable_before = engine.reclaimable_bytes().unwrap();
+        std::mem::drop(engine);
+
+        // 未达到阈值时不merge
+        let engine = Engine::open(Options {
+            compact_on_open: Some(0.99),
+            ..engine_opts.clone()
+        })
+        .expect("Failed to open engine");
+        assert_eq!(engine.last_merge_stat(), None);
+        assert_eq!(engine.reclaimable_bytes(), Ok(reclaimable_before));
+        std::mem::drop(engine);
+
+        let compact_opts = Options {
+            compact_on_open: Some(0.5),
+            ..engine_opts
+        };
+        let engine = Engine::open(compact_opts.clone()).expect("Failed to open engine");
+        assert!(engine.last_merge_stat().is_some());
+        assert!(engine.reclaimable_bytes().unwrap() < reclaimable_before);
+        assert!(engine.reclaimable_ratio().unwrap() < 0.5);
+        for i in 0..100 {
+            assert_eq!(
+                engine.get(get_test_key(i)),
+                Ok(get_test_value(i + 4 * 1000))
+            );
+        }
+        std::mem::drop(engine);
+
+        // merge后再次打开低于阈值，不会重复merge
+        let engine = Engine::open(compact_opts).expect("Failed to open engine");
+        assert!(engine.reclaimable_ratio().unwrap() < 0.5);
+        assert_eq!(engine.list_keys().unwrap().len(), 100);
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_inline_hints() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_inline_hints"),
+            data_file_size: 4 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            inline_hints: true,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for round in 0..3 {
+            for i in 0..300 {
+                engine
+                    .put(get_test_key(i), get_test_value(i + round * 1000))
+                    .expect("Failed to put data");
+            }
+        }
+        for i in 0..50 {
+            engine
+                .delete(get_test_key(i))
+                .expect("Failed to delete data");
+        }
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+
+        // merge不生成单独的hint文件，被merge的文件末尾带有内联hint
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        assert!(!engine_dir.join(HINT_FILE_NAME).exists());
+        assert!(inline_hints_file_id(&engine_dir).is_some());
+        let merged_file_ids: Vec<u32> = engine
+            .older_files
+            .read()
+            .values()
+            .filter(|data_file| data_file.has_inline_hints())
+            .map(|data_file| data_file.get_file_id())
+            .collect();
+        assert!(!merged_file_ids.is_empty());
+        let check_keys = |engine: &Engine| {
+            assert_eq!(engine.list_keys().unwrap().len(), 250);
+            for i in 0..50 {
+                assert_eq!(engine.get(get_test_key(i)), Err(Errors::KeyNotFound));
+            }
+            for i in 50..300 {
+                assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i + 2000)));
+            }
+        };
+        check_keys(&engine);
+        // 读取数据文件时内联hint不会被当作记录
+        for file_id in &merged_file_ids {
+            assert!(engine.read_file_records(*file_id).is_ok());
+        }
+        std::mem::drop(engine);
+
+        // 未开启inline_hints时从数据文件加载索引，同样跳过文件末尾的内联hint
+        let engine = Engine::open(Options {
+            inline_hints: false,
+            ..engine_opts.clone()
+        })
+        .expect("Failed to open engine");
+        check_keys(&engine);
+        std::mem::drop(engine);
+
+        // 再次merge时读取带内联hint的文件
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        engine
+            .put(get_test_key(0), get_test_value(0))
+            .expect("Failed to put data");
+        engine.merge().expect("Failed to merge");
+        std::mem::drop(engine);
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        assert!(!engine_dir.join(HINT_FILE_NAME).exists());
+        assert_eq!(engine.get(get_test_key(0)), Ok(get_test_value(0)));
+        assert_eq!(engine.list_keys().unwrap().len(), 251);
+        std::mem::drop(engine);
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+
+    #[test]
+    fn test_merge_concurrent_reads_writes() {
+        let engine_opts = Options {
+            dir_path: std::env::temp_dir().join("test_merge_concurrent_reads_writes"),
+            data_file_size: 32 * 1024,
+            index_type: IndexType::BTree,
+            use_mmap: false,
+            ..Default::default()
+        };
+        let engine_dir = engine_opts.dir_path.clone();
+        let engine = Engine::open(engine_opts.clone()).expect("Failed to open engine");
+        for i in 0..1000 {
+            engine
+                .put(get_test_key(i), get_test_value(i))
+                .expect("Failed to put data");
+        }
+
+        // 持有的旧文件快照在文件轮转后仍然可用
+        let snapshot = engine.older_files.read().clone();
+        let merging = std::sync::atomic::AtomicBool::new(true);
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                let mut rounds = 0;
+                while merging.load(std::sync::atomic::Ordering::SeqCst) || rounds == 0 {
+                    for i in 0..1000 {
+                        assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+                    }
+                    rounds += 1;
+                }
+            });
+            let writer = scope.spawn(|| {
+                for i in 1000..3000 {
+                    engine
+                        .put(get_test_key(i), get_test_value(i))
+                        .expect("Failed to put data");
+                }
+            });
+            engine.merge().expect("Failed to merge");
+            writer.join().expect("Writer thread panicked");
+            merging.store(false, std::sync::atomic::Ordering::SeqCst);
+            reader.join().expect("Reader thread panicked");
+        });
+        assert!(engine.older_files.read().len() > snapshot.len());
+        for data_file in snapshot.values() {
+            assert!(data_file.read_log_record(0).is_ok());
+        }
+        std::mem::drop(snapshot);
+        std::mem::drop(engine);
+
+        // merge期间的写入不会丢失
+        let engine = Engine::open(engine_opts).expect("Failed to open engine");
+        for i in 0..3000 {
+            assert_eq!(engine.get(get_test_key(i)), Ok(get_test_value(i)));
+        }
+
+        std::fs::remove_dir_all(engine_dir).expect("Failed to remove test directory");
+    }
+}
@@ -1,11 +1,27 @@
 #![allow(dead_code)]
 
-use std::path::PathBuf;
+use std::{fmt, path::PathBuf, sync::Arc, time::Duration};
+
+use crate::{db::Engine, errors::Result};
 
 const DEFAULT_DATA_FILE_SIZE_BYTES: u64 = 256 * 1024 * 1024; // 256MB
 
+/// 首次创建db后执行的回调
+pub type FirstLoadHook = Arc<dyn Fn(&Engine) -> Result<()> + Send + Sync>;
+
+/// key变换函数，索引、读缓存等使用变换后的key，数据文件中仍保存原始key
+pub type KeyTransform = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+/// value编解码器，写入前对value编码，读取后解码，可用于加密或压缩
+pub trait ValueCodec: Send + Sync {
+    /// 编解码器id，随每条记录保存，读取时只解码id相同的记录
+    fn id(&self) -> u8;
+    fn encode(&self, value: &[u8]) -> Vec<u8>;
+    fn decode(&self, value: &[u8]) -> Result<Vec<u8>>;
+}
+
 /// 数据库选项
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct Options {
     /// 数据库目录
     pub(crate) dir_path: PathBuf,
@@ -14,11 +30,175 @@ pub struct Options {
     /// 是否立刻持久化
     pub(crate) sync_write: bool,
     /// 累计写入阈值后再持久化
+    ///
+    /// 累计值从每次打开时的0开始计算：close会持久化所有数据文件，崩溃前未持久化的数据是否已落盘无法判断，
+    /// 因此重启后第一次持久化发生在本次打开后累计写入达到阈值时
     pub(crate) bytes_per_sync: usize,
     /// 索引类型
     pub(crate) index_type: IndexType,
-    /// 是否使用mmap打开数据文件
+    /// 启动加载索引时是否使用mmap读取数据文件
+    ///
+    /// mmap只适合读取较多的旧数据文件，活跃文件的追加写入始终使用标准文件IO，
+    /// 索引加载完成后所有数据文件都切换回标准文件IO
     pub(crate) use_mmap: bool,
+    /// 首次创建db后执行的回调，重新打开时不会执行
+    pub(crate) on_first_load: Option<FirstLoadHook>,
+    /// 打开时已有数据文件的记录格式，新写入的记录始终使用原生格式
+    pub(crate) format: Format,
+    /// 打开时加载索引的时间预算，超出后剩余数据文件在后台加载，
+    /// 加载完成前读取会额外扫描这些文件，写入会等待加载完成
+    pub(crate) max_recovery_duration: Option<Duration>,
+    /// merge时是否按key排序后写入，merge后的数据文件按key有序
+    pub(crate) sort_on_merge: bool,
+    /// merge时按最近更新在前的顺序写入有效记录，热点key集中在merge后数据文件的开头，不能与sort_on_merge同时开启
+    pub(crate) merge_recent_first: bool,
+    /// 从数据文件重建索引后，检查索引中的key数是否与数据文件中有效记录的key数一致，不一致时返回IndexCorrupt，
+    /// 检查期间需要在内存中记录所有key，B+Tree索引未重建时不检查
+    pub(crate) check_consistency_on_open: bool,
+    /// 后台定时持久化活跃数据文件的间隔，None表示不启用
+    pub(crate) sync_interval: Option<Duration>,
+    /// merge期间是否阻塞写入，开启后写入会等待merge完成
+    pub(crate) block_writes_during_merge: bool,
+    /// merge生成的hint索引记录是否带CRC，关闭后hint文件更小、加载更快
+    pub(crate) hint_crc: bool,
+    /// 设置后merge只处理可回收字节占比不低于该值的旧数据文件，None表示merge所有数据文件
+    pub(crate) merge_dead_ratio: Option<f32>,
+    /// 打开时是否跳过无法读取的旧数据文件，跳过的文件中的key会丢失，关闭时打开失败
+    pub(crate) skip_corrupt_files: bool,
+    /// 是否统计get、put、merge的延迟，通过Engine::latency_report获取
+    pub(crate) track_latency: bool,
+    /// 数据文件key前缀压缩的重启点间隔，0表示不开启
+    ///
+    /// 开启后每条记录只保存与上一条记录key的公共前缀长度和后缀，每隔该数量的记录写入一条完整key的记录
+    pub(crate) key_prefix_restart_interval: usize,
+    /// 打开时创建或切换活跃数据文件时是否持久化目录项，保证新数据文件在崩溃后仍然存在
+    pub(crate) sync_dir_on_rotate: bool,
+    /// merge时并行读取数据文件的线程数，写入仍为单线程，1表示不并行
+    pub(crate) merge_threads: usize,
+    /// 是否只允许UTF-8编码的key，开启后写入和删除非UTF-8的key返回InvalidUtf8Key
+    pub(crate) utf8_keys: bool,
+    /// scan时后台线程最多预读的数据文件数，0表示不预读
+    pub(crate) scan_prefetch: usize,
+    /// 写入value前使用的编解码器，已编码的记录读取时需要配置相同id的编解码器
+    pub(crate) value_codec: Option<Arc<dyn ValueCodec>>,
+    /// get读取时数据文件已被删除、且key已移动到其他位置时的重试次数，
+    /// on_read_corruption为SkipToIndex时，读取到损坏记录后重新查询索引也计入该次数
+    pub(crate) read_retries: usize,
+    /// 索引只保存在内存中，不使用hint文件，每次打开都从数据文件重建索引，不支持B+Tree索引
+    pub(crate) index_only_in_memory: bool,
+    /// 打开时不同步加载索引，所有数据文件都在后台加载，加载完成前读取会扫描数据文件，写入会等待加载完成
+    pub(crate) lazy_index: bool,
+    /// 只读打开，不允许写入，用于查看归档的数据库，不支持B+Tree索引
+    ///
+    /// 目录位于只读文件系统、无法创建文件锁时不加锁打开，数据文件以只读方式打开
+    pub(crate) read_only: bool,
+    /// 操作日志文件路径，成功的put和delete会带上时间戳追加到该文件，可重放到其他db，None表示不记录
+    pub(crate) ops_journal: Option<PathBuf>,
+    /// 目录中有B+Tree索引文件但index_type不是B+Tree时，删除该文件并从数据文件重建索引，
+    /// 否则打开时返回IndexTypeMismatch
+    pub(crate) migrate_index: bool,
+    /// 内存索引的估算大小上限（字节），加载或写入新key超出时返回IndexBudgetExceeded，
+    /// None表示不限制，B+Tree索引保存在文件中，不受该限制
+    pub(crate) index_memory_budget: Option<usize>,
+    /// 从数据文件加载索引时，将每个文件的索引更新在一次加锁中批量完成，关闭时逐条更新
+    pub(crate) batch_index_load: bool,
+    /// 打开时可回收字节占所有数据文件的比例不低于该值，先merge再重新打开后返回，None表示不检查
+    pub(crate) compact_on_open: Option<f32>,
+    /// 读写和加载索引前对key做变换，例如转为小写实现不区分大小写的key，迭代时返回写入时的原始key，
+    /// 前缀查询按变换后的key匹配。变换后相同的key视为同一个key，需要在db的整个生命周期中保持不变
+    pub(crate) key_transform: Option<KeyTransform>,
+    /// 按key读取时校验记录中的key与请求的key一致，不一致时返回KeyMismatch，用于发现错误的索引位置
+    pub(crate) verify_key_on_read: bool,
+    /// merge时不生成单独的hint文件，而是将hint写在每个merge后的数据文件末尾，
+    /// 打开时从这些文件末尾加载索引，配置后merge无法从中断处继续
+    pub(crate) inline_hints: bool,
+    /// 数据目录所在磁盘的可用空间低于该值时拒绝写入，返回InsufficientDiskSpace，None表示不检查，
+    /// merge时也会在有效数据之外保留该空间
+    pub(crate) min_free_bytes: Option<u64>,
+    /// get读取到CRC校验失败的记录时的处理方式
+    pub(crate) on_read_corruption: CorruptionPolicy,
+    /// 打开时只加载这些keyspace中的key，keyspace为key的前缀，按变换后的key匹配，None表示加载所有key
+    ///
+    /// 其他keyspace中的key不在索引中，读取返回KeyNotFound，写入返回KeyspaceNotLoaded。
+    /// 索引不完整时不能merge，不支持B+Tree索引和compact_on_open
+    pub(crate) load_keyspaces: Option<Vec<String>>,
+    /// 从数据文件加载索引时最多容忍的CRC校验失败的记录数，0表示不容忍，遇到损坏记录时打开失败
+    ///
+    /// 损坏的记录被跳过，文件id和偏移写入数据目录下的quarantine文件，超过该数量时返回TooManyCorruptRecords
+    pub(crate) max_load_crc_errors: usize,
+    /// 可回收字节占所有数据文件总大小的比例低于该值时，merge不做处理，返回MergeRatioUnreached，
+    /// 取值范围为0到1，0表示总是merge
+    pub(crate) data_file_merge_ratio: f32,
+    /// B+Tree索引的提交间隔，设置后索引更新暂存在内存中，由后台线程每隔该时间在一个事务中提交，
+    /// 减少写放大；提交前崩溃时打开会从数据文件重建索引。None表示每次写入都提交，只对B+Tree索引有效
+    pub(crate) index_flush_interval: Option<Duration>,
+    /// 纯内存模式，数据文件只保存在内存中，不创建目录和文件锁，不读写磁盘，关闭后数据丢失
+    ///
+    /// 忽略dir_path，不支持B+Tree索引、read_only、ops_journal、min_free_bytes、compact_on_open和merge
+    pub(crate) in_memory: bool,
+    /// get_cached读缓存最多保存的key数，缓存满时淘汰任意一项后再放入
+    pub(crate) read_cache_capacity: usize,
+    /// get_timeout同时进行的读取线程数上限，超时的读取线程在读取完成前仍计入，
+    /// 达到上限时get_timeout直接返回TooManyPendingReads
+    pub(crate) max_pending_timeout_reads: usize,
+}
+
+impl fmt::Debug for Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Options")
+            .field("dir_path", &self.dir_path)
+            .field("data_file_size", &self.data_file_size)
+            .field("sync_write", &self.sync_write)
+            .field("bytes_per_sync", &self.bytes_per_sync)
+            .field("index_type", &self.index_type)
+            .field("use_mmap", &self.use_mmap)
+            .field("on_first_load", &self.on_first_load.is_some())
+            .field("format", &self.format)
+            .field("max_recovery_duration", &self.max_recovery_duration)
+            .field("sort_on_merge", &self.sort_on_merge)
+            .field("merge_recent_first", &self.merge_recent_first)
+            .field("check_consistency_on_open", &self.check_consistency_on_open)
+            .field("sync_interval", &self.sync_interval)
+            .field("block_writes_during_merge", &self.block_writes_during_merge)
+            .field("hint_crc", &self.hint_crc)
+            .field("merge_dead_ratio", &self.merge_dead_ratio)
+            .field("skip_corrupt_files", &self.skip_corrupt_files)
+            .field("track_latency", &self.track_latency)
+            .field(
+                "key_prefix_restart_interval",
+                &self.key_prefix_restart_interval,
+            )
+            .field("sync_dir_on_rotate", &self.sync_dir_on_rotate)
+            .field("merge_threads", &self.merge_threads)
+            .field("utf8_keys", &self.utf8_keys)
+            .field("scan_prefetch", &self.scan_prefetch)
+            .field(
+                "value_codec",
+                &self.value_codec.as_ref().map(|codec| codec.id()),
+            )
+            .field("read_retries", &self.read_retries)
+            .field("index_only_in_memory", &self.index_only_in_memory)
+            .field("lazy_index", &self.lazy_index)
+            .field("read_only", &self.read_only)
+            .field("ops_journal", &self.ops_journal)
+            .field("migrate_index", &self.migrate_index)
+            .field("index_memory_budget", &self.index_memory_budget)
+            .field("batch_index_load", &self.batch_index_load)
+            .field("compact_on_open", &self.compact_on_open)
+            .field("key_transform", &self.key_transform.is_some())
+            .field("verify_key_on_read", &self.verify_key_on_read)
+            .field("inline_hints", &self.inline_hints)
+            .field("min_free_bytes", &self.min_free_bytes)
+            .field("on_read_corruption", &self.on_read_corruption)
+            .field("load_keyspaces", &self.load_keyspaces)
+            .field("max_load_crc_errors", &self.max_load_crc_errors)
+            .field("data_file_merge_ratio", &self.data_file_merge_ratio)
+            .field("index_flush_interval", &self.index_flush_interval)
+            .field("in_memory", &self.in_memory)
+            .field("read_cache_capacity", &self.read_cache_capacity)
+            .field("max_pending_timeout_reads", &self.max_pending_timeout_reads)
+            .finish()
+    }
 }
 
 impl Default for Options {
@@ -30,6 +210,45 @@ impl Default for Options {
             bytes_per_sync: 0,
             index_type: IndexType::BPlusTree,
             use_mmap: true,
+            on_first_load: None,
+            format: Format::Native,
+            max_recovery_duration: None,
+            sort_on_merge: false,
+            merge_recent_first: false,
+            check_consistency_on_open: false,
+            sync_interval: None,
+            block_writes_during_merge: false,
+            hint_crc: true,
+            merge_dead_ratio: None,
+            skip_corrupt_files: false,
+            track_latency: false,
+            key_prefix_restart_interval: 0,
+            sync_dir_on_rotate: true,
+            merge_threads: 1,
+            utf8_keys: false,
+            scan_prefetch: 0,
+            value_codec: None,
+            read_retries: 2,
+            index_only_in_memory: false,
+            lazy_index: false,
+            read_only: false,
+            ops_journal: None,
+            migrate_index: false,
+            index_memory_budget: None,
+            batch_index_load: true,
+            compact_on_open: None,
+            key_transform: None,
+            verify_key_on_read: false,
+            inline_hints: false,
+            min_free_bytes: None,
+            on_read_corruption: CorruptionPolicy::Error,
+            load_keyspaces: None,
+            max_load_crc_errors: 0,
+            data_file_merge_ratio: 0.0,
+            index_flush_interval: None,
+            in_memory: false,
+            read_cache_capacity: 1024,
+            max_pending_timeout_reads: 64,
         }
     }
 }
@@ -48,6 +267,14 @@ pub struct IteratorOptions {
     pub(crate) reverse: bool,
     /// 前缀
     pub(crate) prefix: Vec<u8>,
+    /// 只遍历不小于start的key，None表示不限制
+    pub(crate) start: Option<Vec<u8>>,
+    /// 只遍历小于end的key，end_inclusive为true时包括end，None表示不限制
+    ///
+    /// start和end始终按key升序表示范围，逆序时从end开始向start遍历
+    pub(crate) end: Option<Vec<u8>>,
+    /// 是否包括end
+    pub(crate) end_inclusive: bool,
 }
 
 /// 批量写入选项
@@ -57,6 +284,12 @@ pub struct WriteBatchOptions {
     pub(crate) max_batch_size: usize,
     /// 是否立刻持久化
     pub(crate) sync_write: bool,
+    /// 每提交N个批次持久化一次，计数在engine内的所有批次间共享，0表示不启用
+    pub(crate) sync_every: usize,
+    /// 提交时是否将所有记录编码到同一个缓冲区，一次写入数据文件
+    pub(crate) single_write: bool,
+    /// 暂存的记录数达到该值时，put自动提交当前批次并开始新的事务，None表示只在调用commit时提交
+    pub(crate) auto_commit_at: Option<usize>,
 }
 
 impl Default for WriteBatchOptions {
@@ -64,6 +297,9 @@ impl Default for WriteBatchOptions {
         Self {
             max_batch_size: 8192,
             sync_write: false,
+            sync_every: 0,
+            single_write: false,
+            auto_commit_at: None,
         }
     }
 }
@@ -72,4 +308,27 @@ impl Default for WriteBatchOptions {
 pub enum IOType {
     StandardFileIO,
     MmapIO,
+    /// 数据只保存在内存中，不创建文件
+    MemIO,
+}
+
+/// get读取到损坏记录时的处理方式
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum CorruptionPolicy {
+    /// 直接返回InvalidLogRecordCrc
+    #[default]
+    Error,
+    /// 记录日志后重新查询索引，key已被merge或新的写入移动到其他位置时读取新位置，
+    /// 最多重试read_retries次，索引位置没有变化时仍返回InvalidLogRecordCrc
+    SkipToIndex,
+}
+
+/// 数据文件的记录格式
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum Format {
+    /// 本库的记录格式
+    #[default]
+    Native,
+    /// Go版本bitcask的记录格式，只用于读取迁移
+    GoBitcask,
 }